@@ -9,8 +9,10 @@ use itertools::Itertools;
 use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
 use rand::SeedableRng;
+use rayon::prelude::*;
 use tracing::{debug, error, info, warn};
 
+use near_chain_configs::{GCPolicy, MIN_GC_NUM_EPOCHS_TO_KEEP};
 use near_chain_primitives::error::{Error, ErrorKind, LogTransientStorageError};
 use near_primitives::block::{genesis_chunks, Tip};
 use near_primitives::challenge::{
@@ -36,7 +38,7 @@ use near_primitives::transaction::ExecutionOutcomeWithIdAndProof;
 use near_primitives::types::chunk_extra::ChunkExtra;
 use near_primitives::types::{
     AccountId, Balance, BlockExtra, BlockHeight, BlockHeightDelta, EpochId, MerkleHash, NumBlocks,
-    ShardId,
+    ShardId, StateRoot,
 };
 use near_primitives::unwrap_or_return;
 #[cfg(feature = "protocol_feature_block_header_v3")]
@@ -46,7 +48,7 @@ use near_primitives::views::{
     FinalExecutionOutcomeWithReceiptView, FinalExecutionStatus, LightClientBlockView,
     SignedTransactionView,
 };
-use near_store::{ColState, ColStateHeaders, ColStateParts, ShardTries, StoreUpdate};
+use near_store::{ColState, ColStateHeaders, ColStateParts, ShardTries, Store, StoreUpdate};
 
 use near_primitives::state_record::StateRecord;
 
@@ -83,7 +85,7 @@ pub const TX_ROUTING_HEIGHT_HORIZON: BlockHeightDelta = 4;
 const NEAR_BASE: Balance = 1_000_000_000_000_000_000_000_000;
 
 /// Number of epochs for which we keep store data
-pub const NUM_EPOCHS_TO_KEEP_STORE_DATA: u64 = 5;
+pub const NUM_EPOCHS_TO_KEEP_STORE_DATA: u64 = MIN_GC_NUM_EPOCHS_TO_KEEP;
 
 /// Maximum number of height to go through at each step when cleaning forks during garbage collection.
 const GC_FORK_CLEAN_STEP: u64 = 1000;
@@ -192,6 +194,103 @@ impl OrphanBlockPool {
     }
 }
 
+lazy_static! {
+    /// Worker pool for validating and applying state parts, shared by all the shards being
+    /// synced. It uses at most half of all threads, so that the node still functions normally.
+    pub(crate) static ref STATE_PARTS_POOL: rayon::ThreadPool = rayon::ThreadPoolBuilder::new()
+        .num_threads(std::cmp::max(rayon::current_num_threads() / 2, 1))
+        .thread_name(|index| format!("state-parts-{}", index))
+        .build()
+        .expect("Failed to create the state parts pool");
+}
+
+/// Validates the state parts concurrently and saves them to `ColStateParts`.
+pub(crate) fn save_state_parts(
+    store: &Store,
+    runtime_adapter: &dyn RuntimeAdapter,
+    shard_id: ShardId,
+    sync_hash: CryptoHash,
+    state_root: StateRoot,
+    num_parts: u64,
+    parts: &[(u64, Vec<u8>)],
+) -> Result<(), Error> {
+    let invalid_part = STATE_PARTS_POOL.install(|| {
+        parts.par_iter().find_first(|(part_id, data)| {
+            !runtime_adapter.validate_state_part(&state_root, *part_id, num_parts, data)
+        })
+    });
+    if let Some((part_id, _)) = invalid_part {
+        byzantine_assert!(false);
+        return Err(ErrorKind::Other(format!(
+            "set_state_parts failed: validate_state_part failed for part {}",
+            part_id
+        ))
+        .into());
+    }
+
+    // Saving the parts data.
+    let mut store_update = store.store_update();
+    for (part_id, data) in parts {
+        let key = StatePartKey(sync_hash, shard_id, *part_id).try_to_vec()?;
+        store_update.set(ColStateParts, &key, data);
+    }
+    store_update.commit()?;
+    Ok(())
+}
+
+/// Prepares the state parts stored in `ColStateParts` and commits them, a batch at a time, so
+/// that only a batch of parts is kept in memory.
+fn apply_state_parts(
+    store: &Store,
+    runtime_adapter: &dyn RuntimeAdapter,
+    shard_id: ShardId,
+    sync_hash: CryptoHash,
+    state_root: StateRoot,
+    epoch_id: &EpochId,
+    num_parts: u64,
+) -> Result<(), Error> {
+    let start = Instant::now();
+    let pool = &*STATE_PARTS_POOL;
+    let part_ids: Vec<u64> = (0..num_parts).collect();
+    for batch in part_ids.chunks(pool.current_num_threads()) {
+        let store_updates: Vec<Result<StoreUpdate, Error>> = pool.install(|| {
+            batch
+                .par_iter()
+                .map(|part_id| {
+                    let key = StatePartKey(sync_hash, shard_id, *part_id).try_to_vec()?;
+                    let part = store.get(ColStateParts, &key)?.ok_or_else(|| {
+                        ErrorKind::DBNotFoundErr(format!(
+                            "state part {} of shard {}",
+                            part_id, shard_id
+                        ))
+                    })?;
+                    runtime_adapter.prepare_state_part(
+                        shard_id,
+                        &state_root,
+                        *part_id,
+                        num_parts,
+                        &part,
+                        epoch_id,
+                    )
+                })
+                .collect()
+        });
+        for store_update in store_updates {
+            store_update?.commit()?;
+            near_metrics::inc_counter(&metrics::STATE_PARTS_APPLIED_TOTAL);
+        }
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    if elapsed > 0.0 {
+        near_metrics::set_gauge(
+            &metrics::STATE_PARTS_APPLIED_PER_SECOND,
+            (num_parts as f64 / elapsed) as i64,
+        );
+    }
+    info!(target: "chain", "Applied {} state parts of shard {} in {:.1}s", num_parts, shard_id, elapsed);
+    Ok(())
+}
+
 /// Facade to the blockchain block processing and storage.
 /// Provides current view on the state according to the chain state.
 pub struct Chain {
@@ -206,6 +305,8 @@ pub struct Chain {
     pub block_economics_config: BlockEconomicsConfig,
     pub doomslug_threshold_mode: DoomslugThresholdMode,
     pending_states_to_patch: Option<Vec<StateRecord>>,
+    /// Number of epochs of each kind of data kept by garbage collection.
+    pub gc_policy: GCPolicy,
 }
 
 impl Chain {
@@ -243,6 +344,7 @@ impl Chain {
             block_economics_config: BlockEconomicsConfig::from(chain_genesis),
             doomslug_threshold_mode,
             pending_states_to_patch: None,
+            gc_policy: GCPolicy::default(),
         })
     }
 
@@ -358,6 +460,7 @@ impl Chain {
             block_economics_config: BlockEconomicsConfig::from(chain_genesis),
             doomslug_threshold_mode,
             pending_states_to_patch: None,
+            gc_policy: GCPolicy::default(),
         })
     }
 
@@ -554,17 +657,27 @@ impl Chain {
     //    and the Trie is updated with having only Genesis data.
     // 4. State Sync Clearing happens in `reset_data_pre_state_sync()`.
     //
+    // Partial Clearing:
+    // 1. `GCPolicy` allows to keep chunks, outcomes and state for fewer epochs than blocks.
+    //    Blocks are kept for the largest number of epochs as all other data is cleared through them.
+    // 2. Data of each such kind is cleared along the Canonical Chain from its own tail
+    //    (Chunk Tail, Outcome Tail and State Tail) before Forks Cleaning.
+    // 3. Canonical Chain Clearing skips the data which is already cleared this way.
+    //
     pub fn clear_data(
         &mut self,
         tries: ShardTries,
         gc_blocks_limit: NumBlocks,
+        gc_policy: &GCPolicy,
     ) -> Result<(), Error> {
         #[cfg(feature = "delay_detector")]
         let _d = DelayDetector::new("GC".into());
 
         let head = self.store.head()?;
         let tail = self.store.tail()?;
-        let gc_stop_height = self.runtime_adapter.get_gc_stop_height(&head.last_block_hash);
+        let gc_stop_height = self
+            .runtime_adapter
+            .get_gc_stop_height_for_epochs(&head.last_block_hash, gc_policy.blocks_epochs());
 
         if gc_stop_height > head.height {
             return Err(ErrorKind::GCError(
@@ -586,6 +699,12 @@ impl Chain {
         }
         let mut gc_blocks_remaining = gc_blocks_limit;
 
+        // Partial Clearing
+        self.clear_partial_data(tries.clone(), &head, tail, gc_policy, &mut gc_blocks_remaining)?;
+        if gc_blocks_remaining == 0 {
+            return Ok(());
+        }
+
         // Forks Cleaning
         let stop_height = std::cmp::max(tail, fork_tail.saturating_sub(GC_FORK_CLEAN_STEP));
         for height in (stop_height..fork_tail).rev() {
@@ -634,6 +753,77 @@ impl Chain {
         Ok(())
     }
 
+    /// Clears the data which `gc_policy` keeps for fewer epochs than blocks.
+    fn clear_partial_data(
+        &mut self,
+        tries: ShardTries,
+        head: &Tip,
+        tail: BlockHeight,
+        gc_policy: &GCPolicy,
+        gc_blocks_remaining: &mut NumBlocks,
+    ) -> Result<(), Error> {
+        let blocks_epochs = gc_policy.blocks_epochs();
+
+        if gc_policy.state_epochs() < blocks_epochs {
+            let stop_height = self
+                .runtime_adapter
+                .get_gc_stop_height_for_epochs(&head.last_block_hash, gc_policy.state_epochs());
+            let state_tail = std::cmp::max(tail, self.store.state_tail()?);
+            for height in state_tail + 1..stop_height {
+                if *gc_blocks_remaining == 0 {
+                    return Ok(());
+                }
+                let mut chain_store_update = self.store.store_update();
+                if let Ok(block_hash) = chain_store_update.get_block_hash_by_height(height) {
+                    chain_store_update.clear_state_data(&block_hash, tries.clone())?;
+                    *gc_blocks_remaining -= 1;
+                }
+                chain_store_update.update_state_tail(height);
+                chain_store_update.commit()?;
+            }
+        }
+
+        if gc_policy.outcomes_epochs() < blocks_epochs {
+            let stop_height = self
+                .runtime_adapter
+                .get_gc_stop_height_for_epochs(&head.last_block_hash, gc_policy.outcomes_epochs());
+            let outcome_tail = std::cmp::max(tail, self.store.outcome_tail()?);
+            for height in outcome_tail + 1..stop_height {
+                if *gc_blocks_remaining == 0 {
+                    return Ok(());
+                }
+                let mut chain_store_update = self.store.store_update();
+                if let Ok(block_hash) = chain_store_update.get_block_hash_by_height(height) {
+                    let block = chain_store_update.get_block(&block_hash)?.clone();
+                    chain_store_update.gc_outcomes(&block)?;
+                    *gc_blocks_remaining -= 1;
+                }
+                chain_store_update.update_outcome_tail(height);
+                chain_store_update.commit()?;
+            }
+        }
+
+        if gc_policy.chunks_epochs() < blocks_epochs {
+            let stop_height = self
+                .runtime_adapter
+                .get_gc_stop_height_for_epochs(&head.last_block_hash, gc_policy.chunks_epochs());
+            let mut chain_store_update = self.store.store_update();
+            // Chunks included into the Block of `stop_height` may be created earlier than it
+            if let Ok(block_hash) = chain_store_update.get_block_hash_by_height(stop_height) {
+                let min_chunk_height = chain_store_update
+                    .get_block(&block_hash)?
+                    .chunks()
+                    .iter()
+                    .map(|chunk_header| chunk_header.height_created())
+                    .min()
+                    .unwrap_or(stop_height);
+                chain_store_update.clear_chunk_data(min_chunk_height)?;
+                chain_store_update.commit()?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn clear_forks_data(
         &mut self,
         tries: ShardTries,
@@ -1761,6 +1951,60 @@ impl Chain {
         Ok(())
     }
 
+    /// Same as `set_state_part`, but the part is validated and saved on the state parts pool and
+    /// the result is passed to `on_done` there, so the caller isn't blocked.
+    pub fn schedule_set_state_part(
+        &self,
+        shard_id: ShardId,
+        sync_hash: CryptoHash,
+        part_id: u64,
+        num_parts: u64,
+        data: Vec<u8>,
+        on_done: impl FnOnce(Result<(), Error>) + Send + 'static,
+    ) -> Result<(), Error> {
+        let state_root = self.get_state_header(shard_id, sync_hash)?.chunk_prev_state_root();
+        let runtime_adapter = self.runtime_adapter.clone();
+        let store = self.store.owned_store();
+        STATE_PARTS_POOL.spawn(move || {
+            let result = (|| -> Result<(), Error> {
+                if !runtime_adapter.validate_state_part(&state_root, part_id, num_parts, &data) {
+                    byzantine_assert!(false);
+                    return Err(ErrorKind::Other(
+                        "set_state_part failed: validate_state_part failed".into(),
+                    )
+                    .into());
+                }
+                let mut store_update = store.store_update();
+                let key = StatePartKey(sync_hash, shard_id, part_id).try_to_vec()?;
+                store_update.set(ColStateParts, &key, &data);
+                store_update.commit()?;
+                Ok(())
+            })();
+            on_done(result);
+        });
+        Ok(())
+    }
+
+    /// Same as `set_state_part` for several parts at once, which are validated concurrently.
+    pub fn set_state_parts(
+        &mut self,
+        shard_id: ShardId,
+        sync_hash: CryptoHash,
+        num_parts: u64,
+        parts: &[(u64, Vec<u8>)],
+    ) -> Result<(), Error> {
+        let shard_state_header = self.get_state_header(shard_id, sync_hash)?;
+        save_state_parts(
+            &self.store.owned_store(),
+            &*self.runtime_adapter,
+            shard_id,
+            sync_hash,
+            shard_state_header.chunk_prev_state_root(),
+            num_parts,
+            parts,
+        )
+    }
+
     pub fn set_state_finalize(
         &mut self,
         shard_id: ShardId,
@@ -1768,23 +2012,57 @@ impl Chain {
         num_parts: u64,
     ) -> Result<(), Error> {
         let shard_state_header = self.get_state_header(shard_id, sync_hash)?;
-        let mut height = shard_state_header.chunk_height_included();
         let state_root = shard_state_header.chunk_prev_state_root();
         let epoch_id = self.get_block_header(&sync_hash)?.epoch_id().clone();
+        apply_state_parts(
+            &self.store.owned_store(),
+            &*self.runtime_adapter,
+            shard_id,
+            sync_hash,
+            state_root,
+            &epoch_id,
+            num_parts,
+        )?;
+        self.set_state_finalize_applied(shard_id, sync_hash)
+    }
 
-        for part_id in 0..num_parts {
-            let key = StatePartKey(sync_hash, shard_id, part_id).try_to_vec()?;
-            let part = self.store.owned_store().get(ColStateParts, &key)?.unwrap();
-
-            self.runtime_adapter.apply_state_part(
+    /// Applies the downloaded state parts on the state parts pool and passes the result to
+    /// `on_done` there, so the caller isn't blocked. The state sync is finished afterwards by
+    /// `set_state_finalize_applied`.
+    pub fn schedule_apply_state_parts(
+        &self,
+        shard_id: ShardId,
+        sync_hash: CryptoHash,
+        num_parts: u64,
+        on_done: impl FnOnce(Result<(), Error>) + Send + 'static,
+    ) -> Result<(), Error> {
+        let state_root = self.get_state_header(shard_id, sync_hash)?.chunk_prev_state_root();
+        let epoch_id = self.get_block_header(&sync_hash)?.epoch_id().clone();
+        let store = self.store.owned_store();
+        let runtime_adapter = self.runtime_adapter.clone();
+        STATE_PARTS_POOL.spawn(move || {
+            on_done(apply_state_parts(
+                &store,
+                &*runtime_adapter,
                 shard_id,
-                &state_root,
-                part_id,
-                num_parts,
-                &part,
+                sync_hash,
+                state_root,
                 &epoch_id,
-            )?;
-        }
+                num_parts,
+            ))
+        });
+        Ok(())
+    }
+
+    /// Finishes the state sync of a shard whose state parts were applied: applies the chunk of
+    /// the state header and builds the chain up to the sync block.
+    pub fn set_state_finalize_applied(
+        &mut self,
+        shard_id: ShardId,
+        sync_hash: CryptoHash,
+    ) -> Result<(), Error> {
+        let shard_state_header = self.get_state_header(shard_id, sync_hash)?;
+        let mut height = shard_state_header.chunk_height_included();
 
         // Applying the chunk starts here
         let mut chain_update = self.chain_update();
@@ -2089,6 +2367,7 @@ impl Chain {
             &self.genesis,
             self.transaction_validity_period,
             self.pending_states_to_patch.take(),
+            self.gc_policy,
         )
     }
 
@@ -2512,6 +2791,7 @@ pub struct ChainUpdate<'a> {
     #[allow(unused)]
     transaction_validity_period: BlockHeightDelta,
     states_to_patch: Option<Vec<StateRecord>>,
+    gc_policy: GCPolicy,
 }
 
 impl<'a> ChainUpdate<'a> {
@@ -2526,6 +2806,7 @@ impl<'a> ChainUpdate<'a> {
         genesis: &'a Block,
         transaction_validity_period: BlockHeightDelta,
         states_to_patch: Option<Vec<StateRecord>>,
+        gc_policy: GCPolicy,
     ) -> Self {
         let chain_store_update: ChainStoreUpdate<'_> = store.store_update();
         ChainUpdate {
@@ -2539,6 +2820,7 @@ impl<'a> ChainUpdate<'a> {
             genesis,
             transaction_validity_period,
             states_to_patch,
+            gc_policy,
         }
     }
 
@@ -3049,7 +3331,10 @@ impl<'a> ChainUpdate<'a> {
         let prev_height = prev.height();
 
         // Do not accept old forks
-        if prev_height < self.runtime_adapter.get_gc_stop_height(&head.last_block_hash) {
+        let gc_stop_height = self
+            .runtime_adapter
+            .get_gc_stop_height_for_epochs(&head.last_block_hash, self.gc_policy.blocks_epochs());
+        if prev_height < gc_stop_height {
             return Err(ErrorKind::InvalidBlockHeight(prev_height).into());
         }
 
@@ -3465,13 +3750,35 @@ impl<'a> ChainUpdate<'a> {
             };
         if last_final_block_header.height() > final_head.height {
             let tip = Tip::from_header(last_final_block_header);
+            let num_shards = last_final_block_header.chunk_mask().len() as ShardId;
             self.chain_store_update.save_final_head(&tip)?;
+            self.update_flat_state_heads(&tip.last_block_hash, num_shards)?;
             Ok(Some(tip))
         } else {
             Ok(None)
         }
     }
 
+    /// Moves flat state of the shards to the new final block. Shards which don't track the
+    /// block don't have deltas for it and are skipped.
+    fn update_flat_state_heads(
+        &mut self,
+        final_block_hash: &CryptoHash,
+        num_shards: ShardId,
+    ) -> Result<(), Error> {
+        let tries = self.runtime_adapter.get_tries();
+        if !tries.is_flat_state_enabled() {
+            return Ok(());
+        }
+        for shard_id in 0..num_shards {
+            let store_update = tries
+                .update_flat_state_head(shard_id, final_block_hash)
+                .map_err(ErrorKind::StorageError)?;
+            self.chain_store_update.merge(store_update);
+        }
+        Ok(())
+    }
+
     /// Directly updates the head if we've just appended a new block to it or handle
     /// the situation where the block has higher height to have a fork
     fn update_head(&mut self, header: &BlockHeader) -> Result<Option<Tip>, Error> {
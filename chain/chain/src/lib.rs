@@ -7,7 +7,7 @@ pub use lightclient::{create_light_client_block_view, get_epoch_block_producers_
 pub use near_chain_primitives::{self, Error, ErrorKind};
 pub use near_primitives::receipt::ReceiptResult;
 pub use store::{ChainStore, ChainStoreAccess, ChainStoreUpdate};
-pub use store_validator::{ErrorMessage, StoreValidator};
+pub use store_validator::{ErrorMessage, Severity, StoreValidator};
 pub use types::{Block, BlockHeader, BlockStatus, ChainGenesis, Provenance, RuntimeAdapter};
 
 pub mod chain;
@@ -16,6 +16,7 @@ mod lightclient;
 mod metrics;
 pub mod migrations;
 pub mod missing_chunks;
+pub mod state_sync_dir;
 mod store;
 pub mod store_validator;
 pub mod test_utils;
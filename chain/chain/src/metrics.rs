@@ -25,4 +25,14 @@ lazy_static! {
         "near_validator_active_total",
         "The total number of validators active after last block"
     );
+    pub static ref STATE_PARTS_APPLIED_TOTAL: near_metrics::Result<IntCounter> =
+        try_create_int_counter(
+            "near_state_parts_applied_total",
+            "Total number of state parts applied during state sync and catchup"
+        );
+    pub static ref STATE_PARTS_APPLIED_PER_SECOND: near_metrics::Result<IntGauge> =
+        try_create_int_gauge(
+            "near_state_parts_applied_per_second",
+            "Number of state parts applied per second during the last state sync of a shard"
+        );
 }
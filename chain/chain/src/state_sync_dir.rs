@@ -0,0 +1,161 @@
+//! State headers and parts stored in a local directory, used to bootstrap nodes without
+//! downloading the state from peers.
+//!
+//! The layout is `<dir>/<sync_hash>/<shard_id>/header` for the borsh serialized
+//! `ShardStateSyncResponseHeader` and `<dir>/<sync_hash>/<shard_id>/part_<part_id>` for every
+//! state part. The header is written last, so a directory without it is incomplete.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use tracing::debug;
+
+use near_primitives::hash::CryptoHash;
+use near_primitives::syncing::{get_num_state_parts, ShardStateSyncResponseHeader};
+use near_primitives::types::{ShardId, StateRoot};
+use near_store::Store;
+
+use crate::chain::{save_state_parts, STATE_PARTS_POOL};
+use crate::{Chain, Error, RuntimeAdapter};
+
+/// Number of parts read into memory and validated at the same time.
+const IMPORT_BATCH_NUM_PARTS: u64 = 16;
+
+fn shard_dir(dir: &Path, shard_id: ShardId, sync_hash: &CryptoHash) -> PathBuf {
+    dir.join(sync_hash.to_string()).join(shard_id.to_string())
+}
+
+pub fn state_header_path(dir: &Path, shard_id: ShardId, sync_hash: &CryptoHash) -> PathBuf {
+    shard_dir(dir, shard_id, sync_hash).join("header")
+}
+
+pub fn state_part_path(
+    dir: &Path,
+    shard_id: ShardId,
+    sync_hash: &CryptoHash,
+    part_id: u64,
+) -> PathBuf {
+    shard_dir(dir, shard_id, sync_hash).join(format!("part_{}", part_id))
+}
+
+/// Writes the state header and all state parts of the shard to `dir`, computing them if they
+/// are not in `ColStateHeaders` and `ColStateParts` yet. Returns the number of parts.
+pub fn export_state(
+    chain: &mut Chain,
+    dir: &Path,
+    shard_id: ShardId,
+    sync_hash: CryptoHash,
+) -> Result<u64, Error> {
+    let header = chain.get_state_response_header(shard_id, sync_hash)?;
+    let num_parts = get_num_state_parts(header.state_root_node().memory_usage);
+    fs::create_dir_all(shard_dir(dir, shard_id, &sync_hash))?;
+    for part_id in 0..num_parts {
+        let part = chain.get_state_response_part(shard_id, part_id, sync_hash)?;
+        fs::write(state_part_path(dir, shard_id, &sync_hash, part_id), &part)?;
+        debug!(target: "sync", "Exported state part {} of {} of shard {}", part_id, num_parts, shard_id);
+    }
+    fs::write(state_header_path(dir, shard_id, &sync_hash), header.try_to_vec()?)?;
+    Ok(num_parts)
+}
+
+/// Reads the state header of the shard from `dir` and saves it the same way as if it was
+/// downloaded from peers. Returns the number of parts.
+fn import_state_header(
+    chain: &mut Chain,
+    dir: &Path,
+    shard_id: ShardId,
+    sync_hash: CryptoHash,
+) -> Result<u64, Error> {
+    let header = ShardStateSyncResponseHeader::try_from_slice(&fs::read(state_header_path(
+        dir, shard_id, &sync_hash,
+    ))?)?;
+    let num_parts = get_num_state_parts(header.state_root_node().memory_usage);
+    chain.set_state_header(shard_id, sync_hash, header)?;
+    Ok(num_parts)
+}
+
+/// Reads all state parts of the shard from `dir`, a batch at a time, validates and saves them.
+fn import_state_parts(
+    store: &Store,
+    runtime_adapter: &dyn RuntimeAdapter,
+    dir: &Path,
+    shard_id: ShardId,
+    sync_hash: CryptoHash,
+    state_root: StateRoot,
+    num_parts: u64,
+) -> Result<(), Error> {
+    let mut part_id = 0;
+    while part_id < num_parts {
+        let batch_end = std::cmp::min(part_id + IMPORT_BATCH_NUM_PARTS, num_parts);
+        let parts = (part_id..batch_end)
+            .map(|part_id| {
+                Ok((part_id, fs::read(state_part_path(dir, shard_id, &sync_hash, part_id))?))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        save_state_parts(
+            store,
+            runtime_adapter,
+            shard_id,
+            sync_hash,
+            state_root,
+            num_parts,
+            &parts,
+        )?;
+        debug!(target: "sync", "Imported state parts {}..{} of {} of shard {}", part_id, batch_end, num_parts, shard_id);
+        part_id = batch_end;
+    }
+    Ok(())
+}
+
+/// Reads the state header and all state parts of the shard from `dir`, validates and saves them
+/// the same way as if they were downloaded from peers. Returns the number of parts.
+pub fn import_state(
+    chain: &mut Chain,
+    dir: &Path,
+    shard_id: ShardId,
+    sync_hash: CryptoHash,
+) -> Result<u64, Error> {
+    let num_parts = import_state_header(chain, dir, shard_id, sync_hash)?;
+    let state_root = chain.get_state_header(shard_id, sync_hash)?.chunk_prev_state_root();
+    import_state_parts(
+        &chain.store().owned_store(),
+        &*chain.runtime_adapter,
+        dir,
+        shard_id,
+        sync_hash,
+        state_root,
+        num_parts,
+    )?;
+    Ok(num_parts)
+}
+
+/// Same as `import_state`, but only the header is imported right away. The parts are imported on
+/// the state parts pool and the result is passed to `on_done` there, so the caller isn't blocked.
+pub fn schedule_import_state(
+    chain: &mut Chain,
+    dir: &Path,
+    shard_id: ShardId,
+    sync_hash: CryptoHash,
+    on_done: impl FnOnce(Result<u64, Error>) + Send + 'static,
+) -> Result<(), Error> {
+    let num_parts = import_state_header(chain, dir, shard_id, sync_hash)?;
+    let state_root = chain.get_state_header(shard_id, sync_hash)?.chunk_prev_state_root();
+    let store = chain.store().owned_store();
+    let runtime_adapter = chain.runtime_adapter.clone();
+    let dir = dir.to_path_buf();
+    STATE_PARTS_POOL.spawn(move || {
+        on_done(
+            import_state_parts(
+                &store,
+                &*runtime_adapter,
+                &dir,
+                shard_id,
+                sync_hash,
+                state_root,
+                num_parts,
+            )
+            .map(|()| num_parts),
+        )
+    });
+    Ok(())
+}
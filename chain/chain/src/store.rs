@@ -34,18 +34,18 @@ use near_primitives::types::{
 use near_primitives::utils::{get_block_shard_id, index_to_bytes, to_timestamp};
 use near_primitives::views::LightClientBlockView;
 use near_store::{
-    read_with_cache, ColBlock, ColBlockExtra, ColBlockHeader, ColBlockHeight, ColBlockInfo,
-    ColBlockMerkleTree, ColBlockMisc, ColBlockOrdinal, ColBlockPerHeight, ColBlockRefCount,
-    ColBlocksToCatchup, ColChallengedBlocks, ColChunkExtra, ColChunkHashesByHeight,
-    ColChunkPerHeightShard, ColChunks, ColEpochLightClientBlocks, ColGCCount,
-    ColHeaderHashesByHeight, ColIncomingReceipts, ColInvalidChunks, ColLastBlockWithNewChunk,
-    ColNextBlockHashes, ColNextBlockWithNewChunk, ColOutcomeIds, ColOutgoingReceipts,
-    ColPartialChunks, ColProcessedBlockHeights, ColReceiptIdToShardId, ColReceipts, ColState,
-    ColStateChanges, ColStateDlInfos, ColStateHeaders, ColStateParts, ColTransactionResult,
-    ColTransactions, ColTrieChanges, DBCol, KeyForStateChanges, ShardTries, Store, StoreUpdate,
-    TrieChanges, WrappedTrieChanges, CHUNK_TAIL_KEY, FINAL_HEAD_KEY, FORK_TAIL_KEY,
-    HEADER_HEAD_KEY, HEAD_KEY, LARGEST_TARGET_HEIGHT_KEY, LATEST_KNOWN_KEY, SHOULD_COL_GC,
-    TAIL_KEY,
+    get_flat_state_delta_key, read_with_cache, ColBlock, ColBlockExtra, ColBlockHeader,
+    ColBlockHeight, ColBlockInfo, ColBlockMerkleTree, ColBlockMisc, ColBlockOrdinal,
+    ColBlockPerHeight, ColBlockRefCount, ColBlocksToCatchup, ColChallengedBlocks, ColChunkExtra,
+    ColChunkHashesByHeight, ColChunkPerHeightShard, ColChunks, ColEpochLightClientBlocks,
+    ColFlatStateDeltas, ColGCCount, ColHeaderHashesByHeight, ColIncomingReceipts, ColInvalidChunks,
+    ColLastBlockWithNewChunk, ColNextBlockHashes, ColNextBlockWithNewChunk, ColOutcomeIds,
+    ColOutgoingReceipts, ColPartialChunks, ColProcessedBlockHeights, ColReceiptIdToShardId,
+    ColReceipts, ColState, ColStateChanges, ColStateDlInfos, ColStateHeaders, ColStateParts,
+    ColTransactionResult, ColTransactions, ColTrieChanges, DBCol, KeyForStateChanges, ShardTries,
+    Store, StoreUpdate, TrieChanges, WrappedTrieChanges, CHUNK_TAIL_KEY, FINAL_HEAD_KEY,
+    FORK_TAIL_KEY, HEADER_HEAD_KEY, HEAD_KEY, LARGEST_TARGET_HEIGHT_KEY, LATEST_KNOWN_KEY,
+    OUTCOME_TAIL_KEY, SHOULD_COL_GC, STATE_TAIL_KEY, TAIL_KEY,
 };
 
 use crate::byzantine_assert;
@@ -88,6 +88,10 @@ pub trait ChainStoreAccess {
     fn chunk_tail(&self) -> Result<BlockHeight, Error>;
     /// Tail height of the fork cleaning process.
     fn fork_tail(&self) -> Result<BlockHeight, Error>;
+    /// Tail height of the outcomes, when they are kept for fewer epochs than blocks.
+    fn outcome_tail(&self) -> Result<BlockHeight, Error>;
+    /// Tail height of the state, when it's kept for fewer epochs than blocks.
+    fn state_tail(&self) -> Result<BlockHeight, Error>;
     /// Head of the header chain (not the same thing as head_header).
     fn header_head(&self) -> Result<Tip, Error>;
     /// Header of the block at the head of the block chain (not the same thing as header_head).
@@ -772,6 +776,20 @@ impl ChainStoreAccess for ChainStore {
             .map_err(|e| e.into())
     }
 
+    fn outcome_tail(&self) -> Result<BlockHeight, Error> {
+        self.store
+            .get_ser(ColBlockMisc, OUTCOME_TAIL_KEY)
+            .map(|option| option.unwrap_or_else(|| self.genesis_height))
+            .map_err(|e| e.into())
+    }
+
+    fn state_tail(&self) -> Result<BlockHeight, Error> {
+        self.store
+            .get_ser(ColBlockMisc, STATE_TAIL_KEY)
+            .map(|option| option.unwrap_or_else(|| self.genesis_height))
+            .map_err(|e| e.into())
+    }
+
     /// Header of the block at the head of the block chain (not the same thing as header_head).
     fn head_header(&mut self) -> Result<&BlockHeader, Error> {
         self.get_block_header(&self.head()?.last_block_hash)
@@ -1144,6 +1162,8 @@ pub struct ChainStoreUpdate<'a> {
     tail: Option<BlockHeight>,
     chunk_tail: Option<BlockHeight>,
     fork_tail: Option<BlockHeight>,
+    outcome_tail: Option<BlockHeight>,
+    state_tail: Option<BlockHeight>,
     header_head: Option<Tip>,
     final_head: Option<Tip>,
     largest_target_height: Option<BlockHeight>,
@@ -1168,6 +1188,8 @@ impl<'a> ChainStoreUpdate<'a> {
             tail: None,
             chunk_tail: None,
             fork_tail: None,
+            outcome_tail: None,
+            state_tail: None,
             header_head: None,
             final_head: None,
             largest_target_height: None,
@@ -1265,6 +1287,22 @@ impl<'a> ChainStoreAccess for ChainStoreUpdate<'a> {
         }
     }
 
+    fn outcome_tail(&self) -> Result<BlockHeight, Error> {
+        if let Some(outcome_tail) = &self.outcome_tail {
+            Ok(outcome_tail.clone())
+        } else {
+            self.chain_store.outcome_tail()
+        }
+    }
+
+    fn state_tail(&self) -> Result<BlockHeight, Error> {
+        if let Some(state_tail) = &self.state_tail {
+            Ok(state_tail.clone())
+        } else {
+            self.chain_store.state_tail()
+        }
+    }
+
     /// Head of the header chain (not the same thing as head_header).
     fn header_head(&self) -> Result<Tip, Error> {
         if let Some(header_head) = &self.header_head {
@@ -1926,6 +1964,8 @@ impl<'a> ChainStoreUpdate<'a> {
         self.tail = None;
         self.chunk_tail = None;
         self.fork_tail = None;
+        self.outcome_tail = None;
+        self.state_tail = None;
     }
 
     pub fn update_tail(&mut self, height: BlockHeight) {
@@ -1951,14 +1991,59 @@ impl<'a> ChainStoreUpdate<'a> {
         self.chunk_tail = Some(height);
     }
 
+    pub fn update_outcome_tail(&mut self, height: BlockHeight) {
+        self.outcome_tail = Some(height);
+    }
+
+    pub fn update_state_tail(&mut self, height: BlockHeight) {
+        self.state_tail = Some(height);
+    }
+
+    /// Deletes the chunks created below `min_chunk_height` together with the header indexes of
+    /// their heights. Used when all the data below a height is dropped at once, otherwise the
+    /// header indexes are deleted with the blocks of the canonical chain.
     pub fn clear_chunk_data_and_headers(
         &mut self,
         min_chunk_height: BlockHeight,
     ) -> Result<(), Error> {
+        // The chunk tail may be ahead of the blocks, see `GCPolicy`
+        let header_tail = std::cmp::min(self.tail()?, self.chunk_tail()?);
+        for height in header_tail..min_chunk_height {
+            self.clear_header_data_by_height(height)?;
+        }
+        self.clear_chunk_data(min_chunk_height)
+    }
+
+    /// Deletes the chunks created below `min_chunk_height` and the chunk-indexed data.
+    pub fn clear_chunk_data(&mut self, min_chunk_height: BlockHeight) -> Result<(), Error> {
         let chunk_tail = self.chunk_tail()?;
+        if min_chunk_height <= chunk_tail {
+            // Chunks may be kept for fewer epochs than blocks, see `GCPolicy`
+            return Ok(());
+        }
         for height in chunk_tail..min_chunk_height {
+            // Only the chunks of the canonical chain are moved to the cold storage
+            let canonical_chunk_hashes: Option<HashSet<ChunkHash>> =
+                if self.store().has_cold_storage() {
+                    Some(match self.get_block_hash_by_height(height) {
+                        Ok(block_hash) => self
+                            .get_block(&block_hash)?
+                            .chunks()
+                            .iter()
+                            .map(|chunk_header| chunk_header.chunk_hash())
+                            .collect(),
+                        Err(_) => HashSet::new(),
+                    })
+                } else {
+                    None
+                };
             let chunk_hashes = self.chain_store.get_all_chunk_hashes_by_height(height)?;
             for chunk_hash in chunk_hashes {
+                let num_store_updates = self.store_updates.len();
+                let is_fork = canonical_chunk_hashes
+                    .as_ref()
+                    .map_or(false, |chunk_hashes| !chunk_hashes.contains(&chunk_hash));
+
                 // 1. Delete chunk-related data
                 let chunk = self.get_chunk(&chunk_hash)?.clone();
                 debug_assert_eq!(chunk.cloned_header().height_created(), height);
@@ -1974,23 +2059,65 @@ impl<'a> ChainStoreUpdate<'a> {
                 self.gc_col(ColChunks, &chunk_header_hash);
                 self.gc_col(ColPartialChunks, &chunk_header_hash);
                 self.gc_col(ColInvalidChunks, &chunk_header_hash);
-            }
 
-            let header_hashes = self.chain_store.get_all_header_hashes_by_height(height)?;
-            for _header_hash in header_hashes {
-                // 3. Delete header_hash-indexed data
-                // TODO #3488: enable
-                //self.gc_col(ColBlockHeader, &header_hash.into());
+                if is_fork {
+                    for store_update in self.store_updates[num_store_updates..].iter_mut() {
+                        store_update.mark_fork_data();
+                    }
+                }
             }
 
-            // 4. Delete chunks_tail-related data
+            // 3. Delete chunks_tail-related data
             self.gc_col(ColChunkHashesByHeight, &index_to_bytes(height));
-            self.gc_col(ColHeaderHashesByHeight, &index_to_bytes(height));
         }
         self.update_chunk_tail(min_chunk_height);
         Ok(())
     }
 
+    /// Deletes the header index of `height`, once the blocks of the height are garbage
+    /// collected. Unlike the chunks, it doesn't depend on `GCPolicy`, see `clear_block_data`.
+    pub fn clear_header_data_by_height(&mut self, height: BlockHeight) -> Result<(), Error> {
+        let header_hashes = self.chain_store.get_all_header_hashes_by_height(height)?;
+        for _header_hash in header_hashes {
+            // Delete header_hash-indexed data
+            // TODO #3488: enable
+            //self.gc_col(ColBlockHeader, &header_hash.into());
+        }
+        self.gc_col(ColHeaderHashesByHeight, &index_to_bytes(height));
+        Ok(())
+    }
+
+    /// Deletes the state replaced by Block `block_hash` of the canonical chain and its trie
+    /// changes. Does nothing if the trie changes are already deleted.
+    pub fn clear_state_data(
+        &mut self,
+        block_hash: &CryptoHash,
+        tries: ShardTries,
+    ) -> Result<(), Error> {
+        let mut store_update = self.store().store_update();
+        let num_shards = self.get_block_header(block_hash)?.chunk_mask().len() as ShardId;
+        for shard_id in 0..num_shards {
+            self.store()
+                .get_ser(ColTrieChanges, &get_block_shard_id(block_hash, shard_id))?
+                .map(|trie_changes: TrieChanges| {
+                    tries
+                        .apply_deletions(&trie_changes, shard_id, &mut store_update)
+                        .map(|_| {
+                            self.gc_col(ColTrieChanges, &get_block_shard_id(block_hash, shard_id));
+                            self.gc_col(
+                                ColFlatStateDeltas,
+                                &get_flat_state_delta_key(shard_id, block_hash),
+                            );
+                            self.inc_gc_col_state();
+                        })
+                        .map_err(|err| ErrorKind::Other(err.to_string()))
+                })
+                .unwrap_or(Ok(()))?;
+        }
+        self.merge(store_update);
+        Ok(())
+    }
+
     // Clearing block data of `block_hash`, if on a fork.
     // Clearing block data of `block_hash.prev`, if on the Canonical Chain.
     pub fn clear_block_data(
@@ -1998,6 +2125,7 @@ impl<'a> ChainStoreUpdate<'a> {
         mut block_hash: CryptoHash,
         gc_mode: GCMode,
     ) -> Result<(), Error> {
+        let num_store_updates = self.store_updates.len();
         let mut store_update = self.store().store_update();
         let header = self.get_block_header(&block_hash).expect("block header must exist").clone();
 
@@ -2016,6 +2144,10 @@ impl<'a> ChainStoreUpdate<'a> {
                                         ColTrieChanges,
                                         &get_block_shard_id(&block_hash, shard_id),
                                     );
+                                    self.gc_col(
+                                        ColFlatStateDeltas,
+                                        &get_flat_state_delta_key(shard_id, &block_hash),
+                                    );
                                     self.inc_gc_col_state();
                                 })
                                 .map_err(|err| ErrorKind::Other(err.to_string()))
@@ -2025,23 +2157,7 @@ impl<'a> ChainStoreUpdate<'a> {
             }
             GCMode::Canonical(tries) => {
                 // If the block is on canonical chain, we delete the state that's before applying this block
-                for shard_id in 0..header.chunk_mask().len() as ShardId {
-                    self.store()
-                        .get_ser(ColTrieChanges, &get_block_shard_id(&block_hash, shard_id))?
-                        .map(|trie_changes: TrieChanges| {
-                            tries
-                                .apply_deletions(&trie_changes, shard_id, &mut store_update)
-                                .map(|_| {
-                                    self.gc_col(
-                                        ColTrieChanges,
-                                        &get_block_shard_id(&block_hash, shard_id),
-                                    );
-                                    self.inc_gc_col_state();
-                                })
-                                .map_err(|err| ErrorKind::Other(err.to_string()))
-                        })
-                        .unwrap_or(Ok(()))?;
-                }
+                self.clear_state_data(&block_hash, tries)?;
                 // Set `block_hash` on previous one
                 block_hash = *self.get_block_header(&block_hash)?.prev_hash();
             }
@@ -2105,7 +2221,16 @@ impl<'a> ChainStoreUpdate<'a> {
             self.gc_col(ColStateChanges, &key);
         }
         self.gc_col(ColBlockRefCount, &block_hash_vec);
-        self.gc_outcomes(&block)?;
+        // Outcomes of the Canonical Chain may be collected earlier, see `GCPolicy`
+        let outcomes_collected = match gc_mode {
+            GCMode::Canonical(_) => {
+                height != self.chain_store.genesis_height && height <= self.outcome_tail()?
+            }
+            _ => false,
+        };
+        if !outcomes_collected {
+            self.gc_outcomes(&block)?;
+        }
         match gc_mode {
             GCMode::StateSync { clear_block_info: false } => {}
             _ => self.gc_col(ColBlockInfo, &block_hash_vec),
@@ -2129,7 +2254,11 @@ impl<'a> ChainStoreUpdate<'a> {
                         min_chunk_height = chunk_header.height_created();
                     }
                 }
-                self.clear_chunk_data_and_headers(min_chunk_height)?;
+                self.clear_chunk_data(min_chunk_height)?;
+                // Up to the height of the next block, which is where the GC continues
+                for header_height in height..header.height() {
+                    self.clear_header_data_by_height(header_height)?;
+                }
             }
             GCMode::StateSync { .. } => {
                 // 7. State Sync clearing
@@ -2137,6 +2266,12 @@ impl<'a> ChainStoreUpdate<'a> {
             }
         };
         self.merge(store_update);
+        if let GCMode::Fork(_) = gc_mode {
+            // Forks are not moved to the cold storage of archival nodes
+            for store_update in self.store_updates[num_store_updates..].iter_mut() {
+                store_update.mark_fork_data();
+            }
+        }
         Ok(())
     }
 
@@ -2373,6 +2508,9 @@ impl<'a> ChainStoreUpdate<'a> {
             DBCol::ColHeaderHashesByHeight => {
                 store_update.delete(col, key);
             }
+            DBCol::ColFlatStateDeltas => {
+                store_update.delete(col, key);
+            }
             DBCol::ColDbVersion
             | DBCol::ColBlockMisc
             | DBCol::ColGCCount
@@ -2389,7 +2527,9 @@ impl<'a> ChainStoreUpdate<'a> {
             | DBCol::ColEpochValidatorInfo
             | DBCol::ColBlockOrdinal
             | DBCol::_ColTransactionRefCount
-            | DBCol::ColCachedContractCode => {
+            | DBCol::ColCachedContractCode
+            | DBCol::ColFlatState
+            | DBCol::ColFlatStateHeads => {
                 unreachable!();
             }
         }
@@ -2419,6 +2559,8 @@ impl<'a> ChainStoreUpdate<'a> {
         Self::write_col_misc(&mut store_update, TAIL_KEY, &mut self.tail)?;
         Self::write_col_misc(&mut store_update, CHUNK_TAIL_KEY, &mut self.chunk_tail)?;
         Self::write_col_misc(&mut store_update, FORK_TAIL_KEY, &mut self.fork_tail)?;
+        Self::write_col_misc(&mut store_update, OUTCOME_TAIL_KEY, &mut self.outcome_tail)?;
+        Self::write_col_misc(&mut store_update, STATE_TAIL_KEY, &mut self.state_tail)?;
         Self::write_col_misc(&mut store_update, HEADER_HEAD_KEY, &mut self.header_head)?;
         Self::write_col_misc(&mut store_update, FINAL_HEAD_KEY, &mut self.final_head)?;
         Self::write_col_misc(
@@ -2858,6 +3000,7 @@ mod tests {
     use cached::Cached;
     use strum::IntoEnumIterator;
 
+    use near_chain_configs::GCPolicy;
     use near_crypto::KeyType;
     use near_primitives::block::{Block, Tip};
     #[cfg(feature = "expensive_tests")]
@@ -2867,8 +3010,9 @@ mod tests {
     use near_primitives::types::{BlockHeight, EpochId, GCCount, NumBlocks};
     use near_primitives::utils::index_to_bytes;
     use near_primitives::validator_signer::InMemoryValidatorSigner;
+    use near_store::db::{Database, SplitDB, TestDB};
     use near_store::test_utils::create_test_store;
-    use near_store::DBCol;
+    use near_store::{DBCol, Store};
     #[cfg(feature = "expensive_tests")]
     use {crate::store_validator::StoreValidator, near_chain_configs::GenesisConfig};
 
@@ -2881,7 +3025,10 @@ mod tests {
     }
 
     fn get_chain_with_epoch_length(epoch_length: NumBlocks) -> Chain {
-        let store = create_test_store();
+        get_chain_with_store(create_test_store(), epoch_length)
+    }
+
+    fn get_chain_with_store(store: Arc<Store>, epoch_length: NumBlocks) -> Chain {
         let chain_genesis = ChainGenesis::test();
         let validators = vec![vec!["test1"]];
         let runtime_adapter = Arc::new(KeyValueRuntime::new_with_validators(
@@ -3120,7 +3267,7 @@ mod tests {
 
         chain.epoch_length = 1;
         let trie = chain.runtime_adapter.get_tries();
-        assert!(chain.clear_data(trie, 100).is_ok());
+        assert!(chain.clear_data(trie, 100, &GCPolicy::default()).is_ok());
 
         // epoch didn't change so no data is garbage collected.
         for i in 0..15 {
@@ -3186,6 +3333,117 @@ mod tests {
         }
     }
 
+    /// Test that data kept for fewer epochs than blocks is collected ahead of blocks.
+    #[test]
+    fn test_clear_old_data_gc_policy() {
+        let mut chain = get_chain_with_epoch_length(1);
+        let genesis = chain.get_block_by_height(0).unwrap().clone();
+        let signer =
+            Arc::new(InMemoryValidatorSigner::from_seed("test1", KeyType::ED25519, "test1"));
+        let mut prev_block = genesis.clone();
+        let mut blocks = vec![prev_block.clone()];
+        for i in 1..20 {
+            let block = Block::empty_with_height(&prev_block, i, &*signer.clone());
+            blocks.push(block.clone());
+            let mut store_update = chain.mut_store().store_update();
+            store_update.save_block(block.clone());
+            store_update.inc_block_refcount(block.header().prev_hash()).unwrap();
+            store_update.save_head(&Tip::from_header(block.header())).unwrap();
+            store_update.save_block_header(block.header().clone()).unwrap();
+            store_update
+                .chain_store_cache_update
+                .height_to_hashes
+                .insert(i, Some(*block.header().hash()));
+            store_update.save_next_block_hash(&prev_block.hash(), *block.hash());
+            store_update.commit().unwrap();
+
+            prev_block = block.clone();
+        }
+
+        let gc_policy = GCPolicy { blocks: 10, ..GCPolicy::default() };
+        let trie = chain.runtime_adapter.get_tries();
+        assert!(chain.clear_data(trie.clone(), 100, &gc_policy).is_ok());
+
+        for i in 0..20 {
+            if i < 8 {
+                assert!(chain.get_block(&blocks[i].hash()).is_err());
+            } else {
+                assert!(chain.get_block(&blocks[i].hash()).is_ok());
+            }
+        }
+        assert_eq!(chain.store().tail().unwrap(), 8);
+        assert_eq!(chain.store().state_tail().unwrap(), 13);
+        assert_eq!(chain.store().outcome_tail().unwrap(), 13);
+        // The header indexes are collected with the blocks, whatever the chunk tail is.
+        for i in 1..20 {
+            let header_hashes = chain.mut_store().get_all_header_hashes_by_height(i).unwrap();
+            assert_eq!(header_hashes.contains(blocks[i as usize].hash()), i >= 8);
+        }
+
+        // Nothing more to collect
+        assert!(chain.clear_data(trie, 100, &gc_policy).is_ok());
+        assert_eq!(chain.store().tail().unwrap(), 8);
+        assert_eq!(chain.store().state_tail().unwrap(), 13);
+    }
+
+    /// Test that the garbage collected blocks of the canonical chain are moved to the cold
+    /// database of a split store, while the forks are deleted.
+    #[test]
+    fn test_clear_old_data_cold_storage() {
+        let cold = Arc::pin(TestDB::new());
+        let split_db = SplitDB::new(Arc::pin(TestDB::new()), cold.clone());
+        let mut chain = get_chain_with_store(Arc::new(Store::new(Arc::pin(split_db))), 1);
+        let genesis = chain.get_block_by_height(0).unwrap().clone();
+        let signer =
+            Arc::new(InMemoryValidatorSigner::from_seed("test1", KeyType::ED25519, "test1"));
+        let mut prev_block = genesis.clone();
+        let mut blocks = vec![prev_block.clone()];
+        for i in 1..15 {
+            let block = Block::empty_with_height(&prev_block, i, &*signer.clone());
+            blocks.push(block.clone());
+            let mut store_update = chain.mut_store().store_update();
+            store_update.save_block(block.clone());
+            store_update.inc_block_refcount(block.header().prev_hash()).unwrap();
+            store_update.save_head(&Tip::from_header(block.header())).unwrap();
+            store_update.save_block_header(block.header().clone()).unwrap();
+            store_update
+                .chain_store_cache_update
+                .height_to_hashes
+                .insert(i, Some(*block.header().hash()));
+            store_update.save_next_block_hash(&prev_block.hash(), *block.hash());
+            store_update.commit().unwrap();
+
+            prev_block = block.clone();
+        }
+        // A fork at the height of the canonical block 4.
+        let fork_block = Block::empty_with_height(&blocks[2], 4, &*signer.clone());
+        let mut store_update = chain.mut_store().store_update();
+        store_update.save_block(fork_block.clone());
+        store_update.inc_block_refcount(fork_block.header().prev_hash()).unwrap();
+        store_update.save_block_header(fork_block.header().clone()).unwrap();
+        store_update.commit().unwrap();
+
+        let trie = chain.runtime_adapter.get_tries();
+        assert!(chain.clear_data(trie, 100, &GCPolicy::default()).is_ok());
+        assert_eq!(chain.store().tail().unwrap(), 8);
+
+        for i in 0..8 {
+            assert!(chain.get_block(&blocks[i].hash()).is_ok());
+            assert!(cold.get(DBCol::ColBlock, blocks[i].hash().as_ref()).unwrap().is_some());
+        }
+        assert!(chain.get_block(&fork_block.hash()).is_err());
+        assert_eq!(cold.get(DBCol::ColBlock, fork_block.hash().as_ref()).unwrap(), None);
+        let hashes_at_fork_height: Vec<_> = chain
+            .mut_store()
+            .get_all_block_hashes_by_height(4)
+            .unwrap()
+            .values()
+            .flatten()
+            .cloned()
+            .collect();
+        assert_eq!(hashes_at_fork_height, vec![*blocks[4].hash()]);
+    }
+
     #[test]
     fn test_clear_old_data_fixed_height() {
         let mut chain = get_chain();
@@ -3309,7 +3567,7 @@ mod tests {
 
         for iter in 0..10 {
             println!("ITERATION #{:?}", iter);
-            assert!(chain.clear_data(trie.clone(), gc_blocks_limit).is_ok());
+            assert!(chain.clear_data(trie.clone(), gc_blocks_limit, &GCPolicy::default()).is_ok());
 
             // epoch didn't change so no data is garbage collected.
             for i in 0..1000 {
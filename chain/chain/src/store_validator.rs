@@ -7,7 +7,7 @@ use borsh::BorshDeserialize;
 use strum::IntoEnumIterator;
 use tracing::warn;
 
-use near_chain_configs::GenesisConfig;
+use near_chain_configs::{GCPolicy, GenesisConfig};
 use near_primitives::block::{Block, BlockHeader};
 use near_primitives::borsh;
 use near_primitives::epoch_manager::block_info::BlockInfo;
@@ -19,7 +19,7 @@ use near_primitives::syncing::{ShardStateSyncResponseHeader, StateHeaderKey, Sta
 use near_primitives::transaction::ExecutionOutcomeWithIdAndProof;
 use near_primitives::types::chunk_extra::ChunkExtra;
 use near_primitives::types::{AccountId, BlockHeight, EpochId, GCCount, ShardId};
-use near_primitives::utils::get_block_shard_id_rev;
+use near_primitives::utils::{get_block_shard_id_rev, index_to_bytes};
 use near_store::{
     decode_value_with_rc, DBCol, Store, TrieChanges, NUM_COLS, SHOULD_COL_GC, SKIP_COL_GC,
 };
@@ -27,6 +27,7 @@ use validate::StoreValidatorError;
 
 use crate::RuntimeAdapter;
 
+mod repair;
 mod validate;
 
 fn to_string<T: std::fmt::Debug>(v: &T) -> String {
@@ -64,13 +65,88 @@ impl StoreValidatorCache {
     }
 }
 
+/// Names of the validators which can be selected with `StoreValidator::select_validators`.
+pub const VALIDATORS: &[&str] = &[
+    "block_chunks_exist",
+    "block_chunks_height_validity",
+    "block_epoch_exists",
+    "block_hash_validity",
+    "block_header_exists",
+    "block_header_hash_validity",
+    "block_header_height_validity",
+    "block_height_cmp_tail_final",
+    "block_height_validity",
+    "block_increase_refcount",
+    "block_indexed_by_height",
+    "block_info_block_header_exists",
+    "block_info_exists",
+    "block_refcount",
+    "block_refcount_final",
+    "canonical_header_validity",
+    "canonical_prev_block_validity",
+    "chunk_extra_block_exists",
+    "chunk_hash_validity",
+    "chunk_indexed_by_height_created",
+    "chunk_of_height_exists",
+    "chunk_tail_validity",
+    "chunk_tx_exists",
+    "epoch_validity",
+    "gc_col_count",
+    "gc_col_count_final",
+    "header_hash_indexed_by_height",
+    "header_hash_of_height_exists",
+    "last_block_chunk_included",
+    "outcome_by_outcome_id_exists",
+    "outcome_id_block_exists",
+    "outcome_indexed_by_block_hash",
+    "receipt_refcount",
+    "receipt_refcount_final",
+    "state_header_block_exists",
+    "state_part_header_exists",
+    "state_sync_info_block_exists",
+    "state_sync_info_valid",
+    "trie_changes_chunk_extra_exists",
+    "tx_refcount",
+    "tx_refcount_final",
+];
+
+/// Validator from `validate` with its name, which must be listed in `VALIDATORS`.
+macro_rules! validator {
+    ($name:ident) => {
+        (stringify!($name), &validate::$name)
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Data looks suspicious but may be valid.
+    Warning,
+    /// Data is inconsistent.
+    Error,
+    /// Data can't be read.
+    Critical,
+}
+
 #[derive(Debug)]
 pub struct ErrorMessage {
     pub col: String,
     pub key: String,
+    /// Name of the failed validator from `validate.rs`.
+    pub validator: String,
+    /// Height of the Block or Chunk being validated, if known.
+    pub height: Option<BlockHeight>,
+    pub severity: Severity,
     pub err: StoreValidatorError,
 }
 
+/// Change applied to the store in repair mode.
+#[derive(Debug)]
+pub struct RepairMessage {
+    pub col: String,
+    pub key: String,
+    pub action: String,
+}
+
 pub struct StoreValidator {
     me: Option<AccountId>,
     config: GenesisConfig,
@@ -79,6 +155,10 @@ pub struct StoreValidator {
     inner: StoreValidatorCache,
     timeout: Option<u64>,
     start_time: Instant,
+    repair: Option<repair::StoreRepair>,
+    validators: Option<HashSet<String>>,
+    height: Option<BlockHeight>,
+    gc_policy: GCPolicy,
 
     pub errors: Vec<ErrorMessage>,
     tests: u64,
@@ -99,6 +179,10 @@ impl StoreValidator {
             inner: StoreValidatorCache::new(),
             timeout: None,
             start_time: Instant::now(),
+            repair: None,
+            validators: None,
+            height: None,
+            gc_policy: GCPolicy::default(),
             errors: vec![],
             tests: 0,
         }
@@ -106,8 +190,47 @@ impl StoreValidator {
     pub fn set_timeout(&mut self, timeout: u64) {
         self.timeout = Some(timeout)
     }
+    /// Garbage collection policy the store was collected with. Chunk Tail may only be ahead of
+    /// Tail if the policy keeps chunks for fewer epochs than blocks.
+    pub fn set_gc_policy(&mut self, gc_policy: GCPolicy) {
+        self.gc_policy = gc_policy
+    }
+    /// Makes `validate` collect fixes for the inconsistencies it finds, where a fix is safe.
+    /// The fixes are written to the store by `repair`.
+    pub fn enable_repair(&mut self) {
+        self.repair = Some(repair::StoreRepair::default())
+    }
+    /// Applies the fixes collected by `validate` in a single `StoreUpdate` and returns the list
+    /// of changes made. Errors found are still reported in `errors`.
+    pub fn repair(&mut self) -> Result<Vec<RepairMessage>, std::io::Error> {
+        match self.repair.take() {
+            Some(repair) => {
+                repair.store_update(&self.store)?.commit()?;
+                self.repair = Some(repair::StoreRepair::default());
+                Ok(repair.repairs)
+            }
+            None => Ok(vec![]),
+        }
+    }
+    /// Runs only the validators from `validate.rs` with the given names. Final checks rely on
+    /// the validators of the columns they count, so those have to be selected too.
+    /// Runs only the given validators, which must be listed in `VALIDATORS`.
+    pub fn select_validators(&mut self, validators: Vec<String>) -> Result<(), String> {
+        if let Some(unknown) = validators.iter().find(|name| !VALIDATORS.contains(&name.as_str())) {
+            return Err(format!(
+                "Unknown validator {}, expected one of {}",
+                unknown,
+                VALIDATORS.join(", ")
+            ));
+        }
+        self.validators = Some(validators.into_iter().collect());
+        Ok(())
+    }
+    fn is_selected(&self, validator: &str) -> bool {
+        self.validators.as_ref().map_or(true, |validators| validators.contains(validator))
+    }
     pub fn is_failed(&self) -> bool {
-        self.tests == 0 || self.errors.len() > 0
+        self.tests == 0 || self.num_failed() > 0
     }
     pub fn get_gc_counters(&self) -> Vec<(String, u64)> {
         let mut res = vec![];
@@ -126,89 +249,131 @@ impl StoreValidator {
         res.sort();
         res
     }
+    /// Number of errors found, warnings are not counted.
     pub fn num_failed(&self) -> u64 {
-        self.errors.len() as u64
+        self.errors.iter().filter(|error| error.severity > Severity::Warning).count() as u64
     }
     pub fn tests_done(&self) -> u64 {
         self.tests
     }
-    fn process_error<K: std::fmt::Debug>(&mut self, err: StoreValidatorError, key: K, col: DBCol) {
-        self.errors.push(ErrorMessage { key: to_string(&key), col: to_string(&col), err })
+    fn process_error<K: std::fmt::Debug>(
+        &mut self,
+        validator: &str,
+        err: StoreValidatorError,
+        key: K,
+        col: DBCol,
+    ) {
+        let severity = match err {
+            StoreValidatorError::IOError(_) | StoreValidatorError::DBCorruption(_) => {
+                Severity::Critical
+            }
+            _ => Severity::Error,
+        };
+        self.errors.push(ErrorMessage {
+            key: to_string(&key),
+            col: to_string(&col),
+            validator: validator.to_string(),
+            height: self.height,
+            severity,
+            err,
+        })
     }
     fn validate_col(&mut self, col: DBCol) -> Result<(), StoreValidatorError> {
         for (key, value) in self.store.clone().iter_without_rc_logic(col) {
+            self.height = None;
             let key_ref = key.as_ref();
             let value_ref = value.as_ref();
             match col {
                 DBCol::ColBlockHeader => {
                     let block_hash = CryptoHash::try_from(key_ref)?;
                     let header = BlockHeader::try_from_slice(value_ref)?;
+                    self.height = Some(header.height());
                     // Block Header Hash is valid
-                    self.check(&validate::block_header_hash_validity, &block_hash, &header, col);
+                    self.check(validator!(block_header_hash_validity), &block_hash, &header, col);
                     // Block Header Height is valid
-                    self.check(&validate::block_header_height_validity, &block_hash, &header, col);
+                    self.check(validator!(block_header_height_validity), &block_hash, &header, col);
                     // Block Header can be indexed by Height
-                    self.check(&validate::header_hash_indexed_by_height, &block_hash, &header, col);
+                    self.check(
+                        validator!(header_hash_indexed_by_height),
+                        &block_hash,
+                        &header,
+                        col,
+                    );
                 }
                 DBCol::ColBlock => {
                     let block_hash = CryptoHash::try_from(key_ref)?;
                     let block = Block::try_from_slice(value_ref)?;
+                    self.height = Some(block.header().height());
                     // Block Hash is valid
-                    self.check(&validate::block_hash_validity, &block_hash, &block, col);
+                    self.check(validator!(block_hash_validity), &block_hash, &block, col);
                     // Block Height is valid
-                    self.check(&validate::block_height_validity, &block_hash, &block, col);
+                    self.check(validator!(block_height_validity), &block_hash, &block, col);
                     // Block can be indexed by its Height
-                    self.check(&validate::block_indexed_by_height, &block_hash, &block, col);
+                    self.check_and_repair(
+                        validator!(block_indexed_by_height),
+                        &repair::block_indexed_by_height,
+                        &block_hash,
+                        &block,
+                        col,
+                    );
                     // Block Header for current Block exists
-                    self.check(&validate::block_header_exists, &block_hash, &block, col);
+                    self.check(validator!(block_header_exists), &block_hash, &block, col);
                     // Chunks for current Block exist
-                    self.check(&validate::block_chunks_exist, &block_hash, &block, col);
+                    self.check(validator!(block_chunks_exist), &block_hash, &block, col);
                     // Chunks for current Block have Height Created not higher than Block Height
-                    self.check(&validate::block_chunks_height_validity, &block_hash, &block, col);
+                    self.check(validator!(block_chunks_height_validity), &block_hash, &block, col);
                     // BlockInfo for current Block exists
-                    self.check(&validate::block_info_exists, &block_hash, &block, col);
+                    self.check(validator!(block_info_exists), &block_hash, &block, col);
                     // EpochInfo for current Epoch id of Block exists
-                    self.check(&validate::block_epoch_exists, &block_hash, &block, col);
+                    self.check(validator!(block_epoch_exists), &block_hash, &block, col);
                     // Increase Block Refcount
-                    self.check(&validate::block_increase_refcount, &block_hash, &block, col);
+                    self.check(validator!(block_increase_refcount), &block_hash, &block, col);
                 }
                 DBCol::ColBlockHeight => {
                     let height = BlockHeight::try_from_slice(key_ref)?;
                     let hash = CryptoHash::try_from(value_ref)?;
+                    self.height = Some(height);
                     // Block on the Canonical Chain is stored properly
-                    self.check(&validate::canonical_header_validity, &height, &hash, col);
+                    self.check(validator!(canonical_header_validity), &height, &hash, col);
                     // If prev Block exists, it's also on the Canonical Chain and
                     // there are no Blocks in range (prev_height, height) on the Canonical Chain
-                    self.check(&validate::canonical_prev_block_validity, &height, &hash, col);
+                    self.check(validator!(canonical_prev_block_validity), &height, &hash, col);
                 }
                 DBCol::ColChunks => {
                     let chunk_hash = ChunkHash::try_from_slice(key_ref)?;
                     let shard_chunk = ShardChunk::try_from_slice(value_ref)?;
+                    self.height = Some(shard_chunk.height_created());
                     // Chunk Hash is valid
-                    self.check(&validate::chunk_hash_validity, &chunk_hash, &shard_chunk, col);
+                    self.check(validator!(chunk_hash_validity), &chunk_hash, &shard_chunk, col);
                     // Chunk Height Created is not lower than Chunk Tail
-                    self.check(&validate::chunk_tail_validity, &chunk_hash, &shard_chunk, col);
+                    self.check(validator!(chunk_tail_validity), &chunk_hash, &shard_chunk, col);
                     // ShardChunk can be indexed by Height
-                    self.check(
-                        &validate::chunk_indexed_by_height_created,
+                    self.check_and_repair(
+                        validator!(chunk_indexed_by_height_created),
+                        &repair::chunk_indexed_by_height_created,
                         &chunk_hash,
                         &shard_chunk,
                         col,
                     );
                     // Check that all Txs in Chunk exist
-                    self.check(&validate::chunk_tx_exists, &chunk_hash, &shard_chunk, col);
+                    self.check(validator!(chunk_tx_exists), &chunk_hash, &shard_chunk, col);
                 }
                 DBCol::ColChunkExtra => {
                     let (block_hash, _) = get_block_shard_id_rev(key_ref)?;
                     let chunk_extra = ChunkExtra::try_from_slice(value_ref)?;
-                    self.check(&validate::chunk_extra_block_exists, &block_hash, &chunk_extra, col);
+                    self.check(
+                        validator!(chunk_extra_block_exists),
+                        &block_hash,
+                        &chunk_extra,
+                        col,
+                    );
                 }
                 DBCol::ColTrieChanges => {
                     let (block_hash, shard_id) = get_block_shard_id_rev(key_ref)?;
                     let trie_changes = TrieChanges::try_from_slice(value_ref)?;
                     // ShardChunk should exist for current TrieChanges
                     self.check(
-                        &validate::trie_changes_chunk_extra_exists,
+                        validator!(trie_changes_chunk_extra_exists),
                         &(block_hash, shard_id),
                         &trie_changes,
                         col,
@@ -217,15 +382,24 @@ impl StoreValidator {
                 DBCol::ColChunkHashesByHeight => {
                     let height = BlockHeight::try_from_slice(key_ref)?;
                     let chunk_hashes = HashSet::<ChunkHash>::try_from_slice(value_ref)?;
+                    self.height = Some(height);
                     // ShardChunk which can be indexed by Height exists
-                    self.check(&validate::chunk_of_height_exists, &height, &chunk_hashes, col);
+                    self.check_and_repair(
+                        validator!(chunk_of_height_exists),
+                        &repair::chunk_of_height_exists,
+                        &height,
+                        &chunk_hashes,
+                        col,
+                    );
                 }
                 DBCol::ColHeaderHashesByHeight => {
                     let height = BlockHeight::try_from_slice(key_ref)?;
                     let header_hashes = HashSet::<CryptoHash>::try_from_slice(value_ref)?;
+                    self.height = Some(height);
                     // Headers which can be indexed by Height exists
-                    self.check(
-                        &validate::header_hash_of_height_exists,
+                    self.check_and_repair(
+                        validator!(header_hash_of_height_exists),
+                        &repair::header_hash_of_height_exists,
                         &height,
                         &header_hashes,
                         col,
@@ -236,13 +410,13 @@ impl StoreValidator {
                     let outcome_ids = Vec::<CryptoHash>::try_from_slice(value_ref)?;
                     // TransactionResult which can be indexed by Outcome id exists
                     self.check(
-                        &validate::outcome_by_outcome_id_exists,
+                        validator!(outcome_by_outcome_id_exists),
                         &block_hash,
                         &outcome_ids,
                         col,
                     );
                     // Block which can be indexed by Outcome block_hash exists
-                    self.check(&validate::outcome_id_block_exists, &block_hash, &outcome_ids, col);
+                    self.check(validator!(outcome_id_block_exists), &block_hash, &outcome_ids, col);
                 }
                 DBCol::ColTransactionResult => {
                     let outcome_id = CryptoHash::try_from_slice(key_ref)?;
@@ -250,7 +424,7 @@ impl StoreValidator {
                         <Vec<ExecutionOutcomeWithIdAndProof>>::try_from_slice(value_ref)?;
                     // Outcome is reachable in ColOutcomesByBlockHash
                     self.check(
-                        &validate::outcome_indexed_by_block_hash,
+                        validator!(outcome_indexed_by_block_hash),
                         &outcome_id,
                         &outcomes,
                         col,
@@ -261,14 +435,14 @@ impl StoreValidator {
                     let state_sync_info = StateSyncInfo::try_from_slice(value_ref)?;
                     // StateSyncInfo is valid
                     self.check(
-                        &validate::state_sync_info_valid,
+                        validator!(state_sync_info_valid),
                         &block_hash,
                         &state_sync_info,
                         col,
                     );
                     // Block which can be indexed by StateSyncInfo exists
                     self.check(
-                        &validate::state_sync_info_block_exists,
+                        validator!(state_sync_info_block_exists),
                         &block_hash,
                         &state_sync_info,
                         col,
@@ -277,9 +451,10 @@ impl StoreValidator {
                 DBCol::ColBlockInfo => {
                     let block_hash = CryptoHash::try_from(key_ref)?;
                     let block_info = BlockInfo::try_from_slice(value_ref)?;
+                    self.height = Some(*block_info.height());
                     // Block which can be indexed by BlockInfo exists
                     self.check(
-                        &validate::block_info_block_header_exists,
+                        validator!(block_info_block_header_exists),
                         &block_hash,
                         &block_info,
                         col,
@@ -290,44 +465,62 @@ impl StoreValidator {
                         let epoch_id = EpochId::try_from_slice(key_ref)?;
                         let epoch_info = EpochInfo::try_from_slice(value_ref)?;
                         // Epoch should exist
-                        self.check(&validate::epoch_validity, &epoch_id, &epoch_info, col);
+                        self.check(validator!(epoch_validity), &epoch_id, &epoch_info, col);
                     }
                 }
                 DBCol::ColLastBlockWithNewChunk => {
                     let shard_id = ShardId::try_from_slice(key_ref)?;
                     let block_hash = CryptoHash::try_from(value_ref)?;
                     // Block which is stored in ColLastBlockWithNewChunk exists and its ShardChunk is included
-                    self.check(&validate::last_block_chunk_included, &shard_id, &block_hash, col);
+                    self.check(validator!(last_block_chunk_included), &shard_id, &block_hash, col);
                 }
                 DBCol::ColGCCount => {
                     let col = DBCol::try_from_slice(key_ref)?;
                     let count = GCCount::try_from_slice(value_ref)?;
-                    self.check(&validate::gc_col_count, &col, &count, col);
+                    self.check_and_repair(
+                        validator!(gc_col_count),
+                        &repair::gc_col_count,
+                        &col,
+                        &count,
+                        col,
+                    );
                 }
                 DBCol::ColTransactions => {
                     let (_value, rc) = decode_value_with_rc(value_ref);
                     let tx_hash = CryptoHash::try_from(key_ref)?;
-                    self.check(&validate::tx_refcount, &tx_hash, &(rc as u64), col);
+                    self.check(validator!(tx_refcount), &tx_hash, &(rc as u64), col);
                 }
                 DBCol::ColReceipts => {
                     let (_value, rc) = decode_value_with_rc(value_ref);
                     let receipt_id = CryptoHash::try_from(key_ref)?;
-                    self.check(&validate::receipt_refcount, &receipt_id, &(rc as u64), col);
+                    self.check(validator!(receipt_refcount), &receipt_id, &(rc as u64), col);
                 }
                 DBCol::ColBlockRefCount => {
                     let block_hash = CryptoHash::try_from(key_ref)?;
                     let refcount = u64::try_from_slice(value_ref)?;
-                    self.check(&validate::block_refcount, &block_hash, &refcount, col);
+                    self.check_and_repair(
+                        validator!(block_refcount),
+                        &repair::block_refcount,
+                        &block_hash,
+                        &refcount,
+                        col,
+                    );
                 }
                 DBCol::ColStateHeaders => {
                     let key = StateHeaderKey::try_from_slice(key_ref)?;
                     let header = ShardStateSyncResponseHeader::try_from_slice(value_ref)?;
-                    self.check(&validate::state_header_block_exists, &key, &header, col);
+                    self.check(validator!(state_header_block_exists), &key, &header, col);
                 }
                 DBCol::ColStateParts => {
                     let key = StatePartKey::try_from_slice(key_ref)?;
                     let part = value_ref.to_vec();
-                    self.check(&validate::state_part_header_exists, &key, &part, col);
+                    self.check_and_repair(
+                        validator!(state_part_header_exists),
+                        &repair::state_part_header_exists,
+                        &key,
+                        &part,
+                        col,
+                    );
                 }
                 _ => {}
             }
@@ -344,14 +537,12 @@ impl StoreValidator {
 
         // Init checks
         // Check Head-Tail validity and fill cache with their values
-        if let Err(e) = validate::head_tail_validity(self) {
-            self.process_error(e, "HEAD / HEADER_HEAD / TAIL / CHUNK_TAIL", DBCol::ColBlockMisc)
-        }
+        self.check_head_tail();
 
         // Main loop
         for col in DBCol::iter() {
             if let Err(e) = self.validate_col(col) {
-                self.process_error(e, col.to_string(), col)
+                self.process_error("validate_col", e, col.to_string(), col)
             }
             if let Some(timeout) = self.timeout {
                 if self.start_time.elapsed() > Duration::from_millis(timeout) {
@@ -369,38 +560,179 @@ impl StoreValidator {
         }
 
         // Final checks
+        self.height = None;
         // There is no more than one Block which Height is lower than Tail and not equal to Genesis
-        if let Err(e) = validate::block_height_cmp_tail_final(self) {
-            self.process_error(e, "TAIL", DBCol::ColBlockMisc)
-        }
+        self.check_final(validator!(block_height_cmp_tail_final), "TAIL", DBCol::ColBlockMisc);
         // Check GC counters
-        if let Err(_) = validate::gc_col_count_final(self) {
-            // TODO #2861
+        if self.check_final(validator!(gc_col_count_final), "GC_COUNT", DBCol::ColGCCount) {
+            // TODO #2861 tell apart GC bugs from valid states
+            self.errors.last_mut().unwrap().severity = Severity::Warning;
         }
         // Check that all refs are counted
-        if let Err(e) = validate::tx_refcount_final(self) {
-            self.process_error(e, "TX_REFCOUNT", DBCol::ColTransactions)
+        self.check_final(validator!(tx_refcount_final), "TX_REFCOUNT", DBCol::ColTransactions);
+        self.check_final(
+            validator!(receipt_refcount_final),
+            "RECEIPT_REFCOUNT",
+            DBCol::ColReceipts,
+        );
+        // Check that all Block Refcounts are counted
+        let col = DBCol::ColBlockRefCount;
+        if self.check_final(validator!(block_refcount_final), "BLOCK_REFCOUNT", col)
+            && self.repair.is_some()
+        {
+            if let Err(e) = repair::block_refcount_final(self) {
+                self.process_error("block_refcount_final", e, "BLOCK_REFCOUNT", col)
+            }
         }
-        if let Err(e) = validate::receipt_refcount_final(self) {
-            self.process_error(e, "RECEIPT_REFCOUNT", DBCol::ColReceipts)
+    }
+
+    fn check_head_tail(&mut self) {
+        if let Err(e) = validate::head_tail_validity(self) {
+            let key = "HEAD / HEADER_HEAD / TAIL / CHUNK_TAIL";
+            self.process_error("head_tail_validity", e, key, DBCol::ColBlockMisc)
         }
-        // Check that all Block Refcounts are counted
-        if let Err(e) = validate::block_refcount_final(self) {
-            self.process_error(e, "BLOCK_REFCOUNT", DBCol::ColBlockRefCount)
+    }
+
+    /// Validates the data of Blocks at heights in (`from`, `to`]. Only the checks which don't
+    /// need the whole DB to be scanned are done, so it's cheap enough to be run on a live node.
+    /// Stops after the first height at which the timeout set by `set_timeout` is hit.
+    /// Returns the highest height validated.
+    pub fn validate_heights(&mut self, from: BlockHeight, to: BlockHeight) -> BlockHeight {
+        self.start_time = Instant::now();
+        self.check_head_tail();
+        // Everything below Tail is garbage collected
+        let from = std::cmp::max(from, self.inner.tail);
+        for height in from + 1..=to {
+            self.height = Some(height);
+            if let Err(e) = self.validate_height(height) {
+                self.process_error("validate_height", e, height, DBCol::ColBlockPerHeight)
+            }
+            self.height = None;
+            if let Some(timeout) = self.timeout {
+                if self.start_time.elapsed() > Duration::from_millis(timeout) {
+                    return height;
+                }
+            }
         }
+        std::cmp::max(from, to)
     }
 
-    fn check<K: std::fmt::Debug, V>(
+    fn validate_height(&mut self, height: BlockHeight) -> Result<(), StoreValidatorError> {
+        let height_key = index_to_bytes(height);
+        if let Some(hash) = self.store.get_ser::<CryptoHash>(DBCol::ColBlockHeight, &height_key)? {
+            let col = DBCol::ColBlockHeight;
+            self.check(validator!(canonical_header_validity), &height, &hash, col);
+            self.check(validator!(canonical_prev_block_validity), &height, &hash, col);
+        }
+        let block_hashes: Vec<CryptoHash> = self
+            .store
+            .get_ser::<HashMap<EpochId, HashSet<CryptoHash>>>(
+                DBCol::ColBlockPerHeight,
+                &height_key,
+            )?
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|(_, hashes)| hashes)
+            .collect();
+        for block_hash in block_hashes.iter() {
+            let col = DBCol::ColBlockHeader;
+            if let Some(header) = self.store.get_ser::<BlockHeader>(col, block_hash.as_ref())? {
+                self.check(validator!(block_header_hash_validity), block_hash, &header, col);
+                self.check(validator!(block_header_height_validity), block_hash, &header, col);
+            }
+            let col = DBCol::ColBlock;
+            let block = match self.store.get_ser::<Block>(col, block_hash.as_ref())? {
+                Some(block) => block,
+                None => continue,
+            };
+            self.check(validator!(block_hash_validity), block_hash, &block, col);
+            self.check(validator!(block_indexed_by_height), block_hash, &block, col);
+            self.check(validator!(block_header_exists), block_hash, &block, col);
+            self.check(validator!(block_chunks_exist), block_hash, &block, col);
+            self.check(validator!(block_chunks_height_validity), block_hash, &block, col);
+            self.check(validator!(block_info_exists), block_hash, &block, col);
+            for chunk_header in block.chunks().iter() {
+                if chunk_header.height_included() != height {
+                    continue;
+                }
+                let chunk_hash = chunk_header.chunk_hash();
+                let col = DBCol::ColChunks;
+                if let Some(shard_chunk) =
+                    self.store.get_ser::<ShardChunk>(col, chunk_hash.as_ref())?
+                {
+                    self.check(validator!(chunk_hash_validity), &chunk_hash, &shard_chunk, col);
+                    self.check(
+                        validator!(chunk_indexed_by_height_created),
+                        &chunk_hash,
+                        &shard_chunk,
+                        col,
+                    );
+                }
+            }
+        }
+        let col = DBCol::ColChunkHashesByHeight;
+        if let Some(chunk_hashes) = self.store.get_ser::<HashSet<ChunkHash>>(col, &height_key)? {
+            self.check(validator!(chunk_of_height_exists), &height, &chunk_hashes, col);
+        }
+        Ok(())
+    }
+
+    /// Returns whether the check has failed.
+    fn check<K: std::fmt::Debug, V, F>(
         &mut self,
-        f: &dyn Fn(&mut StoreValidator, &K, &V) -> Result<(), StoreValidatorError>,
+        (validator, f): (&'static str, &F),
         key: &K,
         value: &V,
         col: DBCol,
-    ) {
+    ) -> bool
+    where
+        F: Fn(&mut StoreValidator, &K, &V) -> Result<(), StoreValidatorError>,
+    {
+        debug_assert!(VALIDATORS.contains(&validator));
+        if !self.is_selected(validator) {
+            return false;
+        }
         self.tests += 1;
         if let Err(e) = f(self, key, value) {
-            self.process_error(e, key, col);
+            self.process_error(validator, e, key, col);
+            return true;
         }
+        false
+    }
+
+    /// Same as `check`, but in repair mode also runs `fix` if the validation fails.
+    fn check_and_repair<K: std::fmt::Debug, V, F>(
+        &mut self,
+        (validator, f): (&'static str, &F),
+        fix: &dyn Fn(&mut StoreValidator, &K, &V) -> Result<(), StoreValidatorError>,
+        key: &K,
+        value: &V,
+        col: DBCol,
+    ) where
+        F: Fn(&mut StoreValidator, &K, &V) -> Result<(), StoreValidatorError>,
+    {
+        if self.check((validator, f), key, value, col) && self.repair.is_some() {
+            if let Err(e) = fix(self, key, value) {
+                self.process_error(validator, e, key, col);
+            }
+        }
+    }
+
+    /// Runs a final check, which validates the data collected by the checks of all columns.
+    /// Returns whether the check has failed.
+    fn check_final<F>(&mut self, (validator, f): (&'static str, &F), key: &str, col: DBCol) -> bool
+    where
+        F: Fn(&mut StoreValidator) -> Result<(), StoreValidatorError>,
+    {
+        debug_assert!(VALIDATORS.contains(&validator));
+        if !self.is_selected(validator) {
+            return false;
+        }
+        if let Err(e) = f(self) {
+            self.process_error(validator, e, key, col);
+            return true;
+        }
+        false
     }
 }
 
@@ -480,6 +812,70 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_repair() {
+        let (mut chain, mut sv) = init();
+        let genesis = chain.get_block_by_height(0).unwrap().clone();
+        let dangling: HashSet<ChunkHash> = vec![ChunkHash::default()].into_iter().collect();
+        let mut store_update = chain.store().owned_store().store_update();
+        store_update.delete(DBCol::ColBlockPerHeight, &index_to_bytes(0));
+        store_update.set_ser(DBCol::ColChunkHashesByHeight, &index_to_bytes(5), &dangling).unwrap();
+        store_update.commit().unwrap();
+        assert!(validate::block_indexed_by_height(&mut sv, genesis.hash(), &genesis).is_err());
+
+        sv.enable_repair();
+        sv.validate();
+        assert!(sv.is_failed());
+        let repairs = sv.repair().unwrap();
+        assert!(repairs.iter().any(|repair| repair.col == "ColBlockPerHeight"));
+        assert!(repairs.iter().any(|repair| repair.col == "ColChunkHashesByHeight"));
+
+        assert!(validate::block_indexed_by_height(&mut sv, genesis.hash(), &genesis).is_ok());
+        assert!(chain
+            .store()
+            .owned_store()
+            .get_ser::<HashSet<ChunkHash>>(DBCol::ColChunkHashesByHeight, &index_to_bytes(5))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_validate_heights() {
+        let (chain, mut sv) = init();
+        assert_eq!(sv.validate_heights(0, 10), 10);
+        assert!(sv.errors.is_empty());
+
+        let dangling: HashSet<ChunkHash> = vec![ChunkHash::default()].into_iter().collect();
+        let mut store_update = chain.store().owned_store().store_update();
+        store_update.set_ser(DBCol::ColChunkHashesByHeight, &index_to_bytes(5), &dangling).unwrap();
+        store_update.commit().unwrap();
+        assert_eq!(sv.validate_heights(5, 10), 10);
+        assert!(sv.errors.is_empty());
+        assert_eq!(sv.validate_heights(4, 10), 10);
+        assert_eq!(sv.num_failed(), 1);
+
+        sv.set_timeout(0);
+        assert_eq!(sv.validate_heights(4, 10), 5);
+    }
+
+    #[test]
+    fn test_select_validators() {
+        let (chain, mut sv) = init();
+        let dangling: HashSet<ChunkHash> = vec![ChunkHash::default()].into_iter().collect();
+        let mut store_update = chain.store().owned_store().store_update();
+        store_update.set_ser(DBCol::ColChunkHashesByHeight, &index_to_bytes(5), &dangling).unwrap();
+        store_update.commit().unwrap();
+
+        assert!(sv.select_validators(vec!["chunk_of_height".to_string()]).is_err());
+        sv.select_validators(vec!["chunk_of_height_exists".to_string()]).unwrap();
+        sv.validate();
+        assert_eq!(sv.tests_done(), 1);
+        assert_eq!(sv.errors.len(), 1);
+        assert_eq!(sv.errors[0].validator, "chunk_of_height_exists");
+        assert_eq!(sv.errors[0].height, Some(5));
+        assert_eq!(sv.errors[0].severity, Severity::Error);
+    }
+
     #[test]
     fn test_validation_failed() {
         let (_chain, mut sv) = init();
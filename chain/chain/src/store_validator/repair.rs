@@ -0,0 +1,289 @@
+use std::collections::{HashMap, HashSet};
+
+use borsh::BorshSerialize;
+
+use near_primitives::block::{Block, BlockHeader};
+use near_primitives::hash::CryptoHash;
+use near_primitives::sharding::{ChunkHash, ShardChunk};
+use near_primitives::syncing::{
+    get_num_state_parts, ShardStateSyncResponseHeader, StateHeaderKey, StatePartKey,
+};
+use near_primitives::types::{BlockHeight, EpochId, GCCount};
+use near_primitives::utils::index_to_bytes;
+use near_store::{
+    ColBlock, ColBlockHeader, ColBlockPerHeight, ColBlockRefCount, ColChunkHashesByHeight,
+    ColChunks, ColGCCount, ColHeaderHashesByHeight, ColStateHeaders, ColStateParts, DBCol, Store,
+    StoreUpdate,
+};
+
+use crate::store_validator::validate::StoreValidatorError;
+use crate::store_validator::{to_string, RepairMessage};
+use crate::StoreValidator;
+
+/// Fixes collected during validation. Every fix is derived from the data that is already in
+/// the store, so applying it can't lose anything which isn't broken already.
+#[derive(Default)]
+pub(crate) struct StoreRepair {
+    block_refcount: HashMap<CryptoHash, u64>,
+    block_refcount_to_remove: HashSet<CryptoHash>,
+    gc_count_reset: HashSet<DBCol>,
+    block_per_height: HashMap<BlockHeight, HashMap<EpochId, HashSet<CryptoHash>>>,
+    chunk_hashes_to_add: HashMap<BlockHeight, HashSet<ChunkHash>>,
+    chunk_hashes_to_remove: HashMap<BlockHeight, HashSet<ChunkHash>>,
+    header_hashes_to_remove: HashMap<BlockHeight, HashSet<CryptoHash>>,
+    state_parts_to_remove: Vec<StatePartKey>,
+    pub(crate) repairs: Vec<RepairMessage>,
+}
+
+impl StoreRepair {
+    fn report<K: std::fmt::Debug>(&mut self, col: DBCol, key: K, action: String) {
+        self.repairs.push(RepairMessage { col: to_string(&col), key: to_string(&key), action })
+    }
+
+    /// Builds a single `StoreUpdate` with all collected fixes applied on top of the current
+    /// values in `store`.
+    pub(crate) fn store_update(&self, store: &Store) -> Result<StoreUpdate, std::io::Error> {
+        let mut store_update = store.store_update();
+        for (block_hash, refcount) in self.block_refcount.iter() {
+            store_update.set_ser(ColBlockRefCount, block_hash.as_ref(), refcount)?;
+        }
+        for block_hash in self.block_refcount_to_remove.iter() {
+            store_update.delete(ColBlockRefCount, block_hash.as_ref());
+        }
+        for col in self.gc_count_reset.iter() {
+            store_update.delete(ColGCCount, &col.try_to_vec()?);
+        }
+        for (height, missing) in self.block_per_height.iter() {
+            let key = index_to_bytes(*height);
+            let mut epoch_to_hashes = store
+                .get_ser::<HashMap<EpochId, HashSet<CryptoHash>>>(ColBlockPerHeight, &key)?
+                .unwrap_or_default();
+            for (epoch_id, hashes) in missing.iter() {
+                epoch_to_hashes.entry(epoch_id.clone()).or_default().extend(hashes.iter().cloned());
+            }
+            store_update.set_ser(ColBlockPerHeight, &key, &epoch_to_hashes)?;
+        }
+        let heights: HashSet<_> =
+            self.chunk_hashes_to_add.keys().chain(self.chunk_hashes_to_remove.keys()).collect();
+        for height in heights {
+            let key = index_to_bytes(*height);
+            let mut chunk_hashes = store
+                .get_ser::<HashSet<ChunkHash>>(ColChunkHashesByHeight, &key)?
+                .unwrap_or_default();
+            if let Some(dangling) = self.chunk_hashes_to_remove.get(height) {
+                chunk_hashes.retain(|chunk_hash| !dangling.contains(chunk_hash));
+            }
+            if let Some(missing) = self.chunk_hashes_to_add.get(height) {
+                chunk_hashes.extend(missing.iter().cloned());
+            }
+            if chunk_hashes.is_empty() {
+                store_update.delete(ColChunkHashesByHeight, &key);
+            } else {
+                store_update.set_ser(ColChunkHashesByHeight, &key, &chunk_hashes)?;
+            }
+        }
+        for (height, dangling) in self.header_hashes_to_remove.iter() {
+            let key = index_to_bytes(*height);
+            let mut header_hashes = store
+                .get_ser::<HashSet<CryptoHash>>(ColHeaderHashesByHeight, &key)?
+                .unwrap_or_default();
+            header_hashes.retain(|hash| !dangling.contains(hash));
+            if header_hashes.is_empty() {
+                store_update.delete(ColHeaderHashesByHeight, &key);
+            } else {
+                store_update.set_ser(ColHeaderHashesByHeight, &key, &header_hashes)?;
+            }
+        }
+        for key in self.state_parts_to_remove.iter() {
+            store_update.delete(ColStateParts, &key.try_to_vec()?);
+        }
+        Ok(store_update)
+    }
+}
+
+// Fixes are called only if the validator with the same name has failed and repair mode is on.
+
+fn repair(sv: &mut StoreValidator) -> &mut StoreRepair {
+    sv.repair.as_mut().expect("repair is called only in repair mode")
+}
+
+pub(crate) fn block_indexed_by_height(
+    sv: &mut StoreValidator,
+    block_hash: &CryptoHash,
+    block: &Block,
+) -> Result<(), StoreValidatorError> {
+    let height = block.header().height();
+    let epoch_id = block.header().epoch_id().clone();
+    let epoch_to_hashes = sv
+        .store
+        .get_ser::<HashMap<EpochId, HashSet<CryptoHash>>>(
+            ColBlockPerHeight,
+            &index_to_bytes(height),
+        )?
+        .unwrap_or_default();
+    if epoch_to_hashes.values().any(|hashes| hashes.contains(block_hash)) {
+        return Ok(());
+    }
+    let repair = repair(sv);
+    repair
+        .block_per_height
+        .entry(height)
+        .or_default()
+        .entry(epoch_id.clone())
+        .or_default()
+        .insert(*block_hash);
+    repair.report(
+        ColBlockPerHeight,
+        height,
+        format!("index Block {:?} of epoch {:?}", block_hash, epoch_id),
+    );
+    Ok(())
+}
+
+pub(crate) fn chunk_indexed_by_height_created(
+    sv: &mut StoreValidator,
+    _chunk_hash: &ChunkHash,
+    shard_chunk: &ShardChunk,
+) -> Result<(), StoreValidatorError> {
+    let height = shard_chunk.height_created();
+    let chunk_hash = shard_chunk.chunk_hash();
+    let chunk_hashes = sv
+        .store
+        .get_ser::<HashSet<ChunkHash>>(ColChunkHashesByHeight, &index_to_bytes(height))?
+        .unwrap_or_default();
+    if chunk_hashes.contains(&chunk_hash) {
+        return Ok(());
+    }
+    let repair = repair(sv);
+    repair.report(ColChunkHashesByHeight, height, format!("index ShardChunk {:?}", chunk_hash));
+    repair.chunk_hashes_to_add.entry(height).or_default().insert(chunk_hash);
+    Ok(())
+}
+
+pub(crate) fn chunk_of_height_exists(
+    sv: &mut StoreValidator,
+    height: &BlockHeight,
+    chunk_hashes: &HashSet<ChunkHash>,
+) -> Result<(), StoreValidatorError> {
+    let mut dangling = vec![];
+    for chunk_hash in chunk_hashes {
+        match sv.store.get_ser::<ShardChunk>(ColChunks, chunk_hash.as_ref())? {
+            Some(shard_chunk) if shard_chunk.height_created() == *height => {}
+            _ => dangling.push(chunk_hash.clone()),
+        }
+    }
+    let repair = repair(sv);
+    for chunk_hash in dangling {
+        repair.report(ColChunkHashesByHeight, height, format!("drop ShardChunk {:?}", chunk_hash));
+        repair.chunk_hashes_to_remove.entry(*height).or_default().insert(chunk_hash);
+    }
+    Ok(())
+}
+
+pub(crate) fn header_hash_of_height_exists(
+    sv: &mut StoreValidator,
+    height: &BlockHeight,
+    header_hashes: &HashSet<CryptoHash>,
+) -> Result<(), StoreValidatorError> {
+    let mut dangling = vec![];
+    for hash in header_hashes {
+        match sv.store.get_ser::<BlockHeader>(ColBlockHeader, hash.as_ref())? {
+            Some(header) if header.height() == *height => {}
+            _ => dangling.push(*hash),
+        }
+    }
+    let repair = repair(sv);
+    for hash in dangling {
+        repair.report(ColHeaderHashesByHeight, height, format!("drop Header {:?}", hash));
+        repair.header_hashes_to_remove.entry(*height).or_default().insert(hash);
+    }
+    Ok(())
+}
+
+pub(crate) fn gc_col_count(
+    sv: &mut StoreValidator,
+    col: &DBCol,
+    count: &GCCount,
+) -> Result<(), StoreValidatorError> {
+    // Columns which are never garbage collected can't have anything counted
+    let repair = repair(sv);
+    repair.report(ColGCCount, col, format!("reset GC count {:?}", count));
+    repair.gc_count_reset.insert(*col);
+    Ok(())
+}
+
+pub(crate) fn block_refcount(
+    sv: &mut StoreValidator,
+    block_hash: &CryptoHash,
+    refcount: &u64,
+) -> Result<(), StoreValidatorError> {
+    // The validator leaves counted Blocks in the cache on mismatch, take them out here so that
+    // `block_refcount_final` doesn't fix them again
+    let expected = match sv.inner.block_refcount.remove(block_hash) {
+        Some(expected) => expected,
+        None => {
+            let header = sv.store.get_ser::<BlockHeader>(ColBlockHeader, block_hash.as_ref())?;
+            match header {
+                // No Blocks refer to this one, a refcount of 0 is stored as no refcount at all
+                Some(header) if header.height() != sv.config.genesis_height => {
+                    let repair = repair(sv);
+                    repair.report(
+                        ColBlockRefCount,
+                        block_hash,
+                        format!("removed, was {:?}", refcount),
+                    );
+                    repair.block_refcount_to_remove.insert(*block_hash);
+                    return Ok(());
+                }
+                // Either Genesis or a refcount of an unknown Block, leave it as it is
+                _ => return Ok(()),
+            }
+        }
+    };
+    if expected == *refcount {
+        return Ok(());
+    }
+    let repair = repair(sv);
+    repair.report(ColBlockRefCount, block_hash, format!("set {:?}, was {:?}", expected, refcount));
+    repair.block_refcount.insert(*block_hash, expected);
+    Ok(())
+}
+
+pub(crate) fn state_part_header_exists(
+    sv: &mut StoreValidator,
+    key: &StatePartKey,
+    _part: &Vec<u8>,
+) -> Result<(), StoreValidatorError> {
+    let StatePartKey(block_hash, shard_id, part_id) = *key;
+    let header = sv.store.get_ser::<ShardStateSyncResponseHeader>(
+        ColStateHeaders,
+        &StateHeaderKey(shard_id, block_hash).try_to_vec()?,
+    )?;
+    if let Some(header) = header {
+        if part_id < get_num_state_parts(header.state_root_node().memory_usage) {
+            return Ok(());
+        }
+    }
+    let repair = repair(sv);
+    repair.report(ColStateParts, key, "drop orphaned part".to_string());
+    repair.state_parts_to_remove.push(key.clone());
+    Ok(())
+}
+
+// Final fixes
+
+pub(crate) fn block_refcount_final(sv: &mut StoreValidator) -> Result<(), StoreValidatorError> {
+    // Blocks which are referred to by other Blocks but have no refcount stored
+    let mut missing = vec![];
+    for (block_hash, expected) in sv.inner.block_refcount.iter() {
+        if sv.store.get_ser::<Block>(ColBlock, block_hash.as_ref())?.is_some() {
+            missing.push((*block_hash, *expected));
+        }
+    }
+    let repair = repair(sv);
+    for (block_hash, expected) in missing {
+        repair.report(ColBlockRefCount, block_hash, format!("set {:?}, was missing", expected));
+        repair.block_refcount.insert(block_hash, expected);
+    }
+    Ok(())
+}
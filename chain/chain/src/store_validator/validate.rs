@@ -146,9 +146,12 @@ pub(crate) fn head_tail_validity(sv: &mut StoreValidator) -> Result<(), StoreVal
     sv.inner.header_head = header_head.height;
     sv.inner.tail = tail;
     sv.inner.chunk_tail = chunk_tail;
-    if chunk_tail > tail {
+    if chunk_tail > tail && sv.gc_policy.chunks_epochs() >= sv.gc_policy.blocks_epochs() {
         err!("chunk_tail > tail, {:?} > {:?}", chunk_tail, tail);
     }
+    if chunk_tail > head.height {
+        err!("chunk_tail > head.height, {:?} > {:?}", chunk_tail, head);
+    }
     if tail > head.height {
         err!("tail > head.height, {:?} > {:?}", tail, head);
     }
@@ -362,7 +365,9 @@ pub(crate) fn block_chunks_exist(
     block: &Block,
 ) -> Result<(), StoreValidatorError> {
     for chunk_header in block.chunks().iter() {
-        if chunk_header.height_included() == block.header().height() {
+        if chunk_header.height_included() == block.header().height()
+            && chunk_header.height_created() >= sv.inner.chunk_tail
+        {
             if let Some(me) = &sv.me {
                 let cares_about_shard = sv.runtime_adapter.cares_about_shard(
                     Some(&me),
@@ -531,12 +536,14 @@ pub(crate) fn trie_changes_chunk_extra_exists(
     for chunk_header in block.chunks().iter() {
         if chunk_header.shard_id() == *shard_id {
             let chunk_hash = chunk_header.chunk_hash();
-            // 3. ShardChunk with `chunk_hash` should be available
-            unwrap_or_err_db!(
-                sv.store.get_ser::<ShardChunk>(ColChunks, chunk_hash.as_ref()),
-                "Can't get Chunk from storage with ChunkHash {:?}",
-                chunk_hash
-            );
+            // 3. ShardChunk with `chunk_hash` should be available, unless collected before the state
+            if chunk_header.height_created() >= sv.inner.chunk_tail {
+                unwrap_or_err_db!(
+                    sv.store.get_ser::<ShardChunk>(ColChunks, chunk_hash.as_ref()),
+                    "Can't get Chunk from storage with ChunkHash {:?}",
+                    chunk_hash
+                );
+            }
             // 4. Chunk Extra with `block_hash` and `shard_id` should be available
             let chunk_extra = unwrap_or_err_db!(
                 sv.store.get_ser::<ChunkExtra>(
@@ -42,7 +42,7 @@ use near_store::{
     WrappedTrieChanges,
 };
 
-use crate::chain::{Chain, NUM_EPOCHS_TO_KEEP_STORE_DATA};
+use crate::chain::Chain;
 use crate::store::ChainStoreAccess;
 use crate::types::{
     ApplyTransactionResult, BlockHeaderInfo, ChainGenesis, ValidatorInfoIdentifier,
@@ -594,7 +594,7 @@ impl RuntimeAdapter for KeyValueRuntime {
         state_root: &StateRoot,
         _height: BlockHeight,
         _block_timestamp: u64,
-        _prev_block_hash: &CryptoHash,
+        prev_block_hash: &CryptoHash,
         block_hash: &CryptoHash,
         receipts: &[Receipt],
         transactions: &[SignedTransaction],
@@ -759,6 +759,7 @@ impl RuntimeAdapter for KeyValueRuntime {
                 TrieChanges::empty(state_root),
                 Default::default(),
                 block_hash.clone(),
+                *prev_block_hash,
             ),
             new_root: state_root,
             outcomes: tx_results,
@@ -802,6 +803,7 @@ impl RuntimeAdapter for KeyValueRuntime {
         block_hash: &CryptoHash,
         _epoch_id: &EpochId,
         request: &QueryRequest,
+        _include_proof: bool,
     ) -> Result<QueryResponse, near_chain_primitives::error::QueryError> {
         match request {
             QueryRequest::ViewAccount { account_id, .. } => Ok(QueryResponse {
@@ -819,6 +821,7 @@ impl RuntimeAdapter for KeyValueRuntime {
                 ),
                 block_height,
                 block_hash: *block_hash,
+                proof: vec![],
             }),
             QueryRequest::ViewCode { .. } => Ok(QueryResponse {
                 kind: QueryResponseKind::ViewCode(ContractCodeView {
@@ -827,6 +830,7 @@ impl RuntimeAdapter for KeyValueRuntime {
                 }),
                 block_height,
                 block_hash: *block_hash,
+                proof: vec![],
             }),
             QueryRequest::ViewAccessKeyList { .. } => Ok(QueryResponse {
                 kind: QueryResponseKind::AccessKeyList(AccessKeyList {
@@ -837,11 +841,13 @@ impl RuntimeAdapter for KeyValueRuntime {
                 }),
                 block_height,
                 block_hash: *block_hash,
+                proof: vec![],
             }),
             QueryRequest::ViewAccessKey { .. } => Ok(QueryResponse {
                 kind: QueryResponseKind::AccessKey(AccessKey::full_access().into()),
                 block_height,
                 block_hash: *block_hash,
+                proof: vec![],
             }),
             QueryRequest::ViewState { .. } => Ok(QueryResponse {
                 kind: QueryResponseKind::ViewState(ViewStateResult {
@@ -850,6 +856,7 @@ impl RuntimeAdapter for KeyValueRuntime {
                 }),
                 block_height,
                 block_hash: *block_hash,
+                proof: vec![],
             }),
             QueryRequest::CallFunction { .. } => Ok(QueryResponse {
                 kind: QueryResponseKind::CallResult(CallResult {
@@ -858,6 +865,7 @@ impl RuntimeAdapter for KeyValueRuntime {
                 }),
                 block_height,
                 block_hash: *block_hash,
+                proof: vec![],
             }),
         }
     }
@@ -890,7 +898,7 @@ impl RuntimeAdapter for KeyValueRuntime {
         true
     }
 
-    fn apply_state_part(
+    fn prepare_state_part(
         &self,
         _shard_id: ShardId,
         state_root: &StateRoot,
@@ -898,16 +906,17 @@ impl RuntimeAdapter for KeyValueRuntime {
         _num_parts: u64,
         data: &[u8],
         _epoch_id: &EpochId,
-    ) -> Result<(), Error> {
+    ) -> Result<StoreUpdate, Error> {
+        // The state is kept in memory, so it is applied right away and there is nothing to commit
         if part_id != 0 {
-            return Ok(());
+            return Ok(self.store.store_update());
         }
         let state = KVState::try_from_slice(data).unwrap();
         self.state.write().unwrap().insert(state_root.clone(), state.clone());
         let data = state.try_to_vec()?;
         let state_size = data.len() as u64;
         self.state_size.write().unwrap().insert(state_root.clone(), state_size);
-        Ok(())
+        Ok(self.store.store_update())
     }
 
     fn get_state_root_node(
@@ -972,14 +981,18 @@ impl RuntimeAdapter for KeyValueRuntime {
         }
     }
 
-    fn get_gc_stop_height(&self, block_hash: &CryptoHash) -> BlockHeight {
+    fn get_gc_stop_height_for_epochs(
+        &self,
+        block_hash: &CryptoHash,
+        num_epochs: u64,
+    ) -> BlockHeight {
         if !self.no_gc {
             let block_height = self
                 .get_block_header(block_hash)
                 .unwrap_or_default()
                 .map(|h| h.height())
                 .unwrap_or_default();
-            block_height.saturating_sub(NUM_EPOCHS_TO_KEEP_STORE_DATA * self.epoch_length)
+            block_height.saturating_sub(num_epochs * self.epoch_length)
         } else {
             0
         }
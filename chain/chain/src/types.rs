@@ -34,6 +34,7 @@ use near_primitives::version::{
 use near_primitives::views::{EpochValidatorInfo, QueryRequest, QueryResponse};
 use near_store::{PartialStorage, ShardTries, Store, StoreUpdate, Trie, WrappedTrieChanges};
 
+use crate::chain::NUM_EPOCHS_TO_KEEP_STORE_DATA;
 #[cfg(feature = "protocol_feature_block_header_v3")]
 use crate::DoomslugThresholdMode;
 use near_primitives::state_record::StateRecord;
@@ -455,7 +456,17 @@ pub trait RuntimeAdapter: Send + Sync {
     fn get_epoch_start_height(&self, block_hash: &CryptoHash) -> Result<BlockHeight, Error>;
 
     /// Get the block height for which garbage collection should not go over
-    fn get_gc_stop_height(&self, block_hash: &CryptoHash) -> BlockHeight;
+    fn get_gc_stop_height(&self, block_hash: &CryptoHash) -> BlockHeight {
+        self.get_gc_stop_height_for_epochs(block_hash, NUM_EPOCHS_TO_KEEP_STORE_DATA)
+    }
+
+    /// Get the block height for which garbage collection keeping `num_epochs` epochs of data
+    /// should not go over
+    fn get_gc_stop_height_for_epochs(
+        &self,
+        block_hash: &CryptoHash,
+        num_epochs: u64,
+    ) -> BlockHeight;
 
     /// Check if epoch exists.
     fn epoch_exists(&self, epoch_id: &EpochId) -> bool;
@@ -588,6 +599,7 @@ pub trait RuntimeAdapter: Send + Sync {
     ) -> Result<ApplyTransactionResult, Error>;
 
     /// Query runtime with given `path` and `data`.
+    /// If `include_proof` is set, the response carries the trie nodes proving its result.
     fn query(
         &self,
         shard_id: ShardId,
@@ -598,6 +610,7 @@ pub trait RuntimeAdapter: Send + Sync {
         block_hash: &CryptoHash,
         epoch_id: &EpochId,
         request: &QueryRequest,
+        include_proof: bool,
     ) -> Result<QueryResponse, near_chain_primitives::error::QueryError>;
 
     fn get_validator_info(
@@ -624,6 +637,18 @@ pub trait RuntimeAdapter: Send + Sync {
         data: &Vec<u8>,
     ) -> bool;
 
+    /// Computes the changes of a state part without committing them, so that several parts can
+    /// be processed at the same time.
+    fn prepare_state_part(
+        &self,
+        shard_id: ShardId,
+        state_root: &StateRoot,
+        part_id: u64,
+        num_parts: u64,
+        part: &[u8],
+        epoch_id: &EpochId,
+    ) -> Result<StoreUpdate, Error>;
+
     /// Should be executed after accepting all the parts to set up a new state.
     fn apply_state_part(
         &self,
@@ -633,7 +658,11 @@ pub trait RuntimeAdapter: Send + Sync {
         num_parts: u64,
         part: &[u8],
         epoch_id: &EpochId,
-    ) -> Result<(), Error>;
+    ) -> Result<(), Error> {
+        self.prepare_state_part(shard_id, state_root, part_id, num_parts, part, epoch_id)?
+            .commit()?;
+        Ok(())
+    }
 
     /// Returns StateRootNode of a state.
     /// Panics if requested hash is not in storage.
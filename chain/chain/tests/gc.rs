@@ -6,6 +6,7 @@ mod tests {
     use near_chain::test_utils::KeyValueRuntime;
     use near_chain::types::{ChainGenesis, Tip};
     use near_chain::DoomslugThresholdMode;
+    use near_chain_configs::GCPolicy;
     use near_crypto::KeyType;
     use near_primitives::block::Block;
     use near_primitives::merkle::PartialMerkleTree;
@@ -89,6 +90,7 @@ mod tests {
                     trie_changes,
                     Default::default(),
                     *block.hash(),
+                    *block.header().prev_hash(),
                 );
                 store_update.save_trie_changes(wrapped_trie_changes);
 
@@ -146,7 +148,7 @@ mod tests {
         }
 
         // GC execution
-        let clear_data = chain1.clear_data(tries1.clone(), 100);
+        let clear_data = chain1.clear_data(tries1.clone(), 100, &GCPolicy::default());
         if clear_data.is_err() {
             println!("clear data failed = {:?}", clear_data);
             assert!(false);
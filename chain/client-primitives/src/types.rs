@@ -265,11 +265,23 @@ pub struct Query {
     pub query_id: String,
     pub block_reference: BlockReference,
     pub request: QueryRequest,
+    /// Whether the response should carry a proof of its result.
+    pub include_proof: bool,
 }
 
 impl Query {
     pub fn new(block_reference: BlockReference, request: QueryRequest) -> Self {
-        Query { query_id: generate_random_string(10), block_reference, request }
+        Query {
+            query_id: generate_random_string(10),
+            block_reference,
+            request,
+            include_proof: false,
+        }
+    }
+
+    pub fn with_proof(mut self, include_proof: bool) -> Self {
+        self.include_proof = include_proof;
+        self
     }
 }
 
@@ -783,3 +795,64 @@ impl From<near_chain_primitives::Error> for GetProtocolConfigError {
         }
     }
 }
+
+/// Creates a consistent snapshot of the node's database in the given directory.
+pub struct CreateStoreCheckpoint {
+    pub path: std::path::PathBuf,
+}
+
+impl Message for CreateStoreCheckpoint {
+    type Result = Result<StoreCheckpointResponse, CreateStoreCheckpointError>;
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StoreCheckpointResponse {
+    pub path: std::path::PathBuf,
+    pub db_version: near_primitives::version::DbVersion,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum CreateStoreCheckpointError {
+    #[error("Checkpoint path {0} already exists")]
+    PathAlreadyExists(String),
+    #[error("IO Error: {0}")]
+    IOError(String),
+}
+
+impl From<std::io::Error> for CreateStoreCheckpointError {
+    fn from(error: std::io::Error) -> Self {
+        Self::IOError(error.to_string())
+    }
+}
+
+/// Reports size estimates of the node's database columns.
+pub struct GetStoreStats {}
+
+impl Message for GetStoreStats {
+    type Result = Result<StoreStatsResponse, GetStoreStatsError>;
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ColumnStatsView {
+    pub column: String,
+    pub estimated_num_keys: u64,
+    pub estimated_live_data_size: u64,
+    pub sst_files_size: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StoreStatsResponse {
+    pub columns: Vec<ColumnStatsView>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum GetStoreStatsError {
+    #[error("IO Error: {0}")]
+    IOError(String),
+}
+
+impl From<std::io::Error> for GetStoreStatsError {
+    fn from(error: std::io::Error) -> Self {
+        Self::IOError(error.to_string())
+    }
+}
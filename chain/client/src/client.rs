@@ -109,7 +109,9 @@ impl Client {
         } else {
             DoomslugThresholdMode::NoApprovals
         };
-        let chain = Chain::new(runtime_adapter.clone(), &chain_genesis, doomslug_threshold_mode)?;
+        let mut chain =
+            Chain::new(runtime_adapter.clone(), &chain_genesis, doomslug_threshold_mode)?;
+        chain.gc_policy = config.gc_policy;
         let shards_mgr = ShardsManager::new(
             validator_signer.as_ref().map(|x| x.validator_id().clone()),
             runtime_adapter.clone(),
@@ -141,7 +143,11 @@ impl Client {
         );
         let block_sync =
             BlockSync::new(network_adapter.clone(), config.block_fetch_horizon, config.archive);
-        let state_sync = StateSync::new(network_adapter.clone(), config.state_sync_timeout);
+        let state_sync = StateSync::new(
+            network_adapter.clone(),
+            config.state_sync_timeout,
+            config.state_sync_dir.clone(),
+        );
         let num_block_producer_seats = config.num_block_producer_seats as usize;
         let data_parts = runtime_adapter.num_data_parts();
         let parity_parts = runtime_adapter.num_total_parts() - data_parts;
@@ -991,12 +997,15 @@ impl Client {
                 self.chain.get_block_header(last_final_block).map_or(0, |header| header.height())
             };
             self.chain.blocks_with_missing_chunks.prune_blocks_below_height(last_finalized_height);
-            if !self.config.archive {
+            // Archival nodes with cold storage collect garbage too, the history is moved to the
+            // cold database instead of being deleted.
+            if !self.config.archive || self.chain.store().store().has_cold_storage() {
                 let timer = near_metrics::start_timer(&metrics::GC_TIME);
-                if let Err(err) = self
-                    .chain
-                    .clear_data(self.runtime_adapter.get_tries(), self.config.gc_blocks_limit)
-                {
+                if let Err(err) = self.chain.clear_data(
+                    self.runtime_adapter.get_tries(),
+                    self.config.gc_blocks_limit,
+                    &self.config.gc_policy,
+                ) {
                     error!(target: "client", "Can't clear old data, {:?}", err);
                     debug_assert!(false);
                 };
@@ -1569,9 +1578,13 @@ impl Client {
             let network_adapter1 = self.network_adapter.clone();
 
             let state_sync_timeout = self.config.state_sync_timeout;
+            let state_sync_dir = self.config.state_sync_dir.clone();
             let (state_sync, new_shard_sync) =
                 self.catchup_state_syncs.entry(sync_hash).or_insert_with(|| {
-                    (StateSync::new(network_adapter1, state_sync_timeout), HashMap::new())
+                    (
+                        StateSync::new(network_adapter1, state_sync_timeout, state_sync_dir),
+                        HashMap::new(),
+                    )
                 });
 
             debug!(
@@ -5,10 +5,14 @@ use std::sync::{Arc, RwLock};
 use std::thread;
 use std::time::{Duration, Instant};
 
-use actix::{Actor, Addr, Arbiter, Context, Handler};
+use actix::{
+    Actor, ActorFuture, Addr, Arbiter, Context, ContextFutureSpawner, Handler, SyncArbiter,
+    WrapFuture,
+};
 use actix_rt::ArbiterHandle;
 use chrono::Duration as OldDuration;
 use chrono::{DateTime, Utc};
+use futures::channel::oneshot;
 use log::{debug, error, info, trace, warn};
 
 #[cfg(feature = "delay_detector")]
@@ -39,18 +43,19 @@ use near_performance_metrics;
 use near_performance_metrics_macros::{perf, perf_with_debug};
 use near_primitives::hash::CryptoHash;
 use near_primitives::network::{AnnounceAccount, PeerId};
-use near_primitives::types::{BlockHeight, EpochId};
+use near_primitives::types::{BlockHeight, EpochId, ShardId};
 use near_primitives::unwrap_or_return;
 use near_primitives::utils::{from_timestamp, MaybeValidated};
 use near_primitives::validator_signer::ValidatorSigner;
 use near_primitives::version::PROTOCOL_VERSION;
-use near_primitives::views::ValidatorInfo;
+use near_primitives::views::{StoreValidationInfo, ValidatorInfo};
 #[cfg(feature = "adversarial")]
 use near_store::ColBlock;
 use near_telemetry::TelemetryActor;
 
 use crate::client::Client;
 use crate::info::{InfoHelper, ValidatorInfoHelper};
+use crate::store_validation::{StoreValidationActor, ValidateStore};
 use crate::sync::{highest_height_peer, StateSync, StateSyncResult};
 #[cfg(feature = "adversarial")]
 use crate::AdversarialControls;
@@ -91,6 +96,12 @@ pub struct ClientActor {
     doomslug_timer_next_attempt: DateTime<Utc>,
     chunk_request_retry_next_attempt: DateTime<Utc>,
     sync_started: bool,
+    /// Results of the background store validation, None if it's disabled.
+    store_validation: Option<StoreValidationInfo>,
+    /// Validates the store on its own thread, None if the validation is disabled.
+    store_validation_addr: Option<Addr<StoreValidationActor>>,
+    /// Whether a validation run has been requested and hasn't finished yet.
+    store_validation_running: bool,
 }
 
 /// Blocks the program until given genesis time arrives.
@@ -138,6 +149,29 @@ impl ClientActor {
             validator_signer,
             enable_doomslug,
         )?;
+        let store_validation = client.config.store_validation_period.map(|_| {
+            // Only the data written from now on is validated
+            let validated_height = client.chain.head().map(|tip| tip.height).unwrap_or_default();
+            StoreValidationInfo { validated_height, ..Default::default() }
+        });
+        let store_validation_addr = client.config.store_validation_period.map(|_| {
+            let me = client.validator_signer.as_ref().map(|x| x.validator_id().clone());
+            let genesis_height = client.chain.store().get_genesis_height();
+            let runtime_adapter = client.runtime_adapter.clone();
+            let store = client.chain.store().owned_store();
+            let budget = client.config.store_validation_budget;
+            let gc_policy = client.config.gc_policy;
+            SyncArbiter::start(1, move || {
+                StoreValidationActor::new(
+                    me.clone(),
+                    genesis_height,
+                    runtime_adapter.clone(),
+                    store.clone(),
+                    budget,
+                    gc_policy,
+                )
+            })
+        });
 
         let now = Utc::now();
         Ok(ClientActor {
@@ -165,6 +199,9 @@ impl ClientActor {
             doomslug_timer_next_attempt: now,
             chunk_request_retry_next_attempt: now,
             sync_started: false,
+            store_validation,
+            store_validation_addr,
+            store_validation_running: false,
         })
     }
 }
@@ -189,6 +226,9 @@ impl Actor for ClientActor {
 
         // Start periodic logging of current state of the client.
         self.log_summary(ctx);
+
+        // Start background store validation.
+        self.validate_store(ctx);
     }
 }
 
@@ -284,6 +324,7 @@ impl Handler<NetworkClientMessages> for ClientActor {
                             self.client.chain.store().owned_store(),
                         );
                         store_validator.set_timeout(timeout);
+                        store_validator.set_gc_policy(self.client.config.gc_policy);
                         store_validator.validate();
                         if store_validator.is_failed() {
                             error!(target: "client", "Storage Validation failed, {:?}", store_validator.errors);
@@ -393,6 +434,9 @@ impl Handler<NetworkClientMessages> for ClientActor {
                                     .state_sync
                                     .received_requested_part(part_id, shard_id, hash);
                             }
+                            if state_response.has_header() {
+                                self.client.state_sync.received_requested_header(shard_id, hash);
+                            }
 
                             if let Some(shard_download) = shards_to_download.get_mut(&shard_id) {
                                 assert!(
@@ -408,11 +452,14 @@ impl Handler<NetworkClientMessages> for ClientActor {
                     }
 
                     // ... Or one of the catchups
-                    if let Some((_, shards_to_download)) =
+                    if let Some((state_sync, shards_to_download)) =
                         self.client.catchup_state_syncs.get_mut(&hash)
                     {
                         if let Some(part_id) = state_response.part_id() {
-                            self.client.state_sync.received_requested_part(part_id, shard_id, hash);
+                            state_sync.received_requested_part(part_id, shard_id, hash);
+                        }
+                        if state_response.has_header() {
+                            state_sync.received_requested_header(shard_id, hash);
                         }
 
                         if let Some(shard_download) = shards_to_download.get_mut(&shard_id) {
@@ -427,6 +474,7 @@ impl Handler<NetworkClientMessages> for ClientActor {
                     download
                 };
 
+                let mut pending_part = None;
                 if let Some(shard_sync_download) = download {
                     match shard_sync_download.status {
                         ShardSyncStatus::StateDownloadHeader => {
@@ -461,15 +509,20 @@ impl Handler<NetworkClientMessages> for ClientActor {
                                     return NetworkClientResponses::NoResponse;
                                 }
                                 if !shard_sync_download.downloads[part_id as usize].done {
-                                    match self
-                                        .client
-                                        .chain
-                                        .set_state_part(shard_id, hash, part_id, num_parts, &data)
-                                    {
-                                        Ok(()) => {
-                                            shard_sync_download.downloads[part_id as usize].done =
-                                                true;
-                                        }
+                                    // The part is validated and saved on the state parts pool, the
+                                    // download is updated once that is done.
+                                    let (tx, rx) = oneshot::channel();
+                                    match self.client.chain.schedule_set_state_part(
+                                        shard_id,
+                                        hash,
+                                        part_id,
+                                        num_parts,
+                                        data,
+                                        move |result| {
+                                            let _ = tx.send(result);
+                                        },
+                                    ) {
+                                        Ok(()) => pending_part = Some((part_id, rx)),
                                         Err(err) => {
                                             error!(target: "sync", "State sync set_state_part error, shard = {}, part = {}, hash = {}: {:?}", shard_id, part_id, hash, err);
                                             shard_sync_download.downloads[part_id as usize].error =
@@ -485,6 +538,21 @@ impl Handler<NetworkClientMessages> for ClientActor {
                     error!(target: "sync", "State sync received hash {} that we're not expecting, potential malicious peer", hash);
                 }
 
+                if let Some((part_id, rx)) = pending_part {
+                    rx.into_actor(self)
+                        .then(move |result, act, _ctx| {
+                            let result = result.unwrap_or_else(|_| {
+                                Err(near_chain::ErrorKind::Other(
+                                    "set_state_part was cancelled".into(),
+                                )
+                                .into())
+                            });
+                            act.on_state_part_saved(shard_id, hash, part_id, result);
+                            actix::fut::ready(())
+                        })
+                        .spawn(ctx);
+                }
+
                 NetworkClientResponses::NoResponse
             }
             NetworkClientMessages::EpochSyncResponse(_peer_id, _response) => {
@@ -610,6 +678,7 @@ impl Handler<Status> for ClientActor {
                 syncing: self.client.sync_status.is_syncing(),
             },
             validator_account_id,
+            store_validation: self.store_validation.clone(),
         })
     }
 }
@@ -655,6 +724,55 @@ impl ClientActor {
         }
     }
 
+    /// Marks a state part received from the network as downloaded once it's saved, or as failed so
+    /// that it's requested again. The state sync may have moved on in the meantime.
+    fn on_state_part_saved(
+        &mut self,
+        shard_id: ShardId,
+        sync_hash: CryptoHash,
+        part_id: u64,
+        result: Result<(), near_chain::Error>,
+    ) {
+        let in_state_sync = match &self.client.sync_status {
+            SyncStatus::StateSync(hash, shards_to_download) => {
+                *hash == sync_hash && shards_to_download.contains_key(&shard_id)
+            }
+            _ => false,
+        };
+        let shard_sync_download = if in_state_sync {
+            match &mut self.client.sync_status {
+                SyncStatus::StateSync(_, shards_to_download) => {
+                    shards_to_download.get_mut(&shard_id)
+                }
+                _ => None,
+            }
+        } else {
+            self.client
+                .catchup_state_syncs
+                .get_mut(&sync_hash)
+                .and_then(|(_, shards_to_download)| shards_to_download.get_mut(&shard_id))
+        };
+        let shard_sync_download = match shard_sync_download {
+            Some(shard_sync_download)
+                if matches!(shard_sync_download.status, ShardSyncStatus::StateDownloadParts) =>
+            {
+                shard_sync_download
+            }
+            _ => return,
+        };
+        let download = match shard_sync_download.downloads.get_mut(part_id as usize) {
+            Some(download) => download,
+            None => return,
+        };
+        match result {
+            Ok(()) => download.done = true,
+            Err(err) => {
+                error!(target: "sync", "State sync set_state_part error, shard = {}, part = {}, hash = {}: {:?}", shard_id, part_id, sync_hash, err);
+                download.error = true;
+            }
+        }
+    }
+
     /// Check if client Account Id should be sent and send it.
     /// Account Id is sent when is not current a validator but are becoming a validator soon.
     fn check_send_announce_account(&mut self, prev_block_hash: CryptoHash) {
@@ -1469,6 +1587,68 @@ impl ClientActor {
     }
 }
 
+impl ClientActor {
+    /// Periodically validates the heights written since the previous validation, spending at
+    /// most `store_validation_budget` on each run. The validation runs on its own thread.
+    fn validate_store(&self, ctx: &mut Context<Self>) {
+        let period = match self.client.config.store_validation_period {
+            Some(period) => period,
+            None => return,
+        };
+        near_performance_metrics::actix::run_later(
+            ctx,
+            file!(),
+            line!(),
+            period,
+            move |act, ctx| {
+                // State sync resets the store, it's consistent only after syncing is done
+                if !act.client.sync_status.is_syncing() && !act.store_validation_running {
+                    act.validate_store_step(ctx);
+                }
+                act.validate_store(ctx);
+            },
+        );
+    }
+
+    fn validate_store_step(&mut self, ctx: &mut Context<Self>) {
+        let head = unwrap_or_return!(self.client.chain.head());
+        let from_height = match self.store_validation.as_ref() {
+            Some(info) => info.validated_height,
+            None => return,
+        };
+        let request = match self.store_validation_addr.as_ref() {
+            Some(addr) => addr.send(ValidateStore { from_height, to_height: head.height }),
+            None => return,
+        };
+        self.store_validation_running = true;
+        request
+            .into_actor(self)
+            .then(|res, act, _ctx| {
+                act.store_validation_running = false;
+                match res {
+                    Ok(Ok(result)) => {
+                        if let Some(info) = act.store_validation.as_mut() {
+                            info.validated_height = result.validated_height;
+                            info.num_checks += result.num_checks;
+                            info.num_errors += result.num_errors;
+                            if result.last_error.is_some() {
+                                info.last_error = result.last_error;
+                            }
+                        }
+                    }
+                    Ok(Err(err)) => {
+                        warn!(target: "client", "Store validation didn't run: {}", err);
+                    }
+                    Err(err) => {
+                        error!(target: "client", "Failed to send store validation request: {}", err);
+                    }
+                }
+                actix::fut::ready(())
+            })
+            .spawn(ctx);
+    }
+}
+
 /// Starts client in a separate Arbiter (thread).
 pub fn start_client(
     client_config: ClientConfig,
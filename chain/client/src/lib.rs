@@ -2,15 +2,17 @@
 extern crate lazy_static;
 
 pub use near_client_primitives::types::{
-    Error, GetBlock, GetBlockProof, GetBlockProofResponse, GetBlockWithMerkleTree, GetChunk,
-    GetExecutionOutcome, GetExecutionOutcomeResponse, GetExecutionOutcomesForBlock, GetGasPrice,
-    GetNetworkInfo, GetNextLightClientBlock, GetProtocolConfig, GetReceipt, GetStateChanges,
-    GetStateChangesInBlock, GetStateChangesWithCauseInBlock, GetValidatorInfo, GetValidatorOrdered,
-    Query, QueryError, Status, StatusResponse, SyncStatus, TxStatus, TxStatusError,
+    CreateStoreCheckpoint, Error, GetBlock, GetBlockProof, GetBlockProofResponse,
+    GetBlockWithMerkleTree, GetChunk, GetExecutionOutcome, GetExecutionOutcomeResponse,
+    GetExecutionOutcomesForBlock, GetGasPrice, GetNetworkInfo, GetNextLightClientBlock,
+    GetProtocolConfig, GetReceipt, GetStateChanges, GetStateChangesInBlock,
+    GetStateChangesWithCauseInBlock, GetStoreStats, GetValidatorInfo, GetValidatorOrdered, Query,
+    QueryError, Status, StatusResponse, SyncStatus, TxStatus, TxStatusError,
 };
 
 pub use crate::client::Client;
 pub use crate::client_actor::{start_client, ClientActor};
+pub use crate::store_checkpoint::{start_store_checkpoint_actor, StoreCheckpointActor};
 #[cfg(feature = "adversarial")]
 pub use crate::view_client::AdversarialControls;
 pub use crate::view_client::{start_view_client, ViewClientActor};
@@ -19,6 +21,8 @@ mod client;
 mod client_actor;
 mod info;
 mod metrics;
+mod store_checkpoint;
+mod store_validation;
 pub mod sync;
 pub mod test_utils;
 mod view_client;
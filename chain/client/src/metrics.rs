@@ -30,4 +30,22 @@ lazy_static! {
         try_create_int_gauge("near_memory_usage_bytes", "Amount of RAM memory usage");
     pub static ref GC_TIME: near_metrics::Result<Histogram> =
         try_create_histogram("near_gc_time", "Time taken to do garbage collection");
+    pub static ref STORE_VALIDATION_TIME: near_metrics::Result<Histogram> = try_create_histogram(
+        "near_store_validation_time",
+        "Time taken to validate recently written heights of the store"
+    );
+    pub static ref STORE_VALIDATION_CHECKS_TOTAL: near_metrics::Result<IntCounter> =
+        try_create_int_counter(
+            "near_store_validation_checks_total",
+            "Total number of store checks done by the background validation"
+        );
+    pub static ref STORE_VALIDATION_ERRORS_TOTAL: near_metrics::Result<IntCounter> =
+        try_create_int_counter(
+            "near_store_validation_errors_total",
+            "Total number of failed store checks found by the background validation"
+        );
+    pub static ref STORE_VALIDATION_HEIGHT: near_metrics::Result<IntGauge> = try_create_int_gauge(
+        "near_store_validation_height",
+        "Highest height validated by the background store validation"
+    );
 }
@@ -0,0 +1,41 @@
+//! Creation of store checkpoints requested over the admin RPC.
+use std::sync::Arc;
+
+use actix::{Actor, Addr, Handler, SyncArbiter, SyncContext};
+use log::info;
+
+use near_client_primitives::types::{
+    CreateStoreCheckpoint, CreateStoreCheckpointError, StoreCheckpointResponse,
+};
+use near_store::Store;
+
+/// Creates the checkpoints on its own thread, as flushing the memtables and copying the files
+/// which can't be hard-linked may take a while, during which the view client keeps answering
+/// queries.
+pub struct StoreCheckpointActor {
+    store: Arc<Store>,
+}
+
+impl Actor for StoreCheckpointActor {
+    type Context = SyncContext<Self>;
+}
+
+impl Handler<CreateStoreCheckpoint> for StoreCheckpointActor {
+    type Result = Result<StoreCheckpointResponse, CreateStoreCheckpointError>;
+
+    fn handle(&mut self, msg: CreateStoreCheckpoint, _ctx: &mut Self::Context) -> Self::Result {
+        if msg.path.exists() {
+            return Err(CreateStoreCheckpointError::PathAlreadyExists(
+                msg.path.display().to_string(),
+            ));
+        }
+        info!(target: "client", "Creating store checkpoint at {}", msg.path.display());
+        let db_version = self.store.create_checkpoint(&msg.path)?;
+        Ok(StoreCheckpointResponse { path: msg.path, db_version })
+    }
+}
+
+/// Starts the actor creating the store checkpoints in a new thread.
+pub fn start_store_checkpoint_actor(store: Arc<Store>) -> Addr<StoreCheckpointActor> {
+    SyncArbiter::start(1, move || StoreCheckpointActor { store: store.clone() })
+}
@@ -0,0 +1,100 @@
+//! Background validation of the data written to the store, see
+//! `ClientConfig::store_validation_period`.
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix::{Actor, Handler, Message, SyncContext};
+use log::error;
+
+use near_chain::{RuntimeAdapter, StoreValidator};
+use near_chain_configs::{GCPolicy, GenesisConfig};
+use near_primitives::types::{AccountId, BlockHeight};
+use near_store::Store;
+
+use crate::metrics;
+
+/// Validates the store on its own thread, so that the client isn't blocked by it. Each run reads
+/// a read-only snapshot of the store, which the client keeps writing to in the meantime.
+pub(crate) struct StoreValidationActor {
+    me: Option<AccountId>,
+    genesis_height: BlockHeight,
+    runtime_adapter: Arc<dyn RuntimeAdapter>,
+    store: Arc<Store>,
+    budget: Duration,
+    gc_policy: GCPolicy,
+}
+
+impl StoreValidationActor {
+    pub fn new(
+        me: Option<AccountId>,
+        genesis_height: BlockHeight,
+        runtime_adapter: Arc<dyn RuntimeAdapter>,
+        store: Arc<Store>,
+        budget: Duration,
+        gc_policy: GCPolicy,
+    ) -> Self {
+        Self { me, genesis_height, runtime_adapter, store, budget, gc_policy }
+    }
+}
+
+impl Actor for StoreValidationActor {
+    type Context = SyncContext<Self>;
+}
+
+/// Validates the heights above `from_height` up to `to_height`, as many as fit in the budget.
+pub(crate) struct ValidateStore {
+    pub from_height: BlockHeight,
+    pub to_height: BlockHeight,
+}
+
+impl Message for ValidateStore {
+    type Result = Result<StoreValidationResult, String>;
+}
+
+pub(crate) struct StoreValidationResult {
+    /// Highest height validated by the run.
+    pub validated_height: BlockHeight,
+    pub num_checks: u64,
+    pub num_errors: u64,
+    pub last_error: Option<String>,
+}
+
+impl Handler<ValidateStore> for StoreValidationActor {
+    type Result = Result<StoreValidationResult, String>;
+
+    fn handle(&mut self, msg: ValidateStore, _ctx: &mut Self::Context) -> Self::Result {
+        let snapshot = self.store.snapshot().map_err(|err| err.to_string())?;
+        let mut genesis = GenesisConfig::default();
+        genesis.genesis_height = self.genesis_height;
+        let mut store_validator =
+            StoreValidator::new(self.me.clone(), genesis, self.runtime_adapter.clone(), snapshot);
+        store_validator.set_timeout(self.budget.as_millis() as u64);
+        store_validator.set_gc_policy(self.gc_policy);
+
+        let timer = near_metrics::start_timer(&metrics::STORE_VALIDATION_TIME);
+        let validated_height = store_validator.validate_heights(msg.from_height, msg.to_height);
+        near_metrics::stop_timer(timer);
+
+        near_metrics::inc_counter_by(
+            &metrics::STORE_VALIDATION_CHECKS_TOTAL,
+            store_validator.tests_done(),
+        );
+        near_metrics::inc_counter_by(
+            &metrics::STORE_VALIDATION_ERRORS_TOTAL,
+            store_validator.num_failed(),
+        );
+        near_metrics::set_gauge(&metrics::STORE_VALIDATION_HEIGHT, validated_height as i64);
+        for error in store_validator.errors.iter() {
+            error!(target: "client", "Store validation failed, {} {} {}", error.col, error.key, error.err);
+        }
+        Ok(StoreValidationResult {
+            validated_height,
+            num_checks: store_validator.tests_done(),
+            num_errors: store_validator.num_failed(),
+            last_error: store_validator
+                .errors
+                .last()
+                .map(|error| format!("{} {} {}", error.col, error.key, error.err)),
+        })
+    }
+}
@@ -1,18 +1,22 @@
 use near_chain::{ChainStoreAccess, Error};
 use std::cmp::min;
 use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::{ops::Add, time::Duration as TimeDuration};
 
 use ansi_term::Color::{Purple, Yellow};
 use chrono::{DateTime, Duration, Utc};
+use futures::channel::oneshot;
 use futures::{future, FutureExt};
 use log::{debug, error, info, warn};
 use rand::seq::{IteratorRandom, SliceRandom};
 use rand::{thread_rng, Rng};
 
+use near_chain::state_sync_dir::schedule_import_state;
 use near_chain::{Chain, RuntimeAdapter};
+use near_chain_configs::MIN_GC_NUM_EPOCHS_TO_KEEP;
 use near_network::types::{AccountOrPeerIdOrHash, NetworkResponses, ReasonForBan};
 use near_network::{FullPeerInfo, NetworkAdapter, NetworkRequests};
 use near_primitives::block::Tip;
@@ -41,6 +45,10 @@ pub const MAX_STATE_PART_REQUEST: u64 = 16;
 /// Number of state parts already requested stored as pending.
 /// This number should not exceed MAX_STATE_PART_REQUEST times (number of peers in the network).
 pub const MAX_PENDING_PART: u64 = MAX_STATE_PART_REQUEST * 10000;
+/// An invalid or empty state response counts as this many timeouts when scoring peers.
+const STATE_SYNC_INVALID_RESPONSE_PENALTY: u64 = 4;
+/// Targets scoring below this fraction of the best score are not requested while there are others.
+const STATE_SYNC_MIN_RELATIVE_SCORE: f64 = 0.25;
 
 pub const NS_PER_SECOND: u128 = 1_000_000_000;
 
@@ -56,6 +64,22 @@ pub fn highest_height_peer(highest_height_peers: &Vec<FullPeerInfo>) -> Option<F
     }
 }
 
+/// Peers which advertised that they track the shard, marked as preferred over validators.
+fn peers_tracking_shard(
+    highest_height_peers: &Vec<FullPeerInfo>,
+    shard_id: ShardId,
+    archival_only: bool,
+) -> Vec<(AccountOrPeerIdOrHash, bool)> {
+    highest_height_peers
+        .iter()
+        .filter(|peer| {
+            peer.chain_info.tracked_shards.contains(&shard_id)
+                && (!archival_only || peer.chain_info.archival)
+        })
+        .map(|peer| (AccountOrPeerIdOrHash::PeerId(peer.peer_info.id.clone()), true))
+        .collect()
+}
+
 /// Helper to keep track of the Epoch Sync
 // TODO #3488
 #[allow(dead_code)]
@@ -533,7 +557,10 @@ impl BlockSync {
 
         debug!(target: "sync", "Block sync: {}/{} requesting block {} from {} peers", head.height, header_head.height, next_hash, highest_height_peers.len());
 
-        let gc_stop_height = chain.runtime_adapter.get_gc_stop_height(&header_head.last_block_hash);
+        let gc_stop_height = chain.runtime_adapter.get_gc_stop_height_for_epochs(
+            &header_head.last_block_hash,
+            chain.gc_policy.blocks_epochs(),
+        );
 
         let request_from_archival = self.archive && request.height < gc_stop_height;
         let peer = if request_from_archival {
@@ -591,6 +618,66 @@ impl PendingRequestStatus {
     }
 }
 
+/// How well a target served state requests during the current state sync.
+#[derive(Debug, Default, Clone)]
+pub struct StateSyncPeerScore {
+    /// Number of requests sent to the target.
+    pub requests: u64,
+    /// Number of requested parts received from the target.
+    pub responses: u64,
+    /// Number of requests the target didn't answer in time.
+    pub timeouts: u64,
+    /// Number of invalid or empty responses received from the target.
+    pub invalid_responses: u64,
+    /// Total time between sending the requests and receiving the parts.
+    pub total_latency: Duration,
+}
+
+impl StateSyncPeerScore {
+    /// Share of successful responses divided by the average latency. Targets without history get
+    /// the highest score, so that they are tried.
+    pub fn score(&self) -> f64 {
+        let failures = self.timeouts + STATE_SYNC_INVALID_RESPONSE_PENALTY * self.invalid_responses;
+        let reliability = (self.responses + 1) as f64 / (self.responses + 1 + failures) as f64;
+        let average_latency_secs = if self.responses == 0 {
+            0.0
+        } else {
+            self.total_latency.num_milliseconds() as f64 / 1000.0 / self.responses as f64
+        };
+        reliability / (1.0 + average_latency_secs)
+    }
+}
+
+/// Returns the result of the background task of `key` once it's done, starting it with `schedule`
+/// if it isn't running yet.
+fn poll_background_task<T>(
+    tasks: &mut HashMap<(CryptoHash, ShardId), oneshot::Receiver<Result<T, Error>>>,
+    key: (CryptoHash, ShardId),
+    schedule: impl FnOnce(oneshot::Sender<Result<T, Error>>) -> Result<(), Error>,
+) -> Option<Result<T, Error>> {
+    let result = match tasks.get_mut(&key) {
+        None => {
+            let (tx, rx) = oneshot::channel();
+            match schedule(tx) {
+                Ok(()) => {
+                    tasks.insert(key, rx);
+                    return None;
+                }
+                Err(err) => Err(err),
+            }
+        }
+        Some(rx) => match rx.try_recv() {
+            Ok(None) => return None,
+            Ok(Some(result)) => result,
+            Err(oneshot::Canceled) => {
+                Err(near_chain::ErrorKind::Other("background task was cancelled".into()).into())
+            }
+        },
+    };
+    tasks.remove(&key);
+    Some(result)
+}
+
 /// Helper to track state sync.
 pub struct StateSync {
     network_adapter: Arc<dyn NetworkAdapter>,
@@ -599,21 +686,40 @@ pub struct StateSync {
     last_time_block_requested: Option<DateTime<Utc>>,
 
     last_part_id_requested: HashMap<(AccountOrPeerIdOrHash, ShardId), PendingRequestStatus>,
-    /// Map from which part we requested to whom.
-    requested_target: SizedCache<(u64, CryptoHash), AccountOrPeerIdOrHash>,
+    /// Map from which part we requested to whom and when.
+    requested_target: SizedCache<(u64, CryptoHash), (AccountOrPeerIdOrHash, DateTime<Utc>)>,
+    /// Map from the shard whose header we requested to whom and when.
+    requested_header_target: HashMap<(ShardId, CryptoHash), (AccountOrPeerIdOrHash, DateTime<Utc>)>,
+    /// Scores of the targets requested during the current state sync.
+    peer_scores: HashMap<AccountOrPeerIdOrHash, StateSyncPeerScore>,
 
     timeout: Duration,
+    /// Directory to load the state from before requesting it from peers.
+    state_sync_dir: Option<PathBuf>,
+    /// Shards whose state is being loaded from `state_sync_dir` in the background.
+    state_imports: HashMap<(CryptoHash, ShardId), oneshot::Receiver<Result<u64, Error>>>,
+    /// Shards whose downloaded parts are being applied on the state parts pool.
+    state_parts_applying: HashMap<(CryptoHash, ShardId), oneshot::Receiver<Result<(), Error>>>,
 }
 
 impl StateSync {
-    pub fn new(network_adapter: Arc<dyn NetworkAdapter>, timeout: TimeDuration) -> Self {
+    pub fn new(
+        network_adapter: Arc<dyn NetworkAdapter>,
+        timeout: TimeDuration,
+        state_sync_dir: Option<PathBuf>,
+    ) -> Self {
         StateSync {
             network_adapter,
             state_sync_time: Default::default(),
             last_time_block_requested: None,
             last_part_id_requested: Default::default(),
             requested_target: SizedCache::with_size(MAX_PENDING_PART as usize),
+            requested_header_target: Default::default(),
+            peer_scores: Default::default(),
             timeout: Duration::from_std(timeout).unwrap(),
+            state_sync_dir,
+            state_imports: Default::default(),
+            state_parts_applying: Default::default(),
         }
     }
 
@@ -681,6 +787,37 @@ impl StateSync {
                 need_shard = true;
                 init_sync_download.clone()
             });
+            let shard_key = (sync_hash, shard_id);
+            if need_shard || self.state_imports.contains_key(&shard_key) {
+                if let Some(dir) = self.state_sync_dir.clone() {
+                    // Load the whole state at once and go straight to applying it. The parts are
+                    // loaded in the background, the shard isn't requested from peers meanwhile.
+                    let result = poll_background_task(&mut self.state_imports, shard_key, |tx| {
+                        schedule_import_state(chain, &dir, shard_id, sync_hash, move |result| {
+                            let _ = tx.send(result);
+                        })
+                    });
+                    match result {
+                        None => {
+                            all_done = false;
+                            continue;
+                        }
+                        Some(Ok(num_parts)) => {
+                            info!(target: "sync", "State sync loaded {} parts of shard {} for {} from {}", num_parts, shard_id, sync_hash, dir.display());
+                            need_shard = false;
+                            update_sync_status = true;
+                            *shard_sync_download = ShardSyncDownload {
+                                downloads: vec![],
+                                status: ShardSyncStatus::StateDownloadFinalize,
+                            };
+                        }
+                        Some(Err(e)) => {
+                            warn!(target: "sync", "State sync failed to load shard {} for {} from {}, downloading it from peers: {:?}", shard_id, sync_hash, dir.display(), e);
+                            need_shard = true;
+                        }
+                    }
+                }
+            }
             let mut this_done = false;
             match shard_sync_download.status {
                 ShardSyncStatus::StateDownloadHeader => {
@@ -709,6 +846,9 @@ impl StateSync {
                         let error = shard_sync_download.downloads[0].error;
                         download_timeout = now - prev > self.timeout;
                         if download_timeout || error {
+                            if let Some(target) = &shard_sync_download.downloads[0].last_target {
+                                self.record_failure(target, error);
+                            }
                             shard_sync_download.downloads[0].run_me.store(true, Ordering::SeqCst);
                             shard_sync_download.downloads[0].error = false;
                             shard_sync_download.downloads[0].prev_update_time = now;
@@ -727,6 +867,9 @@ impl StateSync {
                             let error = part_download.error;
                             let part_timeout = now - prev > self.timeout;
                             if part_timeout || error {
+                                if let Some(target) = &part_download.last_target {
+                                    self.record_failure(target, error);
+                                }
                                 download_timeout |= part_timeout;
                                 part_download.run_me.store(true, Ordering::SeqCst);
                                 part_download.error = false;
@@ -749,21 +892,42 @@ impl StateSync {
                     let shard_state_header = chain.get_state_header(shard_id, sync_hash)?;
                     let state_num_parts =
                         get_num_state_parts(shard_state_header.state_root_node().memory_usage);
-                    match chain.set_state_finalize(shard_id, sync_hash, state_num_parts) {
-                        Ok(_) => {
-                            update_sync_status = true;
-                            *shard_sync_download = ShardSyncDownload {
-                                downloads: vec![],
-                                status: ShardSyncStatus::StateDownloadComplete,
+                    // The parts are applied in the background, the shard is finalized once
+                    // they're all applied.
+                    let result =
+                        poll_background_task(&mut self.state_parts_applying, shard_key, |tx| {
+                            chain.schedule_apply_state_parts(
+                                shard_id,
+                                sync_hash,
+                                state_num_parts,
+                                move |result| {
+                                    let _ = tx.send(result);
+                                },
+                            )
+                        });
+                    if let Some(result) = result {
+                        match result
+                            .and_then(|()| chain.set_state_finalize_applied(shard_id, sync_hash))
+                        {
+                            Ok(_) => {
+                                update_sync_status = true;
+                                *shard_sync_download = ShardSyncDownload {
+                                    downloads: vec![],
+                                    status: ShardSyncStatus::StateDownloadComplete,
+                                }
+                            }
+                            Err(e) => {
+                                // Cannot finalize the downloaded state.
+                                // The reasonable behavior here is to start from the very beginning.
+                                error!(target: "sync", "State sync finalizing error, shard = {}, hash = {}: {:?}", shard_id, sync_hash, e);
+                                update_sync_status = true;
+                                *shard_sync_download = init_sync_download.clone();
+                                chain.clear_downloaded_parts(
+                                    shard_id,
+                                    sync_hash,
+                                    state_num_parts,
+                                )?;
                             }
-                        }
-                        Err(e) => {
-                            // Cannot finalize the downloaded state.
-                            // The reasonable behavior here is to start from the very beginning.
-                            error!(target: "sync", "State sync finalizing error, shard = {}, hash = {}: {:?}", shard_id, sync_hash, e);
-                            update_sync_status = true;
-                            *shard_sync_download = init_sync_download.clone();
-                            chain.clear_downloaded_parts(shard_id, sync_hash, state_num_parts)?;
                         }
                     }
                 }
@@ -803,6 +967,7 @@ impl StateSync {
                           _ => unreachable!("timeout cannot happen when all state is downloaded"),
                       },
                 );
+                debug!(target: "sync", "State sync peer scores: {:?}", self.peer_scores);
             }
 
             // Execute syncing for shard `shard_id`
@@ -846,6 +1011,21 @@ impl StateSync {
         }
     }
 
+    fn record_response(&mut self, target: &AccountOrPeerIdOrHash, sent_time: DateTime<Utc>) {
+        let score = self.peer_scores.entry(target.clone()).or_default();
+        score.responses += 1;
+        score.total_latency = score.total_latency + (Utc::now() - sent_time);
+    }
+
+    fn record_failure(&mut self, target: &AccountOrPeerIdOrHash, invalid_response: bool) {
+        let score = self.peer_scores.entry(target.clone()).or_default();
+        if invalid_response {
+            score.invalid_responses += 1;
+        } else {
+            score.timeouts += 1;
+        }
+    }
+
     fn sent_request_part(
         &mut self,
         target: AccountOrPeerIdOrHash,
@@ -853,7 +1033,8 @@ impl StateSync {
         shard_id: ShardId,
         sync_hash: CryptoHash,
     ) {
-        self.requested_target.cache_set((part_id, sync_hash), target.clone());
+        self.requested_target.cache_set((part_id, sync_hash), (target.clone(), Utc::now()));
+        self.peer_scores.entry(target.clone()).or_default().requests += 1;
 
         let timeout = self.timeout;
         self.last_part_id_requested
@@ -871,7 +1052,8 @@ impl StateSync {
         sync_hash: CryptoHash,
     ) {
         let key = (part_id, sync_hash);
-        if let Some(target) = self.requested_target.cache_get(&key) {
+        if let Some((target, sent_time)) = self.requested_target.cache_remove(&key) {
+            self.record_response(&target, sent_time);
             if self.last_part_id_requested.get_mut(&(target.clone(), shard_id)).map_or(
                 false,
                 |request| {
@@ -884,9 +1066,19 @@ impl StateSync {
         }
     }
 
+    pub fn received_requested_header(&mut self, shard_id: ShardId, sync_hash: CryptoHash) {
+        if let Some((target, sent_time)) =
+            self.requested_header_target.remove(&(shard_id, sync_hash))
+        {
+            self.record_response(&target, sent_time);
+        }
+    }
+
     /// Find possible targets to download state from.
-    /// Candidates are validators at current epoch and peers at highest height.
+    /// Candidates are validators at current epoch and peers at highest height, or only archival
+    /// peers if the sync hash is so old that other nodes may have garbage collected its state.
     /// Only select candidates that we have no pending request currently ongoing.
+    /// The candidates are ordered from the best to the worst.
     fn possible_targets(
         &mut self,
         me: &Option<AccountId>,
@@ -899,40 +1091,74 @@ impl StateSync {
         // Remove candidates from pending list if request expired due to timeout
         self.last_part_id_requested.retain(|_, request| !request.expired());
 
-        let prev_block_hash = chain.get_block_header(&sync_hash)?.prev_hash();
+        let sync_header = chain.get_block_header(&sync_hash)?.clone();
+        let prev_block_hash = sync_header.prev_hash();
         let epoch_hash = runtime_adapter.get_epoch_id_from_prev_block(&prev_block_hash)?;
 
-        Ok(runtime_adapter
-            .get_epoch_block_producers_ordered(&epoch_hash, &sync_hash)?
-            .iter()
-            .filter_map(|(validator_stake, _slashed)| {
-                let account_id = validator_stake.account_id();
-                if runtime_adapter.cares_about_shard(
-                    Some(account_id),
-                    &prev_block_hash,
-                    shard_id,
-                    false,
-                ) {
-                    if me.as_ref().map(|me| me != account_id).unwrap_or(true) {
-                        Some(AccountOrPeerIdOrHash::AccountId(account_id.clone()))
+        let sync_hash_is_old = chain.header_head()?.height.saturating_sub(sync_header.height())
+            >= (MIN_GC_NUM_EPOCHS_TO_KEEP - 1) * chain.epoch_length;
+        let mut candidates: Vec<(AccountOrPeerIdOrHash, bool)> = if sync_hash_is_old {
+            peers_tracking_shard(highest_height_peers, shard_id, true)
+        } else {
+            vec![]
+        };
+        if candidates.is_empty() {
+            candidates = runtime_adapter
+                .get_epoch_block_producers_ordered(&epoch_hash, &sync_hash)?
+                .iter()
+                .filter_map(|(validator_stake, _slashed)| {
+                    let account_id = validator_stake.account_id();
+                    if runtime_adapter.cares_about_shard(
+                        Some(account_id),
+                        &prev_block_hash,
+                        shard_id,
+                        false,
+                    ) {
+                        if me.as_ref().map(|me| me != account_id).unwrap_or(true) {
+                            Some((AccountOrPeerIdOrHash::AccountId(account_id.clone()), false))
+                        } else {
+                            None
+                        }
                     } else {
                         None
                     }
-                } else {
-                    None
-                }
-            })
-            .chain(highest_height_peers.iter().filter_map(|peer| {
-                if peer.chain_info.tracked_shards.contains(&shard_id) {
-                    Some(AccountOrPeerIdOrHash::PeerId(peer.peer_info.id.clone()))
-                } else {
-                    None
-                }
-            }))
-            .filter(|candidate| {
-                !self.last_part_id_requested.contains_key(&(candidate.clone(), shard_id))
-            })
-            .collect::<Vec<_>>())
+                })
+                .chain(peers_tracking_shard(highest_height_peers, shard_id, false))
+                .collect();
+        } else {
+            debug!(target: "sync", "State sync for old hash {} uses {} archival peers", sync_hash, candidates.len());
+        }
+        candidates.retain(|(candidate, _)| {
+            !self.last_part_id_requested.contains_key(&(candidate.clone(), shard_id))
+        });
+        Ok(self.rank_targets(candidates))
+    }
+
+    /// Orders the targets by their scores, with the ones advertising the shard first among equal
+    /// scores, and drops the targets scoring much worse than the best one.
+    fn rank_targets(
+        &self,
+        mut targets: Vec<(AccountOrPeerIdOrHash, bool)>,
+    ) -> Vec<AccountOrPeerIdOrHash> {
+        let score = |target: &AccountOrPeerIdOrHash| {
+            self.peer_scores.get(target).map_or(1.0, |score| score.score())
+        };
+        // Shuffle first, so that the requests are spread among equally good targets
+        targets.shuffle(&mut thread_rng());
+        let mut targets: Vec<_> = targets
+            .into_iter()
+            .map(|(target, advertised)| (score(&target), advertised, target))
+            .collect();
+        targets.sort_by(|a, b| {
+            b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal).then(b.1.cmp(&a.1))
+        });
+        let min_score =
+            targets.first().map_or(0.0, |(best, _, _)| best * STATE_SYNC_MIN_RELATIVE_SCORE);
+        targets
+            .into_iter()
+            .filter(|(score, _, _)| *score >= min_score)
+            .map(|(_, _, target)| target)
+            .collect()
     }
 
     /// Returns new ShardSyncDownload if successful, otherwise returns given shard_sync_download
@@ -964,7 +1190,10 @@ impl StateSync {
 
         match shard_sync_download.status {
             ShardSyncStatus::StateDownloadHeader => {
-                let target = possible_targets.choose(&mut thread_rng()).cloned().unwrap();
+                let target = possible_targets[0].clone();
+                self.requested_header_target
+                    .insert((shard_id, sync_hash), (target.clone(), Utc::now()));
+                self.peer_scores.entry(target.clone()).or_default().requests += 1;
                 assert!(new_shard_sync_download.downloads[0].run_me.load(Ordering::SeqCst));
                 new_shard_sync_download.downloads[0].run_me.store(false, Ordering::SeqCst);
                 new_shard_sync_download.downloads[0].state_requests_count += 1;
@@ -1072,6 +1301,8 @@ impl StateSync {
 
         if have_block && all_done {
             self.state_sync_time.clear();
+            self.peer_scores.clear();
+            self.requested_header_target.clear();
             return Ok(StateSyncResult::Completed);
         }
 
@@ -1508,4 +1739,52 @@ mod test {
             blocks.iter().take(1).map(|b| *b.hash()).collect::<HashSet<_>>()
         );
     }
+
+    #[test]
+    fn test_state_sync_rank_targets() {
+        let mut state_sync = StateSync::new(
+            Arc::new(MockNetworkAdapter::default()),
+            TimeDuration::from_secs(1),
+            None,
+        );
+        let peer = |seed| {
+            AccountOrPeerIdOrHash::PeerId(PeerId::new(PublicKey::from_seed(KeyType::ED25519, seed)))
+        };
+        let validator = AccountOrPeerIdOrHash::AccountId("test".to_string());
+        // Unknown targets are the best, those advertising the shard first
+        assert_eq!(
+            state_sync.rank_targets(vec![(validator.clone(), false), (peer("a"), true)]),
+            vec![peer("a"), validator.clone()]
+        );
+
+        // Timeouts and invalid parts lower the score, invalid parts more
+        state_sync.record_failure(&peer("a"), false);
+        state_sync.record_failure(&peer("b"), true);
+        assert!(state_sync.peer_scores[&peer("a")].score() < 1.0);
+        assert!(
+            state_sync.peer_scores[&peer("b")].score() < state_sync.peer_scores[&peer("a")].score()
+        );
+        assert_eq!(
+            state_sync.rank_targets(vec![
+                (peer("b"), true),
+                (peer("a"), true),
+                (validator.clone(), false)
+            ]),
+            vec![validator.clone(), peer("a")]
+        );
+
+        // Responses recover the score
+        state_sync.sent_request_part(peer("b"), 0, 0, CryptoHash::default());
+        state_sync.received_requested_part(0, 0, CryptoHash::default());
+        assert_eq!(state_sync.peer_scores[&peer("b")].responses, 1);
+        assert!(state_sync.peer_scores[&peer("b")].score() > 0.25);
+
+        // So do header responses
+        state_sync
+            .requested_header_target
+            .insert((0, CryptoHash::default()), (peer("a"), Utc::now()));
+        state_sync.received_requested_header(0, CryptoHash::default());
+        state_sync.received_requested_header(0, CryptoHash::default());
+        assert_eq!(state_sync.peer_scores[&peer("a")].responses, 1);
+    }
 }
@@ -1134,6 +1134,7 @@ impl TestEnv {
                 last_block.header().hash(),
                 last_block.header().epoch_id(),
                 &QueryRequest::ViewAccount { account_id },
+                false,
             )
             .unwrap();
         match response.kind {
@@ -1157,6 +1158,7 @@ impl TestEnv {
                 last_block.header().hash(),
                 last_block.header().epoch_id(),
                 &QueryRequest::ViewState { account_id, prefix: vec![].into() },
+                false,
             )
             .unwrap();
         match response.kind {
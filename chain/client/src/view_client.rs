@@ -19,11 +19,12 @@ use near_chain::{
 };
 use near_chain_configs::{ClientConfig, ProtocolConfigView};
 use near_client_primitives::types::{
-    Error, GetBlock, GetBlockError, GetBlockProof, GetBlockProofError, GetBlockProofResponse,
-    GetBlockWithMerkleTree, GetChunkError, GetExecutionOutcome, GetExecutionOutcomeError,
-    GetExecutionOutcomesForBlock, GetGasPrice, GetGasPriceError, GetNextLightClientBlockError,
-    GetProtocolConfig, GetProtocolConfigError, GetReceipt, GetReceiptError, GetStateChangesError,
-    GetStateChangesWithCauseInBlock, GetValidatorInfoError, Query, QueryError, TxStatus,
+    ColumnStatsView, Error, GetBlock, GetBlockError, GetBlockProof, GetBlockProofError,
+    GetBlockProofResponse, GetBlockWithMerkleTree, GetChunkError, GetExecutionOutcome,
+    GetExecutionOutcomeError, GetExecutionOutcomesForBlock, GetGasPrice, GetGasPriceError,
+    GetNextLightClientBlockError, GetProtocolConfig, GetProtocolConfigError, GetReceipt,
+    GetReceiptError, GetStateChangesError, GetStateChangesWithCauseInBlock, GetStoreStats,
+    GetStoreStatsError, GetValidatorInfoError, Query, QueryError, StoreStatsResponse, TxStatus,
     TxStatusError,
 };
 #[cfg(feature = "adversarial")]
@@ -272,6 +273,7 @@ impl ViewClientActor {
             header.hash(),
             header.epoch_id(),
             &msg.request,
+            msg.include_proof,
         ) {
             Ok(query_response) => Ok(query_response),
             Err(query_error) => Err(match query_error {
@@ -1262,6 +1264,28 @@ impl Handler<GetGasPrice> for ViewClientActor {
     }
 }
 
+impl Handler<GetStoreStats> for ViewClientActor {
+    type Result = Result<StoreStatsResponse, GetStoreStatsError>;
+
+    #[perf]
+    fn handle(&mut self, _msg: GetStoreStats, _ctx: &mut Self::Context) -> Self::Result {
+        let columns = self
+            .chain
+            .store()
+            .store()
+            .get_column_stats()?
+            .into_iter()
+            .map(|stats| ColumnStatsView {
+                column: format!("{:?}", stats.column),
+                estimated_num_keys: stats.estimated_num_keys,
+                estimated_live_data_size: stats.estimated_live_data_size,
+                sst_files_size: stats.sst_files_size,
+            })
+            .collect();
+        Ok(StoreStatsResponse { columns })
+    }
+}
+
 /// Starts the View Client in a new arbiter (thread).
 pub fn start_view_client(
     validator_account_id: Option<AccountId>,
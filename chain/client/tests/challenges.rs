@@ -10,7 +10,7 @@ use near_chain::{
     Block, ChainGenesis, ChainStoreAccess, DoomslugThresholdMode, Error, ErrorKind, Provenance,
     RuntimeAdapter,
 };
-use near_chain_configs::Genesis;
+use near_chain_configs::{GCPolicy, Genesis};
 use near_client::test_utils::{create_chunk, create_chunk_with_transactions, TestEnv};
 use near_client::Client;
 use near_crypto::{InMemorySigner, KeyType, Signer};
@@ -396,6 +396,7 @@ fn test_verify_chunk_invalid_state_challenge() {
             &genesis_block,
             transaction_validity_period,
             None,
+            GCPolicy::default(),
         );
 
         chain_update.create_chunk_state_challenge(&last_block, &block, &block.chunks()[0]).unwrap()
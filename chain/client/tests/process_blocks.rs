@@ -11,12 +11,13 @@ use num_rational::Rational;
 
 use near_actix_test_utils::run_actix_until_stop;
 use near_chain::chain::NUM_EPOCHS_TO_KEEP_STORE_DATA;
+use near_chain::state_sync_dir::{export_state, import_state};
 use near_chain::types::LatestKnown;
 use near_chain::validate::validate_chunk_with_chunk_extra;
 use near_chain::{
     Block, ChainGenesis, ChainStore, ChainStoreAccess, ErrorKind, Provenance, RuntimeAdapter,
 };
-use near_chain_configs::{ClientConfig, Genesis};
+use near_chain_configs::{ClientConfig, GCPolicy, Genesis};
 use near_chunks::{ChunkStatus, ShardsManager};
 use near_client::test_utils::{create_chunk_on_height, setup_mock_all_validators};
 use near_client::test_utils::{setup_client, setup_mock, TestEnv};
@@ -1467,7 +1468,7 @@ fn test_gc_after_state_sync() {
     // mimic what we do in possible_targets
     assert!(env.clients[1].runtime_adapter.get_epoch_id_from_prev_block(&prev_block_hash).is_ok());
     let tries = env.clients[1].runtime_adapter.get_tries();
-    assert!(env.clients[1].chain.clear_data(tries, 2).is_ok());
+    assert!(env.clients[1].chain.clear_data(tries, 2, &GCPolicy::default()).is_ok());
 }
 
 #[test]
@@ -1900,6 +1901,7 @@ fn test_data_reset_before_state_sync() {
             &head.last_block_hash,
             head_block.header().epoch_id(),
             &QueryRequest::ViewAccount { account_id: "test_account".to_string() },
+            false,
         )
         .unwrap();
     assert!(matches!(response.kind, QueryResponseKind::ViewAccount(_)));
@@ -1914,6 +1916,7 @@ fn test_data_reset_before_state_sync() {
         &head.last_block_hash,
         head_block.header().epoch_id(),
         &QueryRequest::ViewAccount { account_id: "test_account".to_string() },
+        false,
     );
     // TODO(#3742): ViewClient still has data in cache by current design.
     assert!(response.is_ok());
@@ -2213,6 +2216,60 @@ fn test_catchup_gas_price_change() {
     assert_eq!(chunk_extra_after_sync, expected_chunk_extra);
 }
 
+#[test]
+fn test_state_sync_from_dir() {
+    init_test_logger();
+    let epoch_length = 5;
+    let mut genesis = Genesis::test(vec!["test0", "test1"], 1);
+    genesis.config.epoch_length = epoch_length;
+    let chain_genesis = ChainGenesis::from(&genesis);
+    let mut env =
+        TestEnv::new_with_runtime(chain_genesis, 2, 1, create_nightshade_runtimes(&genesis, 2));
+    let genesis_block = env.clients[0].chain.get_block_by_height(0).unwrap().clone();
+    let signer = InMemorySigner::from_seed("test0", KeyType::ED25519, "test0");
+    for i in 0..3 {
+        let tx = SignedTransaction::send_money(
+            i + 1,
+            "test0".to_string(),
+            "test1".to_string(),
+            &signer,
+            1,
+            *genesis_block.hash(),
+        );
+        env.clients[0].process_tx(tx, false, false);
+    }
+    let mut blocks = vec![];
+    for i in 1..=6 {
+        let block = env.clients[0].produce_block(i).unwrap().unwrap();
+        blocks.push(block.clone());
+        env.process_block(0, block.clone(), Provenance::PRODUCED);
+        env.process_block(1, block, Provenance::NONE);
+    }
+    assert!(env.clients[1].chain.get_chunk_extra(blocks[4].hash(), 0).is_err());
+
+    let dir = tempfile::Builder::new().prefix("state_sync_dir").tempdir().unwrap();
+    let sync_hash = *blocks[5].hash();
+    assert!(env.clients[0].chain.check_sync_hash_validity(&sync_hash).unwrap());
+    let num_parts = export_state(&mut env.clients[0].chain, dir.path(), 0, sync_hash).unwrap();
+    // Nothing is there for other sync hashes
+    assert!(import_state(&mut env.clients[1].chain, dir.path(), 0, *blocks[4].hash()).is_err());
+
+    assert_eq!(
+        import_state(&mut env.clients[1].chain, dir.path(), 0, sync_hash).unwrap(),
+        num_parts
+    );
+    assert!(env.clients[1]
+        .chain
+        .set_state_parts(0, sync_hash, num_parts, &[(0, vec![1, 2, 3])])
+        .is_err());
+    env.clients[1].chain.set_state_finalize(0, sync_hash, num_parts).unwrap();
+    let chunk_extra_after_sync =
+        env.clients[1].chain.get_chunk_extra(blocks[4].hash(), 0).unwrap().clone();
+    let expected_chunk_extra =
+        env.clients[0].chain.get_chunk_extra(blocks[4].hash(), 0).unwrap().clone();
+    assert_eq!(chunk_extra_after_sync, expected_chunk_extra);
+}
+
 #[test]
 fn test_block_execution_outcomes() {
     let epoch_length = 5;
@@ -2510,6 +2567,7 @@ fn test_query_final_state() {
                 last_final_block.hash(),
                 last_final_block.header().epoch_id(),
                 &QueryRequest::ViewAccount { account_id },
+                false,
             )
             .unwrap();
         match response.kind {
@@ -5,9 +5,11 @@ pub mod config;
 pub mod gas_price;
 pub mod light_client;
 pub mod network_info;
+pub mod peers;
 pub mod query;
 pub mod receipts;
 pub mod sandbox;
 pub mod status;
+pub mod store;
 pub mod transactions;
 pub mod validator;
@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use near_network::types::{KnownPeerStateView, PeerAdminError, ReasonForBan};
+use near_primitives::network::PeerId;
+
+#[derive(Serialize, Deserialize)]
+pub struct RpcBanPeerRequest {
+    pub peer_id: PeerId,
+    #[serde(default)]
+    pub ban_reason: Option<ReasonForBan>,
+    /// Free-text note on why the peer is banned.
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+impl RpcBanPeerRequest {
+    pub fn parse(value: Option<Value>) -> Result<Self, crate::errors::RpcParseError> {
+        Ok(crate::utils::parse_params::<RpcBanPeerRequest>(value)?)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RpcUnbanPeerRequest {
+    pub peer_id: PeerId,
+}
+
+impl RpcUnbanPeerRequest {
+    pub fn parse(value: Option<Value>) -> Result<Self, crate::errors::RpcParseError> {
+        Ok(crate::utils::parse_params::<RpcUnbanPeerRequest>(value)?)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RpcConnectPeerRequest {
+    /// Peer in the `peer_id@ip:port` format of the boot nodes.
+    pub peer_info: String,
+}
+
+impl RpcConnectPeerRequest {
+    pub fn parse(value: Option<Value>) -> Result<Self, crate::errors::RpcParseError> {
+        Ok(crate::utils::parse_params::<RpcConnectPeerRequest>(value)?)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RpcDisconnectPeerRequest {
+    pub peer_id: PeerId,
+}
+
+impl RpcDisconnectPeerRequest {
+    pub fn parse(value: Option<Value>) -> Result<Self, crate::errors::RpcParseError> {
+        Ok(crate::utils::parse_params::<RpcDisconnectPeerRequest>(value)?)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RpcPeerAdminResponse {}
+
+#[derive(Serialize, Deserialize)]
+pub struct RpcKnownPeersResponse {
+    pub known_peers: Vec<KnownPeerStateView>,
+}
+
+#[derive(thiserror::Error, Debug, Serialize)]
+#[serde(tag = "name", content = "info", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RpcPeerAdminError {
+    #[error("Peer {peer_id} is not known to the node")]
+    UnknownPeer { peer_id: PeerId },
+    #[error("Peer {peer_id} is banned")]
+    PeerBanned { peer_id: PeerId },
+    #[error("Peer {peer_id} is not banned")]
+    NotBanned { peer_id: PeerId },
+    #[error("Peer {peer_id} is not connected")]
+    NotConnected { peer_id: PeerId },
+    #[error("Invalid peer info {peer_info}, expected `peer_id@ip:port`")]
+    InvalidPeerInfo { peer_info: String },
+    #[error("The node reached its limits. Try again later. More details: {error_message}")]
+    InternalError { error_message: String },
+}
+
+impl From<PeerAdminError> for RpcPeerAdminError {
+    fn from(error: PeerAdminError) -> Self {
+        match error {
+            PeerAdminError::UnknownPeer(peer_id) => Self::UnknownPeer { peer_id },
+            PeerAdminError::PeerBanned(peer_id) => Self::PeerBanned { peer_id },
+            PeerAdminError::NotBanned(peer_id) => Self::NotBanned { peer_id },
+            PeerAdminError::NotConnected(peer_id) => Self::NotConnected { peer_id },
+            PeerAdminError::NoAddress(peer_id) => {
+                Self::InvalidPeerInfo { peer_info: peer_id.to_string() }
+            }
+            PeerAdminError::IOError(error_message) => Self::InternalError { error_message },
+        }
+    }
+}
+
+impl From<actix::MailboxError> for RpcPeerAdminError {
+    fn from(error: actix::MailboxError) -> Self {
+        Self::InternalError { error_message: error.to_string() }
+    }
+}
+
+impl From<RpcPeerAdminError> for crate::errors::RpcError {
+    fn from(error: RpcPeerAdminError) -> Self {
+        let error_data = Some(Value::String(error.to_string()));
+        let error_data_value = match serde_json::to_value(error) {
+            Ok(value) => value,
+            Err(err) => {
+                return Self::new_internal_error(
+                    None,
+                    format!("Failed to serialize RpcPeerAdminError: {:?}", err),
+                )
+            }
+        };
+        Self::new_internal_or_handler_error(error_data, error_data_value)
+    }
+}
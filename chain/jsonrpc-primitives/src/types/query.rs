@@ -10,6 +10,11 @@ pub struct RpcQueryRequest {
     pub block_reference: near_primitives::types::BlockReference,
     #[serde(flatten)]
     pub request: near_primitives::views::QueryRequest,
+    /// Return trie nodes proving the result. Off by default, so that queries can be served
+    /// from flat state. `ViewAccessKeyList` and `CallFunction` responses can't be proven, so
+    /// the proof is empty for them and verifying them fails with `UnsupportedQuery`.
+    #[serde(default)]
+    pub include_proof: bool,
 }
 
 #[derive(thiserror::Error, Debug, Serialize)]
@@ -59,6 +64,11 @@ pub enum RpcQueryError {
         block_height: near_primitives::types::BlockHeight,
         block_hash: near_primitives::hash::CryptoHash,
     },
+    #[error("The node can't prove the response, as the query was answered by another node")]
+    ProofUnavailable {
+        block_height: near_primitives::types::BlockHeight,
+        block_hash: near_primitives::hash::CryptoHash,
+    },
     #[error("The node reached its limits. Try again later. More details: {error_message}")]
     InternalError { error_message: String },
 }
@@ -69,6 +79,12 @@ pub struct RpcQueryResponse {
     pub kind: QueryResponseKind,
     pub block_height: near_primitives::types::BlockHeight,
     pub block_hash: near_primitives::hash::CryptoHash,
+    /// Trie nodes proving the response if `include_proof` was set, see
+    /// `near_primitives::trie_proof`. For `ViewState` queries the proof is part of the result and
+    /// is deserialized here as well. Empty for `ViewAccessKeyList` and `CallFunction` queries,
+    /// whose responses can't be proven.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub proof: near_primitives::views::TrieProofPath,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -148,6 +164,7 @@ impl RpcQueryRequest {
             RpcQueryRequest {
                 request,
                 block_reference: near_primitives::types::BlockReference::latest(),
+                include_proof: false,
             }
         } else {
             crate::utils::parse_params::<RpcQueryRequest>(value)?
@@ -217,6 +234,22 @@ impl From<near_primitives::views::QueryResponse> for RpcQueryResponse {
             kind: query_response.kind.into(),
             block_hash: query_response.block_hash,
             block_height: query_response.block_height,
+            proof: query_response.proof,
+        }
+    }
+}
+
+impl From<QueryResponseKind> for near_primitives::views::QueryResponseKind {
+    fn from(query_response_kind: QueryResponseKind) -> Self {
+        match query_response_kind {
+            QueryResponseKind::ViewAccount(account_view) => Self::ViewAccount(account_view),
+            QueryResponseKind::ViewCode(contract_code_view) => Self::ViewCode(contract_code_view),
+            QueryResponseKind::ViewState(view_state_result) => Self::ViewState(view_state_result),
+            QueryResponseKind::CallResult(call_result) => Self::CallResult(call_result),
+            QueryResponseKind::AccessKey(access_key_view) => Self::AccessKey(access_key_view),
+            QueryResponseKind::AccessKeyList(access_key_list) => {
+                Self::AccessKeyList(access_key_list)
+            }
         }
     }
 }
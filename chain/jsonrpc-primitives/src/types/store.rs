@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Serialize, Deserialize)]
+pub struct RpcStoreCheckpointRequest {
+    /// Directory to create the checkpoint in. Must not exist yet and should be on the same
+    /// filesystem as the node's data directory, so that files can be hard-linked. The cold
+    /// database of an archival node with a split store is checkpointed in its `cold`
+    /// subdirectory.
+    pub path: std::path::PathBuf,
+}
+
+impl RpcStoreCheckpointRequest {
+    pub fn parse(value: Option<Value>) -> Result<Self, crate::errors::RpcParseError> {
+        Ok(crate::utils::parse_params::<RpcStoreCheckpointRequest>(value)?)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RpcStoreCheckpointResponse {
+    #[serde(flatten)]
+    pub checkpoint: near_client_primitives::types::StoreCheckpointResponse,
+}
+
+#[derive(thiserror::Error, Debug, Serialize)]
+#[serde(tag = "name", content = "info", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RpcStoreCheckpointError {
+    #[error("Checkpoint path {path} already exists")]
+    PathAlreadyExists { path: String },
+    #[error("The node reached its limits. Try again later. More details: {error_message}")]
+    InternalError { error_message: String },
+}
+
+impl From<near_client_primitives::types::CreateStoreCheckpointError> for RpcStoreCheckpointError {
+    fn from(error: near_client_primitives::types::CreateStoreCheckpointError) -> Self {
+        match error {
+            near_client_primitives::types::CreateStoreCheckpointError::PathAlreadyExists(path) => {
+                Self::PathAlreadyExists { path }
+            }
+            near_client_primitives::types::CreateStoreCheckpointError::IOError(error_message) => {
+                Self::InternalError { error_message }
+            }
+        }
+    }
+}
+
+impl From<actix::MailboxError> for RpcStoreCheckpointError {
+    fn from(error: actix::MailboxError) -> Self {
+        Self::InternalError { error_message: error.to_string() }
+    }
+}
+
+impl From<RpcStoreCheckpointError> for crate::errors::RpcError {
+    fn from(error: RpcStoreCheckpointError) -> Self {
+        let error_data = Some(Value::String(error.to_string()));
+        let error_data_value = match serde_json::to_value(error) {
+            Ok(value) => value,
+            Err(err) => {
+                return Self::new_internal_error(
+                    None,
+                    format!("Failed to serialize RpcStoreCheckpointError: {:?}", err),
+                )
+            }
+        };
+        Self::new_internal_or_handler_error(error_data, error_data_value)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RpcStoreStatsResponse {
+    #[serde(flatten)]
+    pub stats: near_client_primitives::types::StoreStatsResponse,
+}
+
+#[derive(thiserror::Error, Debug, Serialize)]
+#[serde(tag = "name", content = "info", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RpcStoreStatsError {
+    #[error("The node reached its limits. Try again later. More details: {error_message}")]
+    InternalError { error_message: String },
+}
+
+impl From<near_client_primitives::types::GetStoreStatsError> for RpcStoreStatsError {
+    fn from(error: near_client_primitives::types::GetStoreStatsError) -> Self {
+        match error {
+            near_client_primitives::types::GetStoreStatsError::IOError(error_message) => {
+                Self::InternalError { error_message }
+            }
+        }
+    }
+}
+
+impl From<actix::MailboxError> for RpcStoreStatsError {
+    fn from(error: actix::MailboxError) -> Self {
+        Self::InternalError { error_message: error.to_string() }
+    }
+}
+
+impl From<RpcStoreStatsError> for crate::errors::RpcError {
+    fn from(error: RpcStoreStatsError) -> Self {
+        let error_data = Some(Value::String(error.to_string()));
+        let error_data_value = match serde_json::to_value(error) {
+            Ok(value) => value,
+            Err(err) => {
+                return Self::new_internal_error(
+                    None,
+                    format!("Failed to serialize RpcStoreStatsError: {:?}", err),
+                )
+            }
+        };
+        Self::new_internal_or_handler_error(error_data, error_data_value)
+    }
+}
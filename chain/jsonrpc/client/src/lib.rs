@@ -256,6 +256,68 @@ impl JsonRpcClient {
     ) -> RpcRequest<near_jsonrpc_primitives::types::config::RpcProtocolConfigResponse> {
         call_method(&self.client, &self.server_addr, "EXPERIMENTAL_protocol_config", request)
     }
+
+    /// Only served by the admin endpoint, see `RpcConfig::admin_addr`.
+    pub fn create_store_checkpoint(
+        &self,
+        request: near_jsonrpc_primitives::types::store::RpcStoreCheckpointRequest,
+    ) -> RpcRequest<near_jsonrpc_primitives::types::store::RpcStoreCheckpointResponse> {
+        call_method(&self.client, &self.server_addr, "create_store_checkpoint", request)
+    }
+
+    /// Only served by the admin endpoint, see `RpcConfig::admin_addr`.
+    pub fn store_stats(
+        &self,
+    ) -> RpcRequest<near_jsonrpc_primitives::types::store::RpcStoreStatsResponse> {
+        call_method(&self.client, &self.server_addr, "store_stats", expand_params!())
+    }
+
+    /// Only served by the admin endpoint, see `RpcConfig::admin_addr`.
+    pub fn ban_peer(
+        &self,
+        request: near_jsonrpc_primitives::types::peers::RpcBanPeerRequest,
+    ) -> RpcRequest<near_jsonrpc_primitives::types::peers::RpcPeerAdminResponse> {
+        call_method(&self.client, &self.server_addr, "ban_peer", request)
+    }
+
+    /// Only served by the admin endpoint, see `RpcConfig::admin_addr`.
+    pub fn unban_peer(
+        &self,
+        request: near_jsonrpc_primitives::types::peers::RpcUnbanPeerRequest,
+    ) -> RpcRequest<near_jsonrpc_primitives::types::peers::RpcPeerAdminResponse> {
+        call_method(&self.client, &self.server_addr, "unban_peer", request)
+    }
+
+    /// Only served by the admin endpoint, see `RpcConfig::admin_addr`.
+    pub fn connect_peer(
+        &self,
+        request: near_jsonrpc_primitives::types::peers::RpcConnectPeerRequest,
+    ) -> RpcRequest<near_jsonrpc_primitives::types::peers::RpcPeerAdminResponse> {
+        call_method(&self.client, &self.server_addr, "connect_peer", request)
+    }
+
+    /// Only served by the admin endpoint, see `RpcConfig::admin_addr`.
+    pub fn disconnect_peer(
+        &self,
+        request: near_jsonrpc_primitives::types::peers::RpcDisconnectPeerRequest,
+    ) -> RpcRequest<near_jsonrpc_primitives::types::peers::RpcPeerAdminResponse> {
+        call_method(&self.client, &self.server_addr, "disconnect_peer", request)
+    }
+
+    /// Only served by the admin endpoint, see `RpcConfig::admin_addr`.
+    pub fn known_peers(
+        &self,
+    ) -> RpcRequest<near_jsonrpc_primitives::types::peers::RpcKnownPeersResponse> {
+        call_method(&self.client, &self.server_addr, "known_peers", expand_params!())
+    }
+
+    /// Only served by nodes built with the `sandbox` feature.
+    pub fn sandbox_patch_state(
+        &self,
+        request: near_jsonrpc_primitives::types::sandbox::RpcSandboxPatchStateRequest,
+    ) -> RpcRequest<near_jsonrpc_primitives::types::sandbox::RpcSandboxPatchStateResponse> {
+        call_method(&self.client, &self.server_addr, "sandbox_patch_state", request)
+    }
 }
 
 fn create_client() -> Client {
@@ -1,7 +1,7 @@
 use std::string::FromUtf8Error;
 use std::time::Duration;
 
-use actix::Addr;
+use actix::{Addr, Recipient};
 use actix_cors::Cors;
 use actix_web::{http, middleware, web, App, Error as HttpError, HttpResponse, HttpServer};
 use futures::Future;
@@ -10,14 +10,14 @@ use prometheus;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use tokio::time::{sleep, timeout};
-use tracing::info;
+use tracing::{error, info};
 
 use near_chain_configs::GenesisConfig;
 use near_client::{
-    ClientActor, GetBlock, GetBlockProof, GetChunk, GetExecutionOutcome, GetGasPrice,
-    GetNetworkInfo, GetNextLightClientBlock, GetProtocolConfig, GetReceipt, GetStateChanges,
-    GetStateChangesInBlock, GetValidatorInfo, GetValidatorOrdered, Query, Status, TxStatus,
-    TxStatusError, ViewClientActor,
+    ClientActor, CreateStoreCheckpoint, GetBlock, GetBlockProof, GetChunk, GetExecutionOutcome,
+    GetGasPrice, GetNetworkInfo, GetNextLightClientBlock, GetProtocolConfig, GetReceipt,
+    GetStateChanges, GetStateChangesInBlock, GetValidatorInfo, GetValidatorOrdered, Query, Status,
+    TxStatus, TxStatusError, ViewClientActor,
 };
 pub use near_jsonrpc_client as client;
 use near_jsonrpc_primitives::errors::RpcError;
@@ -28,12 +28,13 @@ use near_metrics::{Encoder, TextEncoder};
 use near_network::types::{NetworkAdversarialMessage, NetworkViewClientMessages};
 #[cfg(feature = "sandbox")]
 use near_network::types::{NetworkSandboxMessage, SandboxResponse};
-use near_network::{NetworkClientMessages, NetworkClientResponses};
+use near_network::types::{PeerAdminRequest, PeerAdminResponse, ReasonForBan};
+use near_network::{NetworkClientMessages, NetworkClientResponses, PeerInfo};
 use near_primitives::hash::CryptoHash;
 use near_primitives::serialize::BaseEncode;
 use near_primitives::transaction::SignedTransaction;
 use near_primitives::types::AccountId;
-use near_primitives::views::FinalExecutionOutcomeViewEnum;
+use near_primitives::views::{FinalExecutionOutcomeViewEnum, QueryResponseKind};
 
 mod metrics;
 
@@ -71,6 +72,10 @@ pub struct RpcConfig {
     pub polling_config: RpcPollingConfig,
     #[serde(default)]
     pub limits_config: RpcLimitsConfig,
+    /// Address to serve node management methods (e.g. store checkpoints, peer bans) at. The admin
+    /// endpoint is disabled if not set and is only started on loopback addresses.
+    #[serde(default)]
+    pub admin_addr: Option<String>,
 }
 
 impl Default for RpcConfig {
@@ -80,6 +85,7 @@ impl Default for RpcConfig {
             cors_allowed_origins: vec!["*".to_owned()],
             polling_config: Default::default(),
             limits_config: Default::default(),
+            admin_addr: None,
         }
     }
 }
@@ -199,6 +205,8 @@ struct JsonRpcHandler {
     view_client_addr: Addr<ViewClientActor>,
     polling_config: RpcPollingConfig,
     genesis_config: GenesisConfig,
+    peer_manager_addr: Option<Recipient<PeerAdminRequest>>,
+    store_checkpoint_addr: Option<Recipient<CreateStoreCheckpoint>>,
 }
 
 impl JsonRpcHandler {
@@ -214,6 +222,97 @@ impl JsonRpcHandler {
         }
     }
 
+    pub async fn process_admin(&self, message: Message) -> Result<Message, HttpError> {
+        let id = message.id();
+        match message {
+            Message::Request(request) => {
+                Ok(Message::response(id, self.process_admin_request(request).await))
+            }
+            _ => Ok(Message::error(RpcError::parse_error(
+                "JSON RPC Request format was expected".to_owned(),
+            ))),
+        }
+    }
+
+    async fn process_admin_request(&self, request: Request) -> Result<Value, RpcError> {
+        near_metrics::inc_counter_vec(&metrics::HTTP_RPC_REQUEST_COUNT, &[request.method.as_ref()]);
+        let _rpc_processing_time = near_metrics::start_timer_vec(
+            &metrics::RPC_PROCESSING_TIME,
+            &[request.method.as_ref()],
+        );
+
+        let response: Result<Value, RpcError> = match request.method.as_ref() {
+            // Handlers ordered alphabetically
+            "ban_peer" => {
+                let rpc_ban_peer_request =
+                    near_jsonrpc_primitives::types::peers::RpcBanPeerRequest::parse(
+                        request.params,
+                    )?;
+                let ban_peer_response = self.ban_peer(rpc_ban_peer_request).await?;
+                serde_json::to_value(ban_peer_response)
+                    .map_err(|err| RpcError::serialization_error(err.to_string()))
+            }
+            "connect_peer" => {
+                let rpc_connect_peer_request =
+                    near_jsonrpc_primitives::types::peers::RpcConnectPeerRequest::parse(
+                        request.params,
+                    )?;
+                let connect_peer_response = self.connect_peer(rpc_connect_peer_request).await?;
+                serde_json::to_value(connect_peer_response)
+                    .map_err(|err| RpcError::serialization_error(err.to_string()))
+            }
+            "create_store_checkpoint" => {
+                let rpc_store_checkpoint_request =
+                    near_jsonrpc_primitives::types::store::RpcStoreCheckpointRequest::parse(
+                        request.params,
+                    )?;
+                let store_checkpoint =
+                    self.create_store_checkpoint(rpc_store_checkpoint_request).await?;
+                serde_json::to_value(store_checkpoint)
+                    .map_err(|err| RpcError::serialization_error(err.to_string()))
+            }
+            "disconnect_peer" => {
+                let rpc_disconnect_peer_request =
+                    near_jsonrpc_primitives::types::peers::RpcDisconnectPeerRequest::parse(
+                        request.params,
+                    )?;
+                let disconnect_peer_response =
+                    self.disconnect_peer(rpc_disconnect_peer_request).await?;
+                serde_json::to_value(disconnect_peer_response)
+                    .map_err(|err| RpcError::serialization_error(err.to_string()))
+            }
+            "known_peers" => {
+                let known_peers_response = self.known_peers().await?;
+                serde_json::to_value(known_peers_response)
+                    .map_err(|err| RpcError::serialization_error(err.to_string()))
+            }
+            "store_stats" => {
+                let store_stats_response = self.store_stats().await?;
+                serde_json::to_value(store_stats_response)
+                    .map_err(|err| RpcError::serialization_error(err.to_string()))
+            }
+            "unban_peer" => {
+                let rpc_unban_peer_request =
+                    near_jsonrpc_primitives::types::peers::RpcUnbanPeerRequest::parse(
+                        request.params,
+                    )?;
+                let unban_peer_response = self.unban_peer(rpc_unban_peer_request).await?;
+                serde_json::to_value(unban_peer_response)
+                    .map_err(|err| RpcError::serialization_error(err.to_string()))
+            }
+            _ => Err(RpcError::method_not_found(request.method.clone())),
+        };
+
+        if let Err(err) = &response {
+            near_metrics::inc_counter_vec(
+                &metrics::RPC_ERROR_COUNT,
+                &[request.method.as_ref(), &err.code.to_string()],
+            );
+        }
+
+        response
+    }
+
     async fn process_request(&self, request: Request) -> Result<Value, RpcError> {
         near_metrics::inc_counter_vec(&metrics::HTTP_RPC_REQUEST_COUNT, &[request.method.as_ref()]);
         let _rpc_processing_time = near_metrics::start_timer_vec(
@@ -774,8 +873,27 @@ impl JsonRpcHandler {
         near_jsonrpc_primitives::types::query::RpcQueryResponse,
         near_jsonrpc_primitives::types::query::RpcQueryError,
     > {
-        let query = Query::new(request_data.block_reference, request_data.request);
-        Ok(self.view_client_addr.send(query).await??.into())
+        let include_proof = request_data.include_proof;
+        let query = Query::new(request_data.block_reference, request_data.request)
+            .with_proof(include_proof);
+        let query_response = self.view_client_addr.send(query).await??;
+        // Proofs aren't sent over the network, so a response which should be proven but came
+        // without a proof was answered by another node and can't be returned as requested.
+        let provable = match query_response.kind {
+            QueryResponseKind::ViewAccount(_)
+            | QueryResponseKind::ViewCode(_)
+            | QueryResponseKind::AccessKey(_) => true,
+            QueryResponseKind::ViewState(_)
+            | QueryResponseKind::CallResult(_)
+            | QueryResponseKind::AccessKeyList(_) => false,
+        };
+        if include_proof && provable && query_response.proof.is_empty() {
+            return Err(near_jsonrpc_primitives::types::query::RpcQueryError::ProofUnavailable {
+                block_height: query_response.block_height,
+                block_hash: query_response.block_hash,
+            });
+        }
+        Ok(query_response.into())
     }
 
     async fn tx_status_common(
@@ -932,6 +1050,121 @@ impl JsonRpcHandler {
         Ok(self.client_addr.send(GetNetworkInfo {}).await??.into())
     }
 
+    async fn create_store_checkpoint(
+        &self,
+        request_data: near_jsonrpc_primitives::types::store::RpcStoreCheckpointRequest,
+    ) -> Result<
+        near_jsonrpc_primitives::types::store::RpcStoreCheckpointResponse,
+        near_jsonrpc_primitives::types::store::RpcStoreCheckpointError,
+    > {
+        let store_checkpoint_addr = self.store_checkpoint_addr.as_ref().ok_or_else(|| {
+            near_jsonrpc_primitives::types::store::RpcStoreCheckpointError::InternalError {
+                error_message: "Store checkpoints are not available".to_string(),
+            }
+        })?;
+        let checkpoint =
+            store_checkpoint_addr.send(CreateStoreCheckpoint { path: request_data.path }).await??;
+        Ok(near_jsonrpc_primitives::types::store::RpcStoreCheckpointResponse { checkpoint })
+    }
+
+    async fn store_stats(
+        &self,
+    ) -> Result<
+        near_jsonrpc_primitives::types::store::RpcStoreStatsResponse,
+        near_jsonrpc_primitives::types::store::RpcStoreStatsError,
+    > {
+        let stats = self.view_client_addr.send(GetStoreStats {}).await??;
+        Ok(near_jsonrpc_primitives::types::store::RpcStoreStatsResponse { stats })
+    }
+
+    async fn send_peer_admin_request(
+        &self,
+        request: PeerAdminRequest,
+    ) -> Result<PeerAdminResponse, near_jsonrpc_primitives::types::peers::RpcPeerAdminError> {
+        let peer_manager_addr = self.peer_manager_addr.as_ref().ok_or_else(|| {
+            near_jsonrpc_primitives::types::peers::RpcPeerAdminError::InternalError {
+                error_message: "Network is not running".to_string(),
+            }
+        })?;
+        Ok(peer_manager_addr.send(request).await??)
+    }
+
+    async fn ban_peer(
+        &self,
+        request_data: near_jsonrpc_primitives::types::peers::RpcBanPeerRequest,
+    ) -> Result<
+        near_jsonrpc_primitives::types::peers::RpcPeerAdminResponse,
+        near_jsonrpc_primitives::types::peers::RpcPeerAdminError,
+    > {
+        self.send_peer_admin_request(PeerAdminRequest::Ban {
+            peer_id: request_data.peer_id,
+            ban_reason: request_data.ban_reason.unwrap_or(ReasonForBan::None),
+            comment: request_data.comment,
+        })
+        .await?;
+        Ok(near_jsonrpc_primitives::types::peers::RpcPeerAdminResponse {})
+    }
+
+    async fn unban_peer(
+        &self,
+        request_data: near_jsonrpc_primitives::types::peers::RpcUnbanPeerRequest,
+    ) -> Result<
+        near_jsonrpc_primitives::types::peers::RpcPeerAdminResponse,
+        near_jsonrpc_primitives::types::peers::RpcPeerAdminError,
+    > {
+        self.send_peer_admin_request(PeerAdminRequest::Unban { peer_id: request_data.peer_id })
+            .await?;
+        Ok(near_jsonrpc_primitives::types::peers::RpcPeerAdminResponse {})
+    }
+
+    async fn connect_peer(
+        &self,
+        request_data: near_jsonrpc_primitives::types::peers::RpcConnectPeerRequest,
+    ) -> Result<
+        near_jsonrpc_primitives::types::peers::RpcPeerAdminResponse,
+        near_jsonrpc_primitives::types::peers::RpcPeerAdminError,
+    > {
+        let peer_info = request_data.peer_info.parse::<PeerInfo>().map_err(|_| {
+            near_jsonrpc_primitives::types::peers::RpcPeerAdminError::InvalidPeerInfo {
+                peer_info: request_data.peer_info.clone(),
+            }
+        })?;
+        self.send_peer_admin_request(PeerAdminRequest::Connect { peer_info }).await?;
+        Ok(near_jsonrpc_primitives::types::peers::RpcPeerAdminResponse {})
+    }
+
+    async fn disconnect_peer(
+        &self,
+        request_data: near_jsonrpc_primitives::types::peers::RpcDisconnectPeerRequest,
+    ) -> Result<
+        near_jsonrpc_primitives::types::peers::RpcPeerAdminResponse,
+        near_jsonrpc_primitives::types::peers::RpcPeerAdminError,
+    > {
+        self.send_peer_admin_request(PeerAdminRequest::Disconnect {
+            peer_id: request_data.peer_id,
+        })
+        .await?;
+        Ok(near_jsonrpc_primitives::types::peers::RpcPeerAdminResponse {})
+    }
+
+    async fn known_peers(
+        &self,
+    ) -> Result<
+        near_jsonrpc_primitives::types::peers::RpcKnownPeersResponse,
+        near_jsonrpc_primitives::types::peers::RpcPeerAdminError,
+    > {
+        match self.send_peer_admin_request(PeerAdminRequest::ListKnownPeers).await? {
+            PeerAdminResponse::KnownPeers(known_peers) => {
+                Ok(near_jsonrpc_primitives::types::peers::RpcKnownPeersResponse { known_peers })
+            }
+            PeerAdminResponse::Done => {
+                Err(near_jsonrpc_primitives::types::peers::RpcPeerAdminError::InternalError {
+                    error_message: "Unexpected response of the network".to_string(),
+                })
+            }
+        }
+    }
+
     async fn gas_price(
         &self,
         request_data: near_jsonrpc_primitives::types::gas_price::RpcGasPriceRequest,
@@ -1145,6 +1378,17 @@ fn rpc_handler(
     response.boxed()
 }
 
+fn admin_rpc_handler(
+    message: web::Json<Message>,
+    handler: web::Data<JsonRpcHandler>,
+) -> impl Future<Output = Result<HttpResponse, HttpError>> {
+    let response = async move {
+        let message = handler.process_admin(message.0).await?;
+        Ok(HttpResponse::Ok().json(&message))
+    };
+    response.boxed()
+}
+
 fn status_handler(
     handler: web::Data<JsonRpcHandler>,
 ) -> impl Future<Output = Result<HttpResponse, HttpError>> {
@@ -1215,8 +1459,22 @@ pub fn start_http(
     genesis_config: GenesisConfig,
     client_addr: Addr<ClientActor>,
     view_client_addr: Addr<ViewClientActor>,
+    peer_manager_addr: Option<Recipient<PeerAdminRequest>>,
+    store_checkpoint_addr: Option<Recipient<CreateStoreCheckpoint>>,
 ) {
-    let RpcConfig { addr, cors_allowed_origins, polling_config, limits_config } = config;
+    let RpcConfig { addr, cors_allowed_origins, polling_config, limits_config, admin_addr } =
+        config;
+    if let Some(admin_addr) = admin_addr {
+        start_admin_http(
+            admin_addr,
+            polling_config,
+            genesis_config.clone(),
+            client_addr.clone(),
+            view_client_addr.clone(),
+            peer_manager_addr,
+            store_checkpoint_addr,
+        );
+    }
     info!(target:"network", "Starting http server at {}", addr);
     HttpServer::new(move || {
         App::new()
@@ -1226,6 +1484,8 @@ pub fn start_http(
                 view_client_addr: view_client_addr.clone(),
                 polling_config,
                 genesis_config: genesis_config.clone(),
+                peer_manager_addr: None,
+                store_checkpoint_addr: None,
             })
             .app_data(web::JsonConfig::default().limit(limits_config.json_payload_max_size))
             .wrap(middleware::Logger::default())
@@ -1249,3 +1509,44 @@ pub fn start_http(
     .shutdown_timeout(5)
     .run();
 }
+
+fn start_admin_http(
+    addr: String,
+    polling_config: RpcPollingConfig,
+    genesis_config: GenesisConfig,
+    client_addr: Addr<ClientActor>,
+    view_client_addr: Addr<ViewClientActor>,
+    peer_manager_addr: Option<Recipient<PeerAdminRequest>>,
+    store_checkpoint_addr: Option<Recipient<CreateStoreCheckpoint>>,
+) {
+    match addr.parse::<std::net::SocketAddr>() {
+        Ok(socket_addr) if socket_addr.ip().is_loopback() => {}
+        _ => {
+            error!(
+                target: "network",
+                "Not starting admin http server at {}, it must listen on a loopback address",
+                addr
+            );
+            return;
+        }
+    }
+    info!(target:"network", "Starting admin http server at {}", addr);
+    HttpServer::new(move || {
+        App::new()
+            .data(JsonRpcHandler {
+                client_addr: client_addr.clone(),
+                view_client_addr: view_client_addr.clone(),
+                polling_config,
+                genesis_config: genesis_config.clone(),
+                peer_manager_addr: peer_manager_addr.clone(),
+                store_checkpoint_addr: store_checkpoint_addr.clone(),
+            })
+            .wrap(middleware::Logger::default())
+            .service(web::resource("/").route(web::post().to(admin_rpc_handler)))
+    })
+    .bind(addr)
+    .unwrap()
+    .workers(1)
+    .shutdown_timeout(5)
+    .run();
+}
@@ -41,6 +41,8 @@ pub fn start_all_with_validity_period_and_no_epoch_sync(
         TEST_GENESIS_CONFIG.clone(),
         client_addr.clone(),
         view_client_addr.clone(),
+        None,
+        None,
     );
     (view_client_addr, addr)
 }
@@ -164,6 +164,7 @@ fn test_query_account() {
             .query(near_jsonrpc_primitives::types::query::RpcQueryRequest {
                 block_reference: BlockReference::latest(),
                 request: QueryRequest::ViewAccount { account_id: "test".to_string() },
+                include_proof: false,
             })
             .await
             .unwrap();
@@ -171,6 +172,7 @@ fn test_query_account() {
             .query(near_jsonrpc_primitives::types::query::RpcQueryRequest {
                 block_reference: BlockReference::BlockId(BlockId::Height(0)),
                 request: QueryRequest::ViewAccount { account_id: "test".to_string() },
+                include_proof: false,
             })
             .await
             .unwrap();
@@ -178,6 +180,7 @@ fn test_query_account() {
             .query(near_jsonrpc_primitives::types::query::RpcQueryRequest {
                 block_reference: BlockReference::BlockId(BlockId::Hash(block_hash)),
                 request: QueryRequest::ViewAccount { account_id: "test".to_string() },
+                include_proof: false,
             })
             .await
             .unwrap();
@@ -227,6 +230,7 @@ fn test_query_access_keys() {
             .query(near_jsonrpc_primitives::types::query::RpcQueryRequest {
                 block_reference: BlockReference::latest(),
                 request: QueryRequest::ViewAccessKeyList { account_id: "test".to_string() },
+                include_proof: false,
             })
             .await
             .unwrap();
@@ -278,6 +282,7 @@ fn test_query_access_key() {
                         .parse()
                         .unwrap(),
                 },
+                include_proof: false,
             })
             .await
             .unwrap();
@@ -303,6 +308,7 @@ fn test_query_state() {
                     account_id: "test".to_string(),
                     prefix: vec![].into(),
                 },
+                include_proof: false,
             })
             .await
             .unwrap();
@@ -328,6 +334,7 @@ fn test_query_call_function() {
                     method_name: "method".to_string(),
                     args: vec![].into(),
                 },
+                include_proof: false,
             })
             .await
             .unwrap();
@@ -353,6 +360,7 @@ fn test_query_contract_code() {
             .query(near_jsonrpc_primitives::types::query::RpcQueryRequest {
                 block_reference: BlockReference::latest(),
                 request: QueryRequest::ViewCode { account_id: "test".to_string() },
+                include_proof: false,
             })
             .await
             .unwrap();
@@ -562,6 +570,7 @@ fn test_query_view_account_non_existing_account_must_return_error() {
             .query(near_jsonrpc_primitives::types::query::RpcQueryRequest {
                 block_reference: BlockReference::latest(),
                 request: QueryRequest::ViewAccount { account_id: "invalidaccount".to_string() },
+                include_proof: false,
             })
             .await
             .unwrap();
@@ -584,6 +593,7 @@ fn test_view_access_key_non_existing_account_id_and_public_key_must_return_error
                     account_id: "\u{0}\u{0}\u{0}\u{0}\u{0}9".to_string(),
                     public_key: "99999999999999999999999999999999999999999999".parse().unwrap(),
                 },
+                include_proof: false,
             })
             .await
             .unwrap();
@@ -609,6 +619,7 @@ fn test_call_function_non_existing_account_method_name() {
                     args: vec![].into(),
                     account_id: "\u{0}\u{0}\u{0}\u{0}\u{0}\u{0}\u{0}\u{0}\u{0}\u{0}".to_string(),
                 },
+                include_proof: false,
             })
             .await
             .unwrap();
@@ -630,6 +641,7 @@ fn test_view_access_key_list_non_existing_account() {
                 request: QueryRequest::ViewAccessKeyList {
                     account_id: "\u{c}\u{c}\u{c}\u{c}\u{c}\u{c}\u{c}\u{c}\u{c}\u{c}\u{c}\u{c}\u{c}\u{c}\u{c}\u{c}\u{c}\u{c}\u{c}\u{c}\u{c}\u{0}\u{0}\u{0}\u{0}\u{0}\u{0},".to_string(),
                 },
+                include_proof: false,
             })
             .await
             .unwrap();
@@ -652,6 +664,7 @@ fn test_view_state_non_existing_account_invalid_prefix() {
                     account_id: "\u{0}\u{0}\u{0}\u{0}\u{0}\u{4}\u{0}\u{0}\u{0}\u{8}\u{0}\u{0}\u{0}\u{0}\u{0}eeeeeeeeeeeeeeeeeeeeeeeeeeeee".to_string(),
                     prefix: "eeeeeeeeeeee".as_bytes().to_vec().into(),
                 },
+                include_proof: false,
             })
             .await
             .unwrap();
@@ -0,0 +1,606 @@
+//! Capture of the messages exchanged with peers, to reproduce network bugs offline.
+//!
+//! Every message sent to or received from a peer is appended to rotating files in
+//! `CaptureConfig::dir`. Each record is a little endian `u32` length followed by a borsh encoded
+//! `CapturedMessage`. Captures are read back with `read_capture` and replayed into the actors
+//! under test with `replay_to_client` and `replay_to_peer_manager`.
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use actix::{Actor, Addr, Arbiter, Context, Handler, Message, Recipient};
+use borsh::{BorshDeserialize, BorshSerialize};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, warn};
+
+use near_primitives::hash::CryptoHash;
+use near_primitives::network::PeerId;
+use near_primitives::utils::to_timestamp;
+
+use crate::codec::{bytes_to_peer_message, NETWORK_MESSAGE_MAX_SIZE};
+use crate::peer::peer_message_to_client_message;
+use crate::peer_manager::PeerManagerActor;
+use crate::state_parts::StatePartAssembler;
+use crate::types::{
+    NetworkClientMessages, NetworkClientResponses, NetworkRequests, PeerIdOrHash, PeerMessage,
+    PeersResponse, RoutedMessageBody, RoutedMessageFrom,
+};
+
+const CAPTURE_FILE_PREFIX: &str = "capture-";
+const CAPTURE_FILE_SUFFIX: &str = ".bin";
+
+/// Number of captured messages waiting to be written, peers drop messages beyond it.
+const CAPTURE_MAILBOX_CAPACITY: usize = 10_000;
+
+/// Largest record which can be read back: a message of the maximum size with its metadata.
+const MAX_CAPTURE_RECORD_SIZE: u32 = NETWORK_MESSAGE_MAX_SIZE + 1024;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CaptureConfig {
+    /// Directory to write the capture files to.
+    pub dir: PathBuf,
+    /// Size in bytes after which a new capture file is started.
+    #[serde(default = "default_max_file_size")]
+    pub max_file_size: u64,
+    /// Number of capture files to keep, the oldest ones are removed.
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+}
+
+fn default_max_file_size() -> u64 {
+    256 * bytesize::MIB
+}
+
+fn default_max_files() -> usize {
+    8
+}
+
+impl CaptureConfig {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir, max_file_size: default_max_file_size(), max_files: default_max_files() }
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureDirection {
+    Sent,
+    Received,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Message, Clone, Debug, PartialEq, Eq)]
+#[rtype(result = "()")]
+pub struct CapturedMessage {
+    /// Time the message was sent or received at, in nanoseconds.
+    pub timestamp: u64,
+    pub direction: CaptureDirection,
+    /// Node which captured the message.
+    pub node_id: PeerId,
+    /// Other end of the connection, `None` until the handshake is done.
+    pub peer_id: Option<PeerId>,
+    /// Borsh encoded `PeerMessage`, as sent over the wire.
+    pub data: Vec<u8>,
+}
+
+impl CapturedMessage {
+    pub fn new(
+        direction: CaptureDirection,
+        node_id: PeerId,
+        peer_id: Option<PeerId>,
+        data: Vec<u8>,
+    ) -> Self {
+        Self { timestamp: to_timestamp(Utc::now()), direction, node_id, peer_id, data }
+    }
+
+    pub fn peer_message(&self) -> io::Result<PeerMessage> {
+        bytes_to_peer_message(&self.data)
+    }
+}
+
+fn capture_file_name(index: u64) -> String {
+    format!("{}{:08}{}", CAPTURE_FILE_PREFIX, index, CAPTURE_FILE_SUFFIX)
+}
+
+/// Capture files in `dir` with their indices, oldest first.
+fn capture_files(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let index = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(CAPTURE_FILE_PREFIX))
+            .and_then(|name| name.strip_suffix(CAPTURE_FILE_SUFFIX))
+            .and_then(|index| index.parse::<u64>().ok());
+        if let Some(index) = index {
+            files.push((index, path));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Appends records to the capture files, starting a new file once the current one is full.
+struct CaptureWriter {
+    config: CaptureConfig,
+    /// Index of the current file. Files of earlier runs are kept, so it starts after them.
+    index: u64,
+    file: Option<BufWriter<File>>,
+    file_size: u64,
+}
+
+impl CaptureWriter {
+    fn new(config: CaptureConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        let index = capture_files(&config.dir)?.last().map_or(0, |(index, _)| index + 1);
+        Ok(Self { config, index, file: None, file_size: 0 })
+    }
+
+    fn write(&mut self, msg: &CapturedMessage) -> io::Result<()> {
+        let data = msg.try_to_vec()?;
+        let mut record = Vec::with_capacity(4 + data.len());
+        record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        record.extend_from_slice(&data);
+
+        if self.file.is_some() && self.file_size + record.len() as u64 > self.config.max_file_size {
+            self.flush()?;
+            self.file = None;
+            self.index += 1;
+            self.file_size = 0;
+        }
+        if self.file.is_none() {
+            self.start_file()?;
+        }
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(&record)?;
+        }
+        self.file_size += record.len() as u64;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn start_file(&mut self) -> io::Result<()> {
+        let file = File::create(self.config.dir.join(capture_file_name(self.index)))?;
+        self.file = Some(BufWriter::new(file));
+        let files = capture_files(&self.config.dir)?;
+        let num_removed = files.len().saturating_sub(self.config.max_files.max(1));
+        for (_, path) in files.into_iter().take(num_removed) {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the messages captured by all peers, on its own thread.
+pub struct NetworkCapture {
+    writer: Option<CaptureWriter>,
+}
+
+impl NetworkCapture {
+    pub fn start(config: CaptureConfig) -> Addr<NetworkCapture> {
+        let arbiter = Arbiter::new();
+        NetworkCapture::start_in_arbiter(&arbiter.handle(), move |ctx| {
+            // Bounds the backlog of the writer, peers drop the messages which don't fit.
+            ctx.set_mailbox_capacity(CAPTURE_MAILBOX_CAPACITY);
+            NetworkCapture::new(config)
+        })
+    }
+
+    fn new(config: CaptureConfig) -> Self {
+        let dir = config.dir.clone();
+        let writer = match CaptureWriter::new(config) {
+            Ok(writer) => Some(writer),
+            Err(err) => {
+                error!(target: "network", "Failed to start network capture in {}: {}", dir.display(), err);
+                None
+            }
+        };
+        Self { writer }
+    }
+}
+
+impl Actor for NetworkCapture {
+    type Context = Context<Self>;
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        if let Some(writer) = self.writer.as_mut() {
+            if let Err(err) = writer.flush() {
+                error!(target: "network", "Failed to flush network capture: {}", err);
+            }
+        }
+    }
+}
+
+impl Handler<CapturedMessage> for NetworkCapture {
+    type Result = ();
+
+    fn handle(&mut self, msg: CapturedMessage, _ctx: &mut Self::Context) -> Self::Result {
+        if let Some(writer) = self.writer.as_mut() {
+            if let Err(err) = writer.write(&msg) {
+                error!(target: "network", "Failed to write network capture, stopping it: {}", err);
+                self.writer = None;
+            }
+        }
+    }
+}
+
+/// Reads the messages captured in `dir` one at a time, oldest first. A truncated record at the
+/// end of a file, left by a node which was killed while writing it, is skipped.
+pub struct CaptureReader {
+    files: std::vec::IntoIter<(u64, PathBuf)>,
+    current: Option<(PathBuf, BufReader<File>)>,
+}
+
+impl CaptureReader {
+    /// Reads the next record of the current file, `None` at its end.
+    fn read_record(reader: &mut BufReader<File>) -> io::Result<Option<Vec<u8>>> {
+        if reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        let mut len = [0u8; 4];
+        reader.read_exact(&mut len)?;
+        let len = u32::from_le_bytes(len);
+        if len > MAX_CAPTURE_RECORD_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Capture record of {} bytes is too large", len),
+            ));
+        }
+        let mut data = vec![0u8; len as usize];
+        reader.read_exact(&mut data)?;
+        Ok(Some(data))
+    }
+}
+
+impl Iterator for CaptureReader {
+    type Item = io::Result<CapturedMessage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.current.is_none() {
+                let (_, path) = self.files.next()?;
+                match File::open(&path) {
+                    Ok(file) => self.current = Some((path, BufReader::new(file))),
+                    Err(err) => return Some(Err(err)),
+                }
+            }
+            let (path, reader) = self.current.as_mut()?;
+            match Self::read_record(reader) {
+                Ok(Some(data)) => return Some(CapturedMessage::try_from_slice(&data)),
+                Ok(None) => {}
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                    warn!(target: "network", "Skipping truncated record at the end of {}", path.display());
+                }
+                Err(err) => return Some(Err(err)),
+            }
+            self.current = None;
+        }
+    }
+}
+
+/// Starts reading the messages captured in `dir`.
+pub fn read_capture(dir: &Path) -> io::Result<CaptureReader> {
+    Ok(CaptureReader { files: capture_files(dir)?.into_iter(), current: None })
+}
+
+/// How fast captured messages are replayed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplaySpeed {
+    /// Every message is delivered as soon as the previous one is handled.
+    Unlimited,
+    /// Messages are delivered with the same intervals as they were captured.
+    Original,
+}
+
+struct ReplayClock {
+    speed: ReplaySpeed,
+    /// Time the replay started and timestamp of the first replayed message.
+    start: Option<(Instant, u64)>,
+}
+
+impl ReplayClock {
+    fn new(speed: ReplaySpeed) -> Self {
+        Self { speed, start: None }
+    }
+
+    async fn wait(&mut self, timestamp: u64) {
+        if self.speed == ReplaySpeed::Unlimited {
+            return;
+        }
+        let (start, first_timestamp) = *self.start.get_or_insert((Instant::now(), timestamp));
+        let offset = Duration::from_nanos(timestamp.saturating_sub(first_timestamp));
+        if let Some(delay) = offset.checked_sub(start.elapsed()) {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// Received message with its sender, skipping messages before the handshake and ones which
+/// can't be decoded.
+fn received_peer_message(message: &CapturedMessage) -> Option<(PeerId, PeerMessage)> {
+    let peer_id = message.peer_id.clone()?;
+    match message.peer_message() {
+        Ok(peer_message) => Some((peer_id, peer_message)),
+        Err(err) => {
+            debug!(target: "network", "Skipping captured message from {}: {}", peer_id, err);
+            None
+        }
+    }
+}
+
+/// Wraps a message received from `peer_id` into what the client expects, the same way as `Peer`
+/// does.
+fn to_client_message(
+    peer_message: PeerMessage,
+    node_id: &PeerId,
+    peer_id: PeerId,
+    requested_blocks: &HashSet<(PeerId, CryptoHash)>,
+    state_part_assembler: &mut StatePartAssembler,
+) -> Option<NetworkClientMessages> {
+    if !peer_message.is_client_message() {
+        return None;
+    }
+    // Messages routed through the capturing node weren't delivered to its client.
+    if let PeerMessage::Routed(routed_message) = &peer_message {
+        if let PeerIdOrHash::PeerId(target) = &routed_message.target {
+            if target != node_id {
+                return None;
+            }
+        }
+    }
+    let sender = peer_id.clone();
+    peer_message_to_client_message(
+        peer_message,
+        peer_id,
+        |block_hash| requested_blocks.contains(&(sender, *block_hash)),
+        state_part_assembler,
+    )
+}
+
+/// Replays the messages received from peers into the client. Routed messages are only replayed
+/// if they were addressed to the capturing node, by its id or as responses routed back to it.
+/// Returns the number of delivered messages.
+pub async fn replay_to_client(
+    messages: impl IntoIterator<Item = io::Result<CapturedMessage>>,
+    client_addr: Recipient<NetworkClientMessages>,
+    speed: ReplaySpeed,
+) -> usize {
+    let mut clock = ReplayClock::new(speed);
+    let mut requested_blocks = HashSet::new();
+    let mut state_part_assemblers: HashMap<PeerId, StatePartAssembler> = HashMap::new();
+    let mut delivered = 0;
+    for message in messages {
+        let message = match message {
+            Ok(message) => message,
+            Err(err) => {
+                error!(target: "network", "Stopping replay, failed to read the capture: {}", err);
+                break;
+            }
+        };
+        let (peer_id, peer_message) = match received_peer_message(&message) {
+            Some(received) => received,
+            None => continue,
+        };
+        let state_part_assembler = state_part_assemblers.entry(peer_id.clone()).or_default();
+        if message.direction == CaptureDirection::Sent {
+            // Needed to tell the client whether a block was requested by it, and to accept
+            // the pieces of the state parts requested from the peer.
+            match peer_message {
+                PeerMessage::BlockRequest(block_hash) => {
+                    requested_blocks.insert((peer_id, block_hash));
+                }
+                PeerMessage::Routed(routed_message) => {
+                    if let RoutedMessageBody::StateRequestPart(shard_id, sync_hash, part_id) =
+                        routed_message.body
+                    {
+                        state_part_assembler.expect(shard_id, sync_hash, part_id);
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+        let client_message = match to_client_message(
+            peer_message,
+            &message.node_id,
+            peer_id.clone(),
+            &requested_blocks,
+            state_part_assembler,
+        ) {
+            Some(client_message) => client_message,
+            None => continue,
+        };
+        clock.wait(message.timestamp).await;
+        match client_addr.send(client_message).await {
+            Ok(NetworkClientResponses::Ban { ban_reason }) => {
+                debug!(target: "network", "Client asked to ban {} for {:?} during replay", peer_id, ban_reason);
+            }
+            Ok(_) => {}
+            Err(err) => {
+                error!(target: "network", "Stopping replay, client is unavailable: {}", err);
+                break;
+            }
+        }
+        delivered += 1;
+    }
+    delivered
+}
+
+/// Replays the messages received from peers which are handled by the peer manager itself:
+/// routed messages, routing table syncs, nonce updates and peer lists. Returns the number of
+/// delivered messages.
+pub async fn replay_to_peer_manager(
+    messages: impl IntoIterator<Item = io::Result<CapturedMessage>>,
+    peer_manager_addr: Addr<PeerManagerActor>,
+    speed: ReplaySpeed,
+) -> usize {
+    let mut clock = ReplayClock::new(speed);
+    let mut delivered = 0;
+    for message in messages {
+        let message = match message {
+            Ok(message) => message,
+            Err(err) => {
+                error!(target: "network", "Stopping replay, failed to read the capture: {}", err);
+                break;
+            }
+        };
+        if message.direction != CaptureDirection::Received {
+            continue;
+        }
+        let (peer_id, peer_message) = match received_peer_message(&message) {
+            Some(received) => received,
+            None => continue,
+        };
+        let result = match peer_message {
+            PeerMessage::Routed(msg) if msg.verify() => {
+                clock.wait(message.timestamp).await;
+                peer_manager_addr.send(RoutedMessageFrom { msg, from: peer_id }).await.map(drop)
+            }
+            PeerMessage::RoutingTableSync(sync_data) => {
+                clock.wait(message.timestamp).await;
+                peer_manager_addr.send(NetworkRequests::Sync { peer_id, sync_data }).await.map(drop)
+            }
+            PeerMessage::ResponseUpdateNonce(edge) => {
+                clock.wait(message.timestamp).await;
+                peer_manager_addr.send(NetworkRequests::ResponseUpdateNonce(edge)).await.map(drop)
+            }
+            PeerMessage::PeersResponse(peers) => {
+                clock.wait(message.timestamp).await;
+                peer_manager_addr.send(PeersResponse { peers }).await
+            }
+            _ => continue,
+        };
+        if let Err(err) = result {
+            error!(target: "network", "Stopping replay, peer manager is unavailable: {}", err);
+            break;
+        }
+        delivered += 1;
+    }
+    delivered
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+
+    use actix::{Actor, Context, Handler};
+    use tempfile::tempdir;
+
+    use near_crypto::{KeyType, SecretKey};
+    use near_primitives::hash::hash;
+    use near_primitives::network::PeerId;
+
+    use crate::capture::{
+        capture_file_name, capture_files, read_capture, replay_to_client, CaptureConfig,
+        CaptureDirection, CaptureWriter, CapturedMessage, ReplaySpeed,
+    };
+    use crate::codec::peer_message_to_bytes;
+    use crate::types::{NetworkClientMessages, NetworkClientResponses, PeerMessage};
+
+    fn random_peer_id() -> PeerId {
+        SecretKey::from_random(KeyType::ED25519).public_key().into()
+    }
+
+    fn read_all(dir: &Path) -> Vec<CapturedMessage> {
+        read_capture(dir).unwrap().collect::<io::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn test_capture_rotation() {
+        let dir = tempdir().unwrap();
+        let mut config = CaptureConfig::new(dir.path().to_path_buf());
+        config.max_file_size = 256;
+        config.max_files = 3;
+        let node_id = random_peer_id();
+        let messages: Vec<_> = (0..20u8)
+            .map(|i| {
+                CapturedMessage::new(CaptureDirection::Sent, node_id.clone(), None, vec![i; 64])
+            })
+            .collect();
+        let mut writer = CaptureWriter::new(config.clone()).unwrap();
+        for message in messages.iter() {
+            writer.write(message).unwrap();
+        }
+        drop(writer);
+        assert_eq!(capture_files(dir.path()).unwrap().len(), 3);
+        let captured = read_all(dir.path());
+        assert!(!captured.is_empty());
+        assert_eq!(&captured[..], &messages[messages.len() - captured.len()..]);
+
+        // A restarted node continues after the existing files.
+        let mut writer = CaptureWriter::new(config).unwrap();
+        writer.write(&messages[0]).unwrap();
+        drop(writer);
+        assert_eq!(read_all(dir.path()).last(), Some(&messages[0]));
+
+        // A truncated record is skipped.
+        let num_captured = read_all(dir.path()).len();
+        let (_, last_file) = capture_files(dir.path()).unwrap().pop().unwrap();
+        let data = fs::read(&last_file).unwrap();
+        fs::write(&last_file, &data[..data.len() - 1]).unwrap();
+        assert_eq!(read_all(dir.path()).len(), num_captured - 1);
+    }
+
+    #[test]
+    fn test_oversized_record_is_rejected() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join(capture_file_name(0)), u32::MAX.to_le_bytes()).unwrap();
+        let err = read_capture(dir.path()).unwrap().next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    struct ClientMock {}
+
+    impl Actor for ClientMock {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<NetworkClientMessages> for ClientMock {
+        type Result = NetworkClientResponses;
+
+        fn handle(&mut self, msg: NetworkClientMessages, _ctx: &mut Self::Context) -> Self::Result {
+            assert!(matches!(msg, NetworkClientMessages::BlockHeaders(..)));
+            NetworkClientResponses::NoResponse
+        }
+    }
+
+    #[test]
+    fn test_replay_to_client() {
+        let node_id = random_peer_id();
+        let peer_id = random_peer_id();
+        let capture = |direction, msg: PeerMessage| {
+            CapturedMessage::new(
+                direction,
+                node_id.clone(),
+                Some(peer_id.clone()),
+                peer_message_to_bytes(&msg).unwrap(),
+            )
+        };
+        let messages = vec![
+            capture(CaptureDirection::Sent, PeerMessage::BlockHeadersRequest(vec![hash(&[1])])),
+            capture(CaptureDirection::Received, PeerMessage::BlockHeaders(vec![])),
+            capture(CaptureDirection::Received, PeerMessage::PeersRequest),
+            CapturedMessage::new(CaptureDirection::Received, node_id.clone(), None, vec![1, 2]),
+        ];
+        let delivered = actix::System::new().block_on(async move {
+            let client = ClientMock {}.start();
+            replay_to_client(
+                messages.into_iter().map(Ok),
+                client.recipient(),
+                ReplaySpeed::Original,
+            )
+            .await
+        });
+        assert_eq!(delivered, 1);
+    }
+}
@@ -7,23 +7,30 @@ use tokio_util::codec::{Decoder, Encoder};
 use tracing::error;
 
 use crate::metrics;
+use crate::secure_transport::{FrameCipher, ENCRYPTION_OVERHEAD};
 use crate::types::{PeerMessage, ReasonForBan};
 use near_performance_metrics::framed_write::EncoderCallBack;
 #[cfg(feature = "performance_stats")]
 use near_performance_metrics::stats_enabled::get_thread_stats_logger;
 use near_rust_allocator_proxy::allocator::get_tid;
 
-const NETWORK_MESSAGE_MAX_SIZE: u32 = 512 * MIB as u32;
+pub(crate) const NETWORK_MESSAGE_MAX_SIZE: u32 = 512 * MIB as u32;
 const MAX_CAPACITY: u64 = GIB;
 
 pub struct Codec {
     max_length: u32,
+    /// Encrypts or decrypts the frames of a secure transport.
+    cipher: Option<FrameCipher>,
 }
 
 #[allow(clippy::new_without_default)]
 impl Codec {
     pub fn new() -> Self {
-        Codec { max_length: NETWORK_MESSAGE_MAX_SIZE as u32 }
+        Codec { max_length: NETWORK_MESSAGE_MAX_SIZE as u32, cipher: None }
+    }
+
+    pub fn new_encrypted(cipher: FrameCipher) -> Self {
+        Codec { max_length: NETWORK_MESSAGE_MAX_SIZE as u32, cipher: Some(cipher) }
     }
 }
 
@@ -45,6 +52,10 @@ impl Encoder<Vec<u8>> for Codec {
         if item.len() > self.max_length as usize {
             Err(Error::new(ErrorKind::InvalidInput, "Input is too long"))
         } else {
+            let item = match self.cipher.as_mut() {
+                Some(cipher) => cipher.encrypt(&item)?,
+                None => item,
+            };
             #[cfg(feature = "performance_stats")]
             {
                 let stat = get_thread_stats_logger();
@@ -85,7 +96,8 @@ impl Decoder for Codec {
         len_bytes.copy_from_slice(&buf[0..4]);
         let len = u32::from_le_bytes(len_bytes);
 
-        if len > self.max_length {
+        let overhead = if self.cipher.is_some() { ENCRYPTION_OVERHEAD as u32 } else { 0 };
+        if len > self.max_length + overhead {
             // If this point is reached, abusive peer is banned.
             return Ok(Some(Err(ReasonForBan::Abusive)));
         }
@@ -94,9 +106,15 @@ impl Decoder for Codec {
             // not enough bytes, keep waiting
             Ok(None)
         } else {
-            let res = Some(Ok(buf[4..4 + len as usize].to_vec()));
+            let frame = &buf[4..4 + len as usize];
+            // A frame which fails to decrypt closes the connection, but isn't a reason to ban
+            // the peer, as it could have been tampered with on the way.
+            let res = match self.cipher.as_mut() {
+                Some(cipher) => cipher.decrypt(frame)?,
+                None => frame.to_vec(),
+            };
             buf.advance(4 + len as usize);
-            Ok(res)
+            Ok(Some(Ok(res)))
         }
     }
 }
@@ -9,15 +9,19 @@ pub use types::{
 };
 
 mod cache;
+pub mod capture;
 mod codec;
 pub mod metrics;
 mod peer;
 mod peer_manager;
 pub mod peer_store;
 mod rate_counter;
+pub mod rate_limit;
 #[cfg(feature = "metric_recorder")]
 pub mod recorder;
 pub mod routing;
+mod secure_transport;
+mod state_parts;
 pub mod types;
 pub mod utils;
 
@@ -72,6 +72,11 @@ lazy_static! {
             "near_dropped_messages_count",
             "Total count of messages which were dropped, because write buffer was full"
         );
+    pub static ref CAPTURE_DROPPED_MESSAGES_COUNT: near_metrics::Result<IntCounter> =
+        near_metrics::try_create_int_counter(
+            "near_capture_dropped_messages_count",
+            "Total count of messages which weren't captured, because the capture writer was behind"
+        );
 }
 
 #[derive(Clone)]
@@ -106,6 +111,12 @@ impl NetworkMetrics {
                 counter_name.clone(),
                 try_create_int_counter(counter_name.as_ref(), counter_name.as_ref()).ok(),
             );
+
+            let counter_name = NetworkMetrics::peer_message_rate_limited(name.as_ref());
+            peer_messages.insert(
+                counter_name.clone(),
+                try_create_int_counter(counter_name.as_ref(), counter_name.as_ref()).ok(),
+            );
         }
 
         Self { peer_messages }
@@ -123,6 +134,10 @@ impl NetworkMetrics {
         format!("near_{}_dropped", message_name.to_lowercase())
     }
 
+    pub fn peer_message_rate_limited(message_name: &str) -> String {
+        format!("near_{}_rate_limited", message_name.to_lowercase())
+    }
+
     pub fn inc(&self, message_name: &str) {
         if let Some(counter) = self.peer_messages.get(message_name) {
             inc_counter_opt(counter.as_ref());
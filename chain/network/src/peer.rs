@@ -17,28 +17,33 @@ use tracing::{debug, error, info, trace, warn};
 use near_metrics;
 use near_performance_metrics;
 use near_primitives::block::GenesisId;
+use near_primitives::checked_feature;
 use near_primitives::hash::CryptoHash;
 use near_primitives::logging;
 use near_primitives::network::PeerId;
+use near_primitives::syncing::{ShardStateSyncResponse, ShardStateSyncResponseV2};
 use near_primitives::unwrap_option_or_return;
 use near_primitives::utils::DisplayOption;
 use near_primitives::version::{
     ProtocolVersion, OLDEST_BACKWARD_COMPATIBLE_PROTOCOL_VERSION, PROTOCOL_VERSION,
 };
 
+use crate::capture::{CaptureDirection, CapturedMessage, NetworkCapture};
 use crate::codec::{self, bytes_to_peer_message, peer_message_to_bytes, Codec};
 use crate::rate_counter::RateCounter;
+use crate::rate_limit::{PeerRateLimiter, RateLimitDecision};
 #[cfg(feature = "metric_recorder")]
 use crate::recorder::{PeerMessageMetadata, Status};
 use crate::routing::{Edge, EdgeInfo};
+use crate::state_parts::{split_state_part, state_part_compression, StatePartAssembler};
 use crate::types::{
     Ban, Consolidate, ConsolidateResponse, Handshake, HandshakeFailureReason, HandshakeV2,
     NetworkClientMessages, NetworkClientResponses, NetworkRequests, NetworkViewClientMessages,
-    NetworkViewClientResponses, PeerChainInfo, PeerChainInfoV2, PeerInfo, PeerManagerRequest,
-    PeerMessage, PeerRequest, PeerResponse, PeerStatsResult, PeerStatus, PeerType, PeersRequest,
-    PeersResponse, QueryPeerStats, ReasonForBan, RoutedMessage, RoutedMessageBody,
-    RoutedMessageFrom, SendMessage, StateResponseInfo, Unregister,
-    UPDATE_INTERVAL_LAST_TIME_RECEIVED_MESSAGE,
+    NetworkViewClientResponses, PeerChainInfo, PeerChainInfoV2, PeerIdOrHash, PeerInfo,
+    PeerManagerRequest, PeerMessage, PeerRequest, PeerResponse, PeerStatsResult, PeerStatus,
+    PeerType, PeersRequest, PeersResponse, QueryPeerStats, ReasonForBan, RoutedMessage,
+    RoutedMessageBody, RoutedMessageFrom, SendMessage, StatePartChunk, StateResponseInfo,
+    StateResponseInfoV2, Unregister, UPDATE_INTERVAL_LAST_TIME_RECEIVED_MESSAGE,
 };
 use crate::PeerManagerActor;
 use crate::{metrics, NetworkResponses};
@@ -152,6 +157,125 @@ impl Tracker {
     }
 }
 
+/// Wraps a message received from `peer_id` into what the client expects. Routed messages must be
+/// addressed to us. Returns `None` if the client doesn't handle the message, or if it's a piece of
+/// a state part which isn't complete yet.
+pub(crate) fn peer_message_to_client_message(
+    msg: PeerMessage,
+    peer_id: PeerId,
+    is_requested_block: impl FnOnce(&CryptoHash) -> bool,
+    state_part_assembler: &mut StatePartAssembler,
+) -> Option<NetworkClientMessages> {
+    let network_client_msg = match msg {
+        PeerMessage::Block(block) => {
+            let was_requested = is_requested_block(block.hash());
+            NetworkClientMessages::Block(block, peer_id, was_requested)
+        }
+        PeerMessage::Transaction(transaction) => NetworkClientMessages::Transaction {
+            transaction,
+            is_forwarded: false,
+            check_only: false,
+        },
+        PeerMessage::BlockHeaders(headers) => NetworkClientMessages::BlockHeaders(headers, peer_id),
+        PeerMessage::Routed(routed_message) => {
+            let msg_hash = routed_message.hash();
+
+            match routed_message.body {
+                RoutedMessageBody::BlockApproval(approval) => {
+                    NetworkClientMessages::BlockApproval(approval, peer_id)
+                }
+                RoutedMessageBody::ForwardTx(transaction) => NetworkClientMessages::Transaction {
+                    transaction,
+                    is_forwarded: true,
+                    check_only: false,
+                },
+
+                RoutedMessageBody::StateResponse(info) => {
+                    NetworkClientMessages::StateResponse(StateResponseInfo::V1(info))
+                }
+                RoutedMessageBody::VersionedStateResponse(info) => {
+                    NetworkClientMessages::StateResponse(info)
+                }
+                RoutedMessageBody::PartialEncodedChunkRequest(request) => {
+                    NetworkClientMessages::PartialEncodedChunkRequest(request, msg_hash)
+                }
+                RoutedMessageBody::PartialEncodedChunkResponse(response) => {
+                    NetworkClientMessages::PartialEncodedChunkResponse(response)
+                }
+                RoutedMessageBody::PartialEncodedChunk(partial_encoded_chunk) => {
+                    NetworkClientMessages::PartialEncodedChunk(PartialEncodedChunk::V1(
+                        partial_encoded_chunk,
+                    ))
+                }
+                RoutedMessageBody::VersionedPartialEncodedChunk(chunk) => {
+                    NetworkClientMessages::PartialEncodedChunk(chunk)
+                }
+                RoutedMessageBody::PartialEncodedChunkForward(forward) => {
+                    NetworkClientMessages::PartialEncodedChunkForward(forward)
+                }
+                RoutedMessageBody::Ping(_)
+                | RoutedMessageBody::Pong(_)
+                | RoutedMessageBody::TxStatusRequest(_, _)
+                | RoutedMessageBody::TxStatusResponse(_)
+                | RoutedMessageBody::QueryRequest { .. }
+                | RoutedMessageBody::QueryResponse { .. }
+                | RoutedMessageBody::ReceiptOutcomeRequest(_)
+                | RoutedMessageBody::StateRequestHeader(_, _)
+                | RoutedMessageBody::StateRequestPart(_, _, _)
+                | RoutedMessageBody::Unused => {
+                    error!(target: "network", "Peer receive_client_message received unexpected type: {:?}", routed_message);
+                    return None;
+                }
+            }
+        }
+        PeerMessage::Challenge(challenge) => NetworkClientMessages::Challenge(challenge),
+        PeerMessage::EpochSyncResponse(response) => {
+            NetworkClientMessages::EpochSyncResponse(peer_id, response)
+        }
+        PeerMessage::EpochSyncFinalizationResponse(response) => {
+            NetworkClientMessages::EpochSyncFinalizationResponse(peer_id, response)
+        }
+        PeerMessage::StatePartChunk(chunk) => {
+            let (shard_id, sync_hash, part_id) = (chunk.shard_id, chunk.sync_hash, chunk.part_id);
+            match state_part_assembler.add(chunk) {
+                Ok(Some(part)) => NetworkClientMessages::StateResponse(StateResponseInfo::V2(
+                    StateResponseInfoV2 {
+                        shard_id,
+                        sync_hash,
+                        state_response: ShardStateSyncResponse::V2(ShardStateSyncResponseV2 {
+                            header: None,
+                            part: Some((part_id, part)),
+                        }),
+                    },
+                )),
+                Ok(None) => return None,
+                Err(err) => {
+                    warn!(target: "network", "Received invalid state part from {}: {}", peer_id, err);
+                    return None;
+                }
+            }
+        }
+        PeerMessage::Handshake(_)
+        | PeerMessage::HandshakeV2(_)
+        | PeerMessage::HandshakeFailure(_, _)
+        | PeerMessage::PeersRequest
+        | PeerMessage::PeersResponse(_)
+        | PeerMessage::RoutingTableSync(_)
+        | PeerMessage::LastEdge(_)
+        | PeerMessage::Disconnect
+        | PeerMessage::RequestUpdateNonce(_)
+        | PeerMessage::ResponseUpdateNonce(_)
+        | PeerMessage::BlockRequest(_)
+        | PeerMessage::BlockHeadersRequest(_)
+        | PeerMessage::EpochSyncRequest(_)
+        | PeerMessage::EpochSyncFinalizationRequest(_) => {
+            error!(target: "network", "Peer receive_client_message received unexpected type: {:?}", msg);
+            return None;
+        }
+    };
+    Some(network_client_msg)
+}
+
 pub struct Peer {
     /// This node's id and address (either listening or socket address).
     pub node_info: PeerInfo,
@@ -193,6 +317,18 @@ pub struct Peer {
     peer_counter: Arc<AtomicUsize>,
     /// The last time a Epoch Sync request was received from this peer
     last_time_received_epoch_sync_request: Instant,
+    /// Whether to compress state parts sent to this peer if it supports it.
+    state_part_compression: bool,
+    /// Maximum size of a single message carrying a state part.
+    state_part_chunk_size: usize,
+    /// Pieces of the state parts requested from the peer.
+    state_part_assembler: StatePartAssembler,
+    /// Peer id authenticated by the secure transport, `None` for plaintext connections.
+    transport_peer_id: Option<PeerId>,
+    /// Writer of the messages exchanged with peers, if capturing is enabled.
+    capture_addr: Option<Addr<NetworkCapture>>,
+    /// Limits of the messages received from the peer.
+    rate_limiter: PeerRateLimiter,
 }
 
 impl Peer {
@@ -210,6 +346,11 @@ impl Peer {
         network_metrics: NetworkMetrics,
         txns_since_last_block: Arc<AtomicUsize>,
         peer_counter: Arc<AtomicUsize>,
+        state_part_compression: bool,
+        state_part_chunk_size: usize,
+        transport_peer_id: Option<PeerId>,
+        capture_addr: Option<Addr<NetworkCapture>>,
+        rate_limiter: PeerRateLimiter,
     ) -> Self {
         Peer {
             node_info,
@@ -233,6 +374,12 @@ impl Peer {
             peer_counter,
             last_time_received_epoch_sync_request: Instant::now()
                 - Duration::from_millis(EPOCH_SYNC_PEER_TIMEOUT_MS),
+            state_part_compression,
+            state_part_chunk_size,
+            state_part_assembler: Default::default(),
+            transport_peer_id,
+            capture_addr,
+            rate_limiter,
         }
     }
 
@@ -251,6 +398,18 @@ impl Peer {
         match msg {
             PeerMessage::Block(b) if self.tracker.has_received(b.hash()) => return,
             PeerMessage::BlockRequest(h) => self.tracker.push_request(*h),
+            PeerMessage::Routed(routed) => {
+                // The peer may send the parts requested from it directly, in pieces.
+                if let (
+                    RoutedMessageBody::StateRequestPart(shard_id, sync_hash, part_id),
+                    PeerIdOrHash::PeerId(target),
+                ) = (&routed.body, &routed.target)
+                {
+                    if self.peer_id().as_ref() == Some(target) {
+                        self.state_part_assembler.expect(*shard_id, *sync_hash, *part_id);
+                    }
+                }
+            }
             _ => (),
         };
         #[cfg(feature = "metric_recorder")]
@@ -267,6 +426,7 @@ impl Peer {
             Ok(bytes) => {
                 #[cfg(feature = "metric_recorder")]
                 self.peer_manager_addr.do_send(metadata.set_size(bytes.len()));
+                self.capture(CaptureDirection::Sent, &bytes);
                 self.tracker.increment_sent(bytes.len() as u64);
                 let bytes_len = bytes.len();
                 if !self.framed.write(bytes) {
@@ -359,6 +519,24 @@ impl Peer {
         ctx.stop();
     }
 
+    fn capture(&self, direction: CaptureDirection, data: &[u8]) {
+        if let Some(capture_addr) = &self.capture_addr {
+            // The capture must never slow down the peer, messages are dropped if the writer is
+            // behind.
+            if capture_addr
+                .try_send(CapturedMessage::new(
+                    direction,
+                    self.node_id(),
+                    self.peer_id(),
+                    data.to_vec(),
+                ))
+                .is_err()
+            {
+                near_metrics::inc_counter(&metrics::CAPTURE_DROPPED_MESSAGES_COUNT);
+            }
+        }
+    }
+
     fn node_id(&self) -> PeerId {
         self.node_info.id.clone()
     }
@@ -379,9 +557,11 @@ impl Peer {
 
     fn receive_view_client_message(&mut self, ctx: &mut Context<Peer>, msg: PeerMessage) {
         let mut msg_hash = None;
+        let mut msg_author = None;
         let view_client_message = match msg {
             PeerMessage::Routed(message) => {
                 msg_hash = Some(message.hash());
+                msg_author = Some(message.author.clone());
                 match message.body {
                     RoutedMessageBody::TxStatusRequest(account_id, tx_hash) => {
                         NetworkViewClientMessages::TxStatus {
@@ -442,16 +622,28 @@ impl Peer {
                             .do_send(PeerRequest::RouteBack(body, msg_hash.unwrap()));
                     }
                     Ok(NetworkViewClientResponses::StateResponse(state_response)) => {
-                        let body = match *state_response {
-                            StateResponseInfo::V1(state_response) => {
-                                RoutedMessageBody::StateResponse(state_response)
-                            }
-                            state_response @ StateResponseInfo::V2(_) => {
-                                RoutedMessageBody::VersionedStateResponse(state_response)
-                            }
+                        // Parts requested by this peer itself can be sent directly
+                        let chunks = if msg_author.is_some() && msg_author == act.peer_id() {
+                            act.split_state_part(&state_response)
+                        } else {
+                            None
                         };
-                        act.peer_manager_addr
-                            .do_send(PeerRequest::RouteBack(Box::new(body), msg_hash.unwrap()));
+                        if let Some(chunks) = chunks {
+                            for chunk in chunks {
+                                act.send_message(&PeerMessage::StatePartChunk(chunk));
+                            }
+                        } else {
+                            let body = match *state_response {
+                                StateResponseInfo::V1(state_response) => {
+                                    RoutedMessageBody::StateResponse(state_response)
+                                }
+                                state_response @ StateResponseInfo::V2(_) => {
+                                    RoutedMessageBody::VersionedStateResponse(state_response)
+                                }
+                            };
+                            act.peer_manager_addr
+                                .do_send(PeerRequest::RouteBack(Box::new(body), msg_hash.unwrap()));
+                        }
                     }
                     Ok(NetworkViewClientResponses::Block(block)) => {
                         // MOO need protocol version
@@ -486,105 +678,22 @@ impl Peer {
         near_metrics::inc_counter(&metrics::PEER_CLIENT_MESSAGE_RECEIVED_TOTAL);
         let peer_id = unwrap_option_or_return!(self.peer_id());
 
-        // Wrap peer message into what client expects.
-        let network_client_msg = match msg {
-            PeerMessage::Block(block) => {
-                near_metrics::inc_counter(&metrics::PEER_BLOCK_RECEIVED_TOTAL);
-                let block_hash = *block.hash();
-                self.tracker.push_received(block_hash);
-                self.chain_info.height = max(self.chain_info.height, block.header().height());
-                NetworkClientMessages::Block(block, peer_id, self.tracker.has_request(&block_hash))
-            }
-            PeerMessage::Transaction(transaction) => {
-                near_metrics::inc_counter(&metrics::PEER_TRANSACTION_RECEIVED_TOTAL);
-                NetworkClientMessages::Transaction {
-                    transaction,
-                    is_forwarded: false,
-                    check_only: false,
-                }
-            }
-            PeerMessage::BlockHeaders(headers) => {
-                NetworkClientMessages::BlockHeaders(headers, peer_id)
-            }
-            // All Routed messages received at this point are for us.
-            PeerMessage::Routed(routed_message) => {
-                let msg_hash = routed_message.hash();
-
-                match routed_message.body {
-                    RoutedMessageBody::BlockApproval(approval) => {
-                        NetworkClientMessages::BlockApproval(approval, peer_id)
-                    }
-                    RoutedMessageBody::ForwardTx(transaction) => {
-                        NetworkClientMessages::Transaction {
-                            transaction,
-                            is_forwarded: true,
-                            check_only: false,
-                        }
-                    }
+        if let PeerMessage::Block(block) = &msg {
+            near_metrics::inc_counter(&metrics::PEER_BLOCK_RECEIVED_TOTAL);
+            self.tracker.push_received(*block.hash());
+            self.chain_info.height = max(self.chain_info.height, block.header().height());
+        }
+        if let PeerMessage::Transaction(_) = &msg {
+            near_metrics::inc_counter(&metrics::PEER_TRANSACTION_RECEIVED_TOTAL);
+        }
 
-                    RoutedMessageBody::StateResponse(info) => {
-                        NetworkClientMessages::StateResponse(StateResponseInfo::V1(info))
-                    }
-                    RoutedMessageBody::VersionedStateResponse(info) => {
-                        NetworkClientMessages::StateResponse(info)
-                    }
-                    RoutedMessageBody::PartialEncodedChunkRequest(request) => {
-                        NetworkClientMessages::PartialEncodedChunkRequest(request, msg_hash)
-                    }
-                    RoutedMessageBody::PartialEncodedChunkResponse(response) => {
-                        NetworkClientMessages::PartialEncodedChunkResponse(response)
-                    }
-                    RoutedMessageBody::PartialEncodedChunk(partial_encoded_chunk) => {
-                        NetworkClientMessages::PartialEncodedChunk(PartialEncodedChunk::V1(
-                            partial_encoded_chunk,
-                        ))
-                    }
-                    RoutedMessageBody::VersionedPartialEncodedChunk(chunk) => {
-                        NetworkClientMessages::PartialEncodedChunk(chunk)
-                    }
-                    RoutedMessageBody::PartialEncodedChunkForward(forward) => {
-                        NetworkClientMessages::PartialEncodedChunkForward(forward)
-                    }
-                    RoutedMessageBody::Ping(_)
-                    | RoutedMessageBody::Pong(_)
-                    | RoutedMessageBody::TxStatusRequest(_, _)
-                    | RoutedMessageBody::TxStatusResponse(_)
-                    | RoutedMessageBody::QueryRequest { .. }
-                    | RoutedMessageBody::QueryResponse { .. }
-                    | RoutedMessageBody::ReceiptOutcomeRequest(_)
-                    | RoutedMessageBody::StateRequestHeader(_, _)
-                    | RoutedMessageBody::StateRequestPart(_, _, _)
-                    | RoutedMessageBody::Unused => {
-                        error!(target: "network", "Peer receive_client_message received unexpected type: {:?}", routed_message);
-                        return;
-                    }
-                }
-            }
-            PeerMessage::Challenge(challenge) => NetworkClientMessages::Challenge(challenge),
-            PeerMessage::EpochSyncResponse(response) => {
-                NetworkClientMessages::EpochSyncResponse(peer_id, response)
-            }
-            PeerMessage::EpochSyncFinalizationResponse(response) => {
-                NetworkClientMessages::EpochSyncFinalizationResponse(peer_id, response)
-            }
-            PeerMessage::Handshake(_)
-            | PeerMessage::HandshakeV2(_)
-            | PeerMessage::HandshakeFailure(_, _)
-            | PeerMessage::PeersRequest
-            | PeerMessage::PeersResponse(_)
-            | PeerMessage::RoutingTableSync(_)
-            | PeerMessage::LastEdge(_)
-            | PeerMessage::Disconnect
-            | PeerMessage::RequestUpdateNonce(_)
-            | PeerMessage::ResponseUpdateNonce(_)
-            | PeerMessage::BlockRequest(_)
-            | PeerMessage::BlockHeadersRequest(_)
-            | PeerMessage::EpochSyncRequest(_)
-            | PeerMessage::EpochSyncFinalizationRequest(_) => {
-                error!(target: "network", "Peer receive_client_message received unexpected type: {:?}", msg);
-                return;
-            }
-        };
+        let tracker = &self.tracker;
+        let network_client_msg = unwrap_option_or_return!(peer_message_to_client_message(
+            msg,
+            peer_id,
+            |block_hash| tracker.has_request(block_hash),
+            &mut self.state_part_assembler,
+        ));
 
         self.client_addr
             .send(network_client_msg)
@@ -614,6 +723,31 @@ impl Peer {
             .spawn(ctx);
     }
 
+    /// Splits the state part of `response` into pieces, if the peer supports them.
+    fn split_state_part(&self, response: &StateResponseInfo) -> Option<Vec<StatePartChunk>> {
+        let compression =
+            state_part_compression(self.state_part_compression, self.protocol_version)?;
+        let part = match response {
+            StateResponseInfo::V1(info) => &info.state_response.part,
+            StateResponseInfo::V2(info) => info.state_response.part(),
+        };
+        let (part_id, part) = part.as_ref()?;
+        match split_state_part(
+            response.shard_id(),
+            response.sync_hash(),
+            *part_id,
+            part,
+            compression,
+            self.state_part_chunk_size,
+        ) {
+            Ok(chunks) => Some(chunks),
+            Err(err) => {
+                error!(target: "network", "Failed to compress state part: {}", err);
+                None
+            }
+        }
+    }
+
     /// Hook called on every valid message received from this peer from the network.
     fn on_receive_message(&mut self) {
         if let Some(peer_id) = self.peer_id() {
@@ -704,6 +838,7 @@ impl StreamHandler<Result<Vec<u8>, ReasonForBan>> for Peer {
 
         near_metrics::inc_counter_by(&metrics::PEER_DATA_RECEIVED_BYTES, msg.len() as u64);
         near_metrics::inc_counter(&metrics::PEER_MESSAGE_RECEIVED_TOTAL);
+        self.capture(CaptureDirection::Received, &msg);
 
         #[cfg(feature = "metric_recorder")]
         let msg_size = msg.len();
@@ -745,6 +880,30 @@ impl StreamHandler<Result<Vec<u8>, ReasonForBan>> for Peer {
                 return;
             }
         };
+        // Routed messages for other nodes are only relayed by the peer, so only their size counts.
+        let (limited_variant, routed) = match &peer_msg {
+            PeerMessage::Routed(routed_message) => (
+                Some(peer_msg.msg_variant())
+                    .filter(|_| routed_message.target == PeerIdOrHash::PeerId(self.node_id())),
+                true,
+            ),
+            _ => (Some(peer_msg.msg_variant()), false),
+        };
+        match self.rate_limiter.check(limited_variant, msg.len(), routed) {
+            RateLimitDecision::Allow => {}
+            decision => {
+                self.network_metrics.inc(
+                    NetworkMetrics::peer_message_rate_limited(peer_msg.msg_variant()).as_ref(),
+                );
+                if decision == RateLimitDecision::Ban {
+                    warn!(target: "network", "Banning {} for exceeding message rate limits", self.peer_info);
+                    self.ban_peer(ctx, ReasonForBan::Abusive);
+                } else {
+                    trace!(target: "network", "Dropping {} from {}: rate limit exceeded", peer_msg.msg_variant(), self.peer_info);
+                }
+                return;
+            }
+        }
         if let PeerMessage::Routed(RoutedMessage {
             body: RoutedMessageBody::ForwardTx(_), ..
         }) = &peer_msg
@@ -856,6 +1015,22 @@ impl StreamHandler<Result<Vec<u8>, ReasonForBan>> for Peer {
                     // Connection will be closed by a handshake timeout
                 }
 
+                // The peer must be the one which authenticated the secure transport.
+                if let Some(transport_peer_id) = self.transport_peer_id.clone() {
+                    if transport_peer_id != handshake.peer_id {
+                        warn!(target: "network", "Received handshake from {} over secure transport of {}", handshake.peer_id, transport_peer_id);
+                        self.ban_peer(ctx, ReasonForBan::InvalidPeerId);
+                        return;
+                    }
+                } else if checked_feature!("stable", SecureTransport, handshake.version) {
+                    // The peer supports the secure transport, so it was downgraded on-path. The
+                    // version isn't signed, so the peer manager also refuses plaintext connections
+                    // of peers which spoke the secure transport before.
+                    warn!(target: "network", "Received plaintext handshake from {} of version {}. Disconnecting peer", handshake.peer_id, handshake.version);
+                    ctx.stop();
+                    return;
+                }
+
                 // Verify signature of the new edge in handshake.
                 if !Edge::partial_verify(
                     self.node_id(),
@@ -894,6 +1069,7 @@ impl StreamHandler<Result<Vec<u8>, ReasonForBan>> for Peer {
                         chain_info: handshake.chain_info.clone(),
                         this_edge_info: self.edge_info.clone(),
                         other_edge_info: handshake.edge_info.clone(),
+                        secure_transport: self.transport_peer_id.is_some(),
                     })
                     .into_actor(self)
                     .then(move |res, act, ctx| {
@@ -1,7 +1,7 @@
 use rand::seq::{IteratorRandom, SliceRandom};
 use std::cmp;
 use std::collections::{HashMap, HashSet};
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::pin::Pin;
 use std::sync::atomic::Ordering;
 use std::sync::{atomic::AtomicUsize, Arc};
@@ -14,6 +14,7 @@ use actix::{
 use chrono::Utc;
 use futures::task::Poll;
 use futures::{future, Stream, StreamExt};
+use tokio::io::AsyncReadExt;
 use tokio::net::{TcpListener, TcpStream};
 use tokio_util::codec::FramedRead;
 use tracing::{debug, error, info, trace, warn};
@@ -24,27 +25,32 @@ use near_primitives::types::AccountId;
 use near_primitives::utils::from_timestamp;
 use near_store::Store;
 
+use crate::capture::NetworkCapture;
 use crate::codec::Codec;
 use crate::metrics;
 use crate::peer::Peer;
 use crate::peer_store::{PeerStore, TrustLevel};
+use crate::rate_limit::PeerRateLimiter;
 #[cfg(feature = "metric_recorder")]
 use crate::recorder::{MetricRecorder, PeerMessageMetadata};
 use crate::routing::{
     Edge, EdgeInfo, EdgeType, EdgeVerifierHelper, ProcessEdgeResult, RoutingTable, MAX_NUM_PEERS,
 };
+use crate::secure_transport::{self, Transport};
 use crate::types::{
     AccountOrPeerIdOrHash, Ban, BlockedPorts, Consolidate, ConsolidateResponse, FullPeerInfo,
-    InboundTcpConnect, KnownPeerStatus, KnownProducer, NetworkInfo, NetworkViewClientMessages,
-    NetworkViewClientResponses, OutboundTcpConnect, PeerIdOrHash, PeerList, PeerManagerRequest,
-    PeerMessage, PeerRequest, PeerResponse, PeerType, PeersRequest, PeersResponse, Ping, Pong,
-    QueryPeerStats, RawRoutedMessage, ReasonForBan, RoutedMessage, RoutedMessageBody,
-    RoutedMessageFrom, SendMessage, StateResponseInfo, SyncData, Unregister,
+    InboundTcpConnect, KnownPeerStateView, KnownPeerStatus, KnownProducer, NetworkInfo,
+    NetworkViewClientMessages, NetworkViewClientResponses, OutboundTcpConnect, PeerAdminError,
+    PeerAdminRequest, PeerAdminResponse, PeerIdOrHash, PeerList, PeerManagerRequest, PeerMessage,
+    PeerRequest, PeerResponse, PeerType, PeersRequest, PeersResponse, Ping, Pong, QueryPeerStats,
+    RawRoutedMessage, ReasonForBan, RoutedMessage, RoutedMessageBody, RoutedMessageFrom,
+    SendMessage, StateResponseInfo, SyncData, Unregister,
 };
 use crate::types::{
     EdgeList, KnownPeerState, NetworkClientMessages, NetworkConfig, NetworkRequests,
     NetworkResponses, PeerInfo,
 };
+use crate::utils::ip_subnet;
 #[cfg(feature = "delay_detector")]
 use delay_detector::DelayDetector;
 use metrics::NetworkMetrics;
@@ -77,6 +83,8 @@ const EXPONENTIAL_BACKOFF_LIMIT: u64 = 91;
 /// Time to wait before sending ping to all reachable peers.
 #[cfg(feature = "metric_recorder")]
 const WAIT_BEFORE_PING: u64 = 20_000;
+/// How often to ping active peers to measure round trip times.
+const PING_ACTIVE_PEERS_PERIOD: Duration = Duration::from_secs(60);
 /// Limit number of pending Peer actors to avoid OOM.
 const LIMIT_PENDING_PEERS: usize = 60;
 /// How ofter should we broadcast edges.
@@ -158,6 +166,8 @@ pub struct PeerManagerActor {
     peer_store: PeerStore,
     /// Set of outbound connections that were not consolidated yet.
     outgoing_peers: HashSet<PeerId>,
+    /// Peers whose secure transport handshake timed out, the next connection is in plaintext.
+    plaintext_peers: HashSet<PeerId>,
     /// Active peers (inbound and outbound) with their full peer information.
     active_peers: HashMap<PeerId, ActivePeer>,
     /// Routing table to keep track of account id
@@ -181,6 +191,8 @@ pub struct PeerManagerActor {
     peer_counter: Arc<AtomicUsize>,
     scheduled_routing_table_update: bool,
     edge_verifier_requests_in_progress: u64,
+    /// Writer of the messages exchanged with peers, if capturing is enabled.
+    capture_addr: Option<Addr<NetworkCapture>>,
 }
 
 impl PeerManagerActor {
@@ -194,6 +206,7 @@ impl PeerManagerActor {
             panic!("Exceeded max peer limit: {}", MAX_NUM_PEERS);
         }
 
+        config.rate_limits.validate()?;
         let peer_store = PeerStore::new(store.clone(), &config.boot_nodes)?;
         debug!(target: "network", "Found known peers: {} (boot nodes={})", peer_store.len(), config.boot_nodes.len());
         debug!(target: "network", "Blacklist: {:?}", config.blacklist);
@@ -208,6 +221,8 @@ impl PeerManagerActor {
 
         let txns_since_last_block = Arc::new(AtomicUsize::new(0));
 
+        let capture_addr = config.capture.clone().map(NetworkCapture::start);
+
         Ok(PeerManagerActor {
             peer_id: me,
             config,
@@ -216,6 +231,7 @@ impl PeerManagerActor {
             peer_store,
             active_peers: HashMap::default(),
             outgoing_peers: HashSet::default(),
+            plaintext_peers: HashSet::default(),
             routing_table,
             routing_table_exchange_helper: Default::default(),
             monitor_peers_attempts: 0,
@@ -230,6 +246,7 @@ impl PeerManagerActor {
             peer_counter: Arc::new(AtomicUsize::new(0)),
             scheduled_routing_table_update: false,
             edge_verifier_requests_in_progress: 0,
+            capture_addr,
         })
     }
 
@@ -353,8 +370,9 @@ impl PeerManagerActor {
         if self.outgoing_peers.contains(&full_peer_info.peer_info.id) {
             self.outgoing_peers.remove(&full_peer_info.peer_info.id);
         }
+        self.peer_store.peer_height(&full_peer_info.peer_info.id, full_peer_info.chain_info.height);
         unwrap_or_error!(
-            self.peer_store.peer_connected(&full_peer_info.peer_info),
+            self.peer_store.peer_connected(&full_peer_info.peer_info, peer_type),
             "Failed to save peer data"
         );
 
@@ -474,11 +492,15 @@ impl PeerManagerActor {
         debug!(target: "network", "Unregister peer: {:?} {:?}", peer_id, peer_type);
         // If this is an unconsolidated peer because failed / connected inbound, just delete it.
         if peer_type == PeerType::Outbound && self.outgoing_peers.contains(&peer_id) {
-            self.outgoing_peers.remove(&peer_id);
+            self.outbound_connection_failed(&peer_id);
             return;
         }
 
         if remove_from_peer_store {
+            if let Some(active_peer) = self.active_peers.get(&peer_id) {
+                let height = active_peer.full_peer_info.chain_info.height;
+                self.peer_store.peer_height(&peer_id, height);
+            }
             self.remove_active_peer(ctx, &peer_id, Some(peer_type));
             unwrap_or_error!(
                 self.peer_store.peer_disconnected(&peer_id),
@@ -487,6 +509,15 @@ impl PeerManagerActor {
         }
     }
 
+    /// Forget about a pending outbound connection which failed before the handshake completed.
+    fn outbound_connection_failed(&mut self, peer_id: &PeerId) {
+        self.outgoing_peers.remove(peer_id);
+        unwrap_or_error!(
+            self.peer_store.peer_connection_failed(peer_id),
+            "Failed to save peer data"
+        );
+    }
+
     /// Add peer to ban list.
     /// This function should only be called after Peer instance is stopped.
     /// Note: Use `try_ban_peer` if there might be a Peer instance still active.
@@ -520,6 +551,7 @@ impl PeerManagerActor {
         &mut self,
         recipient: Addr<Self>,
         stream: TcpStream,
+        transport: Transport,
         peer_type: PeerType,
         peer_info: Option<PeerInfo>,
         edge_info: Option<EdgeInfo>,
@@ -528,6 +560,8 @@ impl PeerManagerActor {
         let account_id = self.config.account_id.clone();
         let server_addr = self.config.addr;
         let handshake_timeout = self.config.handshake_timeout;
+        let state_part_compression = self.config.state_part_compression;
+        let state_part_chunk_size = self.config.state_part_chunk_size;
         let client_addr = self.client_addr.clone();
         let view_client_addr = self.view_client_addr.clone();
 
@@ -552,6 +586,8 @@ impl PeerManagerActor {
 
         let network_metrics = self.network_metrics.clone();
         let txns_since_last_block = Arc::clone(&self.txns_since_last_block);
+        let capture_addr = self.capture_addr.clone();
+        let rate_limits = self.config.rate_limits.clone();
 
         // Start every peer actor on separate thread.
         let arbiter = Arbiter::new();
@@ -560,10 +596,17 @@ impl PeerManagerActor {
 
         Peer::start_in_arbiter(&arbiter.handle(), move |ctx| {
             let (read, write) = tokio::io::split(stream);
+            let (leftover, read_codec, write_codec, transport_peer_id) = match transport {
+                Transport::Plaintext { leftover } => (leftover, Codec::new(), Codec::new(), None),
+                Transport::Secure { peer_id, send, recv } => {
+                    (vec![], Codec::new_encrypted(recv), Codec::new_encrypted(send), Some(peer_id))
+                }
+            };
+            let read = std::io::Cursor::new(leftover).chain(read);
 
             // TODO: check if peer is banned or known based on IP address and port.
             Peer::add_stream(
-                FramedRead::new(read, Codec::new())
+                FramedRead::new(read, read_codec)
                     .take_while(|x| match x {
                         Ok(_) => future::ready(true),
                         Err(e) => {
@@ -580,7 +623,7 @@ impl PeerManagerActor {
                 remote_addr,
                 peer_info,
                 peer_type,
-                FramedWrite::new(write, Codec::new(), Codec::new(), ctx),
+                FramedWrite::new(write, write_codec, Codec::new(), ctx),
                 handshake_timeout,
                 recipient,
                 client_addr,
@@ -589,6 +632,11 @@ impl PeerManagerActor {
                 network_metrics,
                 txns_since_last_block,
                 peer_counter,
+                state_part_compression,
+                state_part_chunk_size,
+                transport_peer_id,
+                capture_addr,
+                PeerRateLimiter::new(&rate_limits).expect("Rate limits are validated on start"),
             )
         });
     }
@@ -660,10 +708,42 @@ impl PeerManagerActor {
         (sent_bps, received_bps)
     }
 
-    /// Get a random peer we are not connected to from the known list.
+    /// Number of outbound connections, established or pending, per subnet of the peer address.
+    fn outbound_subnets(&self) -> HashMap<IpAddr, usize> {
+        let active_addrs = self
+            .active_peers
+            .values()
+            .filter(|active_peer| active_peer.peer_type == PeerType::Outbound)
+            .filter_map(|active_peer| active_peer.full_peer_info.peer_info.addr);
+        let pending_addrs = self
+            .outgoing_peers
+            .iter()
+            .filter_map(|peer_id| self.peer_store.get(peer_id))
+            .filter_map(|peer_state| peer_state.peer_info.addr);
+        let mut subnets = HashMap::new();
+        for addr in active_addrs.chain(pending_addrs) {
+            if let Some(subnet) = ip_subnet(&addr.ip()) {
+                *subnets.entry(subnet).or_insert(0) += 1;
+            }
+        }
+        subnets
+    }
+
+    /// Get a peer we are not connected to from the known list, weighted by its connection
+    /// history. Peers in subnets which already have `max_outbound_peers_per_subnet` outbound
+    /// connections are skipped.
     fn sample_random_peer(&self, ignore_fn: impl Fn(&KnownPeerState) -> bool) -> Option<PeerInfo> {
-        let unconnected_peers = self.peer_store.unconnected_peers(ignore_fn);
-        unconnected_peers.choose(&mut rand::thread_rng()).cloned()
+        let subnets = self.outbound_subnets();
+        let max_per_subnet = self.config.max_outbound_peers_per_subnet as usize;
+        self.peer_store.sample_unconnected_peer(|peer_state| {
+            ignore_fn(peer_state)
+                || peer_state
+                    .peer_info
+                    .addr
+                    .and_then(|addr| ip_subnet(&addr.ip()))
+                    .and_then(|subnet| subnets.get(&subnet))
+                    .map_or(false, |count| *count >= max_per_subnet)
+        })
     }
 
     /// Query current peers for more peers.
@@ -794,6 +874,24 @@ impl PeerManagerActor {
         );
     }
 
+    /// Periodically ping active peers, the round trip times are kept in the peer store.
+    fn ping_active_peers(&mut self, ctx: &mut Context<Self>) {
+        for peer_id in self.active_peers.keys().cloned().collect::<Vec<_>>() {
+            let nonce = self.routing_table.get_ping(peer_id.clone());
+            self.send_ping(ctx, nonce, peer_id);
+        }
+
+        near_performance_metrics::actix::run_later(
+            ctx,
+            file!(),
+            line!(),
+            PING_ACTIVE_PEERS_PERIOD,
+            move |act, ctx| {
+                act.ping_active_peers(ctx);
+            },
+        );
+    }
+
     /// Periodically query peer actors for latest weight and traffic info.
     fn monitor_peer_stats(&mut self, ctx: &mut Context<Self>) {
         for (peer_id, active_peer) in self.active_peers.iter() {
@@ -1209,17 +1307,15 @@ impl PeerManagerActor {
     }
 
     /// Handle pong messages. Add pong temporary to the routing table, mostly used for testing.
-    /// If `metric_recorder` feature flag is enabled, save how much time passed since we sent ping.
+    /// Save how much time passed since we sent ping in the peer store, and in the metric
+    /// recorder if `metric_recorder` feature flag is enabled.
     fn handle_pong(&mut self, _ctx: &mut Context<Self>, pong: Pong) {
-        #[cfg(feature = "metric_recorder")]
         let source = pong.source.clone();
-        #[allow(unused_variables)]
-        let latency = self.routing_table.add_pong(pong);
-        #[cfg(feature = "metric_recorder")]
-        latency.and_then::<(), _>(|latency| {
+        if let Some(latency) = self.routing_table.add_pong(pong) {
+            self.peer_store.peer_rtt(&source, latency);
+            #[cfg(feature = "metric_recorder")]
             self.metric_recorder.add_latency(source, latency);
-            None
-        });
+        }
     }
 
     pub(crate) fn get_network_info(&mut self) -> NetworkInfo {
@@ -1333,6 +1429,9 @@ impl Actor for PeerManagerActor {
         // Start active peer stats querying.
         self.monitor_peer_stats(ctx);
 
+        // Periodically ping active peers to keep their round trip times.
+        self.ping_active_peers(ctx);
+
         // Periodically ping all peers to determine latencies between pair of peers.
         #[cfg(feature = "metric_recorder")]
         self.ping_all_peers(ctx);
@@ -1717,12 +1816,39 @@ impl Handler<InboundTcpConnect> for PeerManagerActor {
         #[cfg(feature = "delay_detector")]
         let _d = DelayDetector::new("inbound tcp connect".into());
         if self.is_inbound_allowed() {
-            self.try_connect_peer(ctx.address(), msg.stream, PeerType::Inbound, None, None);
+            // The connection stays pending until the transport is set up.
+            secure_transport::accept(
+                msg.stream,
+                self.config.secret_key.clone(),
+                self.config.require_secure_transport,
+                self.config.handshake_timeout,
+            )
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                match res {
+                    Ok((stream, transport)) => {
+                        act.try_connect_peer(
+                            ctx.address(),
+                            stream,
+                            transport,
+                            PeerType::Inbound,
+                            None,
+                            None,
+                        );
+                    }
+                    Err(err) => {
+                        debug!(target: "network", "Failed to set up transport of inbound connection: {}", err);
+                    }
+                }
+                act.pending_incoming_connections_counter.fetch_sub(1, Ordering::SeqCst);
+                actix::fut::ready(())
+            })
+            .spawn(ctx);
         } else {
             // TODO(1896): Gracefully drop inbound connection for other peer.
             debug!(target: "network", "Inbound connection dropped (network at max capacity).");
+            self.pending_incoming_connections_counter.fetch_sub(1, Ordering::SeqCst);
         }
-        self.pending_incoming_connections_counter.fetch_sub(1, Ordering::SeqCst);
     }
 }
 
@@ -1751,24 +1877,68 @@ impl Handler<OutboundTcpConnect> for PeerManagerActor {
                             debug!(target: "network", "Connecting to {}", msg.peer_info);
                             let edge_info = act.propose_edge(msg.peer_info.id.clone(), None);
 
-                            act.try_connect_peer(
-                                ctx.address(),
+                            if act.plaintext_peers.remove(&msg.peer_info.id) {
+                                // The peer will be refused if its `Handshake` shows that it
+                                // supports the secure transport.
+                                debug!(target: "network", "Connecting to {} in plaintext", msg.peer_info);
+                                act.try_connect_peer(
+                                    ctx.address(),
+                                    stream,
+                                    Transport::Plaintext { leftover: vec![] },
+                                    PeerType::Outbound,
+                                    Some(msg.peer_info),
+                                    Some(edge_info),
+                                );
+                                return actix::fut::ready(());
+                            }
+
+                            secure_transport::connect(
                                 stream,
-                                PeerType::Outbound,
-                                Some(msg.peer_info),
-                                Some(edge_info),
-                            );
+                                act.config.secret_key.clone(),
+                                msg.peer_info.id.clone(),
+                                act.config.handshake_timeout,
+                            )
+                            .into_actor(act)
+                            .then(move |res, act, ctx| {
+                                match res {
+                                    Ok((stream, transport)) => {
+                                        act.try_connect_peer(
+                                            ctx.address(),
+                                            stream,
+                                            transport,
+                                            PeerType::Outbound,
+                                            Some(msg.peer_info),
+                                            Some(edge_info),
+                                        );
+                                    }
+                                    Err(err) => {
+                                        info!(target: "network", "Error setting up transport with {}: {}", msg.peer_info, err);
+                                        if err.kind() == std::io::ErrorKind::TimedOut
+                                            && !act.config.require_secure_transport
+                                            && !act
+                                                .peer_store
+                                                .speaks_secure_transport(&msg.peer_info.id)
+                                        {
+                                            // Old peers ignore the secure transport hello.
+                                            act.plaintext_peers.insert(msg.peer_info.id.clone());
+                                        }
+                                        act.outbound_connection_failed(&msg.peer_info.id);
+                                    }
+                                }
+                                actix::fut::ready(())
+                            })
+                            .spawn(ctx);
                             actix::fut::ready(())
                         }
                         Err(err) => {
                             info!(target: "network", "Error connecting to {}: {}", addr, err);
-                            act.outgoing_peers.remove(&msg.peer_info.id);
+                            act.outbound_connection_failed(&msg.peer_info.id);
                             actix::fut::ready(())
                         }
                     },
                     Err(err) => {
                         info!(target: "network", "Error connecting to {}: {}", addr, err);
-                        act.outgoing_peers.remove(&msg.peer_info.id);
+                        act.outbound_connection_failed(&msg.peer_info.id);
                         actix::fut::ready(())
                     }
                 })
@@ -1779,6 +1949,81 @@ impl Handler<OutboundTcpConnect> for PeerManagerActor {
     }
 }
 
+impl Handler<PeerAdminRequest> for PeerManagerActor {
+    type Result = Result<PeerAdminResponse, PeerAdminError>;
+
+    #[perf]
+    fn handle(&mut self, msg: PeerAdminRequest, ctx: &mut Self::Context) -> Self::Result {
+        #[cfg(feature = "delay_detector")]
+        let _d = DelayDetector::new("peer admin request".into());
+        info!(target: "network", "Admin request: {:?}", msg);
+        match msg {
+            PeerAdminRequest::Ban { peer_id, ban_reason, comment } => {
+                if self.peer_store.get(&peer_id).is_none() {
+                    return Err(PeerAdminError::UnknownPeer(peer_id));
+                }
+                // Active peers are banned once they are disconnected, so the comment goes first.
+                self.peer_store
+                    .peer_ban_comment(&peer_id, comment)
+                    .map_err(|err| PeerAdminError::IOError(err.to_string()))?;
+                self.try_ban_peer(ctx, &peer_id, ban_reason);
+            }
+            PeerAdminRequest::Unban { peer_id } => {
+                match self.peer_store.get(&peer_id) {
+                    None => return Err(PeerAdminError::UnknownPeer(peer_id)),
+                    Some(peer_state) if !peer_state.status.is_banned() => {
+                        return Err(PeerAdminError::NotBanned(peer_id))
+                    }
+                    Some(_) => {}
+                }
+                self.peer_store
+                    .peer_unban(&peer_id)
+                    .map_err(|err| PeerAdminError::IOError(err.to_string()))?;
+            }
+            PeerAdminRequest::Connect { peer_info } => {
+                if peer_info.addr.is_none() {
+                    return Err(PeerAdminError::NoAddress(peer_info.id));
+                }
+                if self.peer_store.is_banned(&peer_info.id) {
+                    return Err(PeerAdminError::PeerBanned(peer_info.id));
+                }
+                if self.active_peers.contains_key(&peer_info.id)
+                    || self.outgoing_peers.contains(&peer_info.id)
+                {
+                    return Ok(PeerAdminResponse::Done);
+                }
+                self.peer_store
+                    .add_trusted_peer(peer_info.clone(), TrustLevel::Direct)
+                    .map_err(|err| PeerAdminError::IOError(err.to_string()))?;
+                self.outgoing_peers.insert(peer_info.id.clone());
+                ctx.notify(OutboundTcpConnect { peer_info });
+            }
+            PeerAdminRequest::Disconnect { peer_id } => match self.active_peers.get(&peer_id) {
+                Some(active_peer) => active_peer.addr.do_send(PeerManagerRequest::UnregisterPeer),
+                None => return Err(PeerAdminError::NotConnected(peer_id)),
+            },
+            PeerAdminRequest::ListKnownPeers => {
+                let ban_window = self.config.ban_window.as_nanos() as u64;
+                let known_peers = self
+                    .peer_store
+                    .iter()
+                    .map(|(_, state)| KnownPeerStateView {
+                        state: state.clone(),
+                        ban_expires_at: match state.status {
+                            KnownPeerStatus::Banned(_, last_banned) => {
+                                Some(last_banned.saturating_add(ban_window))
+                            }
+                            _ => None,
+                        },
+                    })
+                    .collect();
+                return Ok(PeerAdminResponse::KnownPeers(known_peers));
+            }
+        }
+        Ok(PeerAdminResponse::Done)
+    }
+}
+
 impl Handler<Consolidate> for PeerManagerActor {
     type Result = ConsolidateResponse;
 
@@ -1813,6 +2058,11 @@ impl Handler<Consolidate> for PeerManagerActor {
             }
         }
 
+        if !msg.secure_transport && self.peer_store.speaks_secure_transport(&msg.peer_info.id) {
+            debug!(target: "network", "Dropping plaintext connection of peer which speaks secure transport: {:?}", msg.peer_info.id);
+            return ConsolidateResponse::Reject;
+        }
+
         if msg.peer_type == PeerType::Inbound && !self.is_inbound_allowed() {
             // TODO(1896): Gracefully drop inbound connection for other peer.
             debug!(target: "network", "Inbound connection dropped (network at max capacity).");
@@ -1847,6 +2097,7 @@ impl Handler<Consolidate> for PeerManagerActor {
 
         let edge_info_response = if require_response { Some(edge_info.clone()) } else { None };
 
+        let peer_id = msg.peer_info.id.clone();
         // TODO: double check that address is connectable and add account id.
         self.register_peer(
             FullPeerInfo {
@@ -1859,6 +2110,12 @@ impl Handler<Consolidate> for PeerManagerActor {
             msg.actor,
             ctx,
         );
+        if msg.secure_transport {
+            unwrap_or_error!(
+                self.peer_store.peer_secure_transport(&peer_id),
+                "Failed to save peer data"
+            );
+        }
 
         return ConsolidateResponse::Accept(edge_info_response);
     }
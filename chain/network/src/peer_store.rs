@@ -9,14 +9,17 @@ use std::sync::Arc;
 use borsh::BorshSerialize;
 use chrono::Utc;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::{thread_rng, Rng};
 use tracing::{debug, error};
 
 use near_primitives::network::PeerId;
+use near_primitives::types::BlockHeight;
 use near_primitives::utils::to_timestamp;
 use near_store::{ColPeers, Store};
 
-use crate::types::{KnownPeerState, KnownPeerStatus, NetworkConfig, PeerInfo, ReasonForBan};
+use crate::types::{
+    KnownPeerState, KnownPeerStatus, NetworkConfig, PeerInfo, PeerType, ReasonForBan,
+};
 
 /// Level of trust we have about a new (PeerId, Addr) pair.
 #[derive(Eq, PartialEq, Debug, Clone)]
@@ -99,6 +102,9 @@ impl PeerStore {
                 if peer_state.status.is_banned() {
                     current_peer_state.status = peer_state.status;
                 }
+                current_peer_state.stats = peer_state.stats;
+                current_peer_state.ban_comment = peer_state.ban_comment;
+                current_peer_state.secure_transport = peer_state.secure_transport;
                 continue;
             }
 
@@ -120,20 +126,30 @@ impl PeerStore {
         self.peer_states.is_empty()
     }
 
+    pub fn get(&self, peer_id: &PeerId) -> Option<&KnownPeerState> {
+        self.peer_states.get(peer_id)
+    }
+
     pub fn is_banned(&self, peer_id: &PeerId) -> bool {
         self.peer_states
             .get(&peer_id)
             .map_or(false, |known_peer_state| known_peer_state.status.is_banned())
     }
 
+    /// Marks the peer as connected. Only outbound connections count in the connection stats,
+    /// as failed inbound connections can't be attributed to a peer.
     pub fn peer_connected(
         &mut self,
         peer_info: &PeerInfo,
+        peer_type: PeerType,
     ) -> Result<(), Box<dyn std::error::Error>> {
         self.add_trusted_peer(peer_info.clone(), TrustLevel::Signed)?;
         let entry = self.peer_states.get_mut(&peer_info.id).unwrap();
         entry.last_seen = to_timestamp(Utc::now());
         entry.status = KnownPeerStatus::Connected;
+        if peer_type == PeerType::Outbound {
+            entry.stats.successful_connections += 1;
+        }
         let mut store_update = self.store.store_update();
         store_update.set_ser(ColPeers, &peer_info.id.try_to_vec()?, entry)?;
         store_update.commit().map_err(|err| err.into())
@@ -144,7 +160,13 @@ impl PeerStore {
         peer_id: &PeerId,
     ) -> Result<(), Box<dyn std::error::Error>> {
         if let Some(peer_state) = self.peer_states.get_mut(peer_id) {
-            peer_state.last_seen = to_timestamp(Utc::now());
+            let now = to_timestamp(Utc::now());
+            if peer_state.status == KnownPeerStatus::Connected {
+                // `last_seen` is the time the peer connected at.
+                peer_state.stats.uptime_secs +=
+                    now.saturating_sub(peer_state.last_seen) / 1_000_000_000;
+            }
+            peer_state.last_seen = now;
             peer_state.status = KnownPeerStatus::NotConnected;
             let mut store_update = self.store.store_update();
             store_update.set_ser(ColPeers, &peer_id.try_to_vec()?, peer_state)?;
@@ -154,6 +176,37 @@ impl PeerStore {
         }
     }
 
+    /// Records an outbound connection which failed before the handshake completed.
+    pub fn peer_connection_failed(
+        &mut self,
+        peer_id: &PeerId,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(peer_state) = self.peer_states.get_mut(peer_id) {
+            peer_state.stats.failed_connections += 1;
+            let mut store_update = self.store.store_update();
+            store_update.set_ser(ColPeers, &peer_id.try_to_vec()?, peer_state)?;
+            store_update.commit().map_err(|err| err.into())
+        } else {
+            Err(format!("Peer {} is missing in the peer store", peer_id).into())
+        }
+    }
+
+    /// Records the round trip time of a ping. Not written to storage until the next update of
+    /// the peer, e.g. when it disconnects.
+    pub fn peer_rtt(&mut self, peer_id: &PeerId, rtt_ms: f64) {
+        if let Some(peer_state) = self.peer_states.get_mut(peer_id) {
+            peer_state.stats.add_rtt(rtt_ms);
+        }
+    }
+
+    /// Records the height of the peer's chain. Not written to storage until the next update of
+    /// the peer.
+    pub fn peer_height(&mut self, peer_id: &PeerId, height: BlockHeight) {
+        if let Some(peer_state) = self.peer_states.get_mut(peer_id) {
+            peer_state.stats.last_seen_height = height;
+        }
+    }
+
     pub fn peer_ban(
         &mut self,
         peer_id: &PeerId,
@@ -170,9 +223,52 @@ impl PeerStore {
         }
     }
 
+    /// Records the operator's note on why the peer is banned.
+    pub fn peer_ban_comment(
+        &mut self,
+        peer_id: &PeerId,
+        ban_comment: Option<String>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(peer_state) = self.peer_states.get_mut(peer_id) {
+            peer_state.ban_comment = ban_comment;
+            let mut store_update = self.store.store_update();
+            store_update.set_ser(ColPeers, &peer_id.try_to_vec()?, peer_state)?;
+            store_update.commit().map_err(|err| err.into())
+        } else {
+            Err(format!("Peer {} is missing in the peer store", peer_id).into())
+        }
+    }
+
+    /// Remembers that the peer speaks the secure transport.
+    pub fn peer_secure_transport(
+        &mut self,
+        peer_id: &PeerId,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(peer_state) = self.peer_states.get_mut(peer_id) {
+            if peer_state.secure_transport {
+                return Ok(());
+            }
+            peer_state.secure_transport = true;
+            let mut store_update = self.store.store_update();
+            store_update.set_ser(ColPeers, &peer_id.try_to_vec()?, peer_state)?;
+            store_update.commit().map_err(|err| err.into())
+        } else {
+            Err(format!("Peer {} is missing in the peer store", peer_id).into())
+        }
+    }
+
+    /// Whether the peer connected over the secure transport before.
+    pub fn speaks_secure_transport(&self, peer_id: &PeerId) -> bool {
+        self.peer_states.get(peer_id).map_or(false, |peer_state| peer_state.secure_transport)
+    }
+
     pub fn peer_unban(&mut self, peer_id: &PeerId) -> Result<(), Box<dyn std::error::Error>> {
         if let Some(peer_state) = self.peer_states.get_mut(peer_id) {
+            if !peer_state.status.is_banned() {
+                return Err(format!("Peer {} is not banned", peer_id).into());
+            }
             peer_state.status = KnownPeerStatus::NotConnected;
+            peer_state.ban_comment = None;
             let mut store_update = self.store.store_update();
             store_update.set_ser(ColPeers, &peer_id.try_to_vec()?, peer_state)?;
             store_update.commit().map_err(|err| err.into())
@@ -200,25 +296,49 @@ impl PeerStore {
     /// Return unconnected or peers with unknown status that we can try to connect to.
     /// Peers with unknown addresses are filtered out.
     pub fn unconnected_peers(&self, ignore_fn: impl Fn(&KnownPeerState) -> bool) -> Vec<PeerInfo> {
-        self.find_peers(
-            |p| {
-                (p.status == KnownPeerStatus::NotConnected || p.status == KnownPeerStatus::Unknown)
-                    && !ignore_fn(p)
-                    && p.peer_info.addr.is_some()
-            },
-            0,
-        )
+        self.find_peers(|p| is_unconnected(p) && !ignore_fn(p), 0)
     }
 
-    /// Return healthy known peers up to given amount.
+    /// Return one of `unconnected_peers`, chosen with probability proportional to its
+    /// `PeerStats::score`.
+    pub fn sample_unconnected_peer(
+        &self,
+        ignore_fn: impl Fn(&KnownPeerState) -> bool,
+    ) -> Option<PeerInfo> {
+        let peers = self
+            .peer_states
+            .values()
+            .filter(|p| is_unconnected(p) && !ignore_fn(p))
+            .collect::<Vec<_>>();
+        peers
+            .choose_weighted(&mut thread_rng(), |p| p.stats.score())
+            .ok()
+            .map(|p| p.peer_info.clone())
+    }
+
+    /// Return healthy known peers up to given amount, sampled with probability proportional to
+    /// their `PeerStats::score`.
     pub fn healthy_peers(&self, max_count: u32) -> Vec<PeerInfo> {
-        self.find_peers(
-            |p| match p.status {
-                KnownPeerStatus::Banned(_, _) => false,
-                _ => true,
-            },
-            max_count,
-        )
+        let is_healthy = |p: &&KnownPeerState| !p.status.is_banned();
+        if max_count == 0 {
+            return self
+                .peer_states
+                .values()
+                .filter(is_healthy)
+                .map(|p| p.peer_info.clone())
+                .collect();
+        }
+        // Weighted sampling without replacement: keeping the largest `u^(1 / score)` for uniform
+        // `u` picks each next peer with probability proportional to its score.
+        let mut rng = thread_rng();
+        let mut peers = self
+            .peer_states
+            .values()
+            .filter(is_healthy)
+            .map(|p| (rng.gen::<f64>().powf(1.0 / p.stats.score()), &p.peer_info))
+            .collect::<Vec<_>>();
+        peers.sort_by(|(key1, _), (key2, _)| key2.partial_cmp(key1).unwrap());
+        peers.into_iter().take(max_count as usize).map(|(_, peer_info)| peer_info.clone()).collect()
     }
 
     /// Return iterator over all known peers.
@@ -371,12 +491,21 @@ impl PeerStore {
     }
 }
 
+fn is_unconnected(peer_state: &KnownPeerState) -> bool {
+    (peer_state.status == KnownPeerStatus::NotConnected
+        || peer_state.status == KnownPeerStatus::Unknown)
+        && peer_state.peer_info.addr.is_some()
+}
+
 #[cfg(test)]
 mod test {
+    use borsh::BorshDeserialize;
     use near_crypto::{KeyType, SecretKey};
     use near_store::create_store;
     use near_store::test_utils::create_test_store;
 
+    use crate::types::PeerStats;
+
     use super::*;
 
     fn get_peer_id(seed: String) -> PeerId {
@@ -467,7 +596,7 @@ mod test {
         let addr = get_addr(0);
 
         let peer_aa = get_peer_info(peers_id[0].clone(), Some(addr));
-        peer_store.peer_connected(&peer_aa).unwrap();
+        peer_store.peer_connected(&peer_aa, PeerType::Outbound).unwrap();
         assert!(check_exist(&peer_store, &peers_id[0], Some((addr, TrustLevel::Signed))));
 
         let peer_ba = get_peer_info(peers_id[1].clone(), Some(addr));
@@ -490,7 +619,7 @@ mod test {
         let addrs = (0..2).map(|ix| get_addr(ix)).collect::<Vec<_>>();
 
         let peer_aa = get_peer_info(peers_id[0].clone(), Some(addrs[0]));
-        peer_store.peer_connected(&peer_aa).unwrap();
+        peer_store.peer_connected(&peer_aa, PeerType::Outbound).unwrap();
         assert!(check_exist(&peer_store, &peers_id[0], Some((addrs[0], TrustLevel::Signed))));
 
         let peer_ba = get_peer_info(peers_id[0].clone(), Some(addrs[1]));
@@ -511,7 +640,7 @@ mod test {
 
         // Create signed connection A - #A
         let peer_00 = get_peer_info(peers_id[0].clone(), Some(addrs[0]));
-        peer_store.peer_connected(&peer_00).unwrap();
+        peer_store.peer_connected(&peer_00, PeerType::Outbound).unwrap();
         assert!(check_exist(&peer_store, &peers_id[0], Some((addrs[0], TrustLevel::Signed))));
         assert!(check_integrity(&peer_store));
 
@@ -522,7 +651,7 @@ mod test {
         assert!(check_integrity(&peer_store));
 
         // Create signed connection B - #B
-        peer_store.peer_connected(&peer_11).unwrap();
+        peer_store.peer_connected(&peer_11, PeerType::Outbound).unwrap();
         assert!(check_exist(&peer_store, &peers_id[1], Some((addrs[1], TrustLevel::Signed))));
         assert!(check_integrity(&peer_store));
 
@@ -533,14 +662,14 @@ mod test {
         assert!(check_integrity(&peer_store));
 
         // Create signed connection C - #C
-        peer_store.peer_connected(&peer_22).unwrap();
+        peer_store.peer_connected(&peer_22, PeerType::Outbound).unwrap();
         assert!(check_exist(&peer_store, &peers_id[2], Some((addrs[2], TrustLevel::Signed))));
         assert!(check_integrity(&peer_store));
 
         // Create signed connection C - #B
         // This overrides C - #C and B - #B
         let peer_21 = get_peer_info(peers_id[2].clone(), Some(addrs[1]));
-        peer_store.peer_connected(&peer_21).unwrap();
+        peer_store.peer_connected(&peer_21, PeerType::Outbound).unwrap();
         assert!(check_exist(&peer_store, &peers_id[1], None));
         assert!(check_exist(&peer_store, &peers_id[2], Some((addrs[1], TrustLevel::Signed))));
         assert!(check_integrity(&peer_store));
@@ -581,4 +710,110 @@ mod test {
         assert!(check_exist(&peer_store_2, &peers_id[0], Some((addrs[0], TrustLevel::Indirect))));
         assert!(check_integrity(&peer_store_2));
     }
+
+    /// Peers stored before connection stats existed are loaded with empty stats.
+    #[test]
+    fn load_peer_state_without_stats() {
+        let mut peer_state = KnownPeerState::new(gen_peer_info(0));
+        peer_state.stats.successful_connections = 3;
+        let mut old_bytes = vec![];
+        peer_state.peer_info.serialize(&mut old_bytes).unwrap();
+        peer_state.status.serialize(&mut old_bytes).unwrap();
+        peer_state.first_seen.serialize(&mut old_bytes).unwrap();
+        peer_state.last_seen.serialize(&mut old_bytes).unwrap();
+
+        let old_state = KnownPeerState::try_from_slice(&old_bytes).unwrap();
+        assert_eq!(old_state.peer_info, peer_state.peer_info);
+        assert_eq!(old_state.stats, PeerStats::default());
+
+        let new_state = KnownPeerState::try_from_slice(&peer_state.try_to_vec().unwrap()).unwrap();
+        assert_eq!(new_state.stats, peer_state.stats);
+    }
+
+    #[test]
+    fn peer_stats() {
+        let tmp_dir = tempfile::Builder::new().prefix("_test_store_stats").tempdir().unwrap();
+        let peer_info = gen_peer_info(0);
+        {
+            let store = create_store(tmp_dir.path().to_str().unwrap());
+            let mut peer_store = PeerStore::new(store, &[peer_info.clone()]).unwrap();
+            peer_store.peer_connection_failed(&peer_info.id).unwrap();
+            peer_store.peer_connected(&peer_info, PeerType::Outbound).unwrap();
+            peer_store.peer_rtt(&peer_info.id, 10.0);
+            peer_store.peer_rtt(&peer_info.id, 30.0);
+            peer_store.peer_height(&peer_info.id, 100);
+            peer_store.peer_disconnected(&peer_info.id).unwrap();
+            // Inbound connections aren't counted, like their failures.
+            peer_store.peer_connected(&peer_info, PeerType::Inbound).unwrap();
+            peer_store.peer_disconnected(&peer_info.id).unwrap();
+            assert!(peer_store.peer_connection_failed(&gen_peer_info(1).id).is_err());
+        }
+        {
+            let store = create_store(tmp_dir.path().to_str().unwrap());
+            let peer_store = PeerStore::new(store, &[peer_info.clone()]).unwrap();
+            let stats = &peer_store.get(&peer_info.id).unwrap().stats;
+            assert_eq!(stats.successful_connections, 1);
+            assert_eq!(stats.failed_connections, 1);
+            assert_eq!(stats.mean_rtt_ms, Some(20.0));
+            assert_eq!(stats.last_seen_height, 100);
+        }
+    }
+
+    #[test]
+    fn peer_secure_transport() {
+        let store = create_test_store();
+        let peer_info = gen_peer_info(0);
+        {
+            let mut peer_store = PeerStore::new(store.clone(), &[peer_info.clone()]).unwrap();
+            assert!(!peer_store.speaks_secure_transport(&peer_info.id));
+            peer_store.peer_secure_transport(&peer_info.id).unwrap();
+            assert!(peer_store.peer_secure_transport(&gen_peer_info(1).id).is_err());
+        }
+        let peer_store = PeerStore::new(store, &[peer_info.clone()]).unwrap();
+        assert!(peer_store.speaks_secure_transport(&peer_info.id));
+    }
+
+    #[test]
+    fn sample_unconnected_peer_by_score() {
+        let store = create_test_store();
+        let good_peer = gen_peer_info(0);
+        let bad_peer = gen_peer_info(1);
+        let mut peer_store = PeerStore::new(store, &[good_peer.clone(), bad_peer.clone()]).unwrap();
+        for _ in 0..100 {
+            peer_store.peer_connection_failed(&bad_peer.id).unwrap();
+        }
+        let num_good = (0..100)
+            .filter(|_| peer_store.sample_unconnected_peer(|_| false).unwrap() == good_peer)
+            .count();
+        assert!(num_good > 80);
+
+        assert_eq!(
+            peer_store.sample_unconnected_peer(|p| p.peer_info.id == good_peer.id),
+            Some(bad_peer)
+        );
+        assert_eq!(peer_store.sample_unconnected_peer(|_| true), None);
+    }
+
+    #[test]
+    fn healthy_peers_by_score() {
+        let store = create_test_store();
+        let good_peer = gen_peer_info(0);
+        let bad_peer = gen_peer_info(1);
+        let banned_peer = gen_peer_info(2);
+        let mut peer_store =
+            PeerStore::new(store, &[good_peer.clone(), bad_peer.clone(), banned_peer.clone()])
+                .unwrap();
+        for _ in 0..100 {
+            peer_store.peer_connection_failed(&bad_peer.id).unwrap();
+        }
+        peer_store.peer_ban(&banned_peer.id, ReasonForBan::Abusive).unwrap();
+        let num_good =
+            (0..100).filter(|_| peer_store.healthy_peers(1) == vec![good_peer.clone()]).count();
+        assert!(num_good > 80);
+
+        let mut peers = peer_store.healthy_peers(3);
+        peers.sort_by_key(|peer_info| peer_info.id != good_peer.id);
+        assert_eq!(peers, vec![good_peer.clone(), bad_peer.clone()]);
+        assert_eq!(peer_store.healthy_peers(0).len(), 2);
+    }
 }
@@ -0,0 +1,293 @@
+//! Token bucket limits of the messages received from a peer, per message type and in total bytes.
+//!
+//! Messages over a limit are dropped. Peers which keep hitting the limits are banned, except
+//! for routed messages, which the peer may only be relaying.
+use std::collections::HashMap;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use strum::VariantNames;
+
+use crate::rate_counter::RateCounter;
+use crate::types::{PeerMessage, RoutedMessageBody};
+
+/// Token bucket: `burst` tokens at most, refilled by `per_second` tokens every second.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub per_second: u64,
+    pub burst: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RateLimitConfig {
+    /// Number of messages received from a peer, by `PeerMessage` variant or by
+    /// `RoutedMessageBody` variant for routed messages addressed to this node, e.g. `ForwardTx`.
+    /// Unknown variants are rejected when the node starts.
+    #[serde(default)]
+    pub messages: HashMap<String, RateLimit>,
+    /// Number of bytes received from a peer in all messages.
+    #[serde(default)]
+    pub bandwidth: Option<RateLimit>,
+    /// Number of messages dropped within a minute after which the peer is banned.
+    #[serde(default = "default_max_throttled_per_min")]
+    pub max_throttled_per_min: u64,
+}
+
+fn default_max_throttled_per_min() -> u64 {
+    1000
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        let mut messages = HashMap::new();
+        messages.insert("ForwardTx".to_string(), RateLimit { per_second: 200, burst: 1000 });
+        messages.insert("BlockHeadersRequest".to_string(), RateLimit { per_second: 10, burst: 50 });
+        Self { messages, bandwidth: None, max_throttled_per_min: default_max_throttled_per_min() }
+    }
+}
+
+impl RateLimitConfig {
+    /// No limits, used by tests which exchange a lot of messages.
+    pub fn unlimited() -> Self {
+        Self {
+            messages: HashMap::new(),
+            bandwidth: None,
+            max_throttled_per_min: default_max_throttled_per_min(),
+        }
+    }
+
+    /// Checks that the limited message types exist, a misspelled type would never be limited.
+    pub fn validate(&self) -> Result<(), String> {
+        let is_known = |name: &str| {
+            PeerMessage::VARIANTS
+                .iter()
+                .filter(|&&variant| variant != "Routed")
+                .chain(RoutedMessageBody::VARIANTS.iter())
+                .any(|&variant| variant == name)
+        };
+        let mut unknown: Vec<_> =
+            self.messages.keys().filter(|name| !is_known(name)).cloned().collect();
+        if unknown.is_empty() {
+            return Ok(());
+        }
+        unknown.sort();
+        Err(format!("Unknown message types in the rate limits: {}", unknown.join(", ")))
+    }
+}
+
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        Self { limit, tokens: limit.burst as f64, last_refill: now }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * self.limit.per_second as f64).min(self.limit.burst as f64);
+        self.last_refill = now;
+    }
+
+    /// Whether at least one token is left. The balance may go below zero, so that messages
+    /// larger than the burst still pass once the bucket is refilled.
+    fn has_tokens(&self) -> bool {
+        self.tokens >= 1.0
+    }
+
+    fn take(&mut self, amount: u64) {
+        self.tokens -= amount as f64;
+    }
+
+    #[cfg(test)]
+    fn try_take(&mut self, amount: u64, now: Instant) -> bool {
+        self.refill(now);
+        if self.has_tokens() {
+            self.take(amount);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RateLimitDecision {
+    Allow,
+    /// Drop the message.
+    Throttle,
+    /// Drop the message and ban the peer, it hit the limits too often with its own messages.
+    Ban,
+}
+
+/// Limits of a single peer.
+pub struct PeerRateLimiter {
+    max_throttled_per_min: u64,
+    messages: HashMap<String, TokenBucket>,
+    bandwidth: Option<TokenBucket>,
+    throttled: RateCounter,
+}
+
+impl PeerRateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Result<Self, String> {
+        config.validate()?;
+        let now = Instant::now();
+        Ok(Self {
+            max_throttled_per_min: config.max_throttled_per_min,
+            messages: config
+                .messages
+                .iter()
+                .map(|(name, limit)| (name.clone(), TokenBucket::new(*limit, now)))
+                .collect(),
+            bandwidth: config.bandwidth.map(|limit| TokenBucket::new(limit, now)),
+            throttled: RateCounter::new(),
+        })
+    }
+
+    /// Accounts a received message of `size` bytes. `msg_variant` is the type of the message if
+    /// it's subject to the per type limits, `None` if it only counts towards the bandwidth.
+    /// Throttled `routed` messages never get the peer banned.
+    pub fn check(
+        &mut self,
+        msg_variant: Option<&str>,
+        size: usize,
+        routed: bool,
+    ) -> RateLimitDecision {
+        self.check_at(msg_variant, size, routed, Instant::now())
+    }
+
+    fn check_at(
+        &mut self,
+        msg_variant: Option<&str>,
+        size: usize,
+        routed: bool,
+        now: Instant,
+    ) -> RateLimitDecision {
+        let messages = &mut self.messages;
+        let mut message_bucket = msg_variant.and_then(move |variant| messages.get_mut(variant));
+        let mut bandwidth_bucket = self.bandwidth.as_mut();
+        // Tokens are taken only if both buckets allow the message, a dropped message shouldn't
+        // use up the limit of the other bucket.
+        let mut allowed = true;
+        for bucket in message_bucket.iter_mut().chain(bandwidth_bucket.iter_mut()) {
+            bucket.refill(now);
+            allowed &= bucket.has_tokens();
+        }
+        if allowed {
+            if let Some(bucket) = message_bucket {
+                bucket.take(1);
+            }
+            if let Some(bucket) = bandwidth_bucket {
+                bucket.take(size as u64);
+            }
+            return RateLimitDecision::Allow;
+        }
+        if routed {
+            return RateLimitDecision::Throttle;
+        }
+        self.throttled.increment(size as u64);
+        if self.throttled.count_per_min() > self.max_throttled_per_min {
+            RateLimitDecision::Ban
+        } else {
+            RateLimitDecision::Throttle
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::rate_limit::{
+        PeerRateLimiter, RateLimit, RateLimitConfig, RateLimitDecision, TokenBucket,
+    };
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(RateLimit { per_second: 10, burst: 3 }, start);
+        for _ in 0..3 {
+            assert!(bucket.try_take(1, start));
+        }
+        assert!(!bucket.try_take(1, start));
+        assert!(!bucket.try_take(1, start + Duration::from_millis(50)));
+        assert!(bucket.try_take(1, start + Duration::from_millis(150)));
+        // Refills up to the burst only.
+        let later = start + Duration::from_secs(10);
+        for _ in 0..3 {
+            assert!(bucket.try_take(1, later));
+        }
+        assert!(!bucket.try_take(1, later));
+        // Large amounts pass once and leave the bucket in debt.
+        let later = later + Duration::from_secs(1);
+        assert!(bucket.try_take(100, later));
+        assert!(!bucket.try_take(1, later + Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn test_peer_rate_limiter() {
+        let mut config = RateLimitConfig::unlimited();
+        config.messages.insert("ForwardTx".to_string(), RateLimit { per_second: 1, burst: 2 });
+        config.bandwidth = Some(RateLimit { per_second: 1000, burst: 1000 });
+        config.max_throttled_per_min = 2;
+        let mut limiter = PeerRateLimiter::new(&config).unwrap();
+        let now = Instant::now();
+
+        assert_eq!(limiter.check_at(Some("ForwardTx"), 10, true, now), RateLimitDecision::Allow);
+        assert_eq!(limiter.check_at(Some("ForwardTx"), 10, true, now), RateLimitDecision::Allow);
+        // Routed messages are throttled, but don't count towards a ban.
+        for _ in 0..5 {
+            assert_eq!(
+                limiter.check_at(Some("ForwardTx"), 10, true, now),
+                RateLimitDecision::Throttle
+            );
+        }
+        // Routed messages not addressed to this node are only limited by the bandwidth.
+        assert_eq!(limiter.check_at(None, 10, true, now), RateLimitDecision::Allow);
+        // Other types are only limited by the bandwidth.
+        assert_eq!(limiter.check_at(Some("Block"), 2000, false, now), RateLimitDecision::Allow);
+        assert_eq!(limiter.check_at(Some("Block"), 10, false, now), RateLimitDecision::Throttle);
+        assert_eq!(
+            limiter.check_at(Some("BlockRequest"), 10, false, now),
+            RateLimitDecision::Throttle
+        );
+        assert_eq!(limiter.check_at(Some("BlockRequest"), 10, false, now), RateLimitDecision::Ban);
+    }
+
+    #[test]
+    fn test_peer_rate_limiter_takes_tokens_if_allowed() {
+        let mut config = RateLimitConfig::unlimited();
+        config.messages.insert("ForwardTx".to_string(), RateLimit { per_second: 1, burst: 1 });
+        config.bandwidth = Some(RateLimit { per_second: 1, burst: 100 });
+        let mut limiter = PeerRateLimiter::new(&config).unwrap();
+        let now = Instant::now();
+
+        assert_eq!(limiter.check_at(Some("ForwardTx"), 10, false, now), RateLimitDecision::Allow);
+        // Dropped by the message limit, the bandwidth isn't used up.
+        for _ in 0..10 {
+            assert_eq!(
+                limiter.check_at(Some("ForwardTx"), 10, false, now),
+                RateLimitDecision::Throttle
+            );
+        }
+        assert_eq!(limiter.check_at(Some("Block"), 80, false, now), RateLimitDecision::Allow);
+    }
+
+    #[test]
+    fn test_unknown_message_types_are_rejected() {
+        assert!(PeerRateLimiter::new(&RateLimitConfig::default()).is_ok());
+        let mut config = RateLimitConfig::unlimited();
+        config.messages.insert("ForwardTx".to_string(), RateLimit { per_second: 1, burst: 1 });
+        config.messages.insert("Block".to_string(), RateLimit { per_second: 1, burst: 1 });
+        assert!(PeerRateLimiter::new(&config).is_ok());
+        for name in ["ForwardTxs", "Routed"].iter() {
+            let mut config = config.clone();
+            config.messages.insert(name.to_string(), RateLimit { per_second: 1, burst: 1 });
+            assert!(PeerRateLimiter::new(&config).is_err());
+        }
+    }
+}
@@ -0,0 +1,393 @@
+//! Encrypted and authenticated transport between peers, set up before the `Handshake`.
+//!
+//! Both sides send a `SecureHello` with an ephemeral X25519 key and derive a ChaCha20-Poly1305
+//! key for each direction from the shared secret, salted with the hash of both hellos. Then each
+//! side proves that it owns its `PeerId` by signing that hash. The responder authenticates only
+//! after the initiator did, so it doesn't reveal its identity to unauthenticated peers. Every
+//! following frame is encrypted with a counter nonce.
+//!
+//! Old nodes fail to parse the hello as a `PeerMessage` and ignore it, so the initiator times out.
+//! It may then try again in plaintext over a new connection, unless
+//! `NetworkConfig::require_secure_transport` is set. Which transport a peer must use is
+//! negotiated via the protocol version: a plaintext `Handshake` of a peer at or above the version
+//! of `ProtocolFeature::SecureTransport` is refused, as the secure transport was stripped on-path.
+//! The version isn't signed, so once a peer spoke the secure transport, the peer store remembers
+//! it and plaintext connections with that peer are refused regardless of the version.
+use std::io::{self, ErrorKind};
+use std::time::Duration;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use sha2::Sha256;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::debug;
+use x25519_dalek::{EphemeralSecret, PublicKey as X25519PublicKey};
+
+use near_crypto::{SecretKey, Signature};
+use near_primitives::hash::{hash, CryptoHash};
+use near_primitives::network::PeerId;
+
+/// Version of the secure transport this node speaks.
+pub const SECURE_TRANSPORT_VERSION: u32 = 1;
+/// Oldest version of the secure transport this node can talk to.
+pub const OLDEST_SECURE_TRANSPORT_VERSION: u32 = 1;
+/// First byte of a hello frame, never a valid `PeerMessage` variant.
+const SECURE_HELLO_TAG: u8 = 0xff;
+/// Hello and authentication frames are much smaller than this.
+const MAX_HANDSHAKE_FRAME_SIZE: u32 = 4096;
+/// Size of the authentication tag added to every encrypted frame.
+pub const ENCRYPTION_OVERHEAD: usize = 16;
+const TRANSCRIPT_PREFIX: &[u8] = b"near secure transport";
+const INITIATOR: &[u8] = b"initiator";
+const RESPONDER: &[u8] = b"responder";
+
+#[derive(BorshSerialize, BorshDeserialize)]
+struct SecureHello {
+    version: u32,
+    oldest_supported_version: u32,
+    ephemeral_key: [u8; 32],
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+struct SecureAuth {
+    peer_id: PeerId,
+    /// Signature of the transcript hash and the role of the sender.
+    signature: Signature,
+}
+
+/// Transport agreed with the peer.
+pub enum Transport {
+    /// Old peer, `leftover` are the bytes already read from the connection.
+    Plaintext { leftover: Vec<u8> },
+    /// Peer authenticated as `peer_id`, frames are encrypted with `send` and decrypted with `recv`.
+    Secure { peer_id: PeerId, send: FrameCipher, recv: FrameCipher },
+}
+
+/// Encrypts or decrypts the frames sent in one direction.
+pub struct FrameCipher {
+    cipher: ChaCha20Poly1305,
+    nonce: u64,
+}
+
+impl FrameCipher {
+    fn new(key: &[u8; 32]) -> Self {
+        Self { cipher: ChaCha20Poly1305::new(Key::from_slice(key)), nonce: 0 }
+    }
+
+    fn next_nonce(&mut self) -> io::Result<[u8; 12]> {
+        if self.nonce == u64::MAX {
+            return Err(io::Error::new(ErrorKind::Other, "Nonces of the connection exhausted"));
+        }
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&self.nonce.to_le_bytes());
+        self.nonce += 1;
+        Ok(nonce)
+    }
+
+    pub fn encrypt(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let nonce = self.next_nonce()?;
+        self.cipher
+            .encrypt(Nonce::from_slice(&nonce), data)
+            .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "Failed to encrypt frame"))
+    }
+
+    pub fn decrypt(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let nonce = self.next_nonce()?;
+        self.cipher
+            .decrypt(Nonce::from_slice(&nonce), data)
+            .map_err(|_| io::Error::new(ErrorKind::InvalidData, "Failed to decrypt frame"))
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+async fn write_frame(stream: &mut TcpStream, data: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(4 + data.len());
+    frame.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    frame.extend_from_slice(data);
+    stream.write_all(&frame).await
+}
+
+async fn read_frame(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let len = stream.read_u32_le().await?;
+    if len > MAX_HANDSHAKE_FRAME_SIZE {
+        return Err(invalid_data("Handshake frame is too long"));
+    }
+    let mut frame = vec![0; len as usize];
+    stream.read_exact(&mut frame).await?;
+    Ok(frame)
+}
+
+fn hello_frame(secret: &EphemeralSecret) -> io::Result<Vec<u8>> {
+    let hello = SecureHello {
+        version: SECURE_TRANSPORT_VERSION,
+        oldest_supported_version: OLDEST_SECURE_TRANSPORT_VERSION,
+        ephemeral_key: X25519PublicKey::from(secret).to_bytes(),
+    };
+    let mut frame = vec![SECURE_HELLO_TAG];
+    hello.serialize(&mut frame)?;
+    Ok(frame)
+}
+
+fn parse_hello(frame: &[u8]) -> io::Result<SecureHello> {
+    if frame.first() != Some(&SECURE_HELLO_TAG) {
+        return Err(invalid_data("Expected secure transport hello"));
+    }
+    let hello = SecureHello::try_from_slice(&frame[1..])?;
+    if hello.version < OLDEST_SECURE_TRANSPORT_VERSION
+        || hello.oldest_supported_version > SECURE_TRANSPORT_VERSION
+    {
+        return Err(io::Error::new(
+            ErrorKind::Other,
+            format!("Unsupported secure transport version {}", hello.version),
+        ));
+    }
+    Ok(hello)
+}
+
+/// Derives the transcript hash and the ciphers for sending and receiving.
+fn start_session(
+    secret: EphemeralSecret,
+    peer_hello: &SecureHello,
+    initiator_hello: &[u8],
+    responder_hello: &[u8],
+    is_initiator: bool,
+) -> io::Result<(CryptoHash, FrameCipher, FrameCipher)> {
+    let shared = secret.diffie_hellman(&X25519PublicKey::from(peer_hello.ephemeral_key));
+    // Low order points give a secret known to anyone
+    if *shared.as_bytes() == [0u8; 32] {
+        return Err(invalid_data("Invalid ephemeral key"));
+    }
+    let transcript = hash(&[TRANSCRIPT_PREFIX, initiator_hello, responder_hello].concat());
+    let hkdf = Hkdf::<Sha256>::new(Some(transcript.as_ref()), shared.as_bytes());
+    let mut initiator_key = [0u8; 32];
+    let mut responder_key = [0u8; 32];
+    hkdf.expand(INITIATOR, &mut initiator_key).expect("32 bytes is a valid length");
+    hkdf.expand(RESPONDER, &mut responder_key).expect("32 bytes is a valid length");
+    let (send, recv) =
+        if is_initiator { (initiator_key, responder_key) } else { (responder_key, initiator_key) };
+    Ok((transcript, FrameCipher::new(&send), FrameCipher::new(&recv)))
+}
+
+fn auth_data(transcript: &CryptoHash, role: &[u8]) -> Vec<u8> {
+    [transcript.as_ref(), role].concat()
+}
+
+async fn send_auth(
+    stream: &mut TcpStream,
+    cipher: &mut FrameCipher,
+    secret_key: &SecretKey,
+    transcript: &CryptoHash,
+    role: &[u8],
+) -> io::Result<()> {
+    let auth = SecureAuth {
+        peer_id: PeerId::new(secret_key.public_key()),
+        signature: secret_key.sign(&auth_data(transcript, role)),
+    };
+    write_frame(stream, &cipher.encrypt(&auth.try_to_vec()?)?).await
+}
+
+/// Returns the peer id which the peer proved to own.
+async fn receive_auth(
+    stream: &mut TcpStream,
+    cipher: &mut FrameCipher,
+    transcript: &CryptoHash,
+    role: &[u8],
+) -> io::Result<PeerId> {
+    let auth = SecureAuth::try_from_slice(&cipher.decrypt(&read_frame(stream).await?)?)?;
+    if !auth.signature.verify(&auth_data(transcript, role), &auth.peer_id.public_key()) {
+        return Err(invalid_data("Invalid signature of secure transport handshake"));
+    }
+    Ok(auth.peer_id)
+}
+
+async fn with_timeout<T>(
+    timeout: Duration,
+    future: impl std::future::Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    tokio::time::timeout(timeout, future).await.unwrap_or_else(|_| {
+        Err(io::Error::new(ErrorKind::TimedOut, "Secure transport handshake timed out"))
+    })
+}
+
+/// Sets up the secure transport of an outbound connection to `target`. Fails with
+/// `ErrorKind::TimedOut` if the peer doesn't answer within `timeout`, e.g. because it doesn't
+/// support the secure transport. The connection can't be used after a failure.
+pub async fn connect(
+    stream: TcpStream,
+    secret_key: SecretKey,
+    target: PeerId,
+    timeout: Duration,
+) -> io::Result<(TcpStream, Transport)> {
+    with_timeout(timeout, connect_inner(stream, secret_key, target)).await
+}
+
+async fn connect_inner(
+    mut stream: TcpStream,
+    secret_key: SecretKey,
+    target: PeerId,
+) -> io::Result<(TcpStream, Transport)> {
+    let secret = EphemeralSecret::new(OsRng);
+    let initiator_hello = hello_frame(&secret)?;
+    write_frame(&mut stream, &initiator_hello).await?;
+    let responder_hello = read_frame(&mut stream).await?;
+    debug!(target: "network", "Received secure transport hello from {}", target);
+    let peer_hello = parse_hello(&responder_hello)?;
+    let (transcript, mut send, mut recv) =
+        start_session(secret, &peer_hello, &initiator_hello, &responder_hello, true)?;
+    send_auth(&mut stream, &mut send, &secret_key, &transcript, INITIATOR).await?;
+    let peer_id = receive_auth(&mut stream, &mut recv, &transcript, RESPONDER).await?;
+    if peer_id != target {
+        return Err(io::Error::new(
+            ErrorKind::PermissionDenied,
+            format!("Expected peer {}, connected to {}", target, peer_id),
+        ));
+    }
+    Ok((stream, Transport::Secure { peer_id, send, recv }))
+}
+
+/// Sets up the transport of an inbound connection.
+pub async fn accept(
+    stream: TcpStream,
+    secret_key: SecretKey,
+    require_secure: bool,
+    timeout: Duration,
+) -> io::Result<(TcpStream, Transport)> {
+    with_timeout(timeout, accept_inner(stream, secret_key, require_secure)).await
+}
+
+async fn accept_inner(
+    mut stream: TcpStream,
+    secret_key: SecretKey,
+    require_secure: bool,
+) -> io::Result<(TcpStream, Transport)> {
+    // Old peers start with a plaintext `Handshake`, so only look at the first byte of the frame.
+    let len = stream.read_u32_le().await?;
+    let mut leftover = len.to_le_bytes().to_vec();
+    if len > 0 {
+        leftover.push(stream.read_u8().await?);
+    }
+    if leftover.get(4) != Some(&SECURE_HELLO_TAG) {
+        if require_secure {
+            return Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                "Peer doesn't support secure transport",
+            ));
+        }
+        return Ok((stream, Transport::Plaintext { leftover }));
+    }
+    if len > MAX_HANDSHAKE_FRAME_SIZE {
+        return Err(invalid_data("Handshake frame is too long"));
+    }
+    let mut initiator_hello = vec![0; len as usize];
+    initiator_hello[0] = SECURE_HELLO_TAG;
+    stream.read_exact(&mut initiator_hello[1..]).await?;
+    let peer_hello = parse_hello(&initiator_hello)?;
+
+    let secret = EphemeralSecret::new(OsRng);
+    let responder_hello = hello_frame(&secret)?;
+    write_frame(&mut stream, &responder_hello).await?;
+    let (transcript, mut send, mut recv) =
+        start_session(secret, &peer_hello, &initiator_hello, &responder_hello, false)?;
+    let peer_id = receive_auth(&mut stream, &mut recv, &transcript, INITIATOR).await?;
+    send_auth(&mut stream, &mut send, &secret_key, &transcript, RESPONDER).await?;
+    Ok((stream, Transport::Secure { peer_id, send, recv }))
+}
+
+#[cfg(test)]
+mod tests {
+    use near_crypto::KeyType;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    async fn connected_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let outbound = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (inbound, _) = listener.accept().await.unwrap();
+        (outbound, inbound)
+    }
+
+    #[tokio::test]
+    async fn test_secure_transport() {
+        let (outbound, inbound) = connected_pair().await;
+        let initiator_key = SecretKey::from_seed(KeyType::ED25519, "initiator");
+        let responder_key = SecretKey::from_seed(KeyType::SECP256K1, "responder");
+        let target = PeerId::new(responder_key.public_key());
+        let (initiator, responder) = tokio::join!(
+            connect(outbound, initiator_key.clone(), target.clone(), TIMEOUT),
+            accept(inbound, responder_key, true, TIMEOUT)
+        );
+        let (mut initiator_send, mut initiator_recv) = match initiator.unwrap().1 {
+            Transport::Secure { peer_id, send, recv } => {
+                assert_eq!(peer_id, target);
+                (send, recv)
+            }
+            Transport::Plaintext { .. } => panic!("expected secure transport"),
+        };
+        let (mut responder_send, mut responder_recv) = match responder.unwrap().1 {
+            Transport::Secure { peer_id, send, recv } => {
+                assert_eq!(peer_id, PeerId::new(initiator_key.public_key()));
+                (send, recv)
+            }
+            Transport::Plaintext { .. } => panic!("expected secure transport"),
+        };
+
+        let frame = initiator_send.encrypt(b"ping").unwrap();
+        assert_eq!(frame.len(), 4 + ENCRYPTION_OVERHEAD);
+        assert_eq!(responder_recv.decrypt(&frame).unwrap(), b"ping".to_vec());
+        // Replayed and tampered frames are rejected
+        assert!(responder_recv.decrypt(&frame).is_err());
+        let mut frame = responder_send.encrypt(b"pong").unwrap();
+        frame[0] ^= 1;
+        assert!(initiator_recv.decrypt(&frame).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_secure_transport_wrong_target() {
+        let (outbound, inbound) = connected_pair().await;
+        let initiator_key = SecretKey::from_seed(KeyType::ED25519, "initiator");
+        let responder_key = SecretKey::from_seed(KeyType::ED25519, "responder");
+        let target = PeerId::new(SecretKey::from_seed(KeyType::ED25519, "other").public_key());
+        let (initiator, _) = tokio::join!(
+            connect(outbound, initiator_key, target, TIMEOUT),
+            accept(inbound, responder_key, false, TIMEOUT)
+        );
+        assert_eq!(initiator.err().unwrap().kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn test_plaintext_fallback() {
+        // Old responders ignore the hello and wait for a `Handshake`, the initiator times out
+        // instead of continuing in plaintext
+        let (outbound, mut inbound) = connected_pair().await;
+        let initiator_key = SecretKey::from_seed(KeyType::ED25519, "initiator");
+        let target = PeerId::new(SecretKey::from_seed(KeyType::ED25519, "old").public_key());
+        let (initiator, hello) = tokio::join!(
+            connect(outbound, initiator_key, target, Duration::from_millis(100)),
+            async { read_frame(&mut inbound).await }
+        );
+        assert_eq!(hello.unwrap()[0], SECURE_HELLO_TAG);
+        assert_eq!(initiator.err().unwrap().kind(), ErrorKind::TimedOut);
+
+        // Old initiators start with a `Handshake`, which is passed on to the peer
+        let (mut outbound, inbound) = connected_pair().await;
+        let responder_key = SecretKey::from_seed(KeyType::ED25519, "responder");
+        write_frame(&mut outbound, &[0, 1, 2]).await.unwrap();
+        match accept(inbound, responder_key.clone(), false, TIMEOUT).await.unwrap().1 {
+            Transport::Plaintext { leftover } => assert_eq!(leftover, vec![3, 0, 0, 0, 0]),
+            Transport::Secure { .. } => panic!("expected plaintext transport"),
+        }
+        let (mut outbound, inbound) = connected_pair().await;
+        write_frame(&mut outbound, &[0, 1, 2]).await.unwrap();
+        assert!(accept(inbound, responder_key, true, TIMEOUT).await.is_err());
+    }
+}
@@ -0,0 +1,306 @@
+//! Compression and splitting of state parts sent to peers which support `StatePartChunk`.
+//!
+//! Support is negotiated via the protocol version of the `Handshake`: peers whose version enables
+//! `ProtocolFeature::StatePartChunks` decode every `StatePartCompression`. Older peers never
+//! receive a `StatePartChunk`.
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read};
+
+use bytesize::MIB;
+
+use near_primitives::checked_feature;
+use near_primitives::hash::CryptoHash;
+use near_primitives::types::ShardId;
+use near_primitives::version::ProtocolVersion;
+
+use crate::types::{StatePartChunk, StatePartCompression};
+
+/// Largest state part accepted, after decompression.
+const MAX_STATE_PART_SIZE: u64 = 32 * MIB;
+/// Maximum number of pieces of a single state part.
+const MAX_STATE_PART_CHUNKS: u32 = 1024;
+/// Maximum number of bytes of pieces buffered for a single peer. The oldest parts in progress
+/// are dropped when a new piece doesn't fit.
+const MAX_BUFFERED_BYTES: u64 = 32 * MIB;
+/// Maximum number of parts requested from a single peer which are waited for. The oldest
+/// request is forgotten when a new one doesn't fit.
+const MAX_REQUESTED_STATE_PARTS: usize = 64;
+const ZSTD_LEVEL: i32 = 3;
+
+type StatePartKey = (ShardId, CryptoHash, u64);
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Compression of state parts sent in pieces to a peer of `peer_protocol_version`, `None` if
+/// the peer doesn't support pieces.
+pub fn state_part_compression(
+    enabled: bool,
+    peer_protocol_version: ProtocolVersion,
+) -> Option<StatePartCompression> {
+    if !checked_feature!(
+        "protocol_feature_state_part_chunks",
+        StatePartChunks,
+        peer_protocol_version
+    ) {
+        None
+    } else if enabled {
+        Some(StatePartCompression::Zstd)
+    } else {
+        Some(StatePartCompression::None)
+    }
+}
+
+/// Compresses the state part and splits it into pieces of at most `chunk_size` bytes, or into
+/// `MAX_STATE_PART_CHUNKS` pieces if there would be more.
+pub fn split_state_part(
+    shard_id: ShardId,
+    sync_hash: CryptoHash,
+    part_id: u64,
+    part: &[u8],
+    compression: StatePartCompression,
+    chunk_size: usize,
+) -> io::Result<Vec<StatePartChunk>> {
+    let data = match compression {
+        StatePartCompression::None => part.to_vec(),
+        StatePartCompression::Zstd => zstd::stream::encode_all(part, ZSTD_LEVEL)?,
+    };
+    let min_chunk_size =
+        (data.len() + MAX_STATE_PART_CHUNKS as usize - 1) / MAX_STATE_PART_CHUNKS as usize;
+    let chunk_size = chunk_size.max(min_chunk_size).max(1);
+    let pieces: Vec<&[u8]> =
+        if data.is_empty() { vec![&data[..]] } else { data.chunks(chunk_size).collect() };
+    let num_chunks = pieces.len() as u32;
+    Ok(pieces
+        .into_iter()
+        .enumerate()
+        .map(|(chunk_index, piece)| StatePartChunk {
+            shard_id,
+            sync_hash,
+            part_id,
+            chunk_index: chunk_index as u32,
+            num_chunks,
+            compression,
+            data: piece.to_vec(),
+        })
+        .collect())
+}
+
+struct PartInProgress {
+    compression: StatePartCompression,
+    chunks: Vec<Option<Vec<u8>>>,
+    num_received: u32,
+    size: u64,
+}
+
+/// Collects pieces of the state parts requested from a single peer.
+#[derive(Default)]
+pub struct StatePartAssembler {
+    /// Parts requested from the peer, oldest first.
+    requested: VecDeque<StatePartKey>,
+    parts: HashMap<StatePartKey, PartInProgress>,
+    /// Total size of the pieces in `parts`.
+    buffered: u64,
+}
+
+impl StatePartAssembler {
+    /// Records that a state part was requested from the peer, so that its pieces are accepted.
+    pub fn expect(&mut self, shard_id: ShardId, sync_hash: CryptoHash, part_id: u64) {
+        let key = (shard_id, sync_hash, part_id);
+        if self.requested.contains(&key) {
+            return;
+        }
+        if self.requested.len() >= MAX_REQUESTED_STATE_PARTS {
+            if let Some(oldest) = self.requested.pop_front() {
+                self.remove_part(&oldest);
+            }
+        }
+        self.requested.push_back(key);
+    }
+
+    /// Adds a piece of a requested state part. Returns the decompressed part once all its
+    /// pieces arrived.
+    pub fn add(&mut self, chunk: StatePartChunk) -> io::Result<Option<Vec<u8>>> {
+        let StatePartChunk {
+            shard_id,
+            sync_hash,
+            part_id,
+            chunk_index,
+            num_chunks,
+            compression,
+            data,
+        } = chunk;
+        let key = (shard_id, sync_hash, part_id);
+        if !self.requested.contains(&key) {
+            return Err(invalid_data(format!("part {:?} wasn't requested", key)));
+        }
+        if num_chunks > MAX_STATE_PART_CHUNKS || chunk_index >= num_chunks {
+            return Err(invalid_data(format!("piece {} of {}", chunk_index, num_chunks)));
+        }
+        let part = self.parts.entry(key).or_insert_with(|| PartInProgress {
+            compression,
+            chunks: vec![None; num_chunks as usize],
+            num_received: 0,
+            size: 0,
+        });
+        if part.chunks.len() != num_chunks as usize || part.compression != compression {
+            return Err(invalid_data(format!("inconsistent pieces of part {:?}", key)));
+        }
+        if part.chunks[chunk_index as usize].is_some() {
+            return Ok(None);
+        }
+        if part.size + data.len() as u64 > MAX_STATE_PART_SIZE {
+            self.forget(&key);
+            return Err(invalid_data(format!("part {:?} is too large", key)));
+        }
+        self.make_room(&key, data.len() as u64);
+        let part = self.parts.get_mut(&key).unwrap();
+        part.size += data.len() as u64;
+        self.buffered += data.len() as u64;
+        part.chunks[chunk_index as usize] = Some(data);
+        part.num_received += 1;
+        if part.num_received < num_chunks {
+            return Ok(None);
+        }
+
+        let part = self.forget(&key).unwrap();
+        let data: Vec<u8> = part.chunks.into_iter().flat_map(|piece| piece.unwrap()).collect();
+        match part.compression {
+            StatePartCompression::None => Ok(Some(data)),
+            StatePartCompression::Zstd => {
+                let mut part = vec![];
+                zstd::stream::read::Decoder::new(&data[..])?
+                    .take(MAX_STATE_PART_SIZE + 1)
+                    .read_to_end(&mut part)?;
+                if part.len() as u64 > MAX_STATE_PART_SIZE {
+                    return Err(invalid_data(format!("part {:?} is too large", key)));
+                }
+                Ok(Some(part))
+            }
+        }
+    }
+
+    /// Drops the oldest parts in progress other than `key` until `size` more bytes fit. Their
+    /// requests are forgotten, so their remaining pieces are rejected.
+    fn make_room(&mut self, key: &StatePartKey, size: u64) {
+        while self.buffered + size > MAX_BUFFERED_BYTES {
+            let oldest = match self
+                .requested
+                .iter()
+                .find(|other| *other != key && self.parts.contains_key(other))
+            {
+                Some(oldest) => *oldest,
+                None => break,
+            };
+            self.forget(&oldest);
+        }
+    }
+
+    /// Forgets the request of a part and drops its pieces.
+    fn forget(&mut self, key: &StatePartKey) -> Option<PartInProgress> {
+        self.requested.retain(|other| other != key);
+        self.remove_part(key)
+    }
+
+    fn remove_part(&mut self, key: &StatePartKey) -> Option<PartInProgress> {
+        let part = self.parts.remove(key)?;
+        self.buffered -= part.size;
+        Some(part)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_primitives::hash::hash;
+    #[cfg(feature = "protocol_feature_state_part_chunks")]
+    use near_primitives::version::ProtocolFeature;
+    #[cfg(not(feature = "protocol_feature_state_part_chunks"))]
+    use near_primitives::version::PROTOCOL_VERSION;
+
+    use super::*;
+
+    fn assemble(chunks: Vec<StatePartChunk>) -> Option<Vec<u8>> {
+        let mut assembler = StatePartAssembler::default();
+        let mut result = None;
+        for chunk in chunks.into_iter().rev() {
+            assembler.expect(chunk.shard_id, chunk.sync_hash, chunk.part_id);
+            assert!(result.is_none());
+            result = assembler.add(chunk).unwrap();
+        }
+        result
+    }
+
+    #[test]
+    fn test_split_and_assemble() {
+        let part: Vec<u8> = (0..10_000u32).flat_map(|i| (i % 7).to_le_bytes().to_vec()).collect();
+        let sync_hash = hash(&[1]);
+        for compression in [StatePartCompression::None, StatePartCompression::Zstd].iter() {
+            let chunks = split_state_part(0, sync_hash, 2, &part, *compression, 1000).unwrap();
+            assert!(chunks.len() > 1);
+            assert_eq!(assemble(chunks), Some(part.clone()));
+        }
+        let chunks =
+            split_state_part(0, sync_hash, 2, &[], StatePartCompression::None, 1000).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(assemble(chunks), Some(vec![]));
+    }
+
+    #[test]
+    fn test_invalid_chunks() {
+        let sync_hash = hash(&[1]);
+        let mut chunks =
+            split_state_part(0, sync_hash, 2, &[1, 2, 3], StatePartCompression::None, 1).unwrap();
+        let mut assembler = StatePartAssembler::default();
+        // Parts which weren't requested are rejected
+        assert!(assembler.add(chunks[0].clone()).is_err());
+        assembler.expect(0, sync_hash, 2);
+        assert_eq!(assembler.add(chunks[0].clone()).unwrap(), None);
+        chunks[1].num_chunks = 4;
+        assert!(assembler.add(chunks[1].clone()).is_err());
+        chunks[2].chunk_index = 3;
+        assert!(assembler.add(chunks[2].clone()).is_err());
+    }
+
+    #[test]
+    fn test_buffered_bytes_limit() {
+        let sync_hash = hash(&[1]);
+        let mut assembler = StatePartAssembler::default();
+        let piece_size = (MAX_BUFFERED_BYTES / 2 + 1) as usize;
+        let part = vec![0u8; 2 * piece_size];
+        for part_id in 0..2 {
+            assembler.expect(0, sync_hash, part_id);
+            let chunks = split_state_part(
+                0,
+                sync_hash,
+                part_id,
+                &part,
+                StatePartCompression::None,
+                piece_size,
+            )
+            .unwrap();
+            assert_eq!(assembler.add(chunks[0].clone()).unwrap(), None);
+        }
+        // The first part was dropped to make room for the second one
+        assert_eq!(assembler.buffered, piece_size as u64);
+        let chunks =
+            split_state_part(0, sync_hash, 0, &part, StatePartCompression::None, piece_size)
+                .unwrap();
+        assert!(assembler.add(chunks[1].clone()).is_err());
+    }
+
+    #[cfg(feature = "protocol_feature_state_part_chunks")]
+    #[test]
+    fn test_state_part_compression() {
+        let version = ProtocolFeature::StatePartChunks.protocol_version();
+        assert_eq!(state_part_compression(true, version), Some(StatePartCompression::Zstd));
+        assert_eq!(state_part_compression(false, version), Some(StatePartCompression::None));
+        assert_eq!(state_part_compression(true, version - 1), None);
+    }
+
+    #[cfg(not(feature = "protocol_feature_state_part_chunks"))]
+    #[test]
+    fn test_state_part_compression_disabled() {
+        assert_eq!(state_part_compression(true, PROTOCOL_VERSION), None);
+    }
+}
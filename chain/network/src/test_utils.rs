@@ -13,6 +13,7 @@ use near_primitives::network::PeerId;
 use near_primitives::types::EpochId;
 use near_primitives::utils::index_to_bytes;
 
+use crate::rate_limit::RateLimitConfig;
 use crate::types::{NetworkConfig, NetworkInfo, PeerInfo, ReasonForBan, ROUTED_MESSAGE_TTL};
 use crate::{NetworkAdapter, NetworkRequests, NetworkResponses, PeerManagerActor};
 use futures::future::BoxFuture;
@@ -79,6 +80,12 @@ impl NetworkConfig {
             blacklist: HashMap::new(),
             outbound_disabled: false,
             archive: false,
+            state_part_compression: true,
+            state_part_chunk_size: 16 * bytesize::MIB as usize,
+            require_secure_transport: false,
+            capture: None,
+            rate_limits: RateLimitConfig::unlimited(),
+            max_outbound_peers_per_subnet: 2,
         }
     }
 }
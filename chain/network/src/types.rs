@@ -1,6 +1,7 @@
 use std::collections::{HashMap, HashSet};
 use std::convert::{Into, TryFrom, TryInto};
 use std::fmt;
+use std::io;
 use std::net::{AddrParseError, IpAddr, SocketAddr};
 use std::str::FromStr;
 use std::sync::{Arc, Mutex, RwLock};
@@ -41,7 +42,9 @@ use near_primitives::version::{
 };
 use near_primitives::views::{FinalExecutionOutcomeView, QueryRequest, QueryResponse};
 
+use crate::capture::CaptureConfig;
 use crate::peer::Peer;
+use crate::rate_limit::RateLimitConfig;
 #[cfg(feature = "metric_recorder")]
 use crate::recorder::MetricRecorder;
 use crate::routing::{Edge, EdgeInfo, RoutingTableInfo};
@@ -768,6 +771,8 @@ pub enum PeerMessage {
     EpochSyncResponse(EpochSyncResponse),
     EpochSyncFinalizationRequest(EpochId),
     EpochSyncFinalizationResponse(EpochSyncFinalizationResponse),
+
+    StatePartChunk(StatePartChunk),
 }
 
 impl fmt::Display for PeerMessage {
@@ -793,7 +798,8 @@ impl PeerMessage {
             | PeerMessage::Transaction(_)
             | PeerMessage::Challenge(_)
             | PeerMessage::EpochSyncResponse(_)
-            | PeerMessage::EpochSyncFinalizationResponse(_) => true,
+            | PeerMessage::EpochSyncFinalizationResponse(_)
+            | PeerMessage::StatePartChunk(_) => true,
             PeerMessage::Routed(r) => match r.body {
                 RoutedMessageBody::BlockApproval(_)
                 | RoutedMessageBody::ForwardTx(_)
@@ -896,6 +902,18 @@ pub struct NetworkConfig {
     pub outbound_disabled: bool,
     /// Not clear old data, set `true` for archive nodes.
     pub archive: bool,
+    /// Compress state parts sent to peers which support it.
+    pub state_part_compression: bool,
+    /// Maximum size of a single message carrying a state part, larger parts are split.
+    pub state_part_chunk_size: usize,
+    /// Refuse peers which don't support the encrypted transport, see `secure_transport`.
+    pub require_secure_transport: bool,
+    /// Write all messages exchanged with peers to files, see `capture`.
+    pub capture: Option<CaptureConfig>,
+    /// Limits of the messages received from each peer.
+    pub rate_limits: RateLimitConfig,
+    /// Maximum number of outbound connections to peers in the same subnet, see `ip_subnet`.
+    pub max_outbound_peers_per_subnet: u32,
 }
 
 impl NetworkConfig {
@@ -964,7 +982,7 @@ impl FromStr for PatternAddr {
 }
 
 /// Status of the known peers.
-#[derive(BorshSerialize, BorshDeserialize, Serialize, Eq, PartialEq, Debug, Clone)]
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
 pub enum KnownPeerStatus {
     Unknown,
     NotConnected,
@@ -981,13 +999,84 @@ impl KnownPeerStatus {
     }
 }
 
+/// Number of pongs the mean round trip time of a peer is averaged over.
+const RTT_SAMPLES_WINDOW: u64 = 16;
+
+/// Connection history of a known peer.
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Default, Debug, Clone, PartialEq,
+)]
+pub struct PeerStats {
+    /// Number of outbound connections which completed the handshake.
+    pub successful_connections: u64,
+    /// Number of outbound connections which failed before the handshake completed.
+    pub failed_connections: u64,
+    /// Moving average of the round trip time of pings in milliseconds.
+    pub mean_rtt_ms: Option<f64>,
+    pub rtt_samples: u64,
+    /// Total time the peer was connected, in seconds.
+    pub uptime_secs: u64,
+    /// Height of the peer's chain the last time it was connected.
+    pub last_seen_height: BlockHeight,
+}
+
+impl PeerStats {
+    pub fn add_rtt(&mut self, rtt_ms: f64) {
+        self.rtt_samples += 1;
+        let weight = 1.0 / self.rtt_samples.min(RTT_SAMPLES_WINDOW) as f64;
+        let mean_rtt_ms = self.mean_rtt_ms.unwrap_or(rtt_ms);
+        self.mean_rtt_ms = Some(mean_rtt_ms + (rtt_ms - mean_rtt_ms) * weight);
+    }
+
+    /// Weight of the peer when choosing peers to connect to: the handshake success rate,
+    /// discounted by the round trip time and increased by up to a day of uptime. Peers without
+    /// history get the score of a peer with 50% success rate and 100ms round trip time.
+    pub fn score(&self) -> f64 {
+        let success_rate = (self.successful_connections + 1) as f64
+            / (self.successful_connections + self.failed_connections + 2) as f64;
+        let rtt_factor = 100.0 / (100.0 + self.mean_rtt_ms.unwrap_or(100.0));
+        let uptime_factor = 1.0 + (self.uptime_secs as f64 / 86400.0).min(1.0);
+        success_rate * rtt_factor * uptime_factor
+    }
+}
+
 /// Information node stores about known peers.
-#[derive(BorshSerialize, BorshDeserialize, Serialize, Debug, Clone)]
+#[derive(BorshSerialize, Serialize, Deserialize, Debug, Clone)]
 pub struct KnownPeerState {
     pub peer_info: PeerInfo,
     pub status: KnownPeerStatus,
     pub first_seen: u64,
     pub last_seen: u64,
+    pub stats: PeerStats,
+    /// Why the node operator banned the peer, kept until the peer is unbanned.
+    pub ban_comment: Option<String>,
+    /// Whether the peer connected over the secure transport once, after which plaintext
+    /// connections with it are refused.
+    pub secure_transport: bool,
+}
+
+/// Peers stored before `stats`, `ban_comment` and `secure_transport` were added are loaded
+/// without them.
+impl BorshDeserialize for KnownPeerState {
+    fn deserialize(buf: &mut &[u8]) -> Result<Self, io::Error> {
+        let peer_info = PeerInfo::deserialize(buf)?;
+        let status = KnownPeerStatus::deserialize(buf)?;
+        let first_seen = u64::deserialize(buf)?;
+        let last_seen = u64::deserialize(buf)?;
+        let stats =
+            if buf.is_empty() { PeerStats::default() } else { PeerStats::deserialize(buf)? };
+        let ban_comment = if buf.is_empty() { None } else { Option::<String>::deserialize(buf)? };
+        let secure_transport = if buf.is_empty() { false } else { bool::deserialize(buf)? };
+        Ok(KnownPeerState {
+            peer_info,
+            status,
+            first_seen,
+            last_seen,
+            stats,
+            ban_comment,
+            secure_transport,
+        })
+    }
 }
 
 impl KnownPeerState {
@@ -997,6 +1086,9 @@ impl KnownPeerState {
             status: KnownPeerStatus::Unknown,
             first_seen: to_timestamp(Utc::now()),
             last_seen: to_timestamp(Utc::now()),
+            stats: PeerStats::default(),
+            ban_comment: None,
+            secure_transport: false,
         }
     }
 
@@ -1040,6 +1132,55 @@ pub struct OutboundTcpConnect {
     pub peer_info: PeerInfo,
 }
 
+/// Peer management requests of the node operator, served by the admin RPC.
+#[derive(Message, Debug)]
+#[rtype(result = "Result<PeerAdminResponse, PeerAdminError>")]
+pub enum PeerAdminRequest {
+    /// Disconnects the peer and refuses connections from it for `NetworkConfig::ban_window`.
+    Ban {
+        peer_id: PeerId,
+        ban_reason: ReasonForBan,
+        /// Free-text note of the operator, listed with the known peers.
+        comment: Option<String>,
+    },
+    Unban {
+        peer_id: PeerId,
+    },
+    /// Connects to the peer right away, adding it to the known peers.
+    Connect {
+        peer_info: PeerInfo,
+    },
+    Disconnect {
+        peer_id: PeerId,
+    },
+    ListKnownPeers,
+}
+
+#[derive(Debug)]
+pub enum PeerAdminResponse {
+    Done,
+    KnownPeers(Vec<KnownPeerStateView>),
+}
+
+#[derive(Debug)]
+pub enum PeerAdminError {
+    UnknownPeer(PeerId),
+    PeerBanned(PeerId),
+    NotBanned(PeerId),
+    NotConnected(PeerId),
+    NoAddress(PeerId),
+    IOError(String),
+}
+
+/// Known peer and the time its ban expires at.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KnownPeerStateView {
+    #[serde(flatten)]
+    pub state: KnownPeerState,
+    /// Timestamp in nanoseconds, `None` if the peer isn't banned.
+    pub ban_expires_at: Option<u64>,
+}
+
 #[derive(Message, Clone, Debug)]
 #[rtype(result = "()")]
 pub struct SendMessage {
@@ -1059,6 +1200,8 @@ pub struct Consolidate {
     pub this_edge_info: Option<EdgeInfo>,
     // Edge information from other node.
     pub other_edge_info: EdgeInfo,
+    /// Whether the connection runs over the secure transport.
+    pub secure_transport: bool,
 }
 
 impl Message for Consolidate {
@@ -1131,7 +1274,9 @@ where
 }
 
 /// Ban reason.
-#[derive(BorshSerialize, BorshDeserialize, Serialize, Debug, Clone, PartialEq, Eq, Copy)]
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Copy,
+)]
 pub enum ReasonForBan {
     None = 0,
     BadBlock = 1,
@@ -1399,6 +1544,45 @@ impl StateResponseInfo {
     }
 }
 
+/// Compression of state parts sent in `StatePartChunk`.
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum StatePartCompression {
+    None,
+    Zstd,
+}
+
+/// Piece of a state part, sent directly to the requesting peer if its protocol version supports
+/// it, see `state_parts`. Parts which don't fit into a single message are split into several
+/// pieces.
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Clone)]
+pub struct StatePartChunk {
+    pub shard_id: ShardId,
+    pub sync_hash: CryptoHash,
+    pub part_id: u64,
+    /// Index of this piece among `num_chunks` pieces of the part.
+    pub chunk_index: u32,
+    pub num_chunks: u32,
+    /// Compression of the whole part, which is split after compressing.
+    pub compression: StatePartCompression,
+    pub data: Vec<u8>,
+}
+
+impl Debug for StatePartChunk {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(
+            f,
+            "StatePartChunk({}, {}, {}, {}/{}, {:?}, {} bytes)",
+            self.shard_id,
+            self.sync_hash,
+            self.part_id,
+            self.chunk_index,
+            self.num_chunks,
+            self.compression,
+            self.data.len()
+        )
+    }
+}
+
 #[cfg(feature = "adversarial")]
 #[derive(Debug)]
 pub enum NetworkAdversarialMessage {
@@ -1799,6 +1983,7 @@ mod tests {
         assert_size!(FullPeerInfo);
         assert_size!(NetworkInfo);
         assert_size!(StateResponseInfoV1);
+        assert_size!(StatePartChunk);
         assert_size!(QueryPeerStats);
         assert_size!(PartialEncodedChunkRequestMsg);
     }
@@ -1,7 +1,8 @@
 use cached::SizedCache;
 use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
 use std::iter::FromIterator;
-use std::{hash::Hash, net::IpAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 use crate::types::{BlockedPorts, PatternAddr};
 
@@ -38,6 +39,27 @@ where
     blacklist_map
 }
 
+/// Subnet of `ip` used to spread outbound connections, /24 for IPv4 and /48 for IPv6 addresses.
+/// `None` for loopback and private addresses, which aren't limited.
+pub fn ip_subnet(ip: &IpAddr) -> Option<IpAddr> {
+    match ip {
+        IpAddr::V4(ip) => {
+            if ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified() {
+                return None;
+            }
+            let [a, b, c, _] = ip.octets();
+            Some(IpAddr::V4(Ipv4Addr::new(a, b, c, 0)))
+        }
+        IpAddr::V6(ip) => {
+            if ip.is_loopback() || ip.is_unspecified() {
+                return None;
+            }
+            let segments = ip.segments();
+            Some(IpAddr::V6(Ipv6Addr::new(segments[0], segments[1], segments[2], 0, 0, 0, 0, 0)))
+        }
+    }
+}
+
 pub fn cache_to_hashmap<K: Hash + Eq + Clone, V: Clone>(cache: &SizedCache<K, V>) -> HashMap<K, V> {
     let keys: Vec<_> = cache.key_order().cloned().collect();
     keys.into_iter().zip(cache.value_order().cloned()).collect()
@@ -0,0 +1,88 @@
+use actix::actors::mocker::Mocker;
+use actix::{Actor, System};
+use borsh::BorshSerialize;
+
+use near_actix_test_utils::run_actix_until_stop;
+use near_client::{ClientActor, ViewClientActor};
+use near_logger_utils::init_test_logger;
+use near_network::capture::{
+    replay_to_peer_manager, CaptureDirection, CapturedMessage, ReplaySpeed,
+};
+use near_network::test_utils::open_port;
+use near_network::types::{
+    NetworkViewClientMessages, NetworkViewClientResponses, PeerInfo, PeerMessage, PeersRequest,
+};
+use near_network::{NetworkClientResponses, NetworkConfig, PeerManagerActor};
+use near_primitives::network::PeerId;
+use near_store::test_utils::create_test_store;
+
+type ClientMock = Mocker<ClientActor>;
+type ViewClientMock = Mocker<ViewClientActor>;
+
+/// Peers received by a node are replayed into a running peer manager, which then shares them.
+#[test]
+fn replay_peers_response() {
+    init_test_logger();
+
+    run_actix_until_stop(async {
+        let config = NetworkConfig::from_seed("test1", open_port());
+        let node_id: PeerId = config.public_key.clone().into();
+        let client_addr = ClientMock::mock(Box::new(move |_msg, _ctx| {
+            Box::new(Some(NetworkClientResponses::NoResponse))
+        }))
+        .start();
+        let view_client_addr = ViewClientMock::mock(Box::new(move |msg, _ctx| {
+            let msg = msg.downcast_ref::<NetworkViewClientMessages>().unwrap();
+            match msg {
+                NetworkViewClientMessages::GetChainInfo => {
+                    Box::new(Some(NetworkViewClientResponses::ChainInfo {
+                        genesis_id: Default::default(),
+                        height: 1,
+                        tracked_shards: vec![],
+                        archival: false,
+                    }))
+                }
+                _ => Box::new(Some(NetworkViewClientResponses::NoResponse)),
+            }
+        }))
+        .start();
+        let pm = PeerManagerActor::new(
+            create_test_store(),
+            config,
+            client_addr.recipient(),
+            view_client_addr.recipient(),
+        )
+        .unwrap()
+        .start();
+
+        let peer_id = PeerId::random();
+        let received_peer = PeerInfo::new(PeerId::random(), "127.0.0.1:24567".parse().unwrap());
+        let sent_peer = PeerInfo::new(PeerId::random(), "127.0.0.1:24568".parse().unwrap());
+        let capture = |direction, peer_id: Option<PeerId>, peers: Vec<PeerInfo>| {
+            CapturedMessage::new(
+                direction,
+                node_id.clone(),
+                peer_id,
+                PeerMessage::PeersResponse(peers).try_to_vec().unwrap(),
+            )
+        };
+        let messages = vec![
+            // Only messages received after the handshake are replayed.
+            capture(CaptureDirection::Sent, Some(peer_id.clone()), vec![sent_peer.clone()]),
+            capture(CaptureDirection::Received, None, vec![sent_peer.clone()]),
+            capture(CaptureDirection::Received, Some(peer_id.clone()), vec![received_peer.clone()]),
+        ];
+        let delivered = replay_to_peer_manager(
+            messages.into_iter().map(Ok),
+            pm.clone(),
+            ReplaySpeed::Unlimited,
+        )
+        .await;
+        assert_eq!(delivered, 1);
+
+        let peers = pm.send(PeersRequest {}).await.unwrap().peers;
+        assert!(peers.iter().any(|peer| peer.id == received_peer.id));
+        assert!(peers.iter().all(|peer| peer.id != sent_peer.id));
+        System::current().stop();
+    });
+}
@@ -0,0 +1,186 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use actix::actors::mocker::Mocker;
+use actix::{Actor, Addr, System};
+use futures::{future, FutureExt};
+
+use near_actix_test_utils::run_actix_until_stop;
+use near_client::{ClientActor, ViewClientActor};
+use near_logger_utils::init_test_logger;
+use near_network::test_utils::{open_port, GetInfo, WaitOrTimeout};
+use near_network::types::{
+    KnownPeerStateView, KnownPeerStatus, NetworkViewClientMessages, NetworkViewClientResponses,
+    PeerAdminError, PeerAdminRequest, PeerAdminResponse, PeerInfo, ReasonForBan,
+};
+use near_network::{NetworkClientResponses, NetworkConfig, PeerManagerActor};
+use near_primitives::network::PeerId;
+use near_store::test_utils::create_test_store;
+
+type ClientMock = Mocker<ClientActor>;
+type ViewClientMock = Mocker<ViewClientActor>;
+
+/// Peer manager that only connects to the peers it is asked to.
+fn make_peer_manager(seed: &str, port: u16) -> (PeerInfo, PeerManagerActor) {
+    let mut config = NetworkConfig::from_seed(seed, port);
+    config.outbound_disabled = true;
+    let peer_info = PeerInfo::new(
+        config.public_key.clone().into(),
+        format!("127.0.0.1:{}", port).parse().unwrap(),
+    );
+    let client_addr = ClientMock::mock(Box::new(move |_msg, _ctx| {
+        Box::new(Some(NetworkClientResponses::NoResponse))
+    }))
+    .start();
+    let view_client_addr = ViewClientMock::mock(Box::new(move |msg, _ctx| {
+        let msg = msg.downcast_ref::<NetworkViewClientMessages>().unwrap();
+        match msg {
+            NetworkViewClientMessages::GetChainInfo => {
+                Box::new(Some(NetworkViewClientResponses::ChainInfo {
+                    genesis_id: Default::default(),
+                    height: 1,
+                    tracked_shards: vec![],
+                    archival: false,
+                }))
+            }
+            _ => Box::new(Some(NetworkViewClientResponses::NoResponse)),
+        }
+    }))
+    .start();
+    let pm = PeerManagerActor::new(
+        create_test_store(),
+        config,
+        client_addr.recipient(),
+        view_client_addr.recipient(),
+    )
+    .unwrap();
+    (peer_info, pm)
+}
+
+async fn known_peer(pm: &Addr<PeerManagerActor>, peer_id: &PeerId) -> Option<KnownPeerStateView> {
+    match pm.send(PeerAdminRequest::ListKnownPeers).await.unwrap() {
+        Ok(PeerAdminResponse::KnownPeers(known_peers)) => {
+            known_peers.into_iter().find(|peer| &peer.state.peer_info.id == peer_id)
+        }
+        res => panic!("unexpected response {:?}", res),
+    }
+}
+
+/// Unknown peers are rejected without being added, known ones are banned with the operator's
+/// comment and unbanned. Peers which aren't banned can't be unbanned.
+#[test]
+fn ban_and_unban_peer() {
+    init_test_logger();
+
+    run_actix_until_stop(async {
+        let (_, pm) = make_peer_manager("test1", open_port());
+        let pm = pm.start();
+
+        let unknown_peer_id = PeerId::random();
+        let res = pm
+            .send(PeerAdminRequest::Ban {
+                peer_id: unknown_peer_id.clone(),
+                ban_reason: ReasonForBan::Abusive,
+                comment: None,
+            })
+            .await
+            .unwrap();
+        assert!(matches!(res, Err(PeerAdminError::UnknownPeer(_))), "{:?}", res);
+        let res =
+            pm.send(PeerAdminRequest::Unban { peer_id: unknown_peer_id.clone() }).await.unwrap();
+        assert!(matches!(res, Err(PeerAdminError::UnknownPeer(_))), "{:?}", res);
+        assert!(known_peer(&pm, &unknown_peer_id).await.is_none());
+
+        // Nothing listens on the port, the peer is only added to the known peers.
+        let peer_info =
+            PeerInfo::new(PeerId::random(), format!("127.0.0.1:{}", open_port()).parse().unwrap());
+        let peer_id = peer_info.id.clone();
+        let res =
+            pm.send(PeerAdminRequest::Connect { peer_info: peer_info.clone() }).await.unwrap();
+        assert!(matches!(res, Ok(PeerAdminResponse::Done)), "{:?}", res);
+        assert!(known_peer(&pm, &peer_id).await.is_some());
+        let res = pm.send(PeerAdminRequest::Unban { peer_id: peer_id.clone() }).await.unwrap();
+        assert!(matches!(res, Err(PeerAdminError::NotBanned(_))), "{:?}", res);
+
+        let res = pm
+            .send(PeerAdminRequest::Ban {
+                peer_id: peer_id.clone(),
+                ban_reason: ReasonForBan::Abusive,
+                comment: Some("spams transactions".to_string()),
+            })
+            .await
+            .unwrap();
+        assert!(matches!(res, Ok(PeerAdminResponse::Done)), "{:?}", res);
+        let peer = known_peer(&pm, &peer_id).await.unwrap();
+        assert!(matches!(peer.state.status, KnownPeerStatus::Banned(ReasonForBan::Abusive, _)));
+        assert_eq!(peer.state.ban_comment, Some("spams transactions".to_string()));
+        assert!(peer.ban_expires_at.is_some());
+
+        let res = pm.send(PeerAdminRequest::Connect { peer_info }).await.unwrap();
+        assert!(matches!(res, Err(PeerAdminError::PeerBanned(_))), "{:?}", res);
+
+        let res = pm.send(PeerAdminRequest::Unban { peer_id: peer_id.clone() }).await.unwrap();
+        assert!(matches!(res, Ok(PeerAdminResponse::Done)), "{:?}", res);
+        let peer = known_peer(&pm, &peer_id).await.unwrap();
+        assert_eq!(peer.state.status, KnownPeerStatus::NotConnected);
+        assert_eq!(peer.state.ban_comment, None);
+        assert!(peer.ban_expires_at.is_none());
+
+        System::current().stop();
+    });
+}
+
+/// The operator connects to a peer and disconnects from it.
+#[test]
+fn connect_and_disconnect_peer() {
+    init_test_logger();
+
+    run_actix_until_stop(async {
+        let (_, pm1) = make_peer_manager("test1", open_port());
+        let (peer_info2, pm2) = make_peer_manager("test2", open_port());
+        let pm1 = pm1.start();
+        let _pm2 = pm2.start();
+
+        let res = pm1.send(PeerAdminRequest::Disconnect { peer_id: peer_info2.id.clone() }).await;
+        assert!(matches!(res.unwrap(), Err(PeerAdminError::NotConnected(_))));
+        let res = pm1.send(PeerAdminRequest::Connect { peer_info: PeerInfo::random() });
+        assert!(matches!(res.await.unwrap(), Err(PeerAdminError::NoAddress(_))));
+        let res = pm1.send(PeerAdminRequest::Connect { peer_info: peer_info2.clone() }).await;
+        assert!(matches!(res.unwrap(), Ok(PeerAdminResponse::Done)));
+
+        let state = Arc::new(AtomicUsize::new(0));
+        WaitOrTimeout::new(
+            Box::new(move |_| {
+                let pm1_copy = pm1.clone();
+                let peer_id2 = peer_info2.id.clone();
+                let state = state.clone();
+                actix::spawn(pm1.send(GetInfo {}).then(move |res| {
+                    let info = res.unwrap();
+                    match state.load(Ordering::Relaxed) {
+                        0 if info.num_active_peers == 1 => {
+                            state.store(1, Ordering::Relaxed);
+                            actix::spawn(
+                                pm1_copy
+                                    .send(PeerAdminRequest::Disconnect { peer_id: peer_id2 })
+                                    .then(move |res| {
+                                        assert!(matches!(
+                                            res.unwrap(),
+                                            Ok(PeerAdminResponse::Done)
+                                        ));
+                                        state.store(2, Ordering::Relaxed);
+                                        future::ready(())
+                                    }),
+                            );
+                        }
+                        2 if info.num_active_peers == 0 => System::current().stop(),
+                        _ => {}
+                    }
+                    future::ready(())
+                }));
+            }),
+            100,
+            5000,
+        )
+        .start();
+    });
+}
@@ -8,15 +8,22 @@ mod runner;
 use actix::actors::mocker::Mocker;
 use actix::System;
 use actix::{Actor, Arbiter};
+use borsh::BorshSerialize;
 use futures::{future, FutureExt};
 
 use near_actix_test_utils::run_actix_until_stop;
 use near_client::{ClientActor, ViewClientActor};
 use near_logger_utils::init_test_logger;
+use near_network::rate_limit::{RateLimit, RateLimitConfig};
 use near_network::test_utils::{convert_boot_nodes, open_port, GetInfo, StopSignal, WaitOrTimeout};
-use near_network::types::{NetworkViewClientMessages, NetworkViewClientResponses};
-use near_network::{NetworkClientResponses, NetworkConfig, PeerManagerActor};
+use near_network::types::{
+    KnownPeerState, KnownPeerStatus, NetworkViewClientMessages, NetworkViewClientResponses,
+    ReasonForBan,
+};
+use near_network::{NetworkClientResponses, NetworkConfig, NetworkRequests, PeerManagerActor};
+use near_primitives::network::PeerId;
 use near_store::test_utils::create_test_store;
+use near_store::ColPeers;
 
 type ClientMock = Mocker<ClientActor>;
 type ViewClientMock = Mocker<ViewClientActor>;
@@ -122,6 +129,105 @@ fn peers_connect_all() {
     });
 }
 
+/// Check that messages of a peer over the rate limit are dropped and that the peer gets banned
+/// once it keeps exceeding it.
+#[test]
+fn peer_rate_limited() {
+    init_test_logger();
+
+    run_actix_until_stop(async {
+        let (port1, port2) = (open_port(), open_port());
+        let store = create_test_store();
+        let mut config = NetworkConfig::from_seed("test1", port1);
+        config.boot_nodes = convert_boot_nodes(vec![("test2", port2)]);
+        config.rate_limits = RateLimitConfig::unlimited();
+        config
+            .rate_limits
+            .messages
+            .insert("BlockHeadersRequest".to_string(), RateLimit { per_second: 1, burst: 1 });
+        config.rate_limits.max_throttled_per_min = 5;
+        let peer_id1 = PeerId::new(config.public_key.clone());
+        let peer_id2 = PeerId::new(NetworkConfig::from_seed("test2", port2).public_key);
+
+        let received = Arc::new(AtomicUsize::new(0));
+        let received1 = received.clone();
+        let client_addr = ClientMock::mock(Box::new(move |_msg, _ctx| {
+            Box::new(Some(NetworkClientResponses::NoResponse))
+        }))
+        .start();
+        let view_client_addr = ViewClientMock::mock(Box::new(move |msg, _ctx| {
+            let msg = msg.downcast_ref::<NetworkViewClientMessages>().unwrap();
+            match msg {
+                NetworkViewClientMessages::GetChainInfo => {
+                    Box::new(Some(NetworkViewClientResponses::ChainInfo {
+                        genesis_id: Default::default(),
+                        height: 1,
+                        tracked_shards: vec![],
+                        archival: false,
+                    }))
+                }
+                NetworkViewClientMessages::BlockHeadersRequest(_) => {
+                    received1.fetch_add(1, Ordering::Relaxed);
+                    Box::new(Some(NetworkViewClientResponses::NoResponse))
+                }
+                _ => Box::new(Some(NetworkViewClientResponses::NoResponse)),
+            }
+        }))
+        .start();
+        let _pm1 = PeerManagerActor::new(
+            store.clone(),
+            config,
+            client_addr.recipient(),
+            view_client_addr.recipient(),
+        )
+        .unwrap()
+        .start();
+        let pm2 = make_peer_manager("test2", port2, vec![("test1", port1)], 10).start();
+
+        let state = Arc::new(AtomicUsize::new(0));
+        WaitOrTimeout::new(
+            Box::new(move |_| match state.load(Ordering::Relaxed) {
+                0 => {
+                    let state1 = state.clone();
+                    actix::spawn(pm2.send(GetInfo {}).then(move |res| {
+                        if res.unwrap().num_active_peers == 1 {
+                            state1.store(1, Ordering::Relaxed);
+                        }
+                        future::ready(())
+                    }));
+                }
+                1 => {
+                    for _ in 0..10 {
+                        pm2.do_send(NetworkRequests::BlockHeadersRequest {
+                            hashes: vec![],
+                            peer_id: peer_id1.clone(),
+                        });
+                    }
+                    state.store(2, Ordering::Relaxed);
+                }
+                _ => {
+                    let peer_state = store
+                        .get_ser::<KnownPeerState>(ColPeers, &peer_id2.try_to_vec().unwrap())
+                        .unwrap();
+                    if let Some(KnownPeerState {
+                        status: KnownPeerStatus::Banned(ReasonForBan::Abusive, _),
+                        ..
+                    }) = peer_state
+                    {
+                        // The burst and at most one refilled token, the rest was dropped.
+                        let received = received.load(Ordering::Relaxed);
+                        assert!(received >= 1 && received <= 2, "received {}", received);
+                        System::current().stop();
+                    }
+                }
+            }),
+            100,
+            5000,
+        )
+        .start();
+    });
+}
+
 /// Check network is able to recover after node restart.
 #[test]
 fn peer_recover() {
@@ -1,5 +1,6 @@
 //! Chain Client Configuration
-use std::cmp::min;
+use std::cmp::{max, min};
+use std::path::PathBuf;
 use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
@@ -9,6 +10,57 @@ use near_primitives::version::Version;
 
 pub const TEST_STATE_SYNC_TIMEOUT: u64 = 5;
 
+/// Minimum number of epochs of data kept by garbage collection, needed for state sync and
+/// catching up.
+pub const MIN_GC_NUM_EPOCHS_TO_KEEP: u64 = 5;
+
+/// Number of epochs of each kind of data kept by garbage collection. Values lower than
+/// `MIN_GC_NUM_EPOCHS_TO_KEEP` are raised to it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GCPolicy {
+    /// Blocks and block-indexed data. Blocks are needed to collect everything else, so they are
+    /// kept at least as long as any other kind of data.
+    pub blocks: u64,
+    /// Chunks with their transactions and receipts.
+    pub chunks: u64,
+    /// Execution outcomes of transactions and receipts, used by the `tx` RPC.
+    pub outcomes: u64,
+    /// Trie state.
+    pub state: u64,
+}
+
+impl Default for GCPolicy {
+    fn default() -> Self {
+        GCPolicy {
+            blocks: MIN_GC_NUM_EPOCHS_TO_KEEP,
+            chunks: MIN_GC_NUM_EPOCHS_TO_KEEP,
+            outcomes: MIN_GC_NUM_EPOCHS_TO_KEEP,
+            state: MIN_GC_NUM_EPOCHS_TO_KEEP,
+        }
+    }
+}
+
+impl GCPolicy {
+    /// Number of epochs of blocks to keep, never fewer than of any other kind of data.
+    pub fn blocks_epochs(&self) -> u64 {
+        let others = max(self.chunks_epochs(), max(self.outcomes_epochs(), self.state_epochs()));
+        max(self.blocks, others)
+    }
+
+    pub fn chunks_epochs(&self) -> u64 {
+        max(self.chunks, MIN_GC_NUM_EPOCHS_TO_KEEP)
+    }
+
+    pub fn outcomes_epochs(&self) -> u64 {
+        max(self.outcomes, MIN_GC_NUM_EPOCHS_TO_KEEP)
+    }
+
+    pub fn state_epochs(&self) -> u64 {
+        max(self.state, MIN_GC_NUM_EPOCHS_TO_KEEP)
+    }
+}
+
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum LogSummaryStyle {
     #[serde(rename = "plain")]
@@ -53,6 +105,8 @@ pub struct ClientConfig {
     pub header_sync_expected_height_per_second: u64,
     /// How long to wait for a response during state sync
     pub state_sync_timeout: Duration,
+    /// Directory with state headers and parts to load instead of downloading them from peers.
+    pub state_sync_dir: Option<PathBuf>,
     /// Minimum number of peers to start syncing.
     pub min_num_peers: usize,
     /// Period between logging summary information.
@@ -83,6 +137,8 @@ pub struct ClientConfig {
     pub block_header_fetch_horizon: BlockHeightDelta,
     /// Number of blocks to garbage collect at every gc call.
     pub gc_blocks_limit: NumBlocks,
+    /// How many epochs of each kind of data to keep.
+    pub gc_policy: GCPolicy,
     /// Accounts that this client tracks
     pub tracked_accounts: Vec<AccountId>,
     /// Shards that this client tracks
@@ -101,6 +157,11 @@ pub struct ClientConfig {
     /// genesis file.  The value only affects the RPCs without influencing the
     /// protocol thus changing it per-node doesn’t affect the blockchain.
     pub max_gas_burnt_view: Option<Gas>,
+    /// How often to validate the data written to the store since the previous validation.
+    /// None disables the background validation.
+    pub store_validation_period: Option<Duration>,
+    /// Maximum time a single background store validation may take.
+    pub store_validation_budget: Duration,
 }
 
 impl ClientConfig {
@@ -132,6 +193,7 @@ impl ClientConfig {
             header_sync_progress_timeout: Duration::from_secs(2),
             header_sync_stall_ban_timeout: Duration::from_secs(30),
             state_sync_timeout: Duration::from_secs(TEST_STATE_SYNC_TIMEOUT),
+            state_sync_dir: None,
             header_sync_expected_height_per_second: 1,
             min_num_peers: 1,
             log_summary_period: Duration::from_secs(10),
@@ -150,6 +212,7 @@ impl ClientConfig {
             doosmslug_step_period: Duration::from_millis(100),
             block_header_fetch_horizon: 50,
             gc_blocks_limit: 100,
+            gc_policy: GCPolicy::default(),
             tracked_accounts: vec![],
             tracked_shards: vec![],
             archive,
@@ -159,6 +222,8 @@ impl ClientConfig {
             view_client_throttle_period: Duration::from_secs(1),
             trie_viewer_state_size_limit: None,
             max_gas_burnt_view: None,
+            store_validation_period: None,
+            store_validation_budget: Duration::from_millis(50),
         }
     }
 }
@@ -1,7 +1,9 @@
 mod client_config;
 mod genesis_config;
 
-pub use client_config::{ClientConfig, LogSummaryStyle, TEST_STATE_SYNC_TIMEOUT};
+pub use client_config::{
+    ClientConfig, GCPolicy, LogSummaryStyle, MIN_GC_NUM_EPOCHS_TO_KEEP, TEST_STATE_SYNC_TIMEOUT,
+};
 pub use genesis_config::{
     Genesis, GenesisConfig, GenesisRecords, ProtocolConfig, ProtocolConfigView,
 };
@@ -179,6 +179,14 @@ pub fn inc_counter_vec(counter: &Result<IntCounterVec>, label_values: &[&str]) {
     }
 }
 
+pub fn inc_counter_vec_by(counter: &Result<IntCounterVec>, label_values: &[&str], value: u64) {
+    if let Ok(counter) = counter {
+        counter.with_label_values(label_values).inc_by(value);
+    } else {
+        error!(target: "metrics", "Failed to fetch counter");
+    }
+}
+
 pub fn inc_counter_opt(counter: Option<&IntCounter>) {
     if let Some(counter) = counter {
         counter.inc();
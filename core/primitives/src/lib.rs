@@ -24,6 +24,7 @@ pub mod telemetry;
 pub mod test_utils;
 pub mod transaction;
 pub mod trie_key;
+pub mod trie_proof;
 pub mod types;
 pub mod utils;
 pub mod validator_signer;
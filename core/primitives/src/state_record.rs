@@ -1,4 +1,4 @@
-use borsh::BorshDeserialize;
+use borsh::{BorshDeserialize, BorshSerialize};
 use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter};
 
@@ -18,7 +18,7 @@ use crate::trie_key::trie_key_parsers::{
 use crate::types::AccountId;
 
 /// Record in the state storage.
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Debug)]
 pub enum StateRecord {
     /// Account information.
     Account { account_id: AccountId, account: Account },
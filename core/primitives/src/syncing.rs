@@ -157,6 +157,13 @@ pub enum ShardStateSyncResponse {
 }
 
 impl ShardStateSyncResponse {
+    pub fn has_header(&self) -> bool {
+        match self {
+            Self::V1(response) => response.header.is_some(),
+            Self::V2(response) => response.header.is_some(),
+        }
+    }
+
     pub fn part_id(&self) -> Option<u64> {
         match self {
             Self::V1(response) => response.part_id(),
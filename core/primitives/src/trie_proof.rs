@@ -0,0 +1,351 @@
+//! Verification of the trie proofs attached to `query` responses.
+//!
+//! A proof is the set of serialized trie nodes and values read by the node while answering the
+//! query. Together with a trusted state root (e.g. taken from a light client block) it allows to
+//! check the response without trusting the node which produced it.
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+use borsh::BorshDeserialize;
+
+use crate::account::{AccessKey, Account};
+use crate::hash::{hash, CryptoHash};
+use crate::serialize::{from_base64, to_base64};
+use crate::trie_key::{trie_key_parsers, TrieKey};
+use crate::types::StateRoot;
+use crate::views::{AccessKeyView, AccountView, QueryRequest, QueryResponseKind};
+
+const LEAF_NODE: u8 = 0;
+const BRANCH_NODE_NO_VALUE: u8 = 1;
+const BRANCH_NODE_WITH_VALUE: u8 = 2;
+const EXTENSION_NODE: u8 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrieProofError {
+    /// Proof item is not a valid base64 string.
+    InvalidEncoding(String),
+    /// Trie node or value needed to check the response is not in the proof.
+    MissingNode(CryptoHash),
+    /// Proof item with this hash is not a valid trie node.
+    InvalidNode(CryptoHash),
+    /// Response does not match the state with the given root.
+    ResponseMismatch,
+    /// Responses of this kind can not be verified, e.g. function call results.
+    UnsupportedQuery,
+}
+
+impl fmt::Display for TrieProofError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            TrieProofError::InvalidEncoding(err) => write!(f, "Invalid proof encoding: {}", err),
+            TrieProofError::MissingNode(hash) => write!(f, "Proof is missing node {}", hash),
+            TrieProofError::InvalidNode(hash) => write!(f, "Proof has invalid node {}", hash),
+            TrieProofError::ResponseMismatch => write!(f, "Response does not match the proof"),
+            TrieProofError::UnsupportedQuery => write!(f, "Query response can not be verified"),
+        }
+    }
+}
+
+impl std::error::Error for TrieProofError {}
+
+/// Trie node as stored in the state, with the keys as nibbles. `near-store` can't be used here,
+/// it depends on this crate, so its tests check that both decode every node the same way.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RawTrieNode {
+    Leaf(Vec<u8>, CryptoHash),
+    Branch([Option<CryptoHash>; 16], Option<CryptoHash>),
+    Extension(Vec<u8>, CryptoHash),
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn read(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (result, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(result)
+    }
+
+    fn read_u16(&mut self) -> Option<u16> {
+        self.read(2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_u32(&mut self) -> Option<u32> {
+        self.read(4).map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn read_hash(&mut self) -> Option<CryptoHash> {
+        self.read(32).and_then(|bytes| CryptoHash::try_from(bytes).ok())
+    }
+
+    /// Reads an encoded nibble slice and returns its nibbles.
+    fn read_nibbles(&mut self) -> Option<Vec<u8>> {
+        let len = self.read_u32()? as usize;
+        let encoded = self.read(len)?;
+        let first = *encoded.first()?;
+        let offset = if first & 16 == 16 { 1 } else { 2 };
+        Some(
+            (offset..encoded.len() * 2)
+                .map(|i| if i & 1 == 1 { encoded[i / 2] & 15 } else { encoded[i / 2] >> 4 })
+                .collect(),
+        )
+    }
+}
+
+impl RawTrieNode {
+    /// Decodes a serialized `RawTrieNodeWithSize`, ignoring the size.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Reader(bytes.get(..bytes.len().checked_sub(8)?)?);
+        let node = match reader.read(1)?[0] {
+            LEAF_NODE => {
+                let key = reader.read_nibbles()?;
+                reader.read_u32()?;
+                RawTrieNode::Leaf(key, reader.read_hash()?)
+            }
+            node_type @ BRANCH_NODE_NO_VALUE | node_type @ BRANCH_NODE_WITH_VALUE => {
+                let value = if node_type == BRANCH_NODE_WITH_VALUE {
+                    reader.read_u32()?;
+                    Some(reader.read_hash()?)
+                } else {
+                    None
+                };
+                let bitmap = reader.read_u16()?;
+                let mut children: [Option<CryptoHash>; 16] = Default::default();
+                for (i, child) in children.iter_mut().enumerate() {
+                    if bitmap & (1 << i) != 0 {
+                        *child = Some(reader.read_hash()?);
+                    }
+                }
+                RawTrieNode::Branch(children, value)
+            }
+            EXTENSION_NODE => {
+                let key = reader.read_nibbles()?;
+                RawTrieNode::Extension(key, reader.read_hash()?)
+            }
+            _ => return None,
+        };
+        if reader.0.is_empty() {
+            Some(node)
+        } else {
+            None
+        }
+    }
+}
+
+fn to_nibbles(bytes: &[u8]) -> Vec<u8> {
+    bytes.iter().flat_map(|byte| vec![byte >> 4, byte & 15]).collect()
+}
+
+fn from_nibbles(nibbles: &[u8]) -> Vec<u8> {
+    nibbles.chunks(2).map(|pair| pair[0] * 16 + pair.get(1).unwrap_or(&0)).collect()
+}
+
+/// Part of the trie revealed by a proof, nodes and values are addressed by their hashes.
+pub struct PartialTrie {
+    root: StateRoot,
+    nodes: HashMap<CryptoHash, Vec<u8>>,
+}
+
+impl PartialTrie {
+    pub fn new(root: StateRoot, proof: &[String]) -> Result<Self, TrieProofError> {
+        let nodes = proof
+            .iter()
+            .map(|item| {
+                let bytes = from_base64(item)
+                    .map_err(|err| TrieProofError::InvalidEncoding(err.to_string()))?;
+                Ok((hash(&bytes), bytes))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(PartialTrie { root, nodes })
+    }
+
+    fn get_bytes(&self, hash: &CryptoHash) -> Result<&[u8], TrieProofError> {
+        self.nodes.get(hash).map(Vec::as_slice).ok_or(TrieProofError::MissingNode(*hash))
+    }
+
+    fn get_node(&self, hash: &CryptoHash) -> Result<RawTrieNode, TrieProofError> {
+        RawTrieNode::decode(self.get_bytes(hash)?).ok_or(TrieProofError::InvalidNode(*hash))
+    }
+
+    /// Returns the value of `key`, `None` if the proof shows that there is no such key.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, TrieProofError> {
+        match self.get_value_hash(key)? {
+            Some(value_hash) => self.get_bytes(&value_hash).map(|value| Some(value.to_vec())),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the hash of the value of `key`, which doesn't require the value to be in the
+    /// proof. `None` if the proof shows that there is no such key.
+    pub fn get_value_hash(&self, key: &[u8]) -> Result<Option<CryptoHash>, TrieProofError> {
+        let key = to_nibbles(key);
+        let mut key = &key[..];
+        let mut hash = self.root;
+        loop {
+            if hash == CryptoHash::default() {
+                return Ok(None);
+            }
+            let value_hash = match self.get_node(&hash)? {
+                RawTrieNode::Leaf(leaf_key, value_hash) => {
+                    if leaf_key != key {
+                        return Ok(None);
+                    }
+                    value_hash
+                }
+                RawTrieNode::Extension(ext_key, child) => {
+                    if !key.starts_with(&ext_key) {
+                        return Ok(None);
+                    }
+                    key = &key[ext_key.len()..];
+                    hash = child;
+                    continue;
+                }
+                RawTrieNode::Branch(children, value) => match key.split_first() {
+                    None => match value {
+                        Some(value_hash) => value_hash,
+                        None => return Ok(None),
+                    },
+                    Some((nibble, rest)) => match children[*nibble as usize] {
+                        Some(child) => {
+                            key = rest;
+                            hash = child;
+                            continue;
+                        }
+                        None => return Ok(None),
+                    },
+                },
+            };
+            return Ok(Some(value_hash));
+        }
+    }
+
+    /// Returns all the items with keys starting with `prefix` sorted by key. Fails if the proof
+    /// doesn't contain some of them.
+    pub fn get_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, TrieProofError> {
+        let mut items = vec![];
+        if self.root != CryptoHash::default() {
+            self.collect_prefix(&self.root, &mut vec![], &to_nibbles(prefix), &mut items)?;
+        }
+        Ok(items)
+    }
+
+    fn collect_prefix(
+        &self,
+        hash: &CryptoHash,
+        path: &mut Vec<u8>,
+        prefix: &[u8],
+        items: &mut Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<(), TrieProofError> {
+        // Only subtrees which may contain keys with the prefix are visited.
+        let common = std::cmp::min(path.len(), prefix.len());
+        if path[..common] != prefix[..common] {
+            return Ok(());
+        }
+        let path_len = path.len();
+        match self.get_node(hash)? {
+            RawTrieNode::Leaf(key, value_hash) => {
+                path.extend(key);
+                if path.starts_with(prefix) {
+                    items.push((from_nibbles(path), self.get_bytes(&value_hash)?.to_vec()));
+                }
+            }
+            RawTrieNode::Extension(key, child) => {
+                path.extend(key);
+                self.collect_prefix(&child, path, prefix, items)?;
+            }
+            RawTrieNode::Branch(children, value) => {
+                if let Some(value_hash) = value {
+                    if path.starts_with(prefix) {
+                        items.push((from_nibbles(path), self.get_bytes(&value_hash)?.to_vec()));
+                    }
+                }
+                for (nibble, child) in children.iter().enumerate() {
+                    if let Some(child) = child {
+                        path.push(nibble as u8);
+                        self.collect_prefix(child, path, prefix, items)?;
+                        path.pop();
+                    }
+                }
+            }
+        }
+        path.truncate(path_len);
+        Ok(())
+    }
+}
+
+fn check(matches: bool) -> Result<(), TrieProofError> {
+    if matches {
+        Ok(())
+    } else {
+        Err(TrieProofError::ResponseMismatch)
+    }
+}
+
+fn get_required(trie: &PartialTrie, key: TrieKey) -> Result<Vec<u8>, TrieProofError> {
+    trie.get(&key.to_vec())?.ok_or(TrieProofError::ResponseMismatch)
+}
+
+/// Checks the response to `request` against the state with root `state_root`, which is the
+/// state after applying the chunk of the queried shard in the queried block.
+/// `proof` is the `proof` field of the RPC response.
+pub fn verify_query_response(
+    state_root: &StateRoot,
+    request: &QueryRequest,
+    response: &QueryResponseKind,
+    proof: &[String],
+) -> Result<(), TrieProofError> {
+    let trie = PartialTrie::new(*state_root, proof)?;
+    match (request, response) {
+        (QueryRequest::ViewAccount { account_id }, QueryResponseKind::ViewAccount(view)) => {
+            let value = get_required(&trie, TrieKey::Account { account_id: account_id.clone() })?;
+            let account =
+                Account::try_from_slice(&value).map_err(|_| TrieProofError::ResponseMismatch)?;
+            check(&AccountView::from(account) == view)
+        }
+        // The code is already in the response, so the proof doesn't repeat it.
+        (QueryRequest::ViewCode { account_id }, QueryResponseKind::ViewCode(view)) => {
+            let code_hash = trie
+                .get_value_hash(&TrieKey::ContractCode { account_id: account_id.clone() }.to_vec())?
+                .ok_or(TrieProofError::ResponseMismatch)?;
+            check(hash(&view.code) == code_hash && view.hash == code_hash)
+        }
+        (
+            QueryRequest::ViewAccessKey { account_id, public_key },
+            QueryResponseKind::AccessKey(view),
+        ) => {
+            let value = get_required(
+                &trie,
+                TrieKey::AccessKey {
+                    account_id: account_id.clone(),
+                    public_key: public_key.clone(),
+                },
+            )?;
+            let access_key =
+                AccessKey::try_from_slice(&value).map_err(|_| TrieProofError::ResponseMismatch)?;
+            check(&AccessKeyView::from(access_key) == view)
+        }
+        (QueryRequest::ViewState { account_id, prefix }, QueryResponseKind::ViewState(result)) => {
+            let prefix: &[u8] = prefix.as_ref();
+            let query = trie_key_parsers::get_raw_prefix_for_contract_data(account_id, prefix);
+            let acc_sep_len = query.len() - prefix.len();
+            let items = trie.get_prefix(&query)?;
+            check(
+                items.len() == result.values.len()
+                    && items.iter().zip(result.values.iter()).all(|((key, value), item)| {
+                        item.key == to_base64(&key[acc_sep_len..]) && item.value == to_base64(value)
+                    }),
+            )
+        }
+        (QueryRequest::ViewAccount { .. }, _)
+        | (QueryRequest::ViewCode { .. }, _)
+        | (QueryRequest::ViewAccessKey { .. }, _)
+        | (QueryRequest::ViewState { .. }, _) => Err(TrieProofError::ResponseMismatch),
+        (QueryRequest::ViewAccessKeyList { .. }, _) | (QueryRequest::CallFunction { .. }, _) => {
+            Err(TrieProofError::UnsupportedQuery)
+        }
+    }
+}
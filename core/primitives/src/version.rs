@@ -13,7 +13,7 @@ pub struct Version {
 pub type DbVersion = u32;
 
 /// Current version of the database.
-pub const DB_VERSION: DbVersion = 23;
+pub const DB_VERSION: DbVersion = 24;
 
 /// Protocol version type.
 pub use near_primitives_core::types::ProtocolVersion;
@@ -99,6 +99,8 @@ pub enum ProtocolFeature {
     CountRefundReceiptsInGasLimit,
     /// Add `ripemd60` and `ecrecover` host function
     MathExtension,
+    /// Peers encrypt and authenticate connections before the handshake
+    SecureTransport,
 
     // nightly features
     #[cfg(feature = "protocol_feature_evm")]
@@ -109,17 +111,20 @@ pub enum ProtocolFeature {
     AltBn128,
     #[cfg(feature = "protocol_feature_restore_receipts_after_fix")]
     RestoreReceiptsAfterFix,
+    /// State parts are sent to peers compressed and split into `StatePartChunk` pieces
+    #[cfg(feature = "protocol_feature_state_part_chunks")]
+    StatePartChunks,
 }
 
 /// Current latest stable version of the protocol.
 /// Some features (e. g. FixStorageUsage) require that there is at least one epoch with exactly
 /// the corresponding version
 #[cfg(not(feature = "nightly_protocol"))]
-pub const PROTOCOL_VERSION: ProtocolVersion = 46;
+pub const PROTOCOL_VERSION: ProtocolVersion = 47;
 
 /// Current latest nightly version of the protocol.
 #[cfg(feature = "nightly_protocol")]
-pub const PROTOCOL_VERSION: ProtocolVersion = 114;
+pub const PROTOCOL_VERSION: ProtocolVersion = 115;
 
 impl ProtocolFeature {
     pub const fn protocol_version(self) -> ProtocolVersion {
@@ -138,6 +143,7 @@ impl ProtocolFeature {
             ProtocolFeature::CapMaxGasPrice => 46,
             ProtocolFeature::CountRefundReceiptsInGasLimit => 46,
             ProtocolFeature::MathExtension => 46,
+            ProtocolFeature::SecureTransport => 47,
 
             // Nightly features
             #[cfg(feature = "protocol_feature_evm")]
@@ -148,6 +154,8 @@ impl ProtocolFeature {
             ProtocolFeature::BlockHeaderV3 => 109,
             #[cfg(feature = "protocol_feature_restore_receipts_after_fix")]
             ProtocolFeature::RestoreReceiptsAfterFix => 112,
+            #[cfg(feature = "protocol_feature_state_part_chunks")]
+            ProtocolFeature::StatePartChunks => 115,
         }
     }
 }
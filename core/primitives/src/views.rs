@@ -210,6 +210,9 @@ pub struct StateItem {
 #[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct ViewStateResult {
     pub values: Vec<StateItem>,
+    /// Trie nodes proving that `values` are all the items with the requested prefix, see
+    /// `trie_proof::verify_query_response`.
+    #[serde(default)]
     pub proof: TrieProofPath,
 }
 
@@ -288,6 +291,11 @@ pub struct QueryResponse {
     pub kind: QueryResponseKind,
     pub block_height: BlockHeight,
     pub block_hash: CryptoHash,
+    /// Trie nodes proving the response for account, access key and contract code queries, if
+    /// the query asked for a proof. Not sent over the network, so responses of queries routed to
+    /// other nodes have no proof and the RPC refuses to return them when a proof was asked for.
+    #[borsh_skip]
+    pub proof: TrieProofPath,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -326,6 +334,22 @@ pub struct StatusResponse {
     pub sync_info: StatusSyncInfo,
     /// Validator id of the node
     pub validator_account_id: Option<AccountId>,
+    /// Results of the background store validation. None if it's disabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store_validation: Option<StoreValidationInfo>,
+}
+
+/// Results of the store validation run by the node in the background.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct StoreValidationInfo {
+    /// Highest height validated so far.
+    pub validated_height: BlockHeight,
+    /// Number of checks done since the node started.
+    pub num_checks: u64,
+    /// Number of failed checks since the node started.
+    pub num_errors: u64,
+    /// The last failed check.
+    pub last_error: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use near_primitives::types::ShardId;
+
+/// Storage engine used to persist the node's database.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DBBackend {
+    /// RocksDB, the default and the only backend supporting database migrations.
+    RocksDB,
+    /// Embedded pure-Rust database, lighter on resources than RocksDB.
+    Sled,
+}
+
+impl Default for DBBackend {
+    fn default() -> Self {
+        DBBackend::RocksDB
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct StoreConfig {
+    /// Storage engine used for the database in the `data` directory.
+    pub backend: DBBackend,
+    /// Maintain flat state next to the trie, so that view calls read state with a single lookup
+    /// instead of a trie traversal. Flat state of a shard is created in the background once a
+    /// block is finalized after enabling this, which iterates over the whole state of the shard.
+    pub flat_state: bool,
+    /// Trie node caches kept for every shard.
+    pub trie_cache: TrieCacheConfig,
+    /// Commits to the database taking longer than this are logged together with the columns
+    /// which dominated the transaction. Disabled by default.
+    pub slow_commit_threshold: Option<Duration>,
+    /// Database for the history of an archival node, which lets the main database be garbage
+    /// collected like on a regular node. Data is moved there instead of being deleted and reads
+    /// fall back to it. Relative paths are resolved against the home directory.
+    pub cold_store_path: Option<PathBuf>,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        StoreConfig {
+            backend: DBBackend::default(),
+            flat_state: false,
+            trie_cache: TrieCacheConfig::default(),
+            slow_commit_threshold: None,
+            cold_store_path: None,
+        }
+    }
+}
+
+/// Sizes of the trie node caches. The node keeps two caches per shard, one for applying chunks
+/// and one for view calls, and the limits apply to each of them.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct TrieCacheConfig {
+    /// Maximum total size of the cached nodes and values of a shard, in bytes.
+    pub max_bytes: u64,
+    /// Overrides `max_bytes` for particular shards.
+    pub per_shard_max_bytes: HashMap<ShardId, u64>,
+    /// Number of top levels of the trie which are cached separately from other nodes, so that
+    /// reads deep in the trie don't evict them. 0 disables it.
+    pub pinned_levels: usize,
+    /// Maximum total size of the pinned nodes of a shard, in bytes.
+    pub pinned_max_bytes: u64,
+}
+
+impl TrieCacheConfig {
+    pub fn shard_max_bytes(&self, shard_id: ShardId) -> u64 {
+        self.per_shard_max_bytes.get(&shard_id).copied().unwrap_or(self.max_bytes)
+    }
+}
+
+impl Default for TrieCacheConfig {
+    fn default() -> Self {
+        TrieCacheConfig {
+            max_bytes: 50_000_000,
+            per_shard_max_bytes: HashMap::default(),
+            pinned_levels: 0,
+            pinned_max_bytes: 10_000_000,
+        }
+    }
+}
@@ -1,11 +1,13 @@
 #[cfg(not(feature = "single_thread_rocksdb"))]
 use std::cmp;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io;
 use std::marker::PhantomPinned;
-use std::sync::RwLock;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
 
 use borsh::{BorshDeserialize, BorshSerialize};
+use rocksdb::checkpoint::Checkpoint;
 #[cfg(feature = "single_thread_rocksdb")]
 use rocksdb::Env;
 use rocksdb::{
@@ -23,14 +25,43 @@ use std::path::Path;
 use std::sync::atomic::Ordering;
 
 pub(crate) mod refcount;
+mod sled_db;
+mod snapshot;
+mod split_db;
 pub(crate) mod v6_to_v7;
 
+pub use sled_db::SledDB;
+use snapshot::RocksDBSnapshot;
+pub use split_db::SplitDB;
+
+/// Size estimates of a column reported by the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnStats {
+    pub column: DBCol,
+    pub estimated_num_keys: u64,
+    /// Size of the data which is not yet removed by compaction.
+    pub estimated_live_data_size: u64,
+    /// Total size of the column's files on disk.
+    pub sst_files_size: u64,
+}
+
 #[derive(Debug, Clone, PartialEq)]
-pub struct DBError(rocksdb::Error);
+pub enum DBError {
+    RocksDB(rocksdb::Error),
+    Sled(sled::Error),
+    /// The operation isn't implemented by the database backend.
+    NotSupported(&'static str),
+}
 
 impl std::fmt::Display for DBError {
     fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        self.0.fmt(formatter)
+        match self {
+            DBError::RocksDB(err) => err.fmt(formatter),
+            DBError::Sled(err) => err.fmt(formatter),
+            DBError::NotSupported(operation) => {
+                write!(formatter, "{} is not supported by the database backend", operation)
+            }
+        }
     }
 }
 
@@ -38,7 +69,13 @@ impl std::error::Error for DBError {}
 
 impl From<rocksdb::Error> for DBError {
     fn from(err: rocksdb::Error) -> Self {
-        DBError(err)
+        DBError::RocksDB(err)
+    }
+}
+
+impl From<sled::Error> for DBError {
+    fn from(err: sled::Error) -> Self {
+        DBError::Sled(err)
     }
 }
 
@@ -121,10 +158,16 @@ pub enum DBCol {
     ColEpochValidatorInfo = 47,
     /// Header Hashes indexed by Height
     ColHeaderHashesByHeight = 48,
+    /// Flat state: shard id + TrieKey -> reference to the value at the flat state head
+    ColFlatState = 49,
+    /// Flat state deltas: shard id + state root -> changes applied on top of the previous root
+    ColFlatStateDeltas = 50,
+    /// Flat state head: shard id -> state root
+    ColFlatStateHeads = 51,
 }
 
 // Do not move this line from enum DBCol
-pub const NUM_COLS: usize = 49;
+pub const NUM_COLS: usize = 52;
 
 impl std::fmt::Display for DBCol {
     fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
@@ -178,6 +221,9 @@ impl std::fmt::Display for DBCol {
             Self::ColCachedContractCode => "cached code",
             Self::ColEpochValidatorInfo => "epoch validator info",
             Self::ColHeaderHashesByHeight => "header hashes indexed by their height",
+            Self::ColFlatState => "flat state",
+            Self::ColFlatStateDeltas => "flat state deltas",
+            Self::ColFlatStateHeads => "flat state heads",
         };
         write!(formatter, "{}", desc)
     }
@@ -187,6 +233,12 @@ impl DBCol {
     pub fn is_rc(&self) -> bool {
         IS_COL_RC[*self as usize]
     }
+
+    /// Whether the data of the column is moved to the cold database of a `SplitDB` when it's
+    /// garbage collected.
+    pub fn is_cold(&self) -> bool {
+        IS_COL_COLD[*self as usize]
+    }
 }
 
 // List of columns for which GC should be implemented
@@ -211,6 +263,8 @@ lazy_static! {
         col_gc[DBCol::ColEpochValidatorInfo as usize] = false; // https://github.com/nearprotocol/nearcore/pull/2952
         col_gc[DBCol::ColEpochStart as usize] = false; // https://github.com/nearprotocol/nearcore/pull/2952
         col_gc[DBCol::ColCachedContractCode as usize] = false;
+        col_gc[DBCol::ColFlatState as usize] = false; // Updated when flat state head moves
+        col_gc[DBCol::ColFlatStateHeads as usize] = false;
         col_gc
     };
 }
@@ -228,6 +282,35 @@ lazy_static! {
     };
 }
 
+// List of columns which keep the history of the chain on archival nodes
+lazy_static! {
+    pub static ref IS_COL_COLD: Vec<bool> = {
+        let mut col_cold = vec![false; NUM_COLS];
+        col_cold[DBCol::ColBlock as usize] = true;
+        col_cold[DBCol::ColBlockHeader as usize] = true;
+        col_cold[DBCol::ColBlockExtra as usize] = true;
+        col_cold[DBCol::ColBlockInfo as usize] = true;
+        col_cold[DBCol::ColBlockPerHeight as usize] = true;
+        col_cold[DBCol::ColHeaderHashesByHeight as usize] = true;
+        col_cold[DBCol::ColNextBlockHashes as usize] = true;
+        col_cold[DBCol::ColChunks as usize] = true;
+        col_cold[DBCol::ColPartialChunks as usize] = true;
+        col_cold[DBCol::ColChunkExtra as usize] = true;
+        col_cold[DBCol::ColChunkHashesByHeight as usize] = true;
+        col_cold[DBCol::ColChunkPerHeightShard as usize] = true;
+        col_cold[DBCol::ColIncomingReceipts as usize] = true;
+        col_cold[DBCol::ColOutgoingReceipts as usize] = true;
+        col_cold[DBCol::ColReceiptIdToShardId as usize] = true;
+        col_cold[DBCol::ColReceipts as usize] = true;
+        col_cold[DBCol::ColTransactions as usize] = true;
+        col_cold[DBCol::ColTransactionResult as usize] = true;
+        col_cold[DBCol::ColOutcomeIds as usize] = true;
+        col_cold[DBCol::ColState as usize] = true;
+        col_cold[DBCol::ColStateChanges as usize] = true;
+        col_cold
+    };
+}
+
 // List of reference counted columns
 lazy_static! {
     pub static ref IS_COL_RC: Vec<bool> = {
@@ -244,6 +327,8 @@ pub const HEAD_KEY: &[u8; 4] = b"HEAD";
 pub const TAIL_KEY: &[u8; 4] = b"TAIL";
 pub const CHUNK_TAIL_KEY: &[u8; 10] = b"CHUNK_TAIL";
 pub const FORK_TAIL_KEY: &[u8; 9] = b"FORK_TAIL";
+pub const OUTCOME_TAIL_KEY: &[u8; 12] = b"OUTCOME_TAIL";
+pub const STATE_TAIL_KEY: &[u8; 10] = b"STATE_TAIL";
 pub const HEADER_HEAD_KEY: &[u8; 11] = b"HEADER_HEAD";
 pub const FINAL_HEAD_KEY: &[u8; 10] = b"FINAL_HEAD";
 pub const LATEST_KNOWN_KEY: &[u8; 12] = b"LATEST_KNOWN";
@@ -254,6 +339,9 @@ pub const GENESIS_STATE_ROOTS_KEY: &[u8; 19] = b"GENESIS_STATE_ROOTS";
 
 pub struct DBTransaction {
     pub ops: Vec<DBOp>,
+    /// Indices of the `ops` which delete or dereference data of forks rather than of the
+    /// canonical chain. `SplitDB` doesn't copy that data to the cold database.
+    pub fork_ops: HashSet<usize>,
 }
 
 pub enum DBOp {
@@ -264,6 +352,10 @@ pub enum DBOp {
 }
 
 impl DBTransaction {
+    pub fn new() -> Self {
+        DBTransaction { ops: Vec::new(), fork_ops: HashSet::new() }
+    }
+
     pub fn put<K: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, col: DBCol, key: K, value: V) {
         self.ops.push(DBOp::Insert {
             col,
@@ -292,10 +384,15 @@ impl DBTransaction {
     pub fn delete_all(&mut self, col: DBCol) {
         self.ops.push(DBOp::DeleteAll { col });
     }
+
+    /// Marks all the operations added so far as removing data of forks.
+    pub fn mark_fork_ops(&mut self) {
+        self.fork_ops.extend(0..self.ops.len());
+    }
 }
 
 pub struct RocksDB {
-    db: DB,
+    db: Arc<DB>,
     cfs: Vec<*const ColumnFamily>,
 
     check_free_space_counter: std::sync::atomic::AtomicU16,
@@ -388,7 +485,7 @@ impl RocksDBOptions {
         let cfs =
             cf_names.iter().map(|n| db.cf_handle(n).unwrap() as *const ColumnFamily).collect();
         Ok(RocksDB {
-            db,
+            db: Arc::new(db),
             cfs,
             _pin: PhantomPinned,
             check_free_space_interval: self.check_free_space_interval,
@@ -428,7 +525,7 @@ impl RocksDBOptions {
         let cfs =
             cf_names.iter().map(|n| db.cf_handle(n).unwrap() as *const ColumnFamily).collect();
         Ok(RocksDB {
-            db,
+            db: Arc::new(db),
             cfs,
             _pin: PhantomPinned,
             check_free_space_interval: self.check_free_space_interval,
@@ -444,9 +541,11 @@ pub struct TestDB {
 
 pub trait Database: Sync + Send {
     fn transaction(&self) -> DBTransaction {
-        DBTransaction { ops: Vec::new() }
+        DBTransaction::new()
     }
     fn get(&self, col: DBCol, key: &[u8]) -> Result<Option<Vec<u8>>, DBError>;
+    /// Returns the value as stored, including the reference count of reference counted columns.
+    fn get_without_rc_logic(&self, col: DBCol, key: &[u8]) -> Result<Option<Vec<u8>>, DBError>;
     fn iter<'a>(&'a self, column: DBCol) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>;
     fn iter_without_rc_logic<'a>(
         &'a self,
@@ -458,7 +557,26 @@ pub trait Database: Sync + Send {
         key_prefix: &'a [u8],
     ) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>;
     fn write(&self, batch: DBTransaction) -> Result<(), DBError>;
-    fn as_rocksdb(&self) -> Option<&RocksDB> {
+    /// Creates a consistent copy of the database at `path`, which must not exist yet, without
+    /// blocking writes. Returns the copy opened in read-only mode.
+    fn create_checkpoint(&self, _path: &Path) -> Result<Pin<Arc<dyn Database>>, DBError> {
+        Err(DBError::NotSupported("checkpoint"))
+    }
+    /// Opens a read-only view of the current contents of the database, which doesn't see the
+    /// writes done after it was opened.
+    fn snapshot(&self) -> Result<Pin<Arc<dyn Database>>, DBError> {
+        Err(DBError::NotSupported("snapshot"))
+    }
+    /// Returns size estimates of all the columns.
+    fn get_column_stats(&self) -> Result<Vec<ColumnStats>, DBError> {
+        Err(DBError::NotSupported("column statistics"))
+    }
+    /// Compacts the whole key range of the given columns, blocking until it is done.
+    fn compact_columns(&self, _cols: &[DBCol]) -> Result<(), DBError> {
+        Err(DBError::NotSupported("compaction"))
+    }
+    /// Returns the database with the history garbage collected from this one, if any.
+    fn cold_db(&self) -> Option<&dyn Database> {
         None
     }
 }
@@ -470,6 +588,11 @@ impl Database for RocksDB {
         Ok(RocksDB::get_with_rc_logic(col, result))
     }
 
+    fn get_without_rc_logic(&self, col: DBCol, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        let read_options = rocksdb_read_options();
+        Ok(self.db.get_cf_opt(unsafe { &*self.cfs[col as usize] }, key, &read_options)?)
+    }
+
     fn iter_without_rc_logic<'a>(
         &'a self,
         col: DBCol,
@@ -554,8 +677,45 @@ impl Database for RocksDB {
         Ok(self.db.write(batch)?)
     }
 
-    fn as_rocksdb(&self) -> Option<&RocksDB> {
-        Some(self)
+    fn create_checkpoint(&self, path: &Path) -> Result<Pin<Arc<dyn Database>>, DBError> {
+        use strum::IntoEnumIterator;
+        // SST files are hard-linked into the checkpoint when it is on the same filesystem, so
+        // only the memtables have to be flushed and copied.
+        Checkpoint::new(&self.db)?.create_checkpoint(path)?;
+        let checkpoint = RocksDBOptions::default()
+            .cf_names(DBCol::iter().map(|col| format!("col{}", col as usize)).collect())
+            .read_only(path)?;
+        Ok(Arc::pin(checkpoint))
+    }
+
+    fn snapshot(&self) -> Result<Pin<Arc<dyn Database>>, DBError> {
+        Ok(Arc::pin(RocksDBSnapshot::new(self.db.clone(), self.cfs.clone())))
+    }
+
+    fn get_column_stats(&self) -> Result<Vec<ColumnStats>, DBError> {
+        use strum::IntoEnumIterator;
+        DBCol::iter()
+            .map(|col| {
+                let cf_handle = unsafe { &*self.cfs[col as usize] };
+                let property = |name: &str| -> Result<u64, DBError> {
+                    Ok(self.db.property_int_value_cf(cf_handle, name)?.unwrap_or(0))
+                };
+                Ok(ColumnStats {
+                    column: col,
+                    estimated_num_keys: property("rocksdb.estimate-num-keys")?,
+                    estimated_live_data_size: property("rocksdb.estimate-live-data-size")?,
+                    sst_files_size: property("rocksdb.total-sst-files-size")?,
+                })
+            })
+            .collect()
+    }
+
+    fn compact_columns(&self, cols: &[DBCol]) -> Result<(), DBError> {
+        for col in cols {
+            let cf_handle = unsafe { &*self.cfs[*col as usize] };
+            self.db.compact_range_cf(cf_handle, None::<&[u8]>, None::<&[u8]>);
+        }
+        Ok(())
     }
 }
 
@@ -565,6 +725,10 @@ impl Database for TestDB {
         Ok(RocksDB::get_with_rc_logic(col, result))
     }
 
+    fn get_without_rc_logic(&self, col: DBCol, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        Ok(self.db.read().unwrap()[col as usize].get(key).cloned())
+    }
+
     fn iter<'a>(&'a self, col: DBCol) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
         let iterator = self.iter_without_rc_logic(col);
         RocksDB::iter_with_rc_logic(col, iterator)
@@ -616,6 +780,10 @@ impl Database for TestDB {
         }
         Ok(())
     }
+
+    fn snapshot(&self) -> Result<Pin<Arc<dyn Database>>, DBError> {
+        Ok(Arc::pin(TestDB { db: RwLock::new(self.db.read().unwrap().clone()) }))
+    }
 }
 
 /// DB level options
@@ -694,7 +862,27 @@ impl RocksDB {
         })
     }
 
-    fn new_read_only<P: AsRef<std::path::Path>>(path: P) -> Result<Self, DBError> {
+    /// Returns the estimated number of keys of the columns present in the database at `path`,
+    /// opening it in read-only mode.
+    pub fn estimate_num_keys_read_only<P: AsRef<std::path::Path>>(
+        path: P,
+    ) -> Result<Vec<(DBCol, u64)>, DBError> {
+        use strum::IntoEnumIterator;
+        let options = Options::default();
+        let cf_names = DB::list_cf(&options, &path)?;
+        let db = DB::open_cf_for_read_only(&options, &path, cf_names.iter(), false)?;
+        let mut result = vec![];
+        for col in DBCol::iter() {
+            if let Some(cf_handle) = db.cf_handle(&format!("col{}", col as usize)) {
+                let num_keys =
+                    db.property_int_value_cf(cf_handle, "rocksdb.estimate-num-keys")?.unwrap_or(0);
+                result.push((col, num_keys));
+            }
+        }
+        Ok(result)
+    }
+
+    pub(crate) fn new_read_only<P: AsRef<std::path::Path>>(path: P) -> Result<Self, DBError> {
         RocksDBOptions::default().read_only(path)
     }
 
@@ -770,9 +958,12 @@ impl TestDB {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use crate::db::DBCol::ColState;
-    use crate::db::{rocksdb_read_options, DBError, Database, RocksDB};
-    use crate::{create_store, DBCol};
+    use crate::db::{rocksdb_read_options, DBError, Database, RocksDB, SledDB, NUM_COLS};
+    use crate::migrations::set_store_version;
+    use crate::{create_store, DBCol, Store};
 
     impl RocksDB {
         #[cfg(not(feature = "single_thread_rocksdb"))]
@@ -824,6 +1015,38 @@ mod tests {
         assert_eq!(store.get(ColState, &[1]).unwrap(), None);
     }
 
+    #[test]
+    fn test_snapshot() {
+        let tmp_dir = tempfile::Builder::new().prefix("_test_snapshot").tempdir().unwrap();
+        let store = create_store(tmp_dir.path().to_str().unwrap());
+        let mut store_update = store.store_update();
+        store_update.update_refcount(ColState, &[1], &[1], 1);
+        store_update.commit().unwrap();
+        let mut store_update = store.store_update();
+        store_update.update_refcount(ColState, &[1], &[1], 1);
+        store_update.commit().unwrap();
+
+        let snapshot = store.snapshot().unwrap();
+        let mut store_update = store.store_update();
+        store_update.update_refcount(ColState, &[2], &[2], 1);
+        store_update.commit().unwrap();
+
+        // Pending merges of the reference counted columns are resolved in the snapshot too.
+        assert_eq!(snapshot.get(ColState, &[1]).unwrap(), Some(vec![1]));
+        assert_eq!(snapshot.get(ColState, &[2]).unwrap(), None);
+        let mut snapshot_update = snapshot.store_update();
+        snapshot_update.update_refcount(ColState, &[3], &[3], 1);
+        assert!(snapshot_update.commit().is_err());
+
+        assert_eq!(
+            snapshot.iter(ColState).collect::<Vec<_>>(),
+            vec![(vec![1].into_boxed_slice(), vec![1].into_boxed_slice())]
+        );
+        // The snapshot keeps the database open.
+        drop(store);
+        assert_eq!(snapshot.get(ColState, &[1]).unwrap(), Some(vec![1]));
+    }
+
     #[test]
     fn rocksdb_merge_sanity() {
         let tmp_dir = tempfile::Builder::new().prefix("_test_snapshot_sanity").tempdir().unwrap();
@@ -877,4 +1100,138 @@ mod tests {
             assert_eq!(store.get(ColState, &[1]).unwrap(), None);
         }
     }
+
+    #[test]
+    fn sled_refcount_sanity() {
+        let tmp_dir = tempfile::Builder::new().prefix("_test_sled_refcount").tempdir().unwrap();
+        let store = Store::new(Arc::pin(SledDB::new(tmp_dir.path()).unwrap()));
+        for _ in 0..2 {
+            let mut store_update = store.store_update();
+            store_update.update_refcount(ColState, &[1], &[1], 1);
+            store_update.commit().unwrap();
+        }
+        assert_eq!(store.get(ColState, &[1]).unwrap(), Some(vec![1]));
+        assert_eq!(
+            store.iter_without_rc_logic(ColState).collect::<Vec<_>>(),
+            vec![(vec![1].into_boxed_slice(), vec![1, 2, 0, 0, 0, 0, 0, 0, 0].into_boxed_slice())]
+        );
+        for _ in 0..2 {
+            let mut store_update = store.store_update();
+            store_update.update_refcount(ColState, &[1], &[1], -1);
+            store_update.commit().unwrap();
+        }
+        // Unlike RocksDB, zero refcount entries are removed right away.
+        assert_eq!(store.get(ColState, &[1]).unwrap(), None);
+        assert_eq!(store.iter_without_rc_logic(ColState).count(), 0);
+    }
+
+    #[test]
+    fn sled_clear_column_and_prefix() {
+        let tmp_dir = tempfile::Builder::new().prefix("_test_sled_clear").tempdir().unwrap();
+        let store = Store::new(Arc::pin(SledDB::new(tmp_dir.path()).unwrap()));
+        {
+            let mut store_update = store.store_update();
+            store_update.set(DBCol::ColBlockMisc, &[1, 1], &[1]);
+            store_update.set(DBCol::ColBlockMisc, &[1, 2], &[2]);
+            store_update.set(DBCol::ColBlockMisc, &[2, 1], &[3]);
+            store_update.commit().unwrap();
+        }
+        assert_eq!(store.iter_prefix(DBCol::ColBlockMisc, &[1]).count(), 2);
+        {
+            let mut store_update = store.store_update();
+            store_update.delete_all(DBCol::ColBlockMisc);
+            store_update.set(DBCol::ColBlock, &[1], &[1]);
+            store_update.commit().unwrap();
+        }
+        assert_eq!(store.iter(DBCol::ColBlockMisc).count(), 0);
+        assert_eq!(store.get(DBCol::ColBlock, &[1]).unwrap(), Some(vec![1]));
+    }
+
+    #[test]
+    fn sled_snapshot() {
+        let tmp_dir = tempfile::Builder::new().prefix("_test_sled_snapshot").tempdir().unwrap();
+        let store = Store::new(Arc::pin(SledDB::new(tmp_dir.path()).unwrap()));
+        let mut store_update = store.store_update();
+        store_update.update_refcount(ColState, &[1], &[1], 1);
+        store_update.set(DBCol::ColBlockMisc, &[1], &[1]);
+        store_update.commit().unwrap();
+
+        let snapshot = store.snapshot().unwrap();
+        let mut store_update = store.store_update();
+        store_update.update_refcount(ColState, &[1], &[1], -1);
+        store_update.set(DBCol::ColBlockMisc, &[1], &[2]);
+        store_update.commit().unwrap();
+
+        assert_eq!(snapshot.get(ColState, &[1]).unwrap(), Some(vec![1]));
+        assert_eq!(snapshot.get(DBCol::ColBlockMisc, &[1]).unwrap(), Some(vec![1]));
+        assert_eq!(store.get(ColState, &[1]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let tmp_dir = tempfile::Builder::new().prefix("_test_checkpoint").tempdir().unwrap();
+        let store = create_store(tmp_dir.path().join("data").to_str().unwrap());
+        set_store_version(&store, 42);
+        {
+            let mut store_update = store.store_update();
+            store_update.update_refcount(ColState, &[1], &[1], 1);
+            store_update.set(DBCol::ColBlockMisc, &[1], &[1]);
+            store_update.commit().unwrap();
+        }
+        let checkpoint_path = tmp_dir.path().join("checkpoint");
+        assert_eq!(store.create_checkpoint(&checkpoint_path).unwrap(), 42);
+        {
+            let mut store_update = store.store_update();
+            store_update.update_refcount(ColState, &[1], &[1], -1);
+            store_update.set(DBCol::ColBlockMisc, &[1], &[2]);
+            store_update.commit().unwrap();
+        }
+        // Writes after the checkpoint was taken don't leak into it.
+        let checkpoint = create_store(checkpoint_path.to_str().unwrap());
+        assert_eq!(checkpoint.get(ColState, &[1]).unwrap(), Some(vec![1]));
+        assert_eq!(checkpoint.get(DBCol::ColBlockMisc, &[1]).unwrap(), Some(vec![1]));
+        assert_eq!(store.get(ColState, &[1]).unwrap(), None);
+        // Checkpoint directory must not exist.
+        assert!(store.create_checkpoint(&checkpoint_path).is_err());
+    }
+
+    #[test]
+    fn test_checkpoint_not_supported() {
+        let tmp_dir = tempfile::Builder::new().prefix("_test_sled_checkpoint").tempdir().unwrap();
+        let db = SledDB::new(tmp_dir.path().join("data")).unwrap();
+        assert_eq!(
+            db.create_checkpoint(&tmp_dir.path().join("checkpoint")).err(),
+            Some(DBError::NotSupported("checkpoint"))
+        );
+    }
+
+    #[test]
+    fn test_column_stats_and_compaction() {
+        let tmp_dir = tempfile::Builder::new().prefix("_test_column_stats").tempdir().unwrap();
+        let store = create_store(tmp_dir.path().to_str().unwrap());
+        {
+            let mut store_update = store.store_update();
+            for i in 0u32..1000 {
+                store_update.set(DBCol::ColBlockMisc, &i.to_le_bytes(), &[0; 100]);
+            }
+            store_update.commit().unwrap();
+        }
+        store.compact_columns(&[DBCol::ColBlockMisc]).unwrap();
+        let stats = store.get_column_stats().unwrap();
+        assert_eq!(stats.len(), NUM_COLS);
+        let block_misc = stats.iter().find(|stats| stats.column == DBCol::ColBlockMisc).unwrap();
+        assert_eq!(block_misc.estimated_num_keys, 1000);
+        assert!(block_misc.sst_files_size > 0);
+        let state = stats.iter().find(|stats| stats.column == ColState).unwrap();
+        assert_eq!(state.sst_files_size, 0);
+
+        let sled_dir =
+            tempfile::Builder::new().prefix("_test_sled_column_stats").tempdir().unwrap();
+        let db = SledDB::new(sled_dir.path().join("data")).unwrap();
+        assert_eq!(db.get_column_stats().err(), Some(DBError::NotSupported("column statistics")));
+        assert_eq!(
+            db.compact_columns(&[ColState]).err(),
+            Some(DBError::NotSupported("compaction"))
+        );
+    }
 }
@@ -0,0 +1,151 @@
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use sled::transaction::{ConflictableTransactionError, TransactionError};
+use sled::{Transactional, Tree};
+use strum::IntoEnumIterator;
+
+use crate::db::refcount::merge_refcounted_records;
+use crate::db::{DBCol, DBError, DBOp, DBTransaction, Database, RocksDB};
+
+/// Embedded pure-Rust database backed by [sled](https://docs.rs/sled).
+///
+/// Every column is stored in its own sled tree named the same way as the RocksDB column
+/// families (`col{N}`). Reference counted columns don't rely on a merge operator: the refcount
+/// records are merged while the transaction is applied and entries whose refcount drops to zero
+/// are removed right away, so there is nothing left for compaction to clean up.
+pub struct SledDB {
+    db: sled::Db,
+    trees: Vec<Tree>,
+    /// Serializes the writes, so that no other write adds keys to a column between `DeleteAll`
+    /// listing its keys and the transaction deleting them.
+    write_lock: Mutex<()>,
+}
+
+impl SledDB {
+    pub fn new<P: AsRef<std::path::Path>>(path: P) -> Result<Self, DBError> {
+        SledDB::open(sled::open(path)?)
+    }
+
+    fn open(db: sled::Db) -> Result<Self, DBError> {
+        let trees = DBCol::iter()
+            .map(|col| db.open_tree(format!("col{}", col as usize)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(SledDB { db, trees, write_lock: Mutex::new(()) })
+    }
+
+    fn into_boxed_iter<'a>(
+        iterator: sled::Iter,
+    ) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+        Box::new(iterator.map(|item| {
+            let (key, value) = item.expect("Failed to iterate over sled tree");
+            (Box::from(key.as_ref()), Box::from(value.as_ref()))
+        }))
+    }
+}
+
+impl Database for SledDB {
+    fn get(&self, col: DBCol, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        let result = self.trees[col as usize].get(key)?.map(|value| value.to_vec());
+        Ok(RocksDB::get_with_rc_logic(col, result))
+    }
+
+    fn get_without_rc_logic(&self, col: DBCol, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        Ok(self.trees[col as usize].get(key)?.map(|value| value.to_vec()))
+    }
+
+    fn iter<'a>(&'a self, col: DBCol) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+        RocksDB::iter_with_rc_logic(col, self.iter_without_rc_logic(col))
+    }
+
+    fn iter_without_rc_logic<'a>(
+        &'a self,
+        col: DBCol,
+    ) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+        SledDB::into_boxed_iter(self.trees[col as usize].iter())
+    }
+
+    fn iter_prefix<'a>(
+        &'a self,
+        col: DBCol,
+        key_prefix: &'a [u8],
+    ) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+        RocksDB::iter_with_rc_logic(
+            col,
+            SledDB::into_boxed_iter(self.trees[col as usize].scan_prefix(key_prefix)),
+        )
+    }
+
+    fn write(&self, transaction: DBTransaction) -> Result<(), DBError> {
+        let _write_guard = self.write_lock.lock().unwrap();
+        // sled transactions can't clear a tree, so `DeleteAll` is turned into deletes of the keys
+        // present at the moment of the write, which the write lock keeps until the transaction.
+        let mut ops = Vec::with_capacity(transaction.ops.len());
+        for op in transaction.ops {
+            match op {
+                DBOp::DeleteAll { col } => {
+                    for item in self.trees[col as usize].iter().keys() {
+                        ops.push(DBOp::Delete { col, key: item?.to_vec() });
+                    }
+                }
+                op => ops.push(op),
+            }
+        }
+
+        let result = self.trees.as_slice().transaction(|trees| {
+            for op in ops.iter() {
+                match op {
+                    DBOp::Insert { col, key, value } => {
+                        trees[*col as usize].insert(key.as_slice(), value.as_slice())?;
+                    }
+                    DBOp::UpdateRefcount { col, key, value } => {
+                        assert!(col.is_rc());
+                        let tree = &trees[*col as usize];
+                        let mut val =
+                            tree.get(key.as_slice())?.map(|v| v.to_vec()).unwrap_or_default();
+                        merge_refcounted_records(&mut val, value);
+                        if val.is_empty() {
+                            tree.remove(key.as_slice())?;
+                        } else {
+                            tree.insert(key.as_slice(), val)?;
+                        }
+                    }
+                    DBOp::Delete { col, key } => {
+                        trees[*col as usize].remove(key.as_slice())?;
+                    }
+                    DBOp::DeleteAll { .. } => unreachable!("DeleteAll is resolved before"),
+                }
+            }
+            Ok::<(), ConflictableTransactionError<DBError>>(())
+        });
+        match result {
+            Ok(()) => Ok(()),
+            Err(TransactionError::Abort(err)) => Err(err),
+            Err(TransactionError::Storage(err)) => Err(err.into()),
+        }
+    }
+
+    /// sled has no snapshots of its own, so the columns are copied into a temporary database,
+    /// which is removed once the snapshot is dropped. The writes wait for the copy to finish.
+    fn snapshot(&self) -> Result<Pin<Arc<dyn Database>>, DBError> {
+        let _write_guard = self.write_lock.lock().unwrap();
+        let snapshot = SledDB::open(sled::Config::new().temporary(true).open()?)?;
+        for (tree, snapshot_tree) in self.trees.iter().zip(snapshot.trees.iter()) {
+            let mut batch = sled::Batch::default();
+            for item in tree.iter() {
+                let (key, value) = item?;
+                batch.insert(key, value);
+            }
+            snapshot_tree.apply_batch(batch)?;
+        }
+        Ok(Arc::pin(snapshot))
+    }
+}
+
+impl Drop for SledDB {
+    fn drop(&mut self) {
+        if let Err(err) = self.db.flush() {
+            tracing::warn!(target: "store", "Failed to flush sled database: {}", err);
+        }
+    }
+}
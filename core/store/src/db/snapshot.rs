@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use rocksdb::{ColumnFamily, Direction, IteratorMode, Snapshot, DB};
+
+use crate::db::{rocksdb_read_options, DBCol, DBError, DBTransaction, Database, RocksDB};
+
+/// Read-only view of a RocksDB database as of the moment the view was created. The view is an
+/// in-memory RocksDB snapshot, so it takes no disk space, but it keeps the data it sees from
+/// being compacted away until it is dropped.
+pub(crate) struct RocksDBSnapshot {
+    // Declared before `_db`, so that it is released before the database it borrows is closed.
+    snapshot: Snapshot<'static>,
+    _db: Arc<DB>,
+    cfs: Vec<*const ColumnFamily>,
+}
+
+// Snapshot and DB are Send+Sync, and the column families are const pointers into the DB kept
+// alive by `_db`.
+unsafe impl Send for RocksDBSnapshot {}
+unsafe impl Sync for RocksDBSnapshot {}
+
+impl RocksDBSnapshot {
+    pub(crate) fn new(db: Arc<DB>, cfs: Vec<*const ColumnFamily>) -> Self {
+        // The snapshot borrows the database, which lives in the same struct behind the `Arc`.
+        let snapshot =
+            unsafe { std::mem::transmute::<Snapshot<'_>, Snapshot<'static>>(db.snapshot()) };
+        Self { snapshot, _db: db, cfs }
+    }
+
+    fn cf_handle(&self, col: DBCol) -> &ColumnFamily {
+        unsafe { &*self.cfs[col as usize] }
+    }
+}
+
+impl Database for RocksDBSnapshot {
+    fn get(&self, col: DBCol, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        let result = self.get_without_rc_logic(col, key)?;
+        Ok(RocksDB::get_with_rc_logic(col, result))
+    }
+
+    fn get_without_rc_logic(&self, col: DBCol, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        Ok(self.snapshot.get_cf_opt(self.cf_handle(col), key, rocksdb_read_options())?)
+    }
+
+    fn iter<'a>(&'a self, col: DBCol) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+        RocksDB::iter_with_rc_logic(col, self.iter_without_rc_logic(col))
+    }
+
+    fn iter_without_rc_logic<'a>(
+        &'a self,
+        col: DBCol,
+    ) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+        Box::new(self.snapshot.iterator_cf_opt(
+            self.cf_handle(col),
+            rocksdb_read_options(),
+            IteratorMode::Start,
+        ))
+    }
+
+    fn iter_prefix<'a>(
+        &'a self,
+        col: DBCol,
+        key_prefix: &'a [u8],
+    ) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+        let mut read_options = rocksdb_read_options();
+        read_options.set_prefix_same_as_start(true);
+        let iterator = self
+            .snapshot
+            .iterator_cf_opt(
+                self.cf_handle(col),
+                read_options,
+                IteratorMode::From(key_prefix, Direction::Forward),
+            )
+            .take_while(move |(key, _value)| key.starts_with(key_prefix));
+        RocksDB::iter_with_rc_logic(col, iterator)
+    }
+
+    fn write(&self, _transaction: DBTransaction) -> Result<(), DBError> {
+        Err(DBError::NotSupported("writing to a snapshot"))
+    }
+}
@@ -0,0 +1,351 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use crate::db::refcount::{decode_value_with_rc, encode_value_with_rc};
+use crate::db::{DBCol, DBError, DBOp, DBTransaction, Database};
+
+/// Subdirectory of a checkpoint of a split database with the checkpoint of the cold database.
+const COLD_CHECKPOINT_DIR: &str = "cold";
+
+/// Database of an archival node split into a hot database with recent data and a cold database
+/// with the history.
+///
+/// All writes go to the hot database, which is garbage collected the same way as the database
+/// of a regular node. Before data of the canonical chain in a cold column (see `DBCol::is_cold`)
+/// is deleted from the hot database, or its reference count drops to zero, it is copied to the
+/// cold one, so the node keeps the whole history of the canonical chain. Reads of cold
+/// columns fall back to the cold database if the key isn't in the hot one, and iterators merge
+/// the entries of both databases in key order, preferring the hot entry if a key is in both.
+/// Copying to the cold database is idempotent, so a write which failed after the cold database
+/// was updated can be retried.
+pub struct SplitDB {
+    hot: Pin<Arc<dyn Database>>,
+    cold: Pin<Arc<dyn Database>>,
+}
+
+impl SplitDB {
+    pub fn new(hot: Pin<Arc<dyn Database>>, cold: Pin<Arc<dyn Database>>) -> Self {
+        SplitDB { hot, cold }
+    }
+
+    /// Collects the current values of the cold column entries of the canonical chain which
+    /// `transaction` deletes from the hot database, or dereferences so that their reference count
+    /// drops to zero. The values are written with `put`, and reference counted ones with a
+    /// reference count of one, so applying the result twice changes nothing. Data of forks (see
+    /// `DBTransaction::fork_ops`) isn't copied.
+    fn cold_transaction(&self, transaction: &DBTransaction) -> Result<DBTransaction, DBError> {
+        let mut cold_transaction = self.cold.transaction();
+        // The sum of the reference count changes of each key, and whether the canonical chain
+        // dereferences it.
+        let mut rc_deltas: HashMap<(DBCol, &[u8]), (i64, bool)> = HashMap::new();
+        for (index, op) in transaction.ops.iter().enumerate() {
+            let is_fork = transaction.fork_ops.contains(&index);
+            match op {
+                DBOp::Delete { col, key } if col.is_cold() && !is_fork => {
+                    if let Some(value) = self.hot.get(*col, key)? {
+                        cold_transaction.put(*col, key, value);
+                    }
+                }
+                DBOp::UpdateRefcount { col, key, value } if col.is_cold() => {
+                    let (_, rc) = decode_value_with_rc(value);
+                    let (rc_delta, canonical) =
+                        rc_deltas.entry((*col, key.as_slice())).or_insert((0, false));
+                    *rc_delta += rc;
+                    *canonical |= rc < 0 && !is_fork;
+                }
+                _ => {}
+            }
+        }
+        for ((col, key), (rc_delta, canonical)) in rc_deltas {
+            if !canonical {
+                continue;
+            }
+            if let Some(raw_value) = self.hot.get_without_rc_logic(col, key)? {
+                if let (Some(value), rc) = decode_value_with_rc(&raw_value) {
+                    if rc > 0 && rc + rc_delta <= 0 {
+                        cold_transaction.put(col, key, encode_value_with_rc(value, 1));
+                    }
+                }
+            }
+        }
+        Ok(cold_transaction)
+    }
+}
+
+/// Merges the iterators of the hot and the cold database, which both yield keys in ascending
+/// order, skipping the cold entries of keys which are also in the hot database.
+struct MergeIter<'a> {
+    hot: Peekable<Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>>,
+    cold: Peekable<Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>>,
+}
+
+impl<'a> MergeIter<'a> {
+    fn new_boxed(
+        hot: Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>,
+        cold: Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>,
+    ) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+        Box::new(MergeIter { hot: hot.peekable(), cold: cold.peekable() })
+    }
+}
+
+impl<'a> Iterator for MergeIter<'a> {
+    type Item = (Box<[u8]>, Box<[u8]>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ordering = match (self.hot.peek(), self.cold.peek()) {
+            (Some((hot_key, _)), Some((cold_key, _))) => hot_key.cmp(cold_key),
+            (Some(_), None) => Ordering::Less,
+            (None, _) => return self.cold.next(),
+        };
+        match ordering {
+            Ordering::Less => self.hot.next(),
+            Ordering::Equal => {
+                self.cold.next();
+                self.hot.next()
+            }
+            Ordering::Greater => self.cold.next(),
+        }
+    }
+}
+
+impl Database for SplitDB {
+    fn get(&self, col: DBCol, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        match self.hot.get(col, key)? {
+            None if col.is_cold() => self.cold.get(col, key),
+            result => Ok(result),
+        }
+    }
+
+    fn get_without_rc_logic(&self, col: DBCol, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        match self.hot.get_without_rc_logic(col, key)? {
+            None if col.is_cold() => self.cold.get_without_rc_logic(col, key),
+            result => Ok(result),
+        }
+    }
+
+    fn iter<'a>(&'a self, col: DBCol) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+        if !col.is_cold() {
+            return self.hot.iter(col);
+        }
+        MergeIter::new_boxed(self.hot.iter(col), self.cold.iter(col))
+    }
+
+    fn iter_without_rc_logic<'a>(
+        &'a self,
+        col: DBCol,
+    ) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+        if !col.is_cold() {
+            return self.hot.iter_without_rc_logic(col);
+        }
+        MergeIter::new_boxed(
+            self.hot.iter_without_rc_logic(col),
+            self.cold.iter_without_rc_logic(col),
+        )
+    }
+
+    fn iter_prefix<'a>(
+        &'a self,
+        col: DBCol,
+        key_prefix: &'a [u8],
+    ) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+        if !col.is_cold() {
+            return self.hot.iter_prefix(col, key_prefix);
+        }
+        MergeIter::new_boxed(
+            self.hot.iter_prefix(col, key_prefix),
+            self.cold.iter_prefix(col, key_prefix),
+        )
+    }
+
+    fn write(&self, transaction: DBTransaction) -> Result<(), DBError> {
+        // The cold database is written first, so that a failure in between leaves the data in
+        // both databases rather than in neither.
+        let cold_transaction = self.cold_transaction(&transaction)?;
+        if !cold_transaction.ops.is_empty() {
+            self.cold.write(cold_transaction)?;
+        }
+        self.hot.write(transaction)
+    }
+
+    /// The hot database is checkpointed at `path` and the cold one in its `cold` subdirectory.
+    /// The hot checkpoint is taken first: the data deleted from the hot database in between was
+    /// copied to the cold database before, so it is in the cold checkpoint.
+    fn create_checkpoint(&self, path: &Path) -> Result<Pin<Arc<dyn Database>>, DBError> {
+        let hot = self.hot.create_checkpoint(path)?;
+        let cold = self.cold.create_checkpoint(&path.join(COLD_CHECKPOINT_DIR))?;
+        Ok(Arc::pin(SplitDB::new(hot, cold)))
+    }
+
+    fn snapshot(&self) -> Result<Pin<Arc<dyn Database>>, DBError> {
+        Ok(Arc::pin(SplitDB::new(self.hot.snapshot()?, self.cold.snapshot()?)))
+    }
+
+    fn get_column_stats(&self) -> Result<Vec<crate::db::ColumnStats>, DBError> {
+        self.hot.get_column_stats()
+    }
+
+    fn compact_columns(&self, cols: &[DBCol]) -> Result<(), DBError> {
+        self.hot.compact_columns(cols)
+    }
+
+    fn cold_db(&self) -> Option<&dyn Database> {
+        Some(&*self.cold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::db::refcount::encode_value_with_rc;
+    use crate::db::split_db::MergeIter;
+    use crate::db::{DBCol, Database, RocksDB, SplitDB, TestDB};
+    use crate::Store;
+
+    #[test]
+    fn test_split_db() {
+        let hot = Arc::pin(TestDB::new());
+        let cold = Arc::pin(TestDB::new());
+        let store = Store::new(Arc::pin(SplitDB::new(hot.clone(), cold.clone())));
+
+        let mut store_update = store.store_update();
+        store_update.set(DBCol::ColBlock, b"old", b"block");
+        store_update.set(DBCol::ColBlockMisc, b"misc", b"value");
+        store_update.update_refcount(DBCol::ColState, b"node", b"state", 2);
+        store_update.commit().unwrap();
+        assert_eq!(cold.get(DBCol::ColBlock, b"old").unwrap(), None);
+
+        let mut store_update = store.store_update();
+        store_update.delete(DBCol::ColBlock, b"old");
+        store_update.delete(DBCol::ColBlockMisc, b"misc");
+        store_update.update_refcount(DBCol::ColState, b"node", &[], -2);
+        store_update.commit().unwrap();
+
+        assert_eq!(hot.get(DBCol::ColBlock, b"old").unwrap(), None);
+        assert_eq!(store.get(DBCol::ColBlock, b"old").unwrap(), Some(b"block".to_vec()));
+        assert_eq!(store.get(DBCol::ColBlockMisc, b"misc").unwrap(), None);
+        assert_eq!(hot.get(DBCol::ColState, b"node").unwrap(), None);
+        assert_eq!(store.get(DBCol::ColState, b"node").unwrap(), Some(b"state".to_vec()));
+        assert_eq!(
+            cold.iter_without_rc_logic(DBCol::ColState).next().unwrap().1.to_vec(),
+            encode_value_with_rc(b"state", 1)
+        );
+
+        let mut store_update = store.store_update();
+        store_update.set(DBCol::ColBlock, b"new", b"block");
+        store_update.commit().unwrap();
+        let keys: Vec<_> = store.iter(DBCol::ColBlock).map(|(key, _)| key.to_vec()).collect();
+        assert_eq!(keys, vec![b"new".to_vec(), b"old".to_vec()]);
+    }
+
+    #[test]
+    fn test_split_db_move_retried() {
+        let hot = Arc::pin(TestDB::new());
+        let cold = Arc::pin(TestDB::new());
+        let split_db = SplitDB::new(hot.clone(), cold.clone());
+
+        let mut transaction = split_db.transaction();
+        transaction.put(DBCol::ColBlock, b"block", b"value");
+        transaction.update_refcount(DBCol::ColState, b"node", encode_value_with_rc(b"state", 3));
+        split_db.write(transaction).unwrap();
+
+        let mut transaction = split_db.transaction();
+        transaction.delete(DBCol::ColBlock, b"block");
+        transaction.update_refcount(DBCol::ColState, b"node", encode_value_with_rc(&[], -3));
+        // The copy to the cold database succeeded, but the write to the hot one didn't.
+        cold.write(split_db.cold_transaction(&transaction).unwrap()).unwrap();
+        split_db.write(transaction).unwrap();
+
+        assert_eq!(hot.get(DBCol::ColState, b"node").unwrap(), None);
+        assert_eq!(cold.get(DBCol::ColBlock, b"block").unwrap(), Some(b"value".to_vec()));
+        assert_eq!(
+            cold.iter_without_rc_logic(DBCol::ColState).collect::<Vec<_>>(),
+            vec![(
+                b"node".to_vec().into_boxed_slice(),
+                encode_value_with_rc(b"state", 1).into_boxed_slice()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_split_db_copies_dropped_canonical_data() {
+        let hot = Arc::pin(TestDB::new());
+        let cold = Arc::pin(TestDB::new());
+        let store = Store::new(Arc::pin(SplitDB::new(hot.clone(), cold.clone())));
+
+        let mut store_update = store.store_update();
+        store_update.set(DBCol::ColBlock, b"fork", b"block");
+        store_update.update_refcount(DBCol::ColState, b"node", b"state", 2);
+        store_update.update_refcount(DBCol::ColState, b"fork_node", b"state", 1);
+        store_update.commit().unwrap();
+
+        // The node is still referenced.
+        let mut store_update = store.store_update();
+        store_update.update_refcount(DBCol::ColState, b"node", &[], -1);
+        store_update.commit().unwrap();
+        assert_eq!(cold.get(DBCol::ColState, b"node").unwrap(), None);
+
+        let mut store_update = store.store_update();
+        store_update.delete(DBCol::ColBlock, b"fork");
+        store_update.update_refcount(DBCol::ColState, b"fork_node", &[], -1);
+        store_update.mark_fork_data();
+        store_update.update_refcount(DBCol::ColState, b"node", &[], -1);
+        store_update.commit().unwrap();
+
+        assert_eq!(store.get(DBCol::ColBlock, b"fork").unwrap(), None);
+        assert_eq!(store.get(DBCol::ColState, b"fork_node").unwrap(), None);
+        assert_eq!(hot.get(DBCol::ColState, b"node").unwrap(), None);
+        assert_eq!(store.get(DBCol::ColState, b"node").unwrap(), Some(b"state".to_vec()));
+    }
+
+    #[test]
+    fn test_merge_iter() {
+        let entries = |keys: &[&'static [u8]], value: &'static [u8]| {
+            let entries: Vec<(Box<[u8]>, Box<[u8]>)> =
+                keys.iter().map(|key| (key.to_vec().into(), value.to_vec().into())).collect();
+            Box::new(entries.into_iter()) as Box<dyn Iterator<Item = _>>
+        };
+        let merged: Vec<_> = MergeIter::new_boxed(
+            entries(&[b"a", b"c", b"d"], b"hot"),
+            entries(&[b"b", b"c", b"e"], b"cold"),
+        )
+        .map(|(key, value)| (key.to_vec(), value.to_vec()))
+        .collect();
+        assert_eq!(
+            merged,
+            vec![
+                (b"a".to_vec(), b"hot".to_vec()),
+                (b"b".to_vec(), b"cold".to_vec()),
+                (b"c".to_vec(), b"hot".to_vec()),
+                (b"d".to_vec(), b"hot".to_vec()),
+                (b"e".to_vec(), b"cold".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_db_checkpoint() {
+        let tmp_dir = tempfile::Builder::new().prefix("_test_split_checkpoint").tempdir().unwrap();
+        let hot = Arc::pin(RocksDB::new(tmp_dir.path().join("hot")).unwrap());
+        let cold = Arc::pin(RocksDB::new(tmp_dir.path().join("cold")).unwrap());
+        let store = Store::new(Arc::pin(SplitDB::new(hot, cold)));
+        let mut store_update = store.store_update();
+        store_update.set(DBCol::ColBlock, b"old", b"block");
+        store_update.commit().unwrap();
+        let mut store_update = store.store_update();
+        store_update.delete(DBCol::ColBlock, b"old");
+        store_update.set(DBCol::ColBlock, b"new", b"block");
+        store_update.commit().unwrap();
+
+        let checkpoint_path = tmp_dir.path().join("checkpoint");
+        let checkpoint = store.storage.create_checkpoint(&checkpoint_path).unwrap();
+        assert!(checkpoint_path.join(super::COLD_CHECKPOINT_DIR).exists());
+        assert_eq!(checkpoint.get(DBCol::ColBlock, b"old").unwrap(), Some(b"block".to_vec()));
+        assert_eq!(checkpoint.get(DBCol::ColBlock, b"new").unwrap(), Some(b"block".to_vec()));
+        assert_eq!(checkpoint.cold_db().unwrap().get(DBCol::ColBlock, b"new").unwrap(), None);
+    }
+}
@@ -7,16 +7,20 @@ use std::ops::Deref;
 use std::path::Path;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::{fmt, io};
 
 use borsh::{BorshDeserialize, BorshSerialize};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use cached::{Cached, SizedCache};
+use tracing::warn;
 
+pub use config::{DBBackend, StoreConfig, TrieCacheConfig};
 pub use db::DBCol::{self, *};
 pub use db::{
-    CHUNK_TAIL_KEY, FINAL_HEAD_KEY, FORK_TAIL_KEY, HEADER_HEAD_KEY, HEAD_KEY,
-    LARGEST_TARGET_HEIGHT_KEY, LATEST_KNOWN_KEY, NUM_COLS, SHOULD_COL_GC, SKIP_COL_GC, TAIL_KEY,
+    ColumnStats, CHUNK_TAIL_KEY, FINAL_HEAD_KEY, FORK_TAIL_KEY, HEADER_HEAD_KEY, HEAD_KEY,
+    LARGEST_TARGET_HEIGHT_KEY, LATEST_KNOWN_KEY, NUM_COLS, OUTCOME_TAIL_KEY, SHOULD_COL_GC,
+    SKIP_COL_GC, STATE_TAIL_KEY, TAIL_KEY,
 };
 use near_crypto::PublicKey;
 use near_primitives::account::{AccessKey, Account};
@@ -26,20 +30,26 @@ use near_primitives::hash::CryptoHash;
 use near_primitives::receipt::{Receipt, ReceivedData};
 use near_primitives::serialize::to_base;
 use near_primitives::trie_key::{trie_key_parsers, TrieKey};
-use near_primitives::types::{AccountId, CompiledContractCache, StateRoot};
+use near_primitives::types::{AccountId, CompiledContractCache, ShardId, StateRoot};
+use near_primitives::version::DbVersion;
 
 pub use crate::db::refcount::decode_value_with_rc;
 use crate::db::refcount::encode_value_with_rc;
 use crate::db::{
-    DBOp, DBTransaction, Database, RocksDB, GENESIS_JSON_HASH_KEY, GENESIS_STATE_ROOTS_KEY,
+    DBOp, DBTransaction, Database, RocksDB, SledDB, SplitDB, GENESIS_JSON_HASH_KEY,
+    GENESIS_STATE_ROOTS_KEY,
 };
+pub use crate::trie::flat_state::{get_flat_state_delta_key, get_flat_state_head};
+pub use crate::trie::range_proof::{prefix_range_end, TrieRange};
 pub use crate::trie::{
     iterator::TrieIterator, update::TrieUpdate, update::TrieUpdateIterator,
     update::TrieUpdateValuePtr, ApplyStatePartResult, KeyForStateChanges, PartialStorage,
     ShardTries, Trie, TrieChanges, WrappedTrieChanges,
 };
 
+mod config;
 pub mod db;
+mod metrics;
 pub mod migrations;
 pub mod test_utils;
 mod trie;
@@ -47,11 +57,18 @@ mod trie;
 #[derive(Clone)]
 pub struct Store {
     storage: Pin<Arc<dyn Database>>,
+    /// Commits taking longer than this are logged with a breakdown by column.
+    slow_commit_threshold: Option<Duration>,
 }
 
 impl Store {
     pub fn new(storage: Pin<Arc<dyn Database>>) -> Store {
-        Store { storage }
+        Store { storage, slow_commit_threshold: None }
+    }
+
+    pub fn with_slow_commit_threshold(mut self, threshold: Option<Duration>) -> Store {
+        self.slow_commit_threshold = threshold;
+        self
     }
 
     pub fn get(&self, column: DBCol, key: &[u8]) -> Result<Option<Vec<u8>>, io::Error> {
@@ -79,6 +96,7 @@ impl Store {
 
     pub fn store_update(&self) -> StoreUpdate {
         StoreUpdate::new(self.storage.clone())
+            .with_slow_commit_threshold(self.slow_commit_threshold)
     }
 
     pub fn iter<'a>(
@@ -145,8 +163,35 @@ impl Store {
         self.storage.write(transaction).map_err(|e| e.into())
     }
 
-    pub fn get_rocksdb(&self) -> Option<&RocksDB> {
-        self.storage.as_rocksdb()
+    /// Creates a consistent snapshot of all columns at `path` while the store stays writable.
+    /// Returns the database version recorded in the snapshot.
+    pub fn create_checkpoint(&self, path: &Path) -> Result<DbVersion, io::Error> {
+        let db = self.storage.create_checkpoint(path).map_err(Into::<io::Error>::into)?;
+        let checkpoint = Store::new(db);
+        migrations::read_store_version(&checkpoint).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::Other, "Checkpoint is missing the database version")
+        })
+    }
+
+    /// Opens a read-only view of the current contents of the store, which doesn't see the writes
+    /// done after it was opened.
+    pub fn snapshot(&self) -> Result<Arc<Store>, io::Error> {
+        let db = self.storage.snapshot().map_err(Into::<io::Error>::into)?;
+        Ok(Arc::new(Store::new(db)))
+    }
+
+    pub fn get_column_stats(&self) -> Result<Vec<ColumnStats>, io::Error> {
+        self.storage.get_column_stats().map_err(Into::into)
+    }
+
+    /// Runs a manual compaction of `cols`, reclaiming the space of deleted and overwritten data.
+    pub fn compact_columns(&self, cols: &[DBCol]) -> Result<(), io::Error> {
+        self.storage.compact_columns(cols).map_err(Into::into)
+    }
+
+    /// Whether garbage collected data is moved to a cold database instead of being deleted.
+    pub fn has_cold_storage(&self) -> bool {
+        self.storage.cold_db().is_some()
     }
 }
 
@@ -156,18 +201,39 @@ pub struct StoreUpdate {
     transaction: DBTransaction,
     /// Optionally has reference to the trie to clear cache on the commit.
     tries: Option<ShardTries>,
+    /// State roots created by the trie changes in this update, the cache pins their top levels.
+    new_state_roots: Vec<(ShardId, StateRoot)>,
+    slow_commit_threshold: Option<Duration>,
 }
 
 impl StoreUpdate {
     pub fn new(storage: Pin<Arc<dyn Database>>) -> Self {
         let transaction = storage.transaction();
-        StoreUpdate { storage, transaction, tries: None }
+        StoreUpdate {
+            storage,
+            transaction,
+            tries: None,
+            new_state_roots: vec![],
+            slow_commit_threshold: None,
+        }
     }
 
     pub fn new_with_tries(tries: ShardTries) -> Self {
-        let storage = tries.get_store().storage.clone();
+        let store = tries.get_store();
+        let storage = store.storage.clone();
         let transaction = storage.transaction();
-        StoreUpdate { storage, transaction, tries: Some(tries) }
+        StoreUpdate {
+            storage,
+            transaction,
+            tries: Some(tries),
+            new_state_roots: vec![],
+            slow_commit_threshold: store.slow_commit_threshold,
+        }
+    }
+
+    fn with_slow_commit_threshold(mut self, threshold: Option<Duration>) -> Self {
+        self.slow_commit_threshold = threshold;
+        self
     }
 
     pub fn update_refcount(&mut self, column: DBCol, key: &[u8], value: &[u8], rc_delta: i64) {
@@ -212,12 +278,21 @@ impl StoreUpdate {
                 );
             }
         }
+        self.new_state_roots.extend(other.new_state_roots);
 
         self.merge_transaction(other.transaction);
     }
 
+    /// Marks the data which this update deletes or dereferences so far as data of forks, which
+    /// isn't moved to the cold database of an archival node.
+    pub fn mark_fork_data(&mut self) {
+        self.transaction.mark_fork_ops();
+    }
+
     /// Merge DB Transaction.
     pub fn merge_transaction(&mut self, transaction: DBTransaction) {
+        let num_ops = self.transaction.ops.len();
+        self.transaction.fork_ops.extend(transaction.fork_ops.iter().map(|index| num_ops + index));
         for op in transaction.ops {
             match op {
                 DBOp::Insert { col, key, value } => self.transaction.put(col, &key, &value),
@@ -255,12 +330,81 @@ impl StoreUpdate {
                 tries.get_store().storage.deref() as *const _,
                 self.storage.deref() as *const _
             );
-            tries.update_cache(&self.transaction)?;
+            tries.update_cache(&self.transaction, &self.new_state_roots)?;
+        }
+        let column_stats = transaction_column_stats(&self.transaction);
+        let start = Instant::now();
+        let result: Result<(), io::Error> =
+            self.storage.write(self.transaction).map_err(|e| e.into());
+        let elapsed = start.elapsed();
+        near_metrics::observe(&metrics::STORE_COMMIT_TIME, elapsed.as_secs_f64());
+        for stats in column_stats.iter() {
+            let column = format!("{:?}", stats.column);
+            near_metrics::inc_counter_vec_by(
+                &metrics::STORE_COMMIT_OPS_TOTAL,
+                &[&column],
+                stats.num_ops,
+            );
+            near_metrics::inc_counter_vec_by(
+                &metrics::STORE_COMMIT_BYTES_TOTAL,
+                &[&column],
+                stats.num_bytes,
+            );
+        }
+        if self.slow_commit_threshold.map_or(false, |threshold| elapsed >= threshold) {
+            let total_ops: u64 = column_stats.iter().map(|stats| stats.num_ops).sum();
+            let total_bytes: u64 = column_stats.iter().map(|stats| stats.num_bytes).sum();
+            let columns = column_stats
+                .iter()
+                .take(SLOW_COMMIT_LOGGED_COLUMNS)
+                .map(|stats| {
+                    format!("{:?}: {} ops, {} bytes", stats.column, stats.num_ops, stats.num_bytes)
+                })
+                .collect::<Vec<_>>()
+                .join("; ");
+            warn!(target: "store", "Slow commit took {:?}: {} ops, {} bytes, largest columns: {}", elapsed, total_ops, total_bytes, columns);
         }
-        self.storage.write(self.transaction).map_err(|e| e.into())
+        result
     }
 }
 
+/// Number of columns listed in the slow commit log.
+const SLOW_COMMIT_LOGGED_COLUMNS: usize = 5;
+
+/// Size of a transaction's changes to a single column.
+#[derive(Debug, PartialEq, Eq)]
+struct CommitColumnStats {
+    column: DBCol,
+    num_ops: u64,
+    /// Total size of the keys and values written.
+    num_bytes: u64,
+}
+
+/// Returns per column sizes of the transaction, largest columns first.
+fn transaction_column_stats(transaction: &DBTransaction) -> Vec<CommitColumnStats> {
+    let mut stats: Vec<CommitColumnStats> = vec![];
+    for op in transaction.ops.iter() {
+        let (column, num_bytes) = match op {
+            DBOp::Insert { col, key, value } | DBOp::UpdateRefcount { col, key, value } => {
+                (*col, key.len() + value.len())
+            }
+            DBOp::Delete { col, key } => (*col, key.len()),
+            DBOp::DeleteAll { col } => (*col, 0),
+        };
+        match stats.iter_mut().find(|stats| stats.column == column) {
+            Some(stats) => {
+                stats.num_ops += 1;
+                stats.num_bytes += num_bytes as u64;
+            }
+            None => {
+                stats.push(CommitColumnStats { column, num_ops: 1, num_bytes: num_bytes as u64 })
+            }
+        }
+    }
+    stats.sort_by(|a, b| b.num_bytes.cmp(&a.num_bytes).then(b.num_ops.cmp(&a.num_ops)));
+    stats
+}
+
 impl fmt::Debug for StoreUpdate {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "Store Update {{")?;
@@ -300,6 +444,28 @@ pub fn create_store(path: &str) -> Arc<Store> {
     Arc::new(Store::new(db))
 }
 
+/// Returns the path of the cold database configured for the store at `path`, if any.
+pub fn get_cold_store_path(path: &str, config: &StoreConfig) -> Option<String> {
+    config.cold_store_path.as_ref().map(|cold_store_path| {
+        let cold_store_path =
+            Path::new(path).parent().unwrap_or(Path::new("")).join(cold_store_path);
+        cold_store_path.to_str().unwrap().to_owned()
+    })
+}
+
+/// Opens the store at `path` using the backend selected in `config`.
+pub fn create_store_with_config(path: &str, config: &StoreConfig) -> Arc<Store> {
+    let mut db: Pin<Arc<dyn Database>> = match config.backend {
+        DBBackend::RocksDB => Arc::pin(RocksDB::new(path).expect("Failed to open the database")),
+        DBBackend::Sled => Arc::pin(SledDB::new(path).expect("Failed to open the database")),
+    };
+    if let Some(cold_store_path) = get_cold_store_path(path, config) {
+        let cold_db = RocksDB::new(&cold_store_path).expect("Failed to open the cold database");
+        db = Arc::pin(SplitDB::new(db, Arc::pin(cold_db)));
+    }
+    Arc::new(Store::new(db).with_slow_commit_threshold(config.slow_commit_threshold))
+}
+
 /// Reads an object from Trie.
 /// # Errors
 /// see StorageError
@@ -518,9 +684,30 @@ impl CompiledContractCache for StoreCompiledContractCache {
 
 #[cfg(test)]
 mod tests {
+    use crate::db::DBTransaction;
+    use crate::{transaction_column_stats, CommitColumnStats, DBCol};
+
     #[test]
     fn test_no_cache_disabled() {
         #[cfg(feature = "no_cache")]
         panic!("no cache is enabled");
     }
+
+    #[test]
+    fn test_transaction_column_stats() {
+        let mut transaction = DBTransaction::new();
+        transaction.put(DBCol::ColBlockMisc, b"key", &[0; 10]);
+        transaction.put(DBCol::ColState, b"key1", &[0; 100]);
+        transaction.update_refcount(DBCol::ColState, b"key2", &[0; 100]);
+        transaction.delete(DBCol::ColBlockMisc, b"other");
+        transaction.delete_all(DBCol::ColChunks);
+        assert_eq!(
+            transaction_column_stats(&transaction),
+            vec![
+                CommitColumnStats { column: DBCol::ColState, num_ops: 2, num_bytes: 208 },
+                CommitColumnStats { column: DBCol::ColBlockMisc, num_ops: 2, num_bytes: 18 },
+                CommitColumnStats { column: DBCol::ColChunks, num_ops: 1, num_bytes: 0 },
+            ]
+        );
+    }
 }
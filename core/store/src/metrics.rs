@@ -0,0 +1,38 @@
+use near_metrics::{try_create_histogram, try_create_int_counter_vec, Histogram, IntCounterVec};
+
+lazy_static! {
+    pub static ref TRIE_CACHE_HITS_TOTAL: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_trie_cache_hits_total",
+            "Total number of trie node reads served from the cache",
+            &["shard_id", "is_view"]
+        );
+    pub static ref TRIE_CACHE_MISSES_TOTAL: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_trie_cache_misses_total",
+            "Total number of trie node reads which missed the cache",
+            &["shard_id", "is_view"]
+        );
+    pub static ref TRIE_CACHE_EVICTIONS_TOTAL: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_trie_cache_evictions_total",
+            "Total number of trie nodes evicted from the cache because of its size limit",
+            &["shard_id", "is_view"]
+        );
+    pub static ref STORE_COMMIT_TIME: near_metrics::Result<Histogram> = try_create_histogram(
+        "near_store_commit_time",
+        "Time taken to write a store update to the database"
+    );
+    pub static ref STORE_COMMIT_OPS_TOTAL: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_store_commit_ops_total",
+            "Total number of operations written to the database, by column",
+            &["column"]
+        );
+    pub static ref STORE_COMMIT_BYTES_TOTAL: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_store_commit_bytes_total",
+            "Total size of keys and values written to the database, by column",
+            &["column"]
+        );
+}
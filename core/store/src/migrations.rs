@@ -1,8 +1,11 @@
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use borsh::{BorshDeserialize, BorshSerialize};
+use serde::{Deserialize, Serialize};
+use tracing::info;
 
 use near_primitives::hash::{hash, CryptoHash};
 use near_primitives::sharding::{
@@ -20,7 +23,9 @@ use crate::migrations::v6_to_v7::{
 use crate::migrations::v8_to_v9::{
     recompute_col_rc, repair_col_receipt_id_to_shard_id, repair_col_transactions,
 };
-use crate::{create_store, Store, StoreUpdate, Trie, TrieUpdate, FINAL_HEAD_KEY, HEAD_KEY};
+use crate::{
+    create_store, Store, StoreUpdate, Trie, TrieCacheConfig, TrieUpdate, FINAL_HEAD_KEY, HEAD_KEY,
+};
 
 use crate::trie::{TrieCache, TrieCachingStorage};
 use near_crypto::KeyType;
@@ -46,12 +51,23 @@ pub fn get_store_version(path: &str) -> DbVersion {
     RocksDB::get_version(path).expect("Failed to open the database")
 }
 
+/// Returns version of an already opened store regardless of its backend.
+pub fn read_store_version(store: &Store) -> Option<DbVersion> {
+    store.get(DBCol::ColDbVersion, VERSION_KEY).expect("Failed to read the database").map(
+        |version| {
+            serde_json::from_slice(&version)
+                .expect("Failed to parse version. Database must be corrupted.")
+        },
+    )
+}
+
 fn set_store_version_inner(store_update: &mut StoreUpdate, db_version: u32) {
     store_update.set(
         DBCol::ColDbVersion,
         VERSION_KEY,
         &serde_json::to_vec(&db_version).expect("Failed to serialize version"),
     );
+    store_update.delete(DBCol::ColDbVersion, MIGRATION_CHECKPOINT_KEY);
 }
 
 pub fn set_store_version(store: &Store, db_version: u32) {
@@ -60,6 +76,150 @@ pub fn set_store_version(store: &Store, db_version: u32) {
     store_update.commit().expect("Failed to write version to database");
 }
 
+/// Dummy database version set by nodes built with nightly protocol features, so that their
+/// migrations don't conflict with the migrations of the stable releases.
+pub const NIGHTLY_DB_VERSION: DbVersion = 10000;
+
+/// Key in `ColDbVersion` of the progress of the migration which is being applied.
+pub const MIGRATION_CHECKPOINT_KEY: &[u8; 20] = b"MIGRATION_CHECKPOINT";
+
+/// Progress of a migration, committed together with the migrated data so that an interrupted
+/// migration continues from the same place instead of rewriting the data again. Removed when
+/// the database version is bumped.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MigrationCheckpoint {
+    /// Version the database is being migrated to.
+    pub db_version: DbVersion,
+    /// Index of the step of the migration, e.g. of the column being rewritten.
+    pub step: u32,
+    /// Last key processed by the step.
+    pub last_key: Vec<u8>,
+    /// Number of items processed by the step.
+    pub processed: u64,
+}
+
+/// Returns the progress of the interrupted migration of the database at `path`, if any.
+pub fn get_migration_checkpoint(path: &str) -> Option<MigrationCheckpoint> {
+    let db = RocksDB::new_read_only(path).expect("Failed to open the database");
+    read_migration_checkpoint(&Store::new(Arc::pin(db)))
+}
+
+pub fn read_migration_checkpoint(store: &Store) -> Option<MigrationCheckpoint> {
+    store
+        .get(DBCol::ColDbVersion, MIGRATION_CHECKPOINT_KEY)
+        .expect("Failed to read the database")
+        .map(|checkpoint| {
+            serde_json::from_slice(&checkpoint)
+                .expect("Failed to parse migration checkpoint. Database must be corrupted.")
+        })
+}
+
+pub fn set_migration_checkpoint(store_update: &mut StoreUpdate, checkpoint: &MigrationCheckpoint) {
+    store_update.set(
+        DBCol::ColDbVersion,
+        MIGRATION_CHECKPOINT_KEY,
+        &serde_json::to_vec(checkpoint).expect("Failed to serialize migration checkpoint"),
+    );
+}
+
+/// Where a step of a resumable migration starts.
+pub enum MigrationStepStart {
+    /// The step was completed before the migration was interrupted.
+    Done,
+    /// The step continues after `last_key`, or starts from the beginning if it's None.
+    After { last_key: Option<Vec<u8>>, processed: u64 },
+}
+
+impl MigrationStepStart {
+    pub fn new(store: &Store, db_version: DbVersion, step: u32) -> Self {
+        match read_migration_checkpoint(store) {
+            Some(checkpoint) if checkpoint.db_version == db_version && checkpoint.step > step => {
+                MigrationStepStart::Done
+            }
+            Some(checkpoint) if checkpoint.db_version == db_version && checkpoint.step == step => {
+                info!(target: "near", "Resuming migration to version {} at step {} after {} items", db_version, step, checkpoint.processed);
+                MigrationStepStart::After {
+                    last_key: Some(checkpoint.last_key),
+                    processed: checkpoint.processed,
+                }
+            }
+            _ => MigrationStepStart::After { last_key: None, processed: 0 },
+        }
+    }
+}
+
+/// Returns the number of keys in the column estimated by the database, if it supports it.
+pub fn estimate_num_keys(store: &Store, col: DBCol) -> Option<u64> {
+    store
+        .get_column_stats()
+        .ok()?
+        .into_iter()
+        .find(|stats| stats.column == col)
+        .map(|stats| stats.estimated_num_keys)
+}
+
+const MIGRATION_PROGRESS_PERIOD: Duration = Duration::from_secs(10);
+
+/// Periodically logs how many items a migration step has processed and how long the rest of the
+/// step is going to take.
+pub struct MigrationProgress {
+    name: String,
+    total: Option<u64>,
+    processed: u64,
+    processed_at_start: u64,
+    started: Instant,
+    last_report: Instant,
+}
+
+impl MigrationProgress {
+    /// `processed` is the number of items processed before the migration was resumed.
+    pub fn new(name: String, total: Option<u64>, processed: u64) -> Self {
+        let now = Instant::now();
+        MigrationProgress {
+            name,
+            total,
+            processed,
+            processed_at_start: processed,
+            started: now,
+            last_report: now,
+        }
+    }
+
+    pub fn processed(&self) -> u64 {
+        self.processed
+    }
+
+    pub fn inc(&mut self) {
+        self.processed += 1;
+        if self.last_report.elapsed() >= MIGRATION_PROGRESS_PERIOD {
+            self.last_report = Instant::now();
+            self.report();
+        }
+    }
+
+    fn report(&self) {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = (self.processed - self.processed_at_start) as f64 / elapsed.max(1e-3);
+        match self.total {
+            Some(total) if total > self.processed && rate > 0.0 => {
+                let eta = Duration::from_secs(((total - self.processed) as f64 / rate) as u64);
+                info!(target: "near", "{}: {}/{} ({:.1}%), {:.0} items/s, {:?} left", self.name, self.processed, total, self.processed as f64 * 100.0 / total as f64, rate, eta);
+            }
+            _ => {
+                info!(target: "near", "{}: {} items, {:.0} items/s", self.name, self.processed, rate);
+            }
+        }
+    }
+}
+
+impl Drop for MigrationProgress {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            info!(target: "near", "{}: done, {} items in {:?}", self.name, self.processed, self.started.elapsed());
+        }
+    }
+}
+
 fn get_outcomes_by_block_hash(store: &Store, block_hash: &CryptoHash) -> HashSet<CryptoHash> {
     match store.get_ser(DBCol::ColOutcomeIds, block_hash.as_ref()) {
         Ok(Some(hash_set)) => hash_set,
@@ -294,15 +454,39 @@ struct BatchedStoreUpdate<'a> {
     batch_size: usize,
     store: &'a Store,
     store_update: Option<StoreUpdate>,
+    /// Progress committed with every batch if the migration is resumable.
+    checkpoint: Option<MigrationCheckpoint>,
 }
 
 impl<'a> BatchedStoreUpdate<'a> {
     pub fn new(store: &'a Store, batch_size_limit: usize) -> Self {
-        Self { batch_size_limit, batch_size: 0, store, store_update: Some(store.store_update()) }
+        Self {
+            batch_size_limit,
+            batch_size: 0,
+            store,
+            store_update: Some(store.store_update()),
+            checkpoint: None,
+        }
+    }
+
+    /// Records `last_key` as processed by the step of the migration to `db_version`. Must be
+    /// called before the changes made for the key, as they may be committed right away.
+    pub fn checkpoint(
+        &mut self,
+        db_version: DbVersion,
+        step: u32,
+        last_key: &[u8],
+        processed: u64,
+    ) {
+        self.checkpoint =
+            Some(MigrationCheckpoint { db_version, step, last_key: last_key.to_vec(), processed });
     }
 
     fn commit(&mut self) -> Result<(), std::io::Error> {
-        let store_update = self.store_update.take().unwrap();
+        let mut store_update = self.store_update.take().unwrap();
+        if let Some(checkpoint) = &self.checkpoint {
+            set_migration_checkpoint(&mut store_update, checkpoint);
+        }
         store_update.commit()?;
         self.store_update = Some(self.store.store_update());
         self.batch_size = 0;
@@ -335,18 +519,43 @@ impl<'a> BatchedStoreUpdate<'a> {
     }
 }
 
-fn map_col<T, U, F>(store: &Store, col: DBCol, f: F) -> Result<(), std::io::Error>
+/// Rewrites all values of `col`. The rewrite is `step` of the migration to `db_version` and
+/// continues where it stopped if the migration was interrupted.
+fn map_col<T, U, F>(
+    store: &Store,
+    db_version: DbVersion,
+    step: u32,
+    col: DBCol,
+    f: F,
+) -> Result<(), std::io::Error>
 where
     T: BorshDeserialize,
     U: BorshSerialize,
     F: Fn(T) -> U,
 {
-    let keys: Vec<_> = store.iter(col).map(|(key, _)| key).collect();
+    let (last_key, processed) = match MigrationStepStart::new(store, db_version, step) {
+        MigrationStepStart::Done => return Ok(()),
+        MigrationStepStart::After { last_key, processed } => (last_key, processed),
+    };
+    let mut keys: Vec<_> = store
+        .iter(col)
+        .map(|(key, _)| key)
+        .filter(|key| last_key.as_ref().map_or(true, |last_key| key.as_ref() > last_key.as_slice()))
+        .collect();
+    // Keys are processed in order, so that the last processed key is where the step continues.
+    keys.sort();
+    let mut progress = MigrationProgress::new(
+        format!("{:?}", col),
+        Some(processed + keys.len() as u64),
+        processed,
+    );
     let mut store_update = BatchedStoreUpdate::new(store, 10_000_000);
 
     for key in keys {
         let value: T = store.get_ser(col, key.as_ref())?.unwrap();
         let new_value = f(value);
+        progress.inc();
+        store_update.checkpoint(db_version, step, key.as_ref(), progress.processed());
         store_update.set_ser(col, key.as_ref(), &new_value)?;
     }
 
@@ -355,31 +564,46 @@ where
     Ok(())
 }
 
+/// Rewrites all values of `col` computing them from their keys. Like `map_col`, the rewrite is
+/// `step` of the migration to `db_version` and the checkpoint is committed together with the
+/// rewritten values, so that an interrupted migration doesn't rewrite already migrated values.
 #[allow(unused)]
-fn map_col_from_key<U, F>(store: &Store, col: DBCol, f: F) -> Result<(), std::io::Error>
+fn map_col_from_key<U, F>(
+    store: &Store,
+    db_version: DbVersion,
+    step: u32,
+    col: DBCol,
+    f: F,
+) -> Result<(), std::io::Error>
 where
     U: BorshSerialize,
     F: Fn(&[u8]) -> U,
 {
-    let mut store_update = store.store_update();
-    let batch_size_limit = 10_000_000;
-    let mut batch_size = 0;
-    for (key, _) in store.iter(col) {
-        let new_value = f(&key);
-        let new_bytes = new_value.try_to_vec()?;
-        batch_size += key.as_ref().len() + new_bytes.len() + 8;
-        store_update.set(col, key.as_ref(), &new_bytes);
+    let (last_key, processed) = match MigrationStepStart::new(store, db_version, step) {
+        MigrationStepStart::Done => return Ok(()),
+        MigrationStepStart::After { last_key, processed } => (last_key, processed),
+    };
+    let mut keys: Vec<_> = store
+        .iter(col)
+        .map(|(key, _)| key)
+        .filter(|key| last_key.as_ref().map_or(true, |last_key| key.as_ref() > last_key.as_slice()))
+        .collect();
+    keys.sort();
+    let mut progress = MigrationProgress::new(
+        format!("{:?}", col),
+        Some(processed + keys.len() as u64),
+        processed,
+    );
+    let mut store_update = BatchedStoreUpdate::new(store, 10_000_000);
 
-        if batch_size > batch_size_limit {
-            store_update.commit()?;
-            store_update = store.store_update();
-            batch_size = 0;
-        }
+    for key in keys {
+        let new_value = f(key.as_ref());
+        progress.inc();
+        store_update.checkpoint(db_version, step, key.as_ref(), progress.processed());
+        store_update.set_ser(col, key.as_ref(), &new_value)?;
     }
 
-    if batch_size > 0 {
-        store_update.commit()?;
-    }
+    store_update.finish()?;
 
     Ok(())
 }
@@ -388,16 +612,16 @@ where
 pub fn migrate_13_to_14(path: &String) {
     let store = create_store(path);
 
-    map_col(&store, DBCol::ColPartialChunks, |pec: PartialEncodedChunkV1| {
+    map_col(&store, 14, 0, DBCol::ColPartialChunks, |pec: PartialEncodedChunkV1| {
         PartialEncodedChunk::V1(pec)
     })
     .unwrap();
-    map_col(&store, DBCol::ColInvalidChunks, |chunk: EncodedShardChunkV1| {
+    map_col(&store, 14, 1, DBCol::ColInvalidChunks, |chunk: EncodedShardChunkV1| {
         EncodedShardChunk::V1(chunk)
     })
     .unwrap();
-    map_col(&store, DBCol::ColChunks, |chunk: ShardChunkV1| ShardChunk::V1(chunk)).unwrap();
-    map_col(&store, DBCol::ColStateHeaders, |header: ShardStateSyncResponseHeaderV1| {
+    map_col(&store, 14, 2, DBCol::ColChunks, |chunk: ShardChunkV1| ShardChunk::V1(chunk)).unwrap();
+    map_col(&store, 14, 3, DBCol::ColStateHeaders, |header: ShardStateSyncResponseHeaderV1| {
         ShardStateSyncResponseHeader::V1(header)
     })
     .unwrap();
@@ -408,14 +632,34 @@ pub fn migrate_13_to_14(path: &String) {
 /// Make execution outcome ids in `ColOutcomeIds` ordered by replaying the chunks.
 pub fn migrate_14_to_15(path: &String) {
     let store = create_store(path);
-    let trie_store = Box::new(TrieCachingStorage::new(store.clone(), TrieCache::new(), 0));
+    let trie_store = Box::new(TrieCachingStorage::new(
+        store.clone(),
+        TrieCache::new(0, false, &TrieCacheConfig::default()),
+        0,
+    ));
     let trie = Rc::new(Trie::new(trie_store, 0));
 
+    let (last_key, processed) = match MigrationStepStart::new(&store, 15, 0) {
+        MigrationStepStart::Done => unreachable!("migration to version 15 has a single step"),
+        MigrationStepStart::After { last_key, processed } => (last_key, processed),
+    };
+    let mut progress = MigrationProgress::new(
+        "ColOutcomeIds".to_string(),
+        estimate_num_keys(&store, DBCol::ColOutcomeIds),
+        processed,
+    );
     let mut store_update = store.store_update();
     let batch_size_limit = 10_000_000;
     let mut batch_size = 0;
 
     for (key, value) in store.iter_without_rc_logic(DBCol::ColOutcomeIds) {
+        // Keys of the new format, written before the migration was interrupted, are longer.
+        if key.len() != CryptoHash::default().as_ref().len()
+            || last_key.as_ref().map_or(false, |last_key| key.as_ref() <= last_key.as_slice())
+        {
+            continue;
+        }
+        progress.inc();
         let block_hash = CryptoHash::try_from_slice(&key).unwrap();
         let block =
             store.get_ser::<Block>(DBCol::ColBlock, &key).unwrap().expect("block should exist");
@@ -549,11 +793,22 @@ pub fn migrate_14_to_15(path: &String) {
             );
             store_update.delete(DBCol::ColOutcomeIds, &key);
             batch_size += key.len() + value.len() + 40;
-            if batch_size > batch_size_limit {
-                store_update.commit().unwrap();
-                store_update = store.store_update();
-                batch_size = 0;
-            }
+        }
+        // Batches end between blocks, so that the checkpoint never points into a partially
+        // migrated block.
+        if batch_size > batch_size_limit {
+            set_migration_checkpoint(
+                &mut store_update,
+                &MigrationCheckpoint {
+                    db_version: 15,
+                    step: 0,
+                    last_key: key.to_vec(),
+                    processed: progress.processed(),
+                },
+            );
+            store_update.commit().unwrap();
+            store_update = store.store_update();
+            batch_size = 0;
         }
     }
     store_update.commit().unwrap();
@@ -599,7 +854,7 @@ pub fn migrate_17_to_18(path: &String) {
         pub total_supply: Balance,
     }
     let store = create_store(path);
-    map_col_from_key(&store, DBCol::ColBlockInfo, |key| {
+    map_col_from_key(&store, 18, 0, DBCol::ColBlockInfo, |key| {
         let hash = CryptoHash::try_from(key).unwrap();
         let old_block_info =
             store.get_ser::<OldBlockInfo>(DBCol::ColBlockInfo, key).unwrap().unwrap();
@@ -663,7 +918,7 @@ pub fn migrate_21_to_22(path: &String) {
         pub total_supply: Balance,
     }
     let store = create_store(path);
-    map_col_from_key(&store, DBCol::ColBlockInfo, |key| {
+    map_col_from_key(&store, 22, 0, DBCol::ColBlockInfo, |key| {
         let old_block_info =
             store.get_ser::<OldBlockInfo>(DBCol::ColBlockInfo, key).unwrap().unwrap();
         if key == &[0; 32] {
@@ -747,19 +1002,31 @@ pub fn migrate_18_to_new_validator_stake(store: &Store) {
         pub last_block_hash: CryptoHash,
     }
 
-    map_col(&store, DBCol::ColChunkExtra, |extra: ChunkExtraV1| ChunkExtra::V1(extra)).unwrap();
-
-    map_col(&store, DBCol::ColBlockInfo, |info: BlockInfoV1| BlockInfo::V1(info)).unwrap();
+    map_col(&store, NIGHTLY_DB_VERSION, 0, DBCol::ColChunkExtra, |extra: ChunkExtraV1| {
+        ChunkExtra::V1(extra)
+    })
+    .unwrap();
 
-    map_col(&store, DBCol::ColEpochValidatorInfo, |info: OldEpochSummary| EpochSummary {
-        prev_epoch_last_block_hash: info.prev_epoch_last_block_hash,
-        all_proposals: info.all_proposals.into_iter().map(ValidatorStake::V1).collect(),
-        validator_kickout: info.validator_kickout,
-        validator_block_chunk_stats: info.validator_block_chunk_stats,
-        next_version: info.next_version,
+    map_col(&store, NIGHTLY_DB_VERSION, 1, DBCol::ColBlockInfo, |info: BlockInfoV1| {
+        BlockInfo::V1(info)
     })
     .unwrap();
 
+    map_col(
+        &store,
+        NIGHTLY_DB_VERSION,
+        2,
+        DBCol::ColEpochValidatorInfo,
+        |info: OldEpochSummary| EpochSummary {
+            prev_epoch_last_block_hash: info.prev_epoch_last_block_hash,
+            all_proposals: info.all_proposals.into_iter().map(ValidatorStake::V1).collect(),
+            validator_kickout: info.validator_kickout,
+            validator_block_chunk_stats: info.validator_block_chunk_stats,
+            next_version: info.next_version,
+        },
+    )
+    .unwrap();
+
     // DBCol::ColEpochInfo has a special key which contains a different type than all other
     // values (EpochInfoAggregator), so we cannot use `map_col` on it. We need to handle
     // the AGGREGATOR_KEY differently from all others.
@@ -790,3 +1057,76 @@ pub fn migrate_18_to_new_validator_stake(store: &Store) {
     }
     store_update.finish().unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::db::DBCol;
+    use crate::migrations::{
+        map_col, read_migration_checkpoint, set_migration_checkpoint, set_store_version,
+        MigrationCheckpoint,
+    };
+    use crate::test_utils::create_test_store;
+
+    #[test]
+    fn test_map_col_resume() {
+        let store = create_test_store();
+        let mut store_update = store.store_update();
+        for i in 0u8..10 {
+            store_update.set_ser(DBCol::ColBlockInfo, &[i], &(i as u64)).unwrap();
+        }
+        // The migration was interrupted after rewriting keys up to 4 in its second step.
+        set_migration_checkpoint(
+            &mut store_update,
+            &MigrationCheckpoint { db_version: 100, step: 1, last_key: vec![4], processed: 5 },
+        );
+        store_update.commit().unwrap();
+
+        map_col(&store, 100, 0, DBCol::ColBlockInfo, |value: u64| value + 100).unwrap();
+        map_col(&store, 100, 1, DBCol::ColBlockInfo, |value: u64| value * 10).unwrap();
+        for i in 0u8..10 {
+            let value: u64 = store.get_ser(DBCol::ColBlockInfo, &[i]).unwrap().unwrap();
+            assert_eq!(value, if i <= 4 { i as u64 } else { i as u64 * 10 });
+        }
+        assert_eq!(
+            read_migration_checkpoint(&store),
+            Some(MigrationCheckpoint {
+                db_version: 100,
+                step: 1,
+                last_key: vec![9],
+                processed: 10
+            })
+        );
+
+        set_store_version(&store, 100);
+        assert_eq!(read_migration_checkpoint(&store), None);
+    }
+
+    #[test]
+    fn test_map_col_from_key_resume() {
+        let store = create_test_store();
+        let mut store_update = store.store_update();
+        for i in 0u8..10 {
+            store_update.set_ser(DBCol::ColBlockInfo, &[i], &(i as u64)).unwrap();
+        }
+        // Keys up to 4 were already rewritten, rewriting them again would read migrated values.
+        set_migration_checkpoint(
+            &mut store_update,
+            &MigrationCheckpoint { db_version: 100, step: 0, last_key: vec![4], processed: 5 },
+        );
+        store_update.commit().unwrap();
+
+        map_col_from_key(&store, 100, 0, DBCol::ColBlockInfo, |key| {
+            let value: u64 = store.get_ser(DBCol::ColBlockInfo, key).unwrap().unwrap();
+            value + 100
+        })
+        .unwrap();
+        for i in 0u8..10 {
+            let value: u64 = store.get_ser(DBCol::ColBlockInfo, &[i]).unwrap().unwrap();
+            assert_eq!(value, if i <= 4 { i as u64 } else { i as u64 + 100 });
+        }
+        assert_eq!(
+            read_migration_checkpoint(&store).map(|checkpoint| checkpoint.last_key),
+            Some(vec![9])
+        );
+    }
+}
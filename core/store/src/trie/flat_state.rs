@@ -0,0 +1,639 @@
+//! Flat state keeps a `TrieKey -> value` mapping per shard next to the trie, so that a state read
+//! doesn't have to traverse the trie from the state root. Small values are stored inline and are
+//! read with a single lookup, large ones (e.g. contract code) are stored as references to the
+//! values in `ColState`. Prefix reads of `ViewState` queries iterate over the mapping directly.
+//!
+//! The mapping in `ColFlatState` corresponds to a single block per shard, called the flat state
+//! head, which follows the final block. Every block after it writes the changes of its chunk as a
+//! delta into `ColFlatStateDeltas`, keyed by the block hash and pointing to the previous block.
+//! Deltas are keyed by blocks rather than by state roots because different blocks can have the
+//! same state root, e.g. on forks. A read at some block walks the deltas back to the head and
+//! falls back to the trie if the head isn't reachable, so flat state is only an optimisation and
+//! never changes the result of a read.
+//!
+//! Flat state doesn't touch trie nodes, so it is only used for view calls. Chunk application
+//! keeps using the trie because the number of touched nodes is charged as gas. Queries which ask
+//! for a proof use the trie as well.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use tracing::{info, warn};
+
+use near_primitives::hash::{hash, CryptoHash};
+use near_primitives::types::{RawStateChangesWithTrieKey, ShardId, StateRoot};
+use near_primitives::utils::get_block_shard_id;
+
+use crate::{DBCol, StorageError, Store, StoreUpdate, Trie};
+
+/// Number of deltas which can be between the flat state head and the requested block.
+/// Reads further away from the head fall back to the trie.
+const MAX_FLAT_STATE_DELTAS: usize = 100;
+
+/// Number of values written in a single transaction while flat state is being created.
+const FLAT_STATE_CREATION_BATCH_SIZE: usize = 100_000;
+
+/// Values up to this length are stored in flat state itself, longer ones only by reference.
+const MAX_INLINED_VALUE_LENGTH: usize = 4 * 1024;
+
+/// Reference to a value stored in the trie, the same thing a trie leaf stores.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ValueRef {
+    pub length: u32,
+    pub hash: CryptoHash,
+}
+
+impl ValueRef {
+    pub fn new(value: &[u8]) -> Self {
+        ValueRef { length: value.len() as u32, hash: hash(value) }
+    }
+}
+
+/// Value as stored in flat state.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub enum FlatStateValue {
+    /// Reference to a value which has to be read from the trie storage.
+    Ref(ValueRef),
+    Inlined(Vec<u8>),
+}
+
+impl FlatStateValue {
+    pub fn new(value: &[u8]) -> Self {
+        if value.len() <= MAX_INLINED_VALUE_LENGTH {
+            FlatStateValue::Inlined(value.to_vec())
+        } else {
+            FlatStateValue::Ref(ValueRef::new(value))
+        }
+    }
+
+    pub fn to_value_ref(&self) -> ValueRef {
+        match self {
+            FlatStateValue::Ref(value_ref) => value_ref.clone(),
+            FlatStateValue::Inlined(value) => ValueRef::new(value),
+        }
+    }
+}
+
+/// Block the flat state of a shard corresponds to.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FlatStateHead {
+    pub block_hash: CryptoHash,
+    pub state_root: StateRoot,
+}
+
+/// Changes of the flat state made by applying the chunk of a block.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FlatStateDelta {
+    pub prev_block_hash: CryptoHash,
+    /// State root after the changes.
+    pub state_root: StateRoot,
+    /// Sorted by key, `None` means that the key was deleted.
+    pub changes: Vec<(Vec<u8>, Option<FlatStateValue>)>,
+}
+
+impl FlatStateDelta {
+    pub fn from_state_changes(
+        prev_block_hash: CryptoHash,
+        state_root: StateRoot,
+        state_changes: &[RawStateChangesWithTrieKey],
+    ) -> Self {
+        let mut changes: Vec<_> = state_changes
+            .iter()
+            .map(|changes_with_trie_key| {
+                let data = &changes_with_trie_key
+                    .changes
+                    .last()
+                    .expect("Committed entry should have at least one change")
+                    .data;
+                (changes_with_trie_key.trie_key.to_vec(), data.as_deref().map(FlatStateValue::new))
+            })
+            .collect();
+        changes.sort_by(|a, b| a.0.cmp(&b.0));
+        FlatStateDelta { prev_block_hash, state_root, changes }
+    }
+
+    fn get(&self, key: &[u8]) -> Option<&Option<FlatStateValue>> {
+        self.changes
+            .binary_search_by(|(change_key, _)| change_key.as_slice().cmp(key))
+            .ok()
+            .map(|index| &self.changes[index].1)
+    }
+}
+
+pub fn get_flat_state_delta_key(shard_id: ShardId, block_hash: &CryptoHash) -> Vec<u8> {
+    get_block_shard_id(block_hash, shard_id)
+}
+
+fn get_flat_state_key(shard_id: ShardId, key: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(8 + key.len());
+    result.extend_from_slice(&shard_id.to_le_bytes());
+    result.extend_from_slice(key);
+    result
+}
+
+pub fn get_flat_state_head(
+    store: &Store,
+    shard_id: ShardId,
+) -> Result<Option<FlatStateHead>, StorageError> {
+    store
+        .get_ser(DBCol::ColFlatStateHeads, &shard_id.to_le_bytes())
+        .map_err(|_| StorageError::StorageInternalError)
+}
+
+fn get_delta(
+    store: &Store,
+    shard_id: ShardId,
+    block_hash: &CryptoHash,
+) -> Result<Option<FlatStateDelta>, StorageError> {
+    store
+        .get_ser(DBCol::ColFlatStateDeltas, &get_flat_state_delta_key(shard_id, block_hash))
+        .map_err(|_| StorageError::StorageInternalError)
+}
+
+/// Returns deltas leading from `head` to `block_hash`, the newest first, or `None` if
+/// `block_hash` can't be reached from `head` within `max_deltas` deltas.
+fn get_deltas(
+    store: &Store,
+    shard_id: ShardId,
+    block_hash: &CryptoHash,
+    head: &CryptoHash,
+    max_deltas: Option<usize>,
+) -> Result<Option<Vec<FlatStateDelta>>, StorageError> {
+    let mut deltas = vec![];
+    let mut current = *block_hash;
+    while current != *head {
+        if max_deltas.map_or(false, |max_deltas| deltas.len() == max_deltas) {
+            return Ok(None);
+        }
+        match get_delta(store, shard_id, &current)? {
+            Some(delta) => {
+                current = delta.prev_block_hash;
+                deltas.push(delta);
+            }
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(deltas))
+}
+
+/// Deltas resolved for the last requested state root.
+struct ResolvedDeltas {
+    root: StateRoot,
+    head: FlatStateHead,
+    deltas: Option<Vec<FlatStateDelta>>,
+}
+
+/// Read access to the flat state of a shard at a block, attached to view tries.
+pub struct FlatState {
+    store: Arc<Store>,
+    shard_id: ShardId,
+    block_hash: CryptoHash,
+    resolved: RefCell<Option<ResolvedDeltas>>,
+}
+
+impl FlatState {
+    pub(crate) fn new(store: Arc<Store>, shard_id: ShardId, block_hash: CryptoHash) -> Self {
+        FlatState { store, shard_id, block_hash, resolved: RefCell::new(None) }
+    }
+
+    /// Returns deltas from the head to the block if the state root at the block is `root`.
+    fn resolve(&self, root: &StateRoot) -> Result<Option<ResolvedDeltas>, StorageError> {
+        let head = match get_flat_state_head(&self.store, self.shard_id)? {
+            Some(head) => head,
+            None => return Ok(None),
+        };
+        let deltas = get_deltas(
+            &self.store,
+            self.shard_id,
+            &self.block_hash,
+            &head.block_hash,
+            Some(MAX_FLAT_STATE_DELTAS),
+        )?
+        .filter(|deltas| deltas.first().map_or(head.state_root, |delta| delta.state_root) == *root);
+        Ok(Some(ResolvedDeltas { root: *root, head, deltas }))
+    }
+
+    /// Returns the head and the deltas from it to the block if flat state can be used to read
+    /// the state at `root`.
+    fn head_and_deltas<'a>(
+        &self,
+        resolved: &'a mut Option<ResolvedDeltas>,
+        root: &StateRoot,
+    ) -> Result<Option<(FlatStateHead, &'a [FlatStateDelta])>, StorageError> {
+        if resolved.as_ref().map_or(true, |resolved| resolved.root != *root) {
+            *resolved = self.resolve(root)?;
+        }
+        match resolved {
+            Some(ResolvedDeltas { head, deltas: Some(deltas), .. }) => Ok(Some((*head, deltas))),
+            _ => Ok(None),
+        }
+    }
+
+    /// The head and the values are updated in the same transaction, so if the head is still the
+    /// same after reading values, they belong to it. Otherwise the deltas have to be resolved
+    /// again.
+    fn is_head_unchanged(
+        &self,
+        resolved: &mut Option<ResolvedDeltas>,
+        head: FlatStateHead,
+    ) -> Result<bool, StorageError> {
+        if get_flat_state_head(&self.store, self.shard_id)? != Some(head) {
+            *resolved = None;
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Returns the value of `key` at `root`. The outer `None` means that flat state can't answer
+    /// and the trie has to be used.
+    pub(crate) fn get(
+        &self,
+        root: &StateRoot,
+        key: &[u8],
+    ) -> Result<Option<Option<FlatStateValue>>, StorageError> {
+        let mut resolved = self.resolved.borrow_mut();
+        let head = match self.head_and_deltas(&mut resolved, root)? {
+            Some((head, deltas)) => {
+                for delta in deltas {
+                    if let Some(value) = delta.get(key) {
+                        return Ok(Some(value.clone()));
+                    }
+                }
+                head
+            }
+            None => return Ok(None),
+        };
+        let value = self
+            .store
+            .get_ser(DBCol::ColFlatState, &get_flat_state_key(self.shard_id, key))
+            .map_err(|_| StorageError::StorageInternalError)?;
+        if !self.is_head_unchanged(&mut resolved, head)? {
+            return Ok(None);
+        }
+        Ok(Some(value))
+    }
+
+    /// Returns all keys starting with `prefix` and their values at `root`, sorted by key, or
+    /// `None` if flat state can't answer.
+    pub(crate) fn get_prefix(
+        &self,
+        root: &StateRoot,
+        prefix: &[u8],
+    ) -> Result<Option<Vec<(Vec<u8>, FlatStateValue)>>, StorageError> {
+        let mut resolved = self.resolved.borrow_mut();
+        let (head, deltas) = match self.head_and_deltas(&mut resolved, root)? {
+            Some(head_and_deltas) => head_and_deltas,
+            None => return Ok(None),
+        };
+        let mut items = BTreeMap::new();
+        let key_prefix = get_flat_state_key(self.shard_id, prefix);
+        for item in self.store.iter_prefix_ser::<FlatStateValue>(DBCol::ColFlatState, &key_prefix) {
+            let (key, value) = item.map_err(|_| StorageError::StorageInternalError)?;
+            items.insert(key[8..].to_vec(), value);
+        }
+        for delta in deltas.iter().rev() {
+            for (key, value) in delta.changes.iter().filter(|(key, _)| key.starts_with(prefix)) {
+                match value {
+                    Some(value) => items.insert(key.clone(), value.clone()),
+                    None => items.remove(key),
+                };
+            }
+        }
+        if !self.is_head_unchanged(&mut resolved, head)? {
+            return Ok(None);
+        }
+        Ok(Some(items.into_iter().collect()))
+    }
+}
+
+/// Result of moving the flat state head of a shard.
+pub(crate) enum FlatStateHeadUpdate {
+    /// Changes of the flat state to commit.
+    Update(StoreUpdate),
+    /// The shard doesn't have flat state yet and it can be created at the block.
+    Create(FlatStateHead),
+}
+
+/// Moves the flat state head of the shard to the block `new_head` by applying deltas, usually
+/// called when the final block changes.
+///
+/// If the shard doesn't have flat state yet, it can be created once the delta for `new_head`
+/// exists, which means all blocks built on top of it were applied with flat state enabled and
+/// have deltas too.
+///
+/// If `new_head` can't be reached from the current head, flat state of the shard is removed and
+/// created again at one of the next final blocks.
+pub(crate) fn update_flat_state_head(
+    store: &Store,
+    shard_id: ShardId,
+    new_head: &CryptoHash,
+) -> Result<FlatStateHeadUpdate, StorageError> {
+    let mut store_update = store.store_update();
+    match get_flat_state_head(store, shard_id)? {
+        Some(head) if head.block_hash == *new_head => {}
+        Some(head) => match get_deltas(store, shard_id, new_head, &head.block_hash, None)? {
+            Some(deltas) => {
+                for delta in deltas.iter().rev() {
+                    for (key, value) in delta.changes.iter() {
+                        let key = get_flat_state_key(shard_id, key);
+                        match value {
+                            Some(value) => store_update
+                                .set_ser(DBCol::ColFlatState, &key, value)
+                                .map_err(|_| StorageError::StorageInternalError)?,
+                            None => store_update.delete(DBCol::ColFlatState, &key),
+                        }
+                    }
+                }
+                // Applied deltas are keyed by `new_head` and by the blocks between the heads.
+                let mut block_hash = *new_head;
+                for delta in deltas.iter() {
+                    store_update.delete(
+                        DBCol::ColFlatStateDeltas,
+                        &get_flat_state_delta_key(shard_id, &block_hash),
+                    );
+                    block_hash = delta.prev_block_hash;
+                }
+                let state_root = deltas[0].state_root;
+                set_flat_state_head(
+                    &mut store_update,
+                    shard_id,
+                    &FlatStateHead { block_hash: *new_head, state_root },
+                )?;
+            }
+            None => {
+                warn!(target: "store", "Flat state head {} of shard {} is not reachable from {}, removing flat state", head.block_hash, shard_id, new_head);
+                store_update.delete(DBCol::ColFlatStateHeads, &shard_id.to_le_bytes());
+            }
+        },
+        None => {
+            if let Some(delta) = get_delta(store, shard_id, new_head)? {
+                return Ok(FlatStateHeadUpdate::Create(FlatStateHead {
+                    block_hash: *new_head,
+                    state_root: delta.state_root,
+                }));
+            }
+        }
+    }
+    Ok(FlatStateHeadUpdate::Update(store_update))
+}
+
+fn set_flat_state_head(
+    store_update: &mut StoreUpdate,
+    shard_id: ShardId,
+    head: &FlatStateHead,
+) -> Result<(), StorageError> {
+    store_update
+        .set_ser(DBCol::ColFlatStateHeads, &shard_id.to_le_bytes(), head)
+        .map_err(|_| StorageError::StorageInternalError)
+}
+
+/// Creates flat state of the shard by iterating over the trie at `head`. The values are written
+/// in batches and the head is committed together with the last batch, so reads don't use flat
+/// state until it is complete. Takes long for big shards, so it is run in the background, see
+/// `ShardTries::update_flat_state_head`.
+pub(crate) fn create_flat_state(
+    store: &Store,
+    trie: &Trie,
+    shard_id: ShardId,
+    head: &FlatStateHead,
+) -> Result<(), StorageError> {
+    info!(target: "store", "Creating flat state of shard {} at block {}", shard_id, head.block_hash);
+    let commit = |store_update: StoreUpdate| {
+        store_update.commit().map_err(|_| StorageError::StorageInternalError)
+    };
+    // Values left from a removed flat state.
+    let prefix = shard_id.to_le_bytes();
+    let mut store_update = store.store_update();
+    for (key, _) in store.iter_prefix(DBCol::ColFlatState, &prefix) {
+        store_update.delete(DBCol::ColFlatState, &key);
+    }
+    commit(store_update)?;
+
+    let mut store_update = store.store_update();
+    let mut batch_size = 0;
+    let mut num_values = 0;
+    for item in trie.iter(&head.state_root)? {
+        let (key, value) = item?;
+        store_update
+            .set_ser(
+                DBCol::ColFlatState,
+                &get_flat_state_key(shard_id, &key),
+                &FlatStateValue::new(&value),
+            )
+            .map_err(|_| StorageError::StorageInternalError)?;
+        batch_size += 1;
+        num_values += 1;
+        if batch_size == FLAT_STATE_CREATION_BATCH_SIZE {
+            commit(std::mem::replace(&mut store_update, store.store_update()))?;
+            batch_size = 0;
+        }
+    }
+    set_flat_state_head(&mut store_update, shard_id, head)?;
+    commit(store_update)?;
+    info!(target: "store", "Created flat state of shard {} with {} values", shard_id, num_values);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{create_test_store, create_tries, gen_changes, simplify_changes};
+    use crate::ShardTries;
+    use near_primitives::trie_key::{trie_key_parsers, TrieKey};
+    use near_primitives::types::StateChangeCause;
+    use rand::Rng;
+
+    /// Applies `changes` of `block` on top of the state of `prev_block` at `root` the way chain
+    /// does it and returns the new root.
+    fn apply_block(
+        tries: &ShardTries,
+        prev_block: &CryptoHash,
+        block: &CryptoHash,
+        root: &StateRoot,
+        changes: &[(Vec<u8>, Option<Vec<u8>>)],
+    ) -> StateRoot {
+        let mut trie_update = tries.new_trie_update(0, *root);
+        for (key, value) in changes.iter() {
+            let trie_key =
+                TrieKey::ContractData { account_id: "alice".to_string(), key: key.clone() };
+            match value {
+                Some(value) => trie_update.set(trie_key, value.clone()),
+                None => trie_update.remove(trie_key),
+            }
+        }
+        trie_update.commit(StateChangeCause::InitialState);
+        let (trie_changes, state_changes) = trie_update.finalize().unwrap();
+        let (mut store_update, new_root) = tries.apply_all(&trie_changes, 0).unwrap();
+        store_update
+            .set_ser(
+                DBCol::ColFlatStateDeltas,
+                &get_flat_state_delta_key(0, block),
+                &FlatStateDelta::from_state_changes(*prev_block, new_root, &state_changes),
+            )
+            .unwrap();
+        store_update.commit().unwrap();
+        new_root
+    }
+
+    /// Applies blocks with random changes and returns their hashes, state roots and the changed
+    /// keys. The first block is the genesis with the empty state.
+    fn apply_blocks(
+        tries: &ShardTries,
+        num_blocks: u8,
+    ) -> (Vec<CryptoHash>, Vec<StateRoot>, Vec<Vec<u8>>) {
+        let mut rng = rand::thread_rng();
+        let mut blocks = vec![hash(&[0])];
+        let mut roots = vec![Trie::empty_root()];
+        let mut keys = vec![];
+        for i in 1..=num_blocks {
+            // Some blocks don't change the state, so their state root is the same as the
+            // previous one.
+            let mut changes = if rng.gen_bool(0.2) {
+                vec![]
+            } else {
+                simplify_changes(&gen_changes(&mut rng, 20))
+            };
+            // Some values are too long to be inlined.
+            for (_, value) in changes.iter_mut() {
+                if let Some(value) = value.as_mut().filter(|_| rng.gen_bool(0.1)) {
+                    *value = value.repeat(MAX_INLINED_VALUE_LENGTH);
+                }
+            }
+            keys.extend(changes.iter().map(|(key, _)| key.clone()));
+            let block = hash(&[i]);
+            let root =
+                apply_block(tries, blocks.last().unwrap(), &block, roots.last().unwrap(), &changes);
+            blocks.push(block);
+            roots.push(root);
+        }
+        (blocks, roots, keys)
+    }
+
+    fn check_reads(tries: &ShardTries, block: &CryptoHash, root: &StateRoot, keys: &[Vec<u8>]) {
+        let trie = tries.get_trie_for_shard(0);
+        let flat_state = FlatState::new(tries.get_store(), 0, *block);
+        let mut flat_state_trie = tries.get_view_trie_for_shard(0);
+        flat_state_trie.flat_state = Some(FlatState::new(tries.get_store(), 0, *block));
+        for key in keys {
+            let key = TrieKey::ContractData { account_id: "alice".to_string(), key: key.clone() }
+                .to_vec();
+            let expected = trie.get_ref(root, &key).unwrap();
+            let actual = flat_state.get(root, &key).unwrap().expect("flat state is reachable");
+            assert_eq!(
+                actual.map(|value| {
+                    let value_ref = value.to_value_ref();
+                    (value_ref.length, value_ref.hash)
+                }),
+                expected
+            );
+            assert_eq!(flat_state_trie.get(root, &key).unwrap(), trie.get(root, &key).unwrap());
+        }
+        for prefix in [&b""[..], b"a", b"ab", b"c"].iter() {
+            let prefix =
+                trie_key_parsers::get_raw_prefix_for_contract_data(&"alice".to_string(), prefix);
+            let expected: Vec<_> = trie
+                .iter(root)
+                .unwrap()
+                .map(Result::unwrap)
+                .filter(|(key, _)| key.starts_with(&prefix))
+                .collect();
+            let actual = flat_state_trie.get_prefix_from_flat_state(root, &prefix).unwrap();
+            assert_eq!(actual, Some(expected));
+        }
+    }
+
+    fn update_head(store: &Store, head: &CryptoHash) {
+        match update_flat_state_head(store, 0, head).unwrap() {
+            FlatStateHeadUpdate::Update(store_update) => store_update.commit().unwrap(),
+            FlatStateHeadUpdate::Create(_) => panic!("flat state should exist"),
+        }
+    }
+
+    #[test]
+    fn test_flat_state_follows_trie() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..10 {
+            let tries = create_tries();
+            let store = tries.get_store();
+            let (blocks, roots, keys) = apply_blocks(&tries, 5);
+
+            // No flat state yet.
+            let flat_state = FlatState::new(store.clone(), 0, *blocks.last().unwrap());
+            assert_eq!(flat_state.get(roots.last().unwrap(), b"key").unwrap(), None);
+
+            let head = rng.gen_range(1, blocks.len());
+            let trie = tries.get_trie_for_shard(0);
+            match update_flat_state_head(&store, 0, &blocks[head]).unwrap() {
+                FlatStateHeadUpdate::Create(flat_state_head) => {
+                    assert_eq!(
+                        flat_state_head,
+                        FlatStateHead { block_hash: blocks[head], state_root: roots[head] }
+                    );
+                    create_flat_state(&store, &trie, 0, &flat_state_head).unwrap();
+                }
+                FlatStateHeadUpdate::Update(_) => panic!("flat state should be created"),
+            }
+            assert_eq!(get_flat_state_head(&store, 0).unwrap().unwrap().block_hash, blocks[head]);
+            for (block, root) in blocks[head..].iter().zip(roots[head..].iter()) {
+                check_reads(&tries, block, root, &keys);
+            }
+            // The state root has to match the block.
+            if roots[head] != Trie::empty_root() {
+                let flat_state = FlatState::new(store.clone(), 0, blocks[head]);
+                assert_eq!(flat_state.get(&Trie::empty_root(), b"key").unwrap(), None);
+            }
+
+            update_head(&store, blocks.last().unwrap());
+            assert_eq!(
+                get_flat_state_head(&store, 0).unwrap(),
+                Some(FlatStateHead {
+                    block_hash: *blocks.last().unwrap(),
+                    state_root: *roots.last().unwrap()
+                })
+            );
+            check_reads(&tries, blocks.last().unwrap(), roots.last().unwrap(), &keys);
+            // Blocks before the head can't be served from flat state anymore.
+            if head != blocks.len() - 1 {
+                let flat_state = FlatState::new(store.clone(), 0, blocks[head]);
+                assert_eq!(flat_state.get(&roots[head], b"key").unwrap(), None);
+            }
+        }
+    }
+
+    #[test]
+    fn test_flat_state_created_in_background() {
+        let tries = create_tries().with_flat_state(true);
+        let store = tries.get_store();
+        let (blocks, roots, keys) = apply_blocks(&tries, 5);
+
+        // Nothing to commit, flat state is created on another thread.
+        tries.update_flat_state_head(0, &blocks[2]).unwrap().commit().unwrap();
+        let started = std::time::Instant::now();
+        while tries.is_flat_state_being_created(0) {
+            assert!(started.elapsed() < std::time::Duration::from_secs(10));
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(
+            get_flat_state_head(&store, 0).unwrap(),
+            Some(FlatStateHead { block_hash: blocks[2], state_root: roots[2] })
+        );
+
+        tries.update_flat_state_head(0, &blocks[5]).unwrap().commit().unwrap();
+        assert_eq!(get_flat_state_head(&store, 0).unwrap().unwrap().block_hash, blocks[5]);
+        check_reads(&tries, &blocks[5], &roots[5], &keys);
+    }
+
+    #[test]
+    fn test_flat_state_removed_if_unreachable() {
+        let store = create_test_store();
+        let mut store_update = store.store_update();
+        let head = FlatStateHead { block_hash: hash(b"head"), state_root: Trie::empty_root() };
+        set_flat_state_head(&mut store_update, 0, &head).unwrap();
+        store_update.commit().unwrap();
+
+        update_head(&store, &hash(b"other"));
+        assert_eq!(get_flat_state_head(&store, 0).unwrap(), None);
+    }
+}
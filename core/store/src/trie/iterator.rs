@@ -280,10 +280,11 @@ enum IterStep {
     Value(CryptoHash),
 }
 
-impl<'a> Iterator for TrieIterator<'a> {
-    type Item = TrieItem;
-
-    fn next(&mut self) -> Option<Self::Item> {
+impl<'a> TrieIterator<'a> {
+    /// Moves to the next item and returns its key and value hash without reading the value.
+    pub(crate) fn next_value_hash(
+        &mut self,
+    ) -> Option<Result<(Vec<u8>, CryptoHash), StorageError>> {
         loop {
             let iter_step = self.iter_step()?;
             match iter_step {
@@ -295,16 +296,23 @@ impl<'a> Iterator for TrieIterator<'a> {
                     Err(e) => return Some(Err(e)),
                 },
                 IterStep::Continue => {}
-                IterStep::Value(hash) => {
-                    return Some(
-                        self.trie.retrieve_raw_bytes(&hash).map(|value| (self.key(), value)),
-                    )
-                }
+                IterStep::Value(hash) => return Some(Ok((self.key(), hash))),
             }
         }
     }
 }
 
+impl<'a> Iterator for TrieIterator<'a> {
+    type Item = TrieItem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_value_hash().map(|item| {
+            let (key, hash) = item?;
+            self.trie.retrieve_raw_bytes(&hash).map(|value| (key, value))
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeMap;
@@ -14,6 +14,7 @@ use near_primitives::contract::ContractCode;
 use near_primitives::hash::{hash, CryptoHash};
 use near_primitives::types::{ShardId, StateRoot, StateRootNode};
 
+use crate::trie::flat_state::{FlatState, FlatStateValue};
 use crate::trie::insert_delete::NodesStorage;
 use crate::trie::iterator::TrieIterator;
 use crate::trie::nibble_slice::NibbleSlice;
@@ -24,9 +25,11 @@ use crate::trie::trie_storage::{
 pub(crate) use crate::trie::trie_storage::{TrieCache, TrieCachingStorage};
 use crate::StorageError;
 
+pub mod flat_state;
 mod insert_delete;
 pub mod iterator;
 mod nibble_slice;
+pub mod range_proof;
 mod shard_tries;
 mod state_parts;
 mod trie_storage;
@@ -346,8 +349,13 @@ impl RawTrieNode {
         let mut cursor = Cursor::new(bytes);
         match cursor.read_u8()? {
             LEAF_NODE => {
-                let key_length = cursor.read_u32::<LittleEndian>()?;
-                let mut key = vec![0; key_length as usize];
+                let key_length = cursor.read_u32::<LittleEndian>()? as usize;
+                // Values are decoded as nodes when their type is not known, so the length must
+                // not allocate more than the bytes can hold.
+                if key_length > bytes.len() {
+                    return Err(std::io::Error::new(std::io::ErrorKind::Other, "Wrong key length"));
+                }
+                let mut key = vec![0; key_length];
                 cursor.read_exact(&mut key)?;
                 let value_length = cursor.read_u32::<LittleEndian>()?;
                 let mut arr = [0; 32];
@@ -368,8 +376,13 @@ impl RawTrieNode {
                 Ok(RawTrieNode::Branch(children, Some((value_length, value_hash))))
             }
             EXTENSION_NODE => {
-                let key_length = cursor.read_u32::<LittleEndian>()?;
-                let mut key = vec![0; key_length as usize];
+                let key_length = cursor.read_u32::<LittleEndian>()? as usize;
+                // Values are decoded as nodes when their type is not known, so the length must
+                // not allocate more than the bytes can hold.
+                if key_length > bytes.len() {
+                    return Err(std::io::Error::new(std::io::ErrorKind::Other, "Wrong key length"));
+                }
+                let mut key = vec![0; key_length];
                 cursor.read_exact(&mut key)?;
                 let mut child = vec![0; 32];
                 cursor.read_exact(&mut child)?;
@@ -405,8 +418,51 @@ impl RawTrieNodeWithSize {
     }
 }
 
+fn get_raw_node_children(node: &RawTrieNode) -> Vec<CryptoHash> {
+    match node {
+        RawTrieNode::Leaf(_, _, value_hash) => vec![*value_hash],
+        RawTrieNode::Branch(children, value) => children
+            .iter()
+            .flatten()
+            .cloned()
+            .chain(value.map(|(_, value_hash)| value_hash))
+            .collect(),
+        RawTrieNode::Extension(_, child) => vec![*child],
+    }
+}
+
+/// Returns depths of the given nodes which are less than `max_depth` levels below `roots`. Only
+/// the nodes on the way down from the roots are decoded. Children which are not among the given
+/// nodes, e.g. subtries not changed by a commit, are not followed.
+pub(crate) fn get_top_node_depths(
+    roots: &[StateRoot],
+    nodes: &HashMap<CryptoHash, &[u8]>,
+    max_depth: usize,
+) -> HashMap<CryptoHash, usize> {
+    let mut depths = HashMap::new();
+    let mut level = roots.to_vec();
+    for depth in 0..max_depth {
+        let mut next_level = vec![];
+        for hash in level {
+            if depths.contains_key(&hash) {
+                continue;
+            }
+            if let Some(node) =
+                nodes.get(&hash).and_then(|bytes| RawTrieNodeWithSize::decode(bytes).ok())
+            {
+                depths.insert(hash, depth);
+                next_level.extend(get_raw_node_children(&node.node));
+            }
+        }
+        level = next_level;
+    }
+    depths
+}
+
 pub struct Trie {
     pub(crate) storage: Box<dyn TrieStorage>,
+    /// Answers `get_ref` without traversing the trie when possible, see `flat_state`.
+    pub(crate) flat_state: Option<FlatState>,
     pub counter: TouchedNodesCounter,
 }
 
@@ -469,7 +525,7 @@ pub struct ApplyStatePartResult {
 
 impl Trie {
     pub fn new(store: Box<dyn TrieStorage>, _shard_id: ShardId) -> Self {
-        Trie { storage: store, counter: TouchedNodesCounter::default() }
+        Trie { storage: store, flat_state: None, counter: TouchedNodesCounter::default() }
     }
 
     pub fn recording_reads(&self) -> Self {
@@ -480,7 +536,11 @@ impl Trie {
             shard_id: storage.shard_id,
             recorded: RefCell::new(Default::default()),
         };
-        Trie { storage: Box::new(storage), counter: TouchedNodesCounter::default() }
+        Trie {
+            storage: Box::new(storage),
+            flat_state: None,
+            counter: TouchedNodesCounter::default(),
+        }
     }
 
     pub fn empty_root() -> StateRoot {
@@ -503,6 +563,7 @@ impl Trie {
                 recorded_storage,
                 visited_nodes: Default::default(),
             }),
+            flat_state: None,
             counter: TouchedNodesCounter::default(),
         }
     }
@@ -614,6 +675,15 @@ impl Trie {
         self.storage.retrieve_raw_bytes(hash)
     }
 
+    fn retrieve_raw_bytes_at_depth(
+        &self,
+        hash: &CryptoHash,
+        depth: usize,
+    ) -> Result<Vec<u8>, StorageError> {
+        self.counter.increment();
+        self.storage.retrieve_raw_bytes_at_depth(hash, depth)
+    }
+
     pub fn retrieve_root_node(&self, root: &StateRoot) -> Result<StateRootNode, StorageError> {
         if *root == Trie::empty_root() {
             return Ok(StateRootNode::empty());
@@ -637,12 +707,14 @@ impl Trie {
         mut key: NibbleSlice<'_>,
     ) -> Result<Option<(u32, CryptoHash)>, StorageError> {
         let mut hash = *root;
+        let mut depth = 0;
 
         loop {
             if hash == Trie::empty_root() {
                 return Ok(None);
             }
-            let bytes = self.retrieve_raw_bytes(&hash)?;
+            let bytes = self.retrieve_raw_bytes_at_depth(&hash, depth)?;
+            depth += 1;
             let node = RawTrieNodeWithSize::decode(&bytes).map_err(|_| {
                 StorageError::StorageInconsistentState("RawTrieNode decode failed".to_string())
             })?;
@@ -691,17 +763,58 @@ impl Trie {
         root: &CryptoHash,
         key: &[u8],
     ) -> Result<Option<(u32, CryptoHash)>, StorageError> {
+        if let Some(flat_state) = &self.flat_state {
+            if let Some(value) = flat_state.get(root, key)? {
+                return Ok(value.map(|value| {
+                    let value_ref = value.to_value_ref();
+                    (value_ref.length, value_ref.hash)
+                }));
+            }
+        }
         let key = NibbleSlice::new(key);
         self.lookup(root, key)
     }
 
     pub fn get(&self, root: &CryptoHash, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError> {
-        match self.get_ref(root, key)? {
+        if let Some(flat_state) = &self.flat_state {
+            if let Some(value) = flat_state.get(root, key)? {
+                return value.map(|value| self.flat_state_value_into_bytes(value)).transpose();
+            }
+        }
+        match self.lookup(root, NibbleSlice::new(key))? {
             Some((_length, hash)) => self.retrieve_raw_bytes(&hash).map(Some),
             None => Ok(None),
         }
     }
 
+    /// Returns all keys starting with `prefix` and their values, sorted by key, if they can be
+    /// read from flat state. Otherwise the caller has to iterate over the trie.
+    pub fn get_prefix_from_flat_state(
+        &self,
+        root: &CryptoHash,
+        prefix: &[u8],
+    ) -> Result<Option<Vec<(Vec<u8>, Vec<u8>)>>, StorageError> {
+        let items = match &self.flat_state {
+            Some(flat_state) => match flat_state.get_prefix(root, prefix)? {
+                Some(items) => items,
+                None => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+        items
+            .into_iter()
+            .map(|(key, value)| Ok((key, self.flat_state_value_into_bytes(value)?)))
+            .collect::<Result<_, _>>()
+            .map(Some)
+    }
+
+    fn flat_state_value_into_bytes(&self, value: FlatStateValue) -> Result<Vec<u8>, StorageError> {
+        match value {
+            FlatStateValue::Inlined(value) => Ok(value),
+            FlatStateValue::Ref(value_ref) => self.retrieve_raw_bytes(&value_ref.hash),
+        }
+    }
+
     pub(crate) fn convert_to_insertions_and_deletions(
         changes: HashMap<CryptoHash, (Vec<u8>, i32)>,
     ) -> (Vec<TrieRefcountChange>, Vec<TrieRefcountChange>) {
@@ -758,6 +871,9 @@ impl Trie {
 mod tests {
     use rand::Rng;
 
+    use near_primitives::serialize::to_base64;
+    use near_primitives::trie_proof::PartialTrie;
+
     use crate::db::DBCol::ColState;
     use crate::test_utils::{
         create_test_store, create_tries, gen_changes, simplify_changes, test_populate_trie,
@@ -809,6 +925,63 @@ mod tests {
         assert_eq!(node, new_node);
     }
 
+    /// Checks that `RawTrieNode::decode` of `near_primitives::trie_proof`, used to verify the
+    /// proofs of query responses, decodes the node at `hash` and its descendants in the proof the
+    /// same way as `RawTrieNodeWithSize::decode`.
+    fn check_proof_node_decoding(proof: &HashMap<CryptoHash, Vec<u8>>, hash: &CryptoHash) {
+        use near_primitives::trie_proof::RawTrieNode as ProofNode;
+
+        let bytes = &proof[hash];
+        let node = RawTrieNodeWithSize::decode(bytes).unwrap().node;
+        let proof_node = ProofNode::decode(bytes).unwrap();
+        let nibbles = |key: &[u8]| NibbleSlice::from_encoded(key).0.iter().collect::<Vec<_>>();
+        let children = match node {
+            RawTrieNode::Leaf(key, _, value_hash) => {
+                assert_eq!(proof_node, ProofNode::Leaf(nibbles(&key), value_hash));
+                vec![]
+            }
+            RawTrieNode::Branch(children, value) => {
+                let value_hash = value.map(|(_, value_hash)| value_hash);
+                assert_eq!(proof_node, ProofNode::Branch(children, value_hash));
+                children.iter().flatten().cloned().collect()
+            }
+            RawTrieNode::Extension(key, child) => {
+                assert_eq!(proof_node, ProofNode::Extension(nibbles(&key), child));
+                vec![child]
+            }
+        };
+        for child in children.iter().filter(|child| proof.contains_key(child)) {
+            check_proof_node_decoding(proof, child);
+        }
+    }
+
+    #[test]
+    fn test_trie_proof_decoding() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            let tries = create_tries();
+            let changes = simplify_changes(&gen_changes(&mut rng, 50));
+            let root = test_populate_trie(&tries, &Trie::empty_root(), 0, changes.clone());
+            if root == Trie::empty_root() {
+                continue;
+            }
+            // Reading every key records every node of the trie
+            let trie = tries.get_trie_for_shard(0).recording_reads();
+            for (key, _) in changes.iter() {
+                trie.get(&root, key).unwrap();
+            }
+            let proof = trie.recorded_storage().unwrap().nodes.0;
+
+            let proof_map = proof.iter().map(|item| (hash(item), item.clone())).collect();
+            check_proof_node_decoding(&proof_map, &root);
+            let proof: Vec<_> = proof.iter().map(|item| to_base64(item)).collect();
+            let partial_trie = PartialTrie::new(root, &proof).unwrap();
+            for (key, value) in changes.iter() {
+                assert_eq!(&partial_trie.get(key).unwrap(), value);
+            }
+        }
+    }
+
     #[test]
     fn test_basic_trie() {
         let tries = create_tries();
@@ -0,0 +1,177 @@
+use near_primitives::challenge::PartialState;
+use near_primitives::hash::CryptoHash;
+use near_primitives::types::StateRoot;
+
+use crate::{PartialStorage, StorageError, Trie};
+
+/// Contiguous range of trie items together with the trie nodes proving that the range is
+/// complete, i.e. that there are no other keys in the trie within the range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrieRange {
+    /// Items in the range sorted by key.
+    pub items: Vec<(Vec<u8>, Vec<u8>)>,
+    /// Whether the range was cut by `limit`. The next page starts right after the last key.
+    pub has_more: bool,
+    /// Trie nodes and values visited while collecting the range.
+    pub proof: PartialState,
+}
+
+/// Returns the smallest key which is greater than all keys starting with `prefix`, or None if
+/// there is no such key. Allows to query all keys with a prefix as a range.
+pub fn prefix_range_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+impl Trie {
+    /// Returns up to `limit` items with keys in [from, to) and the trie nodes needed to verify
+    /// them with `Trie::verify_range_proof`. Unbounded if `to` is None.
+    ///
+    /// # Panics
+    /// storage must be a TrieCachingStorage
+    ///
+    /// # Errors
+    /// StorageError if the storage is corrupted
+    pub fn get_range_with_proof(
+        &self,
+        root: &StateRoot,
+        from: &[u8],
+        to: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<TrieRange, StorageError> {
+        let with_recording = self.recording_reads();
+        let (items, has_more) = with_recording.collect_range(root, from, to, limit)?;
+        let proof = with_recording.recorded_storage().unwrap().nodes;
+        Ok(TrieRange { items, has_more, proof })
+    }
+
+    /// Checks that `range` is the result of `get_range_with_proof` with the same arguments for
+    /// the trie with the given root. Proofs with missing or extra nodes are rejected.
+    pub fn verify_range_proof(
+        root: &StateRoot,
+        from: &[u8],
+        to: Option<&[u8]>,
+        limit: usize,
+        range: &TrieRange,
+    ) -> bool {
+        let num_nodes = range.proof.0.len();
+        let trie = Trie::from_recorded_storage(PartialStorage { nodes: range.proof.clone() });
+        match trie.collect_range(root, from, to, limit) {
+            Ok((items, has_more)) => {
+                let storage = trie.storage.as_partial_storage().unwrap();
+                items == range.items
+                    && has_more == range.has_more
+                    && storage.visited_nodes.borrow().len() == num_nodes
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Reads the range the same way for the proof generation and verification, so that exactly
+    /// the same nodes are visited.
+    fn collect_range(
+        &self,
+        root: &CryptoHash,
+        from: &[u8],
+        to: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<(Vec<(Vec<u8>, Vec<u8>)>, bool), StorageError> {
+        let mut iterator = self.iter(root)?;
+        iterator.seek(from)?;
+        let mut items = vec![];
+        while let Some(item) = iterator.next_value_hash() {
+            let (key, value_hash) = item?;
+            if to.map_or(false, |to| key.as_slice() >= to) {
+                break;
+            }
+            if items.len() == limit {
+                return Ok((items, true));
+            }
+            let value = self.retrieve_raw_bytes(&value_hash)?;
+            items.push((key, value));
+        }
+        Ok((items, false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use crate::test_utils::{create_tries, gen_changes, simplify_changes, test_populate_trie};
+
+    use super::*;
+
+    #[test]
+    fn test_prefix_range_end() {
+        assert_eq!(prefix_range_end(b"abc"), Some(b"abd".to_vec()));
+        assert_eq!(prefix_range_end(&[1, 255, 255]), Some(vec![2]));
+        assert_eq!(prefix_range_end(&[255]), None);
+        assert_eq!(prefix_range_end(&[]), None);
+    }
+
+    #[test]
+    fn test_range_with_proof() {
+        let mut rng = rand::thread_rng();
+        for _test_run in 0..20 {
+            let tries = create_tries();
+            let trie = tries.get_trie_for_shard(0);
+            let trie_changes = simplify_changes(&gen_changes(&mut rng, 500));
+            let state_root =
+                test_populate_trie(&tries, &Trie::empty_root(), 0, trie_changes.clone());
+            let mut expected: Vec<_> = trie_changes
+                .into_iter()
+                .filter_map(|(key, value)| value.map(|value| (key, value)))
+                .collect();
+            expected.sort();
+
+            let queries = gen_changes(&mut rng, 10).into_iter().map(|(key, _)| key);
+            for from in queries {
+                let to = if rng.gen() { prefix_range_end(&from[..1]) } else { None };
+                let limit = rng.gen_range(0, 20);
+                let range =
+                    trie.get_range_with_proof(&state_root, &from, to.as_deref(), limit).unwrap();
+                let in_range: Vec<_> = expected
+                    .iter()
+                    .filter(|(key, _)| key >= &from && to.as_ref().map_or(true, |to| key < to))
+                    .cloned()
+                    .collect();
+                assert_eq!(range.items, in_range.iter().take(limit).cloned().collect::<Vec<_>>());
+                assert_eq!(range.has_more, in_range.len() > limit);
+                assert!(Trie::verify_range_proof(&state_root, &from, to.as_deref(), limit, &range));
+
+                // A proof is only valid for the same limit, unless the whole range fits into it.
+                assert_eq!(
+                    Trie::verify_range_proof(&state_root, &from, to.as_deref(), limit + 1, &range),
+                    !range.has_more
+                );
+                if !range.items.is_empty() {
+                    let mut forged = range.clone();
+                    forged.items.remove(0);
+                    assert!(!Trie::verify_range_proof(
+                        &state_root,
+                        &from,
+                        to.as_deref(),
+                        limit,
+                        &forged
+                    ));
+                    let mut forged = range.clone();
+                    forged.proof.0.pop();
+                    assert!(!Trie::verify_range_proof(
+                        &state_root,
+                        &from,
+                        to.as_deref(),
+                        limit,
+                        &forged
+                    ));
+                }
+            }
+        }
+    }
+}
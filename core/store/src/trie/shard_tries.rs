@@ -1,7 +1,11 @@
 use crate::db::{DBCol, DBOp, DBTransaction};
+use crate::trie::flat_state::{
+    create_flat_state, get_flat_state_delta_key, update_flat_state_head, FlatState, FlatStateDelta,
+    FlatStateHead, FlatStateHeadUpdate,
+};
 use crate::trie::trie_storage::{TrieCache, TrieCachingStorage};
-use crate::trie::TrieRefcountChange;
-use crate::{StorageError, Store, StoreUpdate, Trie, TrieChanges, TrieUpdate};
+use crate::trie::{TrieRefcountChange, POISONED_LOCK_ERR};
+use crate::{StorageError, Store, StoreUpdate, Trie, TrieCacheConfig, TrieChanges, TrieUpdate};
 use borsh::BorshSerialize;
 use near_primitives::hash::CryptoHash;
 use near_primitives::trie_key::TrieKey;
@@ -9,8 +13,10 @@ use near_primitives::types::{
     NumShards, RawStateChange, RawStateChangesWithTrieKey, ShardId, StateChangeCause, StateRoot,
 };
 use near_primitives::utils::get_block_shard_id;
+use std::collections::HashSet;
 use std::rc::Rc;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use tracing::error;
 
 #[derive(Clone)]
 pub struct ShardTries {
@@ -19,22 +25,54 @@ pub struct ShardTries {
     pub(crate) caches: Arc<Vec<TrieCache>>,
     /// Cache for readers.
     pub(crate) view_caches: Arc<Vec<TrieCache>>,
+    /// Whether flat state is maintained and used by view tries, see `flat_state`.
+    pub(crate) flat_state: bool,
+    /// Shards whose flat state is being created in the background.
+    flat_state_creation: Arc<Mutex<HashSet<ShardId>>>,
 }
 
 impl ShardTries {
-    fn get_new_cache(num_shards: NumShards) -> Arc<Vec<TrieCache>> {
-        Arc::new((0..num_shards).map(|_| TrieCache::new()).collect::<Vec<_>>())
+    fn get_new_cache(
+        num_shards: NumShards,
+        is_view: bool,
+        config: &TrieCacheConfig,
+    ) -> Arc<Vec<TrieCache>> {
+        Arc::new(
+            (0..num_shards)
+                .map(|shard_id| TrieCache::new(shard_id, is_view, config))
+                .collect::<Vec<_>>(),
+        )
     }
 
     pub fn new(store: Arc<Store>, num_shards: NumShards) -> Self {
         assert_ne!(num_shards, 0);
+        let config = TrieCacheConfig::default();
         ShardTries {
             store,
-            caches: Self::get_new_cache(num_shards),
-            view_caches: Self::get_new_cache(num_shards),
+            caches: Self::get_new_cache(num_shards, false, &config),
+            view_caches: Self::get_new_cache(num_shards, true, &config),
+            flat_state: false,
+            flat_state_creation: Default::default(),
         }
     }
 
+    /// Replaces the caches with empty ones sized according to `config`.
+    pub fn with_cache_config(mut self, config: &TrieCacheConfig) -> Self {
+        let num_shards = self.caches.len() as NumShards;
+        self.caches = Self::get_new_cache(num_shards, false, config);
+        self.view_caches = Self::get_new_cache(num_shards, true, config);
+        self
+    }
+
+    pub fn with_flat_state(mut self, flat_state: bool) -> Self {
+        self.flat_state = flat_state;
+        self
+    }
+
+    pub fn is_flat_state_enabled(&self) -> bool {
+        self.flat_state
+    }
+
     pub fn new_trie_update(&self, shard_id: ShardId, state_root: CryptoHash) -> TrieUpdate {
         TrieUpdate::new(Rc::new(self.get_trie_for_shard(shard_id)), state_root)
     }
@@ -43,6 +81,32 @@ impl ShardTries {
         TrieUpdate::new(Rc::new(self.get_view_trie_for_shard(shard_id)), state_root)
     }
 
+    /// Same as `new_trie_update_view` for the state of the shard at `block_hash`, which lets
+    /// reads use flat state if it is enabled.
+    pub fn new_trie_update_view_at_block(
+        &self,
+        shard_id: ShardId,
+        state_root: CryptoHash,
+        block_hash: &CryptoHash,
+    ) -> TrieUpdate {
+        let mut trie = self.get_view_trie_for_shard(shard_id);
+        if self.flat_state {
+            trie.flat_state = Some(FlatState::new(self.store.clone(), shard_id, *block_hash));
+        }
+        TrieUpdate::new(Rc::new(trie), state_root)
+    }
+
+    /// View of the state which records all the read trie nodes, so that they can be returned as
+    /// a proof of the read values, see `Trie::recorded_storage`.
+    pub fn new_trie_update_view_recording(
+        &self,
+        shard_id: ShardId,
+        state_root: CryptoHash,
+    ) -> TrieUpdate {
+        let trie = self.get_trie_for_shard_internal(shard_id, true).recording_reads();
+        TrieUpdate::new(Rc::new(trie), state_root)
+    }
+
     fn get_trie_for_shard_internal(&self, shard_id: ShardId, is_view: bool) -> Trie {
         let cache = if is_view {
             self.view_caches[shard_id as usize].clone()
@@ -61,11 +125,64 @@ impl ShardTries {
         self.get_trie_for_shard_internal(shard_id, true)
     }
 
+    /// Moves the flat state head of the shard to `block_hash`, which should be the new final
+    /// block. If the shard doesn't have flat state yet, starts creating it in the background and
+    /// returns an empty update. Does nothing if flat state is disabled.
+    pub fn update_flat_state_head(
+        &self,
+        shard_id: ShardId,
+        block_hash: &CryptoHash,
+    ) -> Result<StoreUpdate, StorageError> {
+        if !self.flat_state {
+            return Ok(self.store.store_update());
+        }
+        match update_flat_state_head(&self.store, shard_id, block_hash)? {
+            FlatStateHeadUpdate::Update(store_update) => Ok(store_update),
+            FlatStateHeadUpdate::Create(head) => {
+                self.start_flat_state_creation(shard_id, head);
+                Ok(self.store.store_update())
+            }
+        }
+    }
+
+    /// Creates flat state of the shard on a separate thread, unless it is already being created.
+    /// Once created, the head is moved forward by the next calls of `update_flat_state_head`.
+    fn start_flat_state_creation(&self, shard_id: ShardId, head: FlatStateHead) {
+        if !self.flat_state_creation.lock().expect(POISONED_LOCK_ERR).insert(shard_id) {
+            return;
+        }
+        let store = self.store.clone();
+        let flat_state_creation = self.flat_state_creation.clone();
+        std::thread::spawn(move || {
+            // The whole trie is read once, so the nodes are not cached.
+            let cache = TrieCache::new(
+                shard_id,
+                true,
+                &TrieCacheConfig { max_bytes: 0, pinned_levels: 0, ..Default::default() },
+            );
+            let storage = TrieCachingStorage::new(store.clone(), cache, shard_id);
+            let trie = Trie::new(Box::new(storage), shard_id);
+            if let Err(err) = create_flat_state(&store, &trie, shard_id, &head) {
+                error!(target: "store", "Failed to create flat state of shard {}: {}", shard_id, err);
+            }
+            flat_state_creation.lock().expect(POISONED_LOCK_ERR).remove(&shard_id);
+        });
+    }
+
+    /// Whether flat state of the shard is being created in the background.
+    pub fn is_flat_state_being_created(&self, shard_id: ShardId) -> bool {
+        self.flat_state_creation.lock().expect(POISONED_LOCK_ERR).contains(&shard_id)
+    }
+
     pub fn get_store(&self) -> Arc<Store> {
         self.store.clone()
     }
 
-    pub fn update_cache(&self, transaction: &DBTransaction) -> std::io::Result<()> {
+    pub fn update_cache(
+        &self,
+        transaction: &DBTransaction,
+        new_state_roots: &[(ShardId, StateRoot)],
+    ) -> std::io::Result<()> {
         let mut shards = vec![Vec::new(); self.caches.len()];
         for op in &transaction.ops {
             match op {
@@ -85,7 +202,12 @@ impl ShardTries {
             }
         }
         for (shard_id, ops) in shards.into_iter().enumerate() {
-            self.caches[shard_id].update_cache(ops);
+            let new_roots: Vec<_> = new_state_roots
+                .iter()
+                .filter(|(root_shard_id, _)| *root_shard_id == shard_id as ShardId)
+                .map(|(_, root)| *root)
+                .collect();
+            self.caches[shard_id].update_cache(ops, &new_roots);
         }
         Ok(())
     }
@@ -131,6 +253,7 @@ impl ShardTries {
             shard_id,
             &mut store_update,
         )?;
+        store_update.new_state_roots.push((shard_id, trie_changes.new_root));
         if apply_deletions {
             ShardTries::apply_deletions_inner(
                 &trie_changes.deletions,
@@ -153,7 +276,9 @@ impl ShardTries {
             self.clone(),
             shard_id,
             store_update,
-        )
+        )?;
+        store_update.new_state_roots.push((shard_id, trie_changes.new_root));
+        Ok(())
     }
 
     pub fn apply_deletions(
@@ -201,7 +326,7 @@ impl ShardTries {
         assert_eq!(trie_changes.old_root, CryptoHash::default());
         assert!(trie_changes.deletions.is_empty());
         // Not new_with_tries on purpose
-        let mut store_update = StoreUpdate::new(self.get_store().storage.clone());
+        let mut store_update = self.get_store().store_update();
         for TrieRefcountChange { key_hash, value, rc } in trie_changes.insertions.into_iter() {
             let key = TrieCachingStorage::get_key_from_shard_id_and_hash(shard_id, &key_hash);
             store_update.update_refcount(DBCol::ColState, key.as_ref(), &value, rc as i64);
@@ -216,6 +341,7 @@ pub struct WrappedTrieChanges {
     trie_changes: TrieChanges,
     state_changes: Vec<RawStateChangesWithTrieKey>,
     block_hash: CryptoHash,
+    prev_block_hash: CryptoHash,
 }
 
 impl WrappedTrieChanges {
@@ -225,14 +351,46 @@ impl WrappedTrieChanges {
         trie_changes: TrieChanges,
         state_changes: Vec<RawStateChangesWithTrieKey>,
         block_hash: CryptoHash,
+        prev_block_hash: CryptoHash,
     ) -> Self {
-        WrappedTrieChanges { tries, shard_id, trie_changes, state_changes, block_hash }
+        WrappedTrieChanges {
+            tries,
+            shard_id,
+            trie_changes,
+            state_changes,
+            block_hash,
+            prev_block_hash,
+        }
     }
 
     pub fn insertions_into(&self, store_update: &mut StoreUpdate) -> Result<(), StorageError> {
         self.tries.apply_insertions(&self.trie_changes, self.shard_id, store_update)
     }
 
+    /// Save flat state delta into Store if flat state is enabled.
+    pub fn flat_state_delta_into(
+        &self,
+        store_update: &mut StoreUpdate,
+    ) -> Result<(), StorageError> {
+        if !self.tries.flat_state {
+            return Ok(());
+        }
+        // Written even if the state didn't change, so that deltas link every block to the
+        // previous one.
+        let delta = FlatStateDelta::from_state_changes(
+            self.prev_block_hash,
+            self.trie_changes.new_root,
+            &self.state_changes,
+        );
+        store_update
+            .set_ser(
+                DBCol::ColFlatStateDeltas,
+                &get_flat_state_delta_key(self.shard_id, &self.block_hash),
+                &delta,
+            )
+            .map_err(|_| StorageError::StorageInternalError)
+    }
+
     /// Save state changes into Store.
     ///
     /// NOTE: the changes are drained from `self`.
@@ -272,6 +430,7 @@ impl WrappedTrieChanges {
         mut store_update: &mut StoreUpdate,
     ) -> Result<(), Box<dyn std::error::Error>> {
         self.insertions_into(&mut store_update)?;
+        self.flat_state_delta_into(&mut store_update)?;
         self.state_changes_into(&mut store_update);
         store_update.set_ser(
             DBCol::ColTrieChanges,
@@ -2,45 +2,234 @@ use std::collections::{HashMap, HashSet};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 
-use cached::{Cached, SizedCache};
+use lru::LruCache;
 
 use near_primitives::hash::CryptoHash;
 
 use crate::db::refcount::decode_value_with_rc;
-use crate::trie::POISONED_LOCK_ERR;
-use crate::{ColState, StorageError, Store};
-use near_primitives::types::ShardId;
+use crate::trie::{get_top_node_depths, POISONED_LOCK_ERR};
+use crate::{metrics, ColState, StorageError, Store, TrieCacheConfig};
+use near_metrics::{IntCounter, IntCounterVec};
+use near_primitives::types::{ShardId, StateRoot};
 use std::cell::RefCell;
 use std::convert::{TryFrom, TryInto};
 use std::io::ErrorKind;
 
+/// LRU cache bounded by the total size of the cached values instead of their number.
+struct SizeBoundedLru {
+    cache: LruCache<CryptoHash, Vec<u8>>,
+    max_bytes: u64,
+    total_bytes: u64,
+}
+
+impl SizeBoundedLru {
+    fn new(max_bytes: u64) -> Self {
+        SizeBoundedLru { cache: LruCache::unbounded(), max_bytes, total_bytes: 0 }
+    }
+
+    fn entry_size(value: &[u8]) -> u64 {
+        (std::mem::size_of::<CryptoHash>() + value.len()) as u64
+    }
+
+    fn get(&mut self, hash: &CryptoHash) -> Option<&Vec<u8>> {
+        self.cache.get(hash)
+    }
+
+    /// Inserts the value and returns the number of evicted entries.
+    fn put(&mut self, hash: CryptoHash, value: Vec<u8>) -> u64 {
+        let size = Self::entry_size(&value);
+        if size > self.max_bytes {
+            self.remove(&hash);
+            return 0;
+        }
+        self.total_bytes += size;
+        if let Some(old_value) = self.cache.put(hash, value) {
+            self.total_bytes -= Self::entry_size(&old_value);
+        }
+        let mut evicted = 0;
+        while self.total_bytes > self.max_bytes {
+            match self.cache.pop_lru() {
+                Some((_, value)) => {
+                    self.total_bytes -= Self::entry_size(&value);
+                    evicted += 1;
+                }
+                None => break,
+            }
+        }
+        evicted
+    }
+
+    fn remove(&mut self, hash: &CryptoHash) {
+        if let Some(value) = self.cache.pop(hash) {
+            self.total_bytes -= Self::entry_size(&value);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.cache.clear();
+        self.total_bytes = 0;
+    }
+}
+
+struct TrieCacheInner {
+    /// Number of top levels of the trie which go to `pinned` instead of `lru`.
+    pinned_levels: usize,
+    lru: SizeBoundedLru,
+    pinned: SizeBoundedLru,
+}
+
+impl TrieCacheInner {
+    /// Returns the cached value. `depth` is the depth of the node in the trie if it is known, a
+    /// node found in `lru` at a pinned depth is moved to `pinned`. Also returns the number of
+    /// entries evicted by the move.
+    fn get(&mut self, hash: &CryptoHash, depth: Option<usize>) -> (Option<Vec<u8>>, u64) {
+        if let Some(value) = self.pinned.get(hash) {
+            return (Some(value.clone()), 0);
+        }
+        let value = self.lru.get(hash).cloned();
+        match (&value, depth) {
+            (Some(value), Some(depth)) if depth < self.pinned_levels => {
+                self.lru.remove(hash);
+                let evicted = self.pinned.put(*hash, value.clone());
+                (Some(value.clone()), evicted)
+            }
+            _ => (value, 0),
+        }
+    }
+
+    /// Caches the value and returns the number of evicted entries. `depth` is the depth of the
+    /// node in the trie if it is known.
+    fn put(&mut self, hash: CryptoHash, value: Vec<u8>, depth: Option<usize>) -> u64 {
+        if value.len() >= TRIE_LIMIT_CACHED_VALUE_SIZE {
+            return 0;
+        }
+        match depth {
+            Some(depth) if depth < self.pinned_levels => {
+                self.lru.remove(&hash);
+                self.pinned.put(hash, value)
+            }
+            // A pinned node stays pinned when it is written again.
+            _ if self.pinned.cache.contains(&hash) => self.pinned.put(hash, value),
+            _ => self.lru.put(hash, value),
+        }
+    }
+
+    fn remove(&mut self, hash: &CryptoHash) {
+        self.lru.remove(hash);
+        self.pinned.remove(hash);
+    }
+}
+
+/// Counters of a single cache, resolved once so that reads don't look up the labels.
+#[derive(Clone)]
+struct TrieCacheMetrics {
+    hits: Option<IntCounter>,
+    misses: Option<IntCounter>,
+    evictions: Option<IntCounter>,
+}
+
+impl TrieCacheMetrics {
+    fn new(shard_id: ShardId, is_view: bool) -> Self {
+        let labels = [shard_id.to_string(), is_view.to_string()];
+        let labels = [labels[0].as_str(), labels[1].as_str()];
+        let counter = |counter: &near_metrics::Result<IntCounterVec>| {
+            counter.as_ref().ok().map(|counter| counter.with_label_values(&labels))
+        };
+        TrieCacheMetrics {
+            hits: counter(&metrics::TRIE_CACHE_HITS_TOTAL),
+            misses: counter(&metrics::TRIE_CACHE_MISSES_TOTAL),
+            evictions: counter(&metrics::TRIE_CACHE_EVICTIONS_TOTAL),
+        }
+    }
+
+    fn evicted(&self, evicted: u64) {
+        if evicted > 0 {
+            near_metrics::inc_counter_by_opt(self.evictions.as_ref(), evicted);
+        }
+    }
+}
+
+/// Cache of trie nodes and values of a single shard, bounded by their total size in bytes.
+///
+/// Nodes at the top levels of the trie, as configured by `TrieCacheConfig::pinned_levels`, are
+/// kept in a separate area, so that reads deep in the trie can't evict them.
 #[derive(Clone)]
-pub struct TrieCache(Arc<Mutex<SizedCache<CryptoHash, Vec<u8>>>>);
+pub struct TrieCache {
+    inner: Arc<Mutex<TrieCacheInner>>,
+    metrics: TrieCacheMetrics,
+}
 
 impl TrieCache {
-    pub fn new() -> Self {
-        Self(Arc::new(Mutex::new(SizedCache::with_size(TRIE_MAX_CACHE_SIZE))))
+    pub fn new(shard_id: ShardId, is_view: bool, config: &TrieCacheConfig) -> Self {
+        #[cfg(not(feature = "no_cache"))]
+        let (max_bytes, pinned_levels) = (config.shard_max_bytes(shard_id), config.pinned_levels);
+        #[cfg(feature = "no_cache")]
+        let (max_bytes, pinned_levels) = (0, 0);
+        Self {
+            inner: Arc::new(Mutex::new(TrieCacheInner {
+                pinned_levels,
+                lru: SizeBoundedLru::new(max_bytes),
+                pinned: SizeBoundedLru::new(if pinned_levels > 0 {
+                    config.pinned_max_bytes
+                } else {
+                    0
+                }),
+            })),
+            metrics: TrieCacheMetrics::new(shard_id, is_view),
+        }
     }
 
     pub fn clear(&self) {
-        self.0.lock().expect(POISONED_LOCK_ERR).cache_clear()
+        let mut guard = self.inner.lock().expect(POISONED_LOCK_ERR);
+        guard.lru.clear();
+        guard.pinned.clear();
     }
 
-    pub fn update_cache(&self, ops: Vec<(CryptoHash, Option<Vec<u8>>)>) {
-        let mut guard = self.0.lock().expect(POISONED_LOCK_ERR);
-        for (hash, opt_value_rc) in ops {
+    fn get(&self, hash: &CryptoHash, depth: Option<usize>) -> Option<Vec<u8>> {
+        let (value, evicted) = self.inner.lock().expect(POISONED_LOCK_ERR).get(hash, depth);
+        let counter = if value.is_some() { &self.metrics.hits } else { &self.metrics.misses };
+        near_metrics::inc_counter_opt(counter.as_ref());
+        self.metrics.evicted(evicted);
+        value
+    }
+
+    fn put(&self, hash: CryptoHash, value: Vec<u8>, depth: Option<usize>) {
+        let evicted = self.inner.lock().expect(POISONED_LOCK_ERR).put(hash, value, depth);
+        self.metrics.evicted(evicted);
+    }
+
+    /// Applies the refcount changes of a commit. New nodes at the top levels below the state
+    /// roots created by the commit go to the pinned area, the same as when they are read.
+    pub fn update_cache(&self, ops: Vec<(CryptoHash, Option<Vec<u8>>)>, new_roots: &[StateRoot]) {
+        let mut guard = self.inner.lock().expect(POISONED_LOCK_ERR);
+        // Roots which are pinned already had their top levels pinned when they were created.
+        let new_roots: Vec<_> =
+            new_roots.iter().filter(|root| !guard.pinned.cache.contains(*root)).cloned().collect();
+        let depths = if guard.pinned_levels == 0 || new_roots.is_empty() {
+            HashMap::new()
+        } else {
+            let new_nodes: HashMap<_, _> = ops
+                .iter()
+                .filter_map(|(hash, value_rc)| {
+                    decode_value_with_rc(value_rc.as_ref()?).0.map(|value| (*hash, value))
+                })
+                .collect();
+            get_top_node_depths(&new_roots, &new_nodes, guard.pinned_levels)
+        };
+        let mut evicted = 0;
+        for (hash, opt_value_rc) in ops.iter() {
             if let Some(value_rc) = opt_value_rc {
                 if let (Some(value), _rc) = decode_value_with_rc(&value_rc) {
-                    if value.len() < TRIE_LIMIT_CACHED_VALUE_SIZE {
-                        guard.cache_set(hash, value.to_vec());
-                    }
+                    evicted += guard.put(*hash, value.to_vec(), depths.get(hash).copied());
                 } else {
-                    guard.cache_remove(&hash);
+                    guard.remove(&hash);
                 }
             } else {
-                guard.cache_remove(&hash);
+                guard.remove(&hash);
             }
         }
+        drop(guard);
+        self.metrics.evicted(evicted);
     }
 }
 
@@ -50,6 +239,16 @@ pub trait TrieStorage {
     /// StorageError if the storage fails internally or the hash is not present.
     fn retrieve_raw_bytes(&self, hash: &CryptoHash) -> Result<Vec<u8>, StorageError>;
 
+    /// Same as `retrieve_raw_bytes` for a node at the given depth from the state root, which
+    /// allows the storage to treat the top levels of the trie differently.
+    fn retrieve_raw_bytes_at_depth(
+        &self,
+        hash: &CryptoHash,
+        _depth: usize,
+    ) -> Result<Vec<u8>, StorageError> {
+        self.retrieve_raw_bytes(hash)
+    }
+
     fn as_caching_storage(&self) -> Option<&TrieCachingStorage> {
         None
     }
@@ -118,13 +317,6 @@ impl TrieStorage for TrieMemoryPartialStorage {
     }
 }
 
-/// Maximum number of cache entries.
-#[cfg(not(feature = "no_cache"))]
-const TRIE_MAX_CACHE_SIZE: usize = 10000;
-
-#[cfg(feature = "no_cache")]
-const TRIE_MAX_CACHE_SIZE: usize = 1;
-
 /// Values above this size (in bytes) are never cached.
 /// Note that Trie inner nodes are always smaller than this.
 const TRIE_LIMIT_CACHED_VALUE_SIZE: usize = 4000;
@@ -159,11 +351,14 @@ impl TrieCachingStorage {
     }
 }
 
-impl TrieStorage for TrieCachingStorage {
-    fn retrieve_raw_bytes(&self, hash: &CryptoHash) -> Result<Vec<u8>, StorageError> {
-        let mut guard = self.cache.0.lock().expect(POISONED_LOCK_ERR);
-        if let Some(val) = guard.cache_get(hash) {
-            Ok(val.clone())
+impl TrieCachingStorage {
+    fn retrieve_raw_bytes_impl(
+        &self,
+        hash: &CryptoHash,
+        depth: Option<usize>,
+    ) -> Result<Vec<u8>, StorageError> {
+        if let Some(val) = self.cache.get(hash, depth) {
+            Ok(val)
         } else {
             let key = Self::get_key_from_shard_id_and_hash(self.shard_id, hash);
             let val = self
@@ -171,9 +366,7 @@ impl TrieStorage for TrieCachingStorage {
                 .get(ColState, key.as_ref())
                 .map_err(|_| StorageError::StorageInternalError)?;
             if let Some(val) = val {
-                if val.len() < TRIE_LIMIT_CACHED_VALUE_SIZE {
-                    guard.cache_set(*hash, val.clone());
-                }
+                self.cache.put(*hash, val.clone(), depth);
                 Ok(val)
             } else {
                 // not StorageError::TrieNodeMissing because it's only for TrieMemoryPartialStorage
@@ -181,6 +374,20 @@ impl TrieStorage for TrieCachingStorage {
             }
         }
     }
+}
+
+impl TrieStorage for TrieCachingStorage {
+    fn retrieve_raw_bytes(&self, hash: &CryptoHash) -> Result<Vec<u8>, StorageError> {
+        self.retrieve_raw_bytes_impl(hash, None)
+    }
+
+    fn retrieve_raw_bytes_at_depth(
+        &self,
+        hash: &CryptoHash,
+        depth: usize,
+    ) -> Result<Vec<u8>, StorageError> {
+        self.retrieve_raw_bytes_impl(hash, Some(depth))
+    }
 
     fn as_caching_storage(&self) -> Option<&TrieCachingStorage> {
         Some(self)
@@ -207,3 +414,106 @@ impl TouchedNodesCounter {
         self.counter.load(Ordering::SeqCst)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::create_tries;
+    use crate::Trie;
+    use near_primitives::hash::hash;
+
+    fn value(len: usize) -> Vec<u8> {
+        vec![1; len]
+    }
+
+    #[test]
+    fn test_size_bounded_lru() {
+        let entry_size = SizeBoundedLru::entry_size(&value(68));
+        let mut lru = SizeBoundedLru::new(entry_size * 3);
+        for i in 0..3u8 {
+            assert_eq!(lru.put(hash(&[i]), value(68)), 0);
+        }
+        assert_eq!(lru.total_bytes, entry_size * 3);
+        // Touch the first entry so that the second one is evicted.
+        assert!(lru.get(&hash(&[0])).is_some());
+        assert_eq!(lru.put(hash(&[3]), value(68)), 1);
+        assert!(lru.get(&hash(&[1])).is_none());
+        assert!(lru.get(&hash(&[0])).is_some());
+        // A value twice as big evicts two entries.
+        assert_eq!(lru.put(hash(&[4]), value(68 * 2 + 32)), 2);
+        assert_eq!(lru.total_bytes, entry_size * 3);
+        // Values which don't fit are not cached at all.
+        assert_eq!(lru.put(hash(&[5]), value(68 * 3 + 100)), 0);
+        assert!(lru.get(&hash(&[5])).is_none());
+        lru.remove(&hash(&[4]));
+        lru.remove(&hash(&[0]));
+        assert_eq!(lru.total_bytes, 0);
+    }
+
+    #[test]
+    fn test_pinned_nodes_are_not_evicted() {
+        let config = TrieCacheConfig {
+            max_bytes: 1000,
+            pinned_levels: 2,
+            pinned_max_bytes: 1000,
+            ..Default::default()
+        };
+        let cache = TrieCache::new(0, false, &config);
+        cache.put(hash(b"root"), value(100), Some(0));
+        cache.put(hash(b"child"), value(100), Some(1));
+        for i in 0..100u8 {
+            cache.put(hash(&[i]), value(100), Some(2));
+            cache.put(hash(&[i, i]), value(100), None);
+        }
+        assert!(cache.get(&hash(b"root"), None).is_some());
+        assert!(cache.get(&hash(b"child"), None).is_some());
+        assert!(cache.get(&hash(&[0]), None).is_none());
+        assert!(cache.inner.lock().unwrap().lru.total_bytes <= config.max_bytes);
+
+        cache.inner.lock().unwrap().remove(&hash(b"root"));
+        assert!(cache.get(&hash(b"root"), None).is_none());
+    }
+
+    #[test]
+    fn test_nodes_read_at_pinned_depth_are_pinned() {
+        let config =
+            TrieCacheConfig { pinned_levels: 1, pinned_max_bytes: 1000, ..Default::default() };
+        let cache = TrieCache::new(0, false, &config);
+        cache.put(hash(b"root"), value(100), None);
+        assert!(cache.get(&hash(b"root"), Some(1)).is_some());
+        assert!(!cache.inner.lock().unwrap().pinned.cache.contains(&hash(b"root")));
+        assert!(cache.get(&hash(b"root"), Some(0)).is_some());
+        let guard = cache.inner.lock().unwrap();
+        assert!(guard.pinned.cache.contains(&hash(b"root")));
+        assert!(!guard.lru.cache.contains(&hash(b"root")));
+    }
+
+    #[test]
+    fn test_committed_nodes_are_pinned() {
+        let config =
+            TrieCacheConfig { pinned_levels: 2, pinned_max_bytes: 100_000, ..Default::default() };
+        let tries = create_tries().with_cache_config(&config);
+        // First nibbles of the keys are 0..4, so the root is a branch with 4 children.
+        let changes: Vec<_> =
+            (0..50u8).map(|i| (vec![i, i % 7, i % 3], Some(vec![i; 10]))).collect();
+        let trie = tries.get_trie_for_shard(0);
+        let trie_changes = trie.update(&Trie::empty_root(), changes.into_iter()).unwrap();
+        let (store_update, root) = tries.apply_all(&trie_changes, 0).unwrap();
+        store_update.commit().unwrap();
+
+        let guard = tries.caches[0].inner.lock().unwrap();
+        assert!(guard.pinned.cache.contains(&root));
+        assert_eq!(guard.pinned.cache.len(), 5);
+        assert!(guard.lru.cache.len() > 0);
+    }
+
+    #[test]
+    fn test_per_shard_max_bytes() {
+        let mut config = TrieCacheConfig { max_bytes: 1000, ..Default::default() };
+        config.per_shard_max_bytes.insert(1, 0);
+        let cache = TrieCache::new(1, false, &config);
+        cache.put(hash(b"node"), value(10), None);
+        assert!(cache.get(&hash(b"node"), None).is_none());
+        assert_eq!(config.shard_max_bytes(0), 1000);
+    }
+}
@@ -21,7 +21,8 @@ use near_primitives::state_record::StateRecord;
 use near_primitives::types::chunk_extra::ChunkExtra;
 use near_primitives::types::{AccountId, Balance, EpochId, ShardId, StateChangeCause, StateRoot};
 use near_store::{
-    create_store, get_account, set_access_key, set_account, set_code, ColState, Store, TrieUpdate,
+    create_store_with_config, get_account, set_access_key, set_account, set_code, ColState, Store,
+    StoreConfig, TrieUpdate,
 };
 use nearcore::{get_store_path, NightshadeRuntime};
 
@@ -85,8 +86,8 @@ impl GenesisBuilder {
         }
     }
 
-    pub fn from_config(home_dir: &Path, genesis: Arc<Genesis>) -> Self {
-        let store = create_store(&get_store_path(home_dir));
+    pub fn from_config(home_dir: &Path, genesis: Arc<Genesis>, store_config: &StoreConfig) -> Self {
+        let store = create_store_with_config(&get_store_path(home_dir), store_config);
         Self::from_config_and_store(home_dir, genesis, store)
     }
 
@@ -12,10 +12,12 @@ use num_rational::Rational;
 use serde::{Deserialize, Serialize};
 use tracing::info;
 
-use near_chain_configs::{ClientConfig, Genesis, GenesisConfig, LogSummaryStyle};
+use near_chain_configs::{ClientConfig, GCPolicy, Genesis, GenesisConfig, LogSummaryStyle};
 use near_crypto::{InMemorySigner, KeyFile, KeyType, PublicKey, Signer};
 #[cfg(feature = "json_rpc")]
 use near_jsonrpc::RpcConfig;
+use near_network::capture::CaptureConfig;
+use near_network::rate_limit::RateLimitConfig;
 use near_network::test_utils::open_port;
 use near_network::types::ROUTED_MESSAGE_TTL;
 use near_network::utils::blacklist_from_iter;
@@ -33,6 +35,7 @@ use near_primitives::validator_signer::{InMemoryValidatorSigner, ValidatorSigner
 use near_primitives::version::PROTOCOL_VERSION;
 #[cfg(feature = "rosetta_rpc")]
 use near_rosetta_rpc::RosettaRpcConfig;
+use near_store::StoreConfig;
 use near_telemetry::TelemetryConfig;
 
 /// Initial balance used in tests.
@@ -192,6 +195,19 @@ fn default_peer_stats_period() -> Duration {
     Duration::from_secs(5)
 }
 
+/// Maximum number of outbound connections to peers in the same /24 (IPv4) or /48 (IPv6) subnet.
+fn default_max_outbound_peers_per_subnet() -> u32 {
+    2
+}
+
+fn default_state_part_compression() -> bool {
+    true
+}
+
+fn default_state_part_chunk_size() -> u64 {
+    16 * 1024 * 1024
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Network {
     /// Address to listen for incoming connections.
@@ -242,6 +258,26 @@ pub struct Network {
     /// Period to check on peer status
     #[serde(default = "default_peer_stats_period")]
     pub peer_stats_period: Duration,
+    /// Compress state parts sent to peers which support it.
+    #[serde(default = "default_state_part_compression")]
+    pub state_part_compression: bool,
+    /// Maximum size of a single message carrying a state part in bytes, larger parts are split.
+    #[serde(default = "default_state_part_chunk_size")]
+    pub state_part_chunk_size: u64,
+    /// Refuse peers which don't support the encrypted transport. Old peers connect in plaintext
+    /// otherwise.
+    #[serde(default)]
+    pub require_secure_transport: bool,
+    /// Write all messages exchanged with peers to rotating files, to replay them in tests.
+    #[serde(default)]
+    pub capture: Option<CaptureConfig>,
+    /// Limits of the messages received from each peer, by message type and in total bytes.
+    #[serde(default)]
+    pub rate_limits: RateLimitConfig,
+    /// Maximum number of outbound connections to peers in the same /24 (IPv4) or /48 (IPv6)
+    /// subnet. Loopback and private addresses aren't limited.
+    #[serde(default = "default_max_outbound_peers_per_subnet")]
+    pub max_outbound_peers_per_subnet: u32,
 }
 
 impl Default for Network {
@@ -264,6 +300,12 @@ impl Default for Network {
             blacklist: vec![],
             ttl_account_id_router: default_ttl_account_id_router(),
             peer_stats_period: default_peer_stats_period(),
+            state_part_compression: default_state_part_compression(),
+            state_part_chunk_size: default_state_part_chunk_size(),
+            require_secure_transport: false,
+            capture: None,
+            rate_limits: RateLimitConfig::default(),
+            max_outbound_peers_per_subnet: default_max_outbound_peers_per_subnet(),
         }
     }
 }
@@ -321,6 +363,10 @@ fn default_trie_viewer_state_size_limit() -> Option<u64> {
     Some(50_000)
 }
 
+fn default_store_validation_budget() -> Duration {
+    Duration::from_millis(50)
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Consensus {
     /// Minimum number of peers to start syncing.
@@ -424,6 +470,8 @@ pub struct Config {
     pub log_summary_style: LogSummaryStyle,
     #[serde(default = "default_gc_blocks_limit")]
     pub gc_blocks_limit: NumBlocks,
+    /// How many epochs of each kind of data garbage collection keeps.
+    pub gc_policy: GCPolicy,
     #[serde(default = "default_view_client_threads")]
     pub view_client_threads: usize,
     pub epoch_sync_enabled: bool,
@@ -434,6 +482,18 @@ pub struct Config {
     /// If set, overrides value in genesis configuration.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_gas_burnt_view: Option<Gas>,
+    /// Database backend configuration.
+    pub store: StoreConfig,
+    /// If set, the node validates recently written heights of the store with this period.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store_validation_period: Option<Duration>,
+    /// Maximum time a single background store validation may take.
+    #[serde(default = "default_store_validation_budget")]
+    pub store_validation_budget: Duration,
+    /// If set, state sync loads the state headers and parts from this directory, as written by
+    /// `neard export_state_parts`, before falling back to peers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_sync_dir: Option<PathBuf>,
 }
 
 impl Default for Config {
@@ -455,11 +515,16 @@ impl Default for Config {
             archive: false,
             log_summary_style: LogSummaryStyle::Colored,
             gc_blocks_limit: default_gc_blocks_limit(),
+            gc_policy: GCPolicy::default(),
             epoch_sync_enabled: true,
             view_client_threads: default_view_client_threads(),
             view_client_throttle_period: default_view_client_throttle_period(),
             trie_viewer_state_size_limit: default_trie_viewer_state_size_limit(),
             max_gas_burnt_view: None,
+            store: StoreConfig::default(),
+            store_validation_period: None,
+            store_validation_budget: default_store_validation_budget(),
+            state_sync_dir: None,
         }
     }
 }
@@ -590,6 +655,7 @@ pub struct NearConfig {
     #[cfg(feature = "rosetta_rpc")]
     pub rosetta_rpc_config: Option<RosettaRpcConfig>,
     pub telemetry_config: TelemetryConfig,
+    pub store_config: StoreConfig,
     pub genesis: Genesis,
     pub validator_signer: Option<Arc<dyn ValidatorSigner>>,
 }
@@ -623,6 +689,7 @@ impl NearConfig {
                     .consensus
                     .header_sync_expected_height_per_second,
                 state_sync_timeout: config.consensus.state_sync_timeout,
+                state_sync_dir: config.state_sync_dir,
                 min_num_peers: config.consensus.min_num_peers,
                 log_summary_period: Duration::from_secs(10),
                 produce_empty_blocks: config.consensus.produce_empty_blocks,
@@ -642,11 +709,14 @@ impl NearConfig {
                 archive: config.archive,
                 log_summary_style: config.log_summary_style,
                 gc_blocks_limit: config.gc_blocks_limit,
+                gc_policy: config.gc_policy,
                 view_client_threads: config.view_client_threads,
                 epoch_sync_enabled: config.epoch_sync_enabled,
                 view_client_throttle_period: config.view_client_throttle_period,
                 trie_viewer_state_size_limit: config.trie_viewer_state_size_limit,
                 max_gas_burnt_view: config.max_gas_burnt_view,
+                store_validation_period: config.store_validation_period,
+                store_validation_budget: config.store_validation_budget,
             },
             network_config: NetworkConfig {
                 public_key: network_key_pair.public_key,
@@ -691,8 +761,15 @@ impl NearConfig {
                 blacklist: blacklist_from_iter(config.network.blacklist),
                 outbound_disabled: false,
                 archive: config.archive,
+                state_part_compression: config.network.state_part_compression,
+                state_part_chunk_size: config.network.state_part_chunk_size as usize,
+                require_secure_transport: config.network.require_secure_transport,
+                capture: config.network.capture,
+                rate_limits: config.network.rate_limits,
+                max_outbound_peers_per_subnet: config.network.max_outbound_peers_per_subnet,
             },
             telemetry_config: config.telemetry,
+            store_config: config.store,
             #[cfg(feature = "json_rpc")]
             rpc_config: config.rpc,
             #[cfg(feature = "rosetta_rpc")]
@@ -7,15 +7,22 @@ use actix_rt::ArbiterHandle;
 use tracing::{error, info, trace};
 
 use near_chain::ChainGenesis;
+#[cfg(feature = "json_rpc")]
+use near_client::start_store_checkpoint_actor;
 #[cfg(feature = "adversarial")]
 use near_client::AdversarialControls;
 use near_client::{start_client, start_view_client, ClientActor, ViewClientActor};
 use near_network::{NetworkRecipient, PeerManagerActor};
+use near_primitives::version::DbVersion;
 #[cfg(feature = "rosetta_rpc")]
 use near_rosetta_rpc::start_rosetta_rpc;
 #[cfg(feature = "performance_stats")]
 use near_rust_allocator_proxy::allocator::reset_memory_usage_max;
-use near_store::{create_store, Store};
+use near_store::db::RocksDB;
+use near_store::migrations::get_migration_checkpoint;
+use near_store::{
+    create_store, create_store_with_config, get_cold_store_path, DBBackend, DBCol, Store,
+};
 use near_telemetry::TelemetryActor;
 
 pub use crate::config::{init_configs, load_config, load_test_config, NearConfig, NEAR_BASE};
@@ -24,7 +31,8 @@ pub use crate::runtime::NightshadeRuntime;
 use near_store::migrations::{
     fill_col_outcomes_by_hash, fill_col_transaction_refcount, get_store_version, migrate_10_to_11,
     migrate_11_to_12, migrate_13_to_14, migrate_14_to_15, migrate_17_to_18, migrate_21_to_22,
-    migrate_6_to_7, migrate_7_to_8, migrate_8_to_9, migrate_9_to_10, set_store_version,
+    migrate_6_to_7, migrate_7_to_8, migrate_8_to_9, migrate_9_to_10, read_store_version,
+    set_store_version, NIGHTLY_DB_VERSION,
 };
 
 #[cfg(feature = "protocol_feature_block_header_v3")]
@@ -68,6 +76,231 @@ pub fn get_default_home() -> String {
     }
 }
 
+/// How a migration updates the database.
+enum StoreMigrationKind {
+    /// Only bumps the version of the database, e.g. when new columns are added. Missing columns
+    /// are created when the database is opened, but the version still needs to be bumped because
+    /// older binaries can't open the database with new columns.
+    BumpVersion,
+    /// Updates the opened store, after which the version is bumped.
+    Store(fn(&Store)),
+    /// Opens the database at the path itself and bumps the version.
+    Path(fn(&String)),
+    /// Same as `Path`, but also depends on the config of the node.
+    Config(fn(&String, &NearConfig)),
+}
+
+/// Migration of the database from version `version - 1` to `version`.
+struct StoreMigration {
+    version: DbVersion,
+    description: &'static str,
+    /// Columns the migration rewrites.
+    cols: &'static [DBCol],
+    kind: StoreMigrationKind,
+}
+
+fn migrate_9_to_10_with_config(path: &String, near_config: &NearConfig) {
+    migrate_9_to_10(path, near_config.client_config.archive);
+}
+
+/// Migrations applied by `apply_store_migrations`, in order. Add new migrations here.
+const STORE_MIGRATIONS: &[StoreMigration] = &[
+    StoreMigration {
+        version: 2,
+        description: "add gc column",
+        cols: &[],
+        kind: StoreMigrationKind::BumpVersion,
+    },
+    // Also renames LastComponentNonce to ColLastComponentNonce. The column number is the same,
+    // so it doesn't need additional updates.
+    StoreMigration {
+        version: 3,
+        description: "add ColOutcomesByBlockHash",
+        cols: &[DBCol::ColTransactionResult],
+        kind: StoreMigrationKind::Store(fill_col_outcomes_by_hash),
+    },
+    StoreMigration {
+        version: 4,
+        description: "add ColTransactionRefCount",
+        cols: &[DBCol::ColTransactions],
+        kind: StoreMigrationKind::Store(fill_col_transaction_refcount),
+    },
+    // Old heights are not backfilled since at worst some heights are processed again.
+    StoreMigration {
+        version: 5,
+        description: "add ColProcessedBlockHeights",
+        cols: &[],
+        kind: StoreMigrationKind::BumpVersion,
+    },
+    // There were no merge records before, so the old storage works.
+    StoreMigration {
+        version: 6,
+        description: "add merge operator to ColState",
+        cols: &[],
+        kind: StoreMigrationKind::BumpVersion,
+    },
+    StoreMigration {
+        version: 7,
+        description: "use 8 bytes refcount in ColState, make ColTransactions and ColReceiptIdToShardId refcounted",
+        cols: &[DBCol::ColState, DBCol::ColTransactions, DBCol::ColReceiptIdToShardId],
+        kind: StoreMigrationKind::Path(migrate_6_to_7),
+    },
+    StoreMigration {
+        version: 8,
+        description: "delete values in ColStateParts",
+        cols: &[DBCol::ColStateParts],
+        kind: StoreMigrationKind::Path(migrate_7_to_8),
+    },
+    StoreMigration {
+        version: 9,
+        description: "repair ColTransactions and ColReceiptIdToShardId",
+        cols: &[DBCol::ColTransactions, DBCol::ColReceiptIdToShardId],
+        kind: StoreMigrationKind::Path(migrate_8_to_9),
+    },
+    StoreMigration {
+        version: 10,
+        description: "populate partial encoded chunks",
+        cols: &[DBCol::ColChunks],
+        kind: StoreMigrationKind::Config(migrate_9_to_10_with_config),
+    },
+    StoreMigration {
+        version: 11,
+        description: "add final head",
+        cols: &[],
+        kind: StoreMigrationKind::Path(migrate_10_to_11),
+    },
+    StoreMigration {
+        version: 12,
+        description: "populate ColReceipts",
+        cols: &[DBCol::ColChunks],
+        kind: StoreMigrationKind::Path(migrate_11_to_12),
+    },
+    StoreMigration {
+        version: 13,
+        description: "fix inconsistencies in ColTransactionResult",
+        cols: &[DBCol::ColTransactionResult],
+        kind: StoreMigrationKind::Config(migrate_12_to_13),
+    },
+    StoreMigration {
+        version: 14,
+        description: "store versioned enums for shard chunks",
+        cols: &[
+            DBCol::ColPartialChunks,
+            DBCol::ColInvalidChunks,
+            DBCol::ColChunks,
+            DBCol::ColStateHeaders,
+        ],
+        kind: StoreMigrationKind::Path(migrate_13_to_14),
+    },
+    StoreMigration {
+        version: 15,
+        description: "order execution outcome ids within each shard",
+        cols: &[DBCol::ColOutcomeIds],
+        kind: StoreMigrationKind::Path(migrate_14_to_15),
+    },
+    StoreMigration {
+        version: 16,
+        description: "add column for compiled contracts",
+        cols: &[],
+        kind: StoreMigrationKind::BumpVersion,
+    },
+    StoreMigration {
+        version: 17,
+        description: "add column for epoch validator info",
+        cols: &[],
+        kind: StoreMigrationKind::BumpVersion,
+    },
+    StoreMigration {
+        version: 18,
+        description: "add hash to BlockInfo and ColHeaderHashesByHeight",
+        cols: &[DBCol::ColBlockInfo],
+        kind: StoreMigrationKind::Path(migrate_17_to_18),
+    },
+    StoreMigration {
+        version: 19,
+        description: "populate ColEpochValidatorInfo for archival nodes",
+        cols: &[DBCol::ColEpochInfo],
+        kind: StoreMigrationKind::Config(migrate_18_to_19),
+    },
+    StoreMigration {
+        version: 20,
+        description: "fix execution outcomes on mainnet archival nodes",
+        cols: &[],
+        kind: StoreMigrationKind::Config(migrate_19_to_20),
+    },
+    // Genesis::json_hash function has changed.
+    StoreMigration {
+        version: 21,
+        description: "delete genesis json hash",
+        cols: &[],
+        kind: StoreMigrationKind::Path(migrate_20_to_21),
+    },
+    // Rectifies inflation.
+    StoreMigration {
+        version: 22,
+        description: "add timestamp to BlockInfo",
+        cols: &[DBCol::ColBlockInfo],
+        kind: StoreMigrationKind::Path(migrate_21_to_22),
+    },
+    StoreMigration {
+        version: 23,
+        description: "fix receipts of a mainnet block on archival nodes",
+        cols: &[],
+        kind: StoreMigrationKind::Config(migrate_22_to_23),
+    },
+    StoreMigration {
+        version: 24,
+        description: "add columns for flat state",
+        cols: &[],
+        kind: StoreMigrationKind::BumpVersion,
+    },
+];
+
+/// Migrations applied to the cold database of a split store by `apply_cold_store_migrations`, in
+/// order. The cold database only has the columns which keep the history (see `DBCol::is_cold`)
+/// and is created at the current version, so the migrations of the hot database don't apply to
+/// it. Add new migrations of cold columns here.
+const COLD_STORE_MIGRATIONS: &[StoreMigration] = &[];
+
+/// Logs the migrations `apply_store_migrations` would apply to the database at `path` and the
+/// estimated number of keys they go through, without changing the database.
+pub fn estimate_store_migrations(path: &String) {
+    let db_version = get_store_version(path);
+    if db_version >= near_primitives::version::DB_VERSION {
+        info!(target: "near", "DB version {} is up to date, there are no migrations to apply", db_version);
+        return;
+    }
+    let num_keys = RocksDB::estimate_num_keys_read_only(path).unwrap_or_else(|err| {
+        panic!("Failed to read column statistics of the database: {:?}", err)
+    });
+    let checkpoint = get_migration_checkpoint(path);
+    let mut total_keys = 0;
+    for StoreMigration { version, description, cols, .. } in
+        STORE_MIGRATIONS.iter().filter(|migration| migration.version > db_version)
+    {
+        let cols_keys: Vec<_> = cols
+            .iter()
+            .map(|col| {
+                let keys = num_keys.iter().find(|(c, _)| c == col).map_or(0, |(_, keys)| *keys);
+                (col, keys)
+            })
+            .collect();
+        total_keys += cols_keys.iter().map(|(_, keys)| keys).sum::<u64>();
+        let cols_keys = cols_keys
+            .iter()
+            .map(|(col, keys)| format!("{:?} ~{} keys", col, keys))
+            .collect::<Vec<_>>()
+            .join(", ");
+        info!(target: "near", "Migration to version {}: {}. {}", version, description, if cols_keys.is_empty() { "No data to rewrite".to_string() } else { cols_keys });
+        if let Some(checkpoint) =
+            checkpoint.as_ref().filter(|checkpoint| checkpoint.db_version == *version)
+        {
+            info!(target: "near", "Migration to version {} was interrupted at step {} after {} items and will be resumed", version, checkpoint.step, checkpoint.processed);
+        }
+    }
+    info!(target: "near", "Migrations from version {} to {} go through ~{} keys", db_version, near_primitives::version::DB_VERSION, total_keys);
+}
+
 /// Function checks current version of the database and applies migrations to the database.
 pub fn apply_store_migrations(path: &String, near_config: &NearConfig) {
     let db_version = get_store_version(path);
@@ -79,142 +312,11 @@ pub fn apply_store_migrations(path: &String, near_config: &NearConfig) {
         return;
     }
 
-    // Add migrations here based on `db_version`.
-    if db_version <= 1 {
-        // version 1 => 2: add gc column
-        // Does not need to do anything since open db with option `create_missing_column_families`
-        // Nevertheless need to bump db version, because db_version 1 binary can't open db_version 2 db
-        info!(target: "near", "Migrate DB from version 1 to 2");
-        let store = create_store(&path);
-        set_store_version(&store, 2);
-    }
-    if db_version <= 2 {
-        // version 2 => 3: add ColOutcomesByBlockHash + rename LastComponentNonce -> ColLastComponentNonce
-        // The column number is the same, so we don't need additional updates
-        info!(target: "near", "Migrate DB from version 2 to 3");
-        let store = create_store(&path);
-        fill_col_outcomes_by_hash(&store);
-        set_store_version(&store, 3);
-    }
-    if db_version <= 3 {
-        // version 3 => 4: add ColTransactionRefCount
-        info!(target: "near", "Migrate DB from version 3 to 4");
-        let store = create_store(&path);
-        fill_col_transaction_refcount(&store);
-        set_store_version(&store, 4);
-    }
-    if db_version <= 4 {
-        info!(target: "near", "Migrate DB from version 4 to 5");
-        // version 4 => 5: add ColProcessedBlockHeights
-        // we don't need to backfill the old heights since at worst we will just process some heights
-        // again.
-        let store = create_store(&path);
-        set_store_version(&store, 5);
-    }
-    if db_version <= 5 {
-        info!(target: "near", "Migrate DB from version 5 to 6");
-        // version 5 => 6: add merge operator to ColState
-        // we don't have merge records before so old storage works
-        let store = create_store(&path);
-        set_store_version(&store, 6);
-    }
-    if db_version <= 6 {
-        info!(target: "near", "Migrate DB from version 6 to 7");
-        // version 6 => 7:
-        // - make ColState use 8 bytes for refcount (change to merge operator)
-        // - move ColTransactionRefCount into ColTransactions
-        // - make ColReceiptIdToShardId refcounted
-        migrate_6_to_7(path);
-    }
-    if db_version <= 7 {
-        info!(target: "near", "Migrate DB from version 7 to 8");
-        // version 7 => 8:
-        // delete values in column `StateColParts`
-        migrate_7_to_8(path);
-    }
-    if db_version <= 8 {
-        info!(target: "near", "Migrate DB from version 8 to 9");
-        // version 8 => 9:
-        // Repair `ColTransactions`, `ColReceiptIdToShardId`
-        migrate_8_to_9(path);
-    }
-    if db_version <= 9 {
-        info!(target: "near", "Migrate DB from version 9 to 10");
-        // version 9 => 10;
-        // populate partial encoded chunks for chunks that exist in storage
-        migrate_9_to_10(path, near_config.client_config.archive);
-    }
-    if db_version <= 10 {
-        info!(target: "near", "Migrate DB from version 10 to 11");
-        // version 10 => 11
-        // Add final head
-        migrate_10_to_11(path);
-    }
-    if db_version <= 11 {
-        info!(target: "near", "Migrate DB from version 11 to 12");
-        // version 11 => 12;
-        // populate ColReceipts with existing receipts
-        migrate_11_to_12(path);
-    }
-    if db_version <= 12 {
-        info!(target: "near", "Migrate DB from version 12 to 13");
-        // version 12 => 13;
-        // migrate ColTransactionResult to fix the inconsistencies there
-        migrate_12_to_13(path, near_config);
-    }
-    if db_version <= 13 {
-        info!(target: "near", "Migrate DB from version 13 to 14");
-        // version 13 => 14;
-        // store versioned enums for shard chunks
-        migrate_13_to_14(path);
-    }
-    if db_version <= 14 {
-        info!(target: "near", "Migrate DB from version 14 to 15");
-        // version 14 => 15;
-        // Change ColOutcomesByBlockHash to be ordered within each shard
-        migrate_14_to_15(path);
-    }
-    if db_version <= 15 {
-        info!(target: "near", "Migrate DB from version 15 to 16");
-        // version 15 => 16: add column for compiled contracts
-        let store = create_store(&path);
-        set_store_version(&store, 16);
-    }
-    if db_version <= 16 {
-        info!(target: "near", "Migrate DB from version 16 to 17");
-        // version 16 => 17: add column for storing epoch validator info
-        let store = create_store(&path);
-        set_store_version(&store, 17);
-    }
-    if db_version <= 17 {
-        info!(target: "near", "Migrate DB from version 17 to 18");
-        // version 17 => 18: add `hash` to `BlockInfo` and ColHeaderHashesByHeight
-        migrate_17_to_18(&path);
-    }
-    if db_version <= 18 {
-        info!(target: "near", "Migrate DB from version 18 to 19");
-        // version 18 => 19: populate ColEpochValidatorInfo for archival nodes
-        migrate_18_to_19(&path, near_config);
-    }
-    if db_version <= 19 {
-        info!(target: "near", "Migrate DB from version 19 to 20");
-        // version 19 => 20: fix execution outcome
-        migrate_19_to_20(&path, &near_config);
-    }
-    if db_version <= 20 {
-        info!(target: "near", "Migrate DB from version 20 to 21");
-        // version 20 => 21: delete genesis json hash due to change in Genesis::json_hash function
-        migrate_20_to_21(&path);
-    }
-    if db_version <= 21 {
-        info!(target: "near", "Migrate DB from version 21 to 22");
-        // version 21 => 22: rectify inflation: add `timestamp` to `BlockInfo`
-        migrate_21_to_22(&path);
-    }
-    if db_version <= 22 {
-        info!(target: "near", "Migrate DB from version 22 to 23");
-        migrate_22_to_23(&path, &near_config);
+    for migration in STORE_MIGRATIONS.iter().filter(|migration| migration.version > db_version) {
+        info!(target: "near", "Migrate DB from version {} to {}: {}", migration.version - 1, migration.version, migration.description);
+        apply_store_migration(path, migration, near_config);
     }
+
     #[cfg(feature = "nightly_protocol")]
     {
         let store = create_store(&path);
@@ -225,7 +327,7 @@ pub fn apply_store_migrations(path: &String, near_config: &NearConfig) {
         }
 
         // set some dummy value to avoid conflict with other migrations from nightly features
-        set_store_version(&store, 10000);
+        set_store_version(&store, NIGHTLY_DB_VERSION);
     }
 
     #[cfg(not(feature = "nightly_protocol"))]
@@ -235,15 +337,68 @@ pub fn apply_store_migrations(path: &String, near_config: &NearConfig) {
     }
 }
 
+/// Applies the migrations of the cold database of a split store at `path`.
+pub fn apply_cold_store_migrations(path: &String, near_config: &NearConfig) {
+    let db_version = get_store_version(path);
+    if db_version > near_primitives::version::DB_VERSION {
+        error!(target: "near", "Cold DB version {} is created by a newer version of neard, please update neard or delete data", db_version);
+        std::process::exit(1);
+    }
+    if db_version == near_primitives::version::DB_VERSION {
+        return;
+    }
+
+    for migration in COLD_STORE_MIGRATIONS.iter().filter(|migration| migration.version > db_version)
+    {
+        info!(target: "near", "Migrate cold DB from version {} to {}: {}", migration.version - 1, migration.version, migration.description);
+        apply_store_migration(path, migration, near_config);
+    }
+    // The versions which don't change the cold columns only bump the version.
+    set_store_version(&create_store(&path), near_primitives::version::DB_VERSION);
+}
+
+fn apply_store_migration(path: &String, migration: &StoreMigration, near_config: &NearConfig) {
+    match migration.kind {
+        StoreMigrationKind::BumpVersion => {
+            let store = create_store(&path);
+            set_store_version(&store, migration.version);
+        }
+        StoreMigrationKind::Store(migrate) => {
+            let store = create_store(&path);
+            migrate(&store);
+            set_store_version(&store, migration.version);
+        }
+        StoreMigrationKind::Path(migrate) => migrate(path),
+        StoreMigrationKind::Config(migrate) => migrate(path, near_config),
+    }
+}
+
 pub fn init_and_migrate_store(home_dir: &Path, near_config: &NearConfig) -> Arc<Store> {
     let path = get_store_path(home_dir);
     let store_exists = store_path_exists(&path);
-    if store_exists {
+    if store_exists && near_config.store_config.backend == DBBackend::RocksDB {
         apply_store_migrations(&path, near_config);
     }
-    let store = create_store(&path);
+    // Migrations open the hot database on its own, so the cold database of a split store has
+    // its own version and is migrated separately.
+    if let Some(cold_path) = get_cold_store_path(&path, &near_config.store_config) {
+        if store_path_exists(&cold_path) {
+            apply_cold_store_migrations(&cold_path, near_config);
+        } else {
+            set_store_version(&create_store(&cold_path), near_primitives::version::DB_VERSION);
+        }
+    }
+    let store = create_store_with_config(&path, &near_config.store_config);
     if !store_exists {
         set_store_version(&store, near_primitives::version::DB_VERSION);
+    } else if near_config.store_config.backend != DBBackend::RocksDB {
+        // Migrations are only implemented for RocksDB, other backends have to be created from
+        // scratch by the same version of neard.
+        let db_version = read_store_version(&store);
+        if db_version != Some(near_primitives::version::DB_VERSION) {
+            error!(target: "near", "DB version {:?} doesn't match {} and migrations are only supported for RocksDB backend, please delete data", db_version, near_primitives::version::DB_VERSION);
+            std::process::exit(1);
+        }
     }
     store
 }
@@ -254,15 +409,18 @@ pub fn start_with_config(
 ) -> (Addr<ClientActor>, Addr<ViewClientActor>, Vec<ArbiterHandle>) {
     let store = init_and_migrate_store(home_dir, &config);
 
-    let runtime = Arc::new(NightshadeRuntime::new(
-        home_dir,
-        Arc::clone(&store),
-        &config.genesis,
-        config.client_config.tracked_accounts.clone(),
-        config.client_config.tracked_shards.clone(),
-        config.client_config.trie_viewer_state_size_limit,
-        config.client_config.max_gas_burnt_view,
-    ));
+    let runtime = Arc::new(
+        NightshadeRuntime::new(
+            home_dir,
+            Arc::clone(&store),
+            &config.genesis,
+            config.client_config.tracked_accounts.clone(),
+            config.client_config.tracked_shards.clone(),
+            config.client_config.trie_viewer_state_size_limit,
+            config.client_config.max_gas_burnt_view,
+        )
+        .with_store_config(&config.store_config),
+    );
 
     let telemetry = TelemetryActor::new(config.telemetry_config.clone()).start();
     let chain_genesis = ChainGenesis::from(&config.genesis);
@@ -292,15 +450,6 @@ pub fn start_with_config(
         #[cfg(feature = "adversarial")]
         adv.clone(),
     );
-    #[cfg(feature = "json_rpc")]
-    if let Some(rpc_config) = config.rpc_config {
-        near_jsonrpc::start_http(
-            rpc_config,
-            config.genesis.config.clone(),
-            client_actor.clone(),
-            view_client.clone(),
-        );
-    }
     #[cfg(feature = "rosetta_rpc")]
     if let Some(rosetta_rpc_config) = config.rosetta_rpc_config {
         start_rosetta_rpc(
@@ -318,12 +467,26 @@ pub fn start_with_config(
     let client_actor1 = client_actor.clone().recipient();
     let view_client1 = view_client.clone().recipient();
     let network_config = config.network_config;
+    #[cfg(feature = "json_rpc")]
+    let store1 = store.clone();
 
     let network_actor = PeerManagerActor::start_in_arbiter(&arbiter.handle(), move |_ctx| {
         PeerManagerActor::new(store, network_config, client_actor1, view_client1).unwrap()
     });
 
-    network_adapter.set_recipient(network_actor.recipient());
+    network_adapter.set_recipient(network_actor.clone().recipient());
+
+    #[cfg(feature = "json_rpc")]
+    if let Some(rpc_config) = config.rpc_config {
+        near_jsonrpc::start_http(
+            rpc_config,
+            config.genesis.config.clone(),
+            client_actor.clone(),
+            view_client.clone(),
+            Some(network_actor.recipient()),
+            Some(start_store_checkpoint_actor(store1).recipient()),
+        );
+    }
 
     trace!(target: "diagnostic", key="log", "Starting NEAR node with diagnostic activated");
 
@@ -13,7 +13,10 @@ use near_primitives::sharding::{ChunkHash, ShardChunkHeader, ShardChunkV1};
 use near_primitives::transaction::ExecutionOutcomeWithIdAndProof;
 use near_primitives::types::Gas;
 use near_primitives::types::{BlockHeight, ShardId};
-use near_store::migrations::set_store_version;
+use near_store::migrations::{
+    set_migration_checkpoint, set_store_version, MigrationCheckpoint, MigrationProgress,
+    MigrationStepStart,
+};
 use near_store::{create_store, DBCol, StoreUpdate};
 use std::path::Path;
 
@@ -225,8 +228,19 @@ pub fn migrate_19_to_20(path: &String, near_config: &NearConfig) {
         );
         let shard_id = 0;
         // This is hardcoded for mainnet specifically. Blocks with lower heights have been checked.
-        let start_height = 34691244;
+        let (start_height, processed) = match MigrationStepStart::new(&store, 20, 0) {
+            MigrationStepStart::After { last_key: Some(last_key), processed } => {
+                (BlockHeight::try_from_slice(&last_key).unwrap() + 1, processed)
+            }
+            _ => (34691244, 0),
+        };
+        let mut progress = MigrationProgress::new(
+            "execution outcomes".to_string(),
+            Some(head.height.saturating_sub(start_height) + 1 + processed),
+            processed,
+        );
         for block_height in start_height..=head.height {
+            progress.inc();
             if let Ok(block_hash) = chain_store.get_block_hash_by_height(block_height) {
                 let block = chain_store.get_block(&block_hash).unwrap().clone();
                 if block.chunks()[shard_id as usize].height_included() != block.header().height() {
@@ -266,6 +280,17 @@ pub fn migrate_19_to_20(path: &String, near_config: &NearConfig) {
                             apply_result.outcomes,
                             outcome_paths,
                         );
+                        let mut store_update = store.store_update();
+                        set_migration_checkpoint(
+                            &mut store_update,
+                            &MigrationCheckpoint {
+                                db_version: 20,
+                                step: 0,
+                                last_key: block_height.to_le_bytes().to_vec(),
+                                processed: progress.processed(),
+                            },
+                        );
+                        chain_store_update.merge(store_update);
                         chain_store_update.commit().unwrap();
                     }
                 }
@@ -8,7 +8,6 @@ use borsh::ser::BorshSerialize;
 use borsh::BorshDeserialize;
 use tracing::{debug, error, info, warn};
 
-use near_chain::chain::NUM_EPOCHS_TO_KEEP_STORE_DATA;
 use near_chain::types::{ApplyTransactionResult, BlockHeaderInfo, ValidatorInfoIdentifier};
 use near_chain::{BlockHeader, Error, ErrorKind, RuntimeAdapter};
 #[cfg(feature = "protocol_feature_block_header_v3")]
@@ -29,6 +28,7 @@ use near_primitives::epoch_manager::EpochConfig;
 use near_primitives::errors::{EpochError, InvalidTxError, RuntimeError};
 use near_primitives::hash::{hash, CryptoHash};
 use near_primitives::receipt::Receipt;
+use near_primitives::serialize::to_base64;
 use near_primitives::sharding::ChunkHash;
 use near_primitives::state_record::{state_record_to_account_id, StateRecord};
 use near_primitives::transaction::SignedTransaction;
@@ -41,14 +41,14 @@ use near_primitives::types::{
 use near_primitives::version::ProtocolVersion;
 use near_primitives::views::{
     AccessKeyInfoView, CallResult, EpochValidatorInfo, QueryRequest, QueryResponse,
-    QueryResponseKind, ViewApplyState, ViewStateResult,
+    QueryResponseKind, TrieProofPath, ViewApplyState, ViewStateResult,
 };
 use near_vm_runner::precompile_contract;
 
 use near_store::{
     get_genesis_hash, get_genesis_state_roots, set_genesis_hash, set_genesis_state_roots,
     ApplyStatePartResult, ColState, PartialStorage, ShardTries, Store, StoreCompiledContractCache,
-    StoreUpdate, Trie, WrappedTrieChanges,
+    StoreConfig, StoreUpdate, Trie, TrieUpdate, WrappedTrieChanges,
 };
 use node_runtime::adapter::ViewRuntimeAdapter;
 use node_runtime::state_viewer::TrieViewer;
@@ -201,6 +201,36 @@ impl NightshadeRuntime {
         }
     }
 
+    /// Applies the trie related parts of the store config: flat state and cache sizes.
+    pub fn with_store_config(mut self, config: &StoreConfig) -> Self {
+        self.tries =
+            self.tries.with_flat_state(config.flat_state).with_cache_config(&config.trie_cache);
+        self
+    }
+
+    /// Runs a view of the state at the given block. If `include_proof` is set, the view reads
+    /// the trie directly and also returns the raw trie nodes it has read as a proof of its
+    /// result, otherwise it goes through flat state (if enabled) and the proof is empty.
+    fn view_at_block<T>(
+        &self,
+        shard_id: ShardId,
+        state_root: StateRoot,
+        block_hash: &CryptoHash,
+        include_proof: bool,
+        view: impl FnOnce(&TrieUpdate) -> T,
+    ) -> (T, Vec<Vec<u8>>) {
+        if !include_proof {
+            let state_update =
+                self.tries.new_trie_update_view_at_block(shard_id, state_root, block_hash);
+            return (view(&state_update), vec![]);
+        }
+        let state_update = self.tries.new_trie_update_view_recording(shard_id, state_root);
+        let result = view(&state_update);
+        let proof =
+            state_update.trie.recorded_storage().map(|storage| storage.nodes.0).unwrap_or_default();
+        (result, proof)
+    }
+
     fn get_epoch_height_from_prev_block(
         &self,
         prev_block_hash: &CryptoHash,
@@ -503,6 +533,7 @@ impl NightshadeRuntime {
                 apply_result.trie_changes,
                 apply_result.state_changes,
                 block_hash.clone(),
+                *prev_block_hash,
             ),
             new_root: apply_result.state_root,
             outcomes: apply_result.outcomes,
@@ -1014,7 +1045,11 @@ impl RuntimeAdapter for NightshadeRuntime {
         epoch_manager.get_epoch_start_height(block_hash).map_err(Error::from)
     }
 
-    fn get_gc_stop_height(&self, block_hash: &CryptoHash) -> BlockHeight {
+    fn get_gc_stop_height_for_epochs(
+        &self,
+        block_hash: &CryptoHash,
+        num_epochs: u64,
+    ) -> BlockHeight {
         let genesis_height = self.genesis_config.genesis_height;
         macro_rules! unwrap_result_or_return {
             ($obj: expr) => {
@@ -1034,7 +1069,7 @@ impl RuntimeAdapter for NightshadeRuntime {
             // maintain pointers to avoid cloning.
             let mut last_block_in_prev_epoch = *epoch_first_block_info.prev_hash();
             let mut epoch_start_height = *epoch_first_block_info.height();
-            for _ in 0..NUM_EPOCHS_TO_KEEP_STORE_DATA - 1 {
+            for _ in 0..num_epochs.saturating_sub(1) {
                 let epoch_first_block =
                     *epoch_manager.get_block_info(&last_block_in_prev_epoch)?.epoch_first_block();
                 let epoch_first_block_info = epoch_manager.get_block_info(&epoch_first_block)?;
@@ -1266,26 +1301,53 @@ impl RuntimeAdapter for NightshadeRuntime {
         block_hash: &CryptoHash,
         epoch_id: &EpochId,
         request: &QueryRequest,
+        include_proof: bool,
     ) -> Result<QueryResponse, near_chain::near_chain_primitives::error::QueryError> {
         match request {
             QueryRequest::ViewAccount { account_id } => {
-                let account = self
-                    .view_account(shard_id, *state_root, account_id)
-                    .map_err(|err| near_chain::near_chain_primitives::error::QueryError::from_view_account_error(err, block_height, *block_hash))?;
+                let (account, proof) = self.view_at_block(
+                    shard_id,
+                    *state_root,
+                    block_hash,
+                    include_proof,
+                    |state_update| self.trie_viewer.view_account(state_update, account_id),
+                );
+                let account = account.map_err(|err| {
+                    near_chain::near_chain_primitives::error::QueryError::from_view_account_error(
+                        err,
+                        block_height,
+                        *block_hash,
+                    )
+                })?;
                 Ok(QueryResponse {
                     kind: QueryResponseKind::ViewAccount(account.into()),
                     block_height,
                     block_hash: *block_hash,
+                    proof: proof.iter().map(to_base64).collect(),
                 })
             }
             QueryRequest::ViewCode { account_id } => {
-                let contract_code = self
-                    .view_contract_code(shard_id, *state_root, account_id)
+                let (contract_code, proof) = self.view_at_block(
+                    shard_id,
+                    *state_root,
+                    block_hash,
+                    include_proof,
+                    |state_update| self.trie_viewer.view_contract_code(state_update, account_id),
+                );
+                let contract_code = contract_code
                     .map_err(|err| near_chain::near_chain_primitives::error::QueryError::from_view_contract_code_error(err, block_height, *block_hash))?;
+                // The code itself is already in the response and is checked against the code
+                // hash from the proof, so its value node is left out.
+                let proof = proof
+                    .iter()
+                    .filter(|node| hash(node) != contract_code.hash)
+                    .map(to_base64)
+                    .collect();
                 Ok(QueryResponse {
                     kind: QueryResponseKind::ViewCode(contract_code.into()),
                     block_height,
                     block_hash: *block_hash,
+                    proof,
                 })
             }
             QueryRequest::CallFunction { account_id, method_name, args } => {
@@ -1330,11 +1392,19 @@ impl RuntimeAdapter for NightshadeRuntime {
                     }),
                     block_height,
                     block_hash: *block_hash,
+                    proof: vec![],
                 })
             }
             QueryRequest::ViewState { account_id, prefix } => {
+                // The range proof is built by the viewer itself and carried in the result.
+                let state_update = if include_proof {
+                    self.tries.new_trie_update_view(shard_id, *state_root)
+                } else {
+                    self.tries.new_trie_update_view_at_block(shard_id, *state_root, block_hash)
+                };
                 let view_state_result = self
-                    .view_state(shard_id, *state_root, account_id, prefix.as_ref())
+                    .trie_viewer
+                    .view_state(&state_update, account_id, prefix.as_ref(), include_proof)
                     .map_err(|err| {
                         near_chain::near_chain_primitives::error::QueryError::from_view_state_error(
                             err,
@@ -1346,11 +1416,14 @@ impl RuntimeAdapter for NightshadeRuntime {
                     kind: QueryResponseKind::ViewState(view_state_result),
                     block_height,
                     block_hash: *block_hash,
+                    proof: vec![],
                 })
             }
             QueryRequest::ViewAccessKeyList { account_id } => {
+                let state_update =
+                    self.tries.new_trie_update_view_at_block(shard_id, *state_root, block_hash);
                 let access_key_list =
-                    self.view_access_keys(shard_id, *state_root, account_id).map_err(|err| {
+                    self.trie_viewer.view_access_keys(&state_update, account_id).map_err(|err| {
                         near_chain::near_chain_primitives::error::QueryError::from_view_access_key_error(
                             err,
                             block_height,
@@ -1369,11 +1442,20 @@ impl RuntimeAdapter for NightshadeRuntime {
                     ),
                     block_height,
                     block_hash: *block_hash,
+                    proof: vec![],
                 })
             }
             QueryRequest::ViewAccessKey { account_id, public_key } => {
-                let access_key = self
-                    .view_access_key(shard_id, *state_root, account_id, public_key)
+                let (access_key, proof) = self.view_at_block(
+                    shard_id,
+                    *state_root,
+                    block_hash,
+                    include_proof,
+                    |state_update| {
+                        self.trie_viewer.view_access_key(state_update, account_id, public_key)
+                    },
+                );
+                let access_key = access_key
                     .map_err(|err| {
                         near_chain::near_chain_primitives::error::QueryError::from_view_access_key_error(
                             err,
@@ -1385,6 +1467,7 @@ impl RuntimeAdapter for NightshadeRuntime {
                     kind: QueryResponseKind::AccessKey(access_key.into()),
                     block_height,
                     block_hash: *block_hash,
+                    proof: proof.iter().map(to_base64).collect(),
                 })
             }
         }
@@ -1446,7 +1529,7 @@ impl RuntimeAdapter for NightshadeRuntime {
         }
     }
 
-    fn apply_state_part(
+    fn prepare_state_part(
         &self,
         shard_id: ShardId,
         state_root: &StateRoot,
@@ -1454,7 +1537,7 @@ impl RuntimeAdapter for NightshadeRuntime {
         num_parts: u64,
         data: &[u8],
         epoch_id: &EpochId,
-    ) -> Result<(), Error> {
+    ) -> Result<StoreUpdate, Error> {
         let part = BorshDeserialize::try_from_slice(data)
             .expect("Part was already validated earlier, so could never fail here");
         let ApplyStatePartResult { trie_changes, contract_codes } =
@@ -1463,7 +1546,7 @@ impl RuntimeAdapter for NightshadeRuntime {
         let (store_update, _) =
             tries.apply_all(&trie_changes, shard_id).expect("TrieChanges::into never fails");
         self.precompile_contracts(epoch_id, contract_codes)?;
-        Ok(store_update.commit()?)
+        Ok(store_update)
     }
 
     fn get_state_root_node(
@@ -1615,7 +1698,8 @@ impl node_runtime::adapter::ViewRuntimeAdapter for NightshadeRuntime {
         current_protocol_version: ProtocolVersion,
         #[cfg(feature = "protocol_feature_evm")] evm_chain_id: u64,
     ) -> Result<Vec<u8>, node_runtime::state_viewer::errors::CallFunctionError> {
-        let state_update = self.get_tries().new_trie_update_view(shard_id, state_root);
+        let state_update =
+            self.get_tries().new_trie_update_view_at_block(shard_id, state_root, block_hash);
         let view_state = ViewApplyState {
             block_height: height,
             prev_block_hash: *prev_block_hash,
@@ -1669,7 +1753,7 @@ impl node_runtime::adapter::ViewRuntimeAdapter for NightshadeRuntime {
         prefix: &[u8],
     ) -> Result<ViewStateResult, node_runtime::state_viewer::errors::ViewStateError> {
         let state_update = self.get_tries().new_trie_update_view(shard_id, state_root);
-        self.trie_viewer.view_state(&state_update, account_id, prefix)
+        self.trie_viewer.view_state(&state_update, account_id, prefix, false)
     }
 }
 
@@ -428,6 +428,7 @@ fn test_query_rpc_account_view_unknown_block_must_return_error() {
                 request: near_primitives::views::QueryRequest::ViewAccount {
                     account_id: "near.0".to_string(),
                 },
+                include_proof: false,
             })
             .await;
 
@@ -677,6 +677,7 @@ fn test_query_rpc_account_view_must_succeed() {
                 request: near_primitives::views::QueryRequest::ViewAccount {
                     account_id: "near.0".to_string(),
                 },
+                include_proof: false,
             })
             .await
             .unwrap();
@@ -715,6 +716,7 @@ fn test_query_rpc_account_view_invalid_account_must_return_error() {
                 request: near_primitives::views::QueryRequest::ViewAccount {
                     account_id: "1nval$d*@cc0ount".to_string(),
                 },
+                include_proof: false,
             })
             .await;
 
@@ -749,6 +751,7 @@ fn test_query_rpc_account_view_account_doesnt_exist_must_return_error() {
                 request: near_primitives::views::QueryRequest::ViewAccount {
                     account_id: "accountdoesntexist.0".to_string(),
                 },
+                include_proof: false,
             })
             .await;
 
@@ -1,9 +1,15 @@
 use super::{DEFAULT_HOME, NEARD_VERSION, NEARD_VERSION_STRING, PROTOCOL_VERSION};
 use clap::{AppSettings, Clap};
-use near_primitives::types::{Gas, NumSeats, NumShards};
+use near_chain::state_sync_dir::export_state;
+use near_chain::{Chain, ChainGenesis, DoomslugThresholdMode, RuntimeAdapter};
+use near_jsonrpc_primitives::types::store::RpcStoreCheckpointRequest;
+use near_primitives::hash::CryptoHash;
+use near_primitives::types::{Gas, NumSeats, NumShards, ShardId};
+use near_store::create_store_with_config;
 use nearcore::get_store_path;
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::{env, fs, io};
 #[cfg(feature = "adversarial")]
 use tracing::error;
@@ -45,6 +51,10 @@ impl NeardCmd {
             NeardSubCommand::Init(cmd) => cmd.run(&home_dir),
             NeardSubCommand::Testnet(cmd) => cmd.run(&home_dir),
             NeardSubCommand::Run(cmd) => cmd.run(&home_dir),
+            NeardSubCommand::StoreCheckpoint(cmd) => cmd.run(&home_dir),
+            NeardSubCommand::StoreStats(cmd) => cmd.run(&home_dir),
+            NeardSubCommand::MigrateStore(cmd) => cmd.run(&home_dir),
+            NeardSubCommand::ExportStateParts(cmd) => cmd.run(&home_dir),
 
             NeardSubCommand::UnsafeResetData => {
                 let store_path = get_store_path(&home_dir);
@@ -87,6 +97,21 @@ pub(super) enum NeardSubCommand {
     /// and config)
     #[clap(name = "testnet")]
     Testnet(TestnetCmd),
+    /// Creates a consistent copy of the node's database, possibly while the node is running
+    #[clap(name = "store_checkpoint")]
+    StoreCheckpoint(StoreCheckpointCmd),
+    /// Reports per column size estimates of the node's database and runs manual compaction
+    #[clap(name = "store_stats")]
+    StoreStats(StoreStatsCmd),
+    /// Applies pending database migrations without starting the node. Interrupted migrations
+    /// continue where they stopped
+    #[clap(name = "migrate_store")]
+    MigrateStore(MigrateStoreCmd),
+    /// Writes the state headers and parts of an epoch to a directory, which other nodes can load
+    /// with `state_sync_dir` instead of downloading the state from peers. Requires the node to be
+    /// stopped
+    #[clap(name = "export_state_parts")]
+    ExportStateParts(ExportStatePartsCmd),
     /// (unsafe) Remove all the config, keys, data and effectively removing all information about
     /// the network
     #[clap(name = "unsafe_reset_all")]
@@ -284,6 +309,200 @@ impl TestnetCmd {
     }
 }
 
+#[derive(Clap)]
+pub(super) struct StoreCheckpointCmd {
+    /// Directory to create the checkpoint in. Must not exist and should be on the same
+    /// filesystem as the data directory, so that files can be hard-linked instead of copied.
+    #[clap(parse(from_os_str))]
+    destination: PathBuf,
+    /// Admin RPC address of a running node (e.g. "http://127.0.0.1:3031"). If not given, the
+    /// database in the home directory is opened directly, which requires the node to be stopped.
+    #[clap(long)]
+    admin_rpc_addr: Option<String>,
+}
+
+impl StoreCheckpointCmd {
+    pub(super) fn run(self, home_dir: &Path) {
+        let db_version = match self.admin_rpc_addr {
+            Some(admin_rpc_addr) => {
+                let request = RpcStoreCheckpointRequest { path: self.destination.clone() };
+                let sys = actix::System::new();
+                let response = sys
+                    .block_on(async move {
+                        near_jsonrpc_client::new_client(&admin_rpc_addr)
+                            .create_store_checkpoint(request)
+                            .await
+                    })
+                    .unwrap_or_else(|err| panic!("Failed to create checkpoint: {:?}", err));
+                response.checkpoint.db_version
+            }
+            None => {
+                let near_config = nearcore::config::load_config_without_genesis_records(home_dir);
+                let store =
+                    create_store_with_config(&get_store_path(home_dir), &near_config.store_config);
+                store.create_checkpoint(&self.destination).expect("Failed to create checkpoint")
+            }
+        };
+        info!(target: "neard", "Created checkpoint of database version {} at {}", db_version, self.destination.display());
+    }
+}
+
+#[derive(Clap)]
+pub(super) struct StoreStatsCmd {
+    /// Admin RPC address of a running node (e.g. "http://127.0.0.1:3031"). If not given, the
+    /// database in the home directory is opened directly, which requires the node to be stopped.
+    #[clap(long)]
+    admin_rpc_addr: Option<String>,
+    /// Comma separated columns to compact before reporting, e.g. "ColState,ColTrieChanges".
+    /// Compaction is only available with the node stopped.
+    #[clap(long, use_delimiter = true)]
+    compact: Vec<String>,
+}
+
+impl StoreStatsCmd {
+    pub(super) fn run(self, home_dir: &Path) {
+        let columns: Vec<(String, u64, u64, u64)> = match self.admin_rpc_addr {
+            Some(admin_rpc_addr) => {
+                assert!(self.compact.is_empty(), "--compact requires the node to be stopped");
+                let sys = actix::System::new();
+                let response = sys
+                    .block_on(async move {
+                        near_jsonrpc_client::new_client(&admin_rpc_addr).store_stats().await
+                    })
+                    .unwrap_or_else(|err| panic!("Failed to get store stats: {:?}", err));
+                response
+                    .stats
+                    .columns
+                    .into_iter()
+                    .map(|col| {
+                        (
+                            col.column,
+                            col.estimated_num_keys,
+                            col.estimated_live_data_size,
+                            col.sst_files_size,
+                        )
+                    })
+                    .collect()
+            }
+            None => {
+                let near_config = nearcore::config::load_config_without_genesis_records(home_dir);
+                let store =
+                    create_store_with_config(&get_store_path(home_dir), &near_config.store_config);
+                let stats = store.get_column_stats().expect("Failed to get store stats");
+                if !self.compact.is_empty() {
+                    let cols: Vec<_> = self
+                        .compact
+                        .iter()
+                        .map(|name| {
+                            stats
+                                .iter()
+                                .map(|stats| stats.column)
+                                .find(|col| &format!("{:?}", col) == name)
+                                .unwrap_or_else(|| panic!("Unknown column {}", name))
+                        })
+                        .collect();
+                    info!(target: "neard", "Compacting {}", self.compact.join(", "));
+                    store.compact_columns(&cols).expect("Failed to compact columns");
+                }
+                store
+                    .get_column_stats()
+                    .expect("Failed to get store stats")
+                    .into_iter()
+                    .map(|stats| {
+                        (
+                            format!("{:?}", stats.column),
+                            stats.estimated_num_keys,
+                            stats.estimated_live_data_size,
+                            stats.sst_files_size,
+                        )
+                    })
+                    .collect()
+            }
+        };
+        println!("{:<36} {:>14} {:>18} {:>18}", "column", "keys", "live data bytes", "sst bytes");
+        for (column, num_keys, live_data_size, sst_files_size) in columns {
+            println!(
+                "{:<36} {:>14} {:>18} {:>18}",
+                column, num_keys, live_data_size, sst_files_size
+            );
+        }
+    }
+}
+
+#[derive(Clap)]
+pub(super) struct MigrateStoreCmd {
+    /// Only report the pending migrations and the number of keys they go through, without
+    /// changing the database.
+    #[clap(long)]
+    dry_run: bool,
+}
+
+impl MigrateStoreCmd {
+    pub(super) fn run(self, home_dir: &Path) {
+        let path = get_store_path(home_dir);
+        if !nearcore::store_path_exists(&path) {
+            panic!("There is no database at {}", path);
+        }
+        if self.dry_run {
+            nearcore::estimate_store_migrations(&path);
+        } else {
+            let near_config = nearcore::config::load_config_without_genesis_records(home_dir);
+            nearcore::init_and_migrate_store(home_dir, &near_config);
+            info!(target: "neard", "Database at {} is migrated", path);
+        }
+    }
+}
+
+#[derive(Clap)]
+pub(super) struct ExportStatePartsCmd {
+    /// Directory to write the state to.
+    #[clap(parse(from_os_str))]
+    destination: PathBuf,
+    /// Hash of the first block of the epoch to export the state for.
+    #[clap(long)]
+    sync_hash: CryptoHash,
+    /// Comma separated shards to export (default all).
+    #[clap(long, use_delimiter = true)]
+    shard_ids: Vec<ShardId>,
+}
+
+impl ExportStatePartsCmd {
+    pub(super) fn run(self, home_dir: &Path) {
+        let near_config = nearcore::load_config(home_dir);
+        let store = create_store_with_config(&get_store_path(home_dir), &near_config.store_config);
+        let runtime_adapter: Arc<dyn RuntimeAdapter> = Arc::new(nearcore::NightshadeRuntime::new(
+            home_dir,
+            store,
+            &near_config.genesis,
+            near_config.client_config.tracked_accounts.clone(),
+            near_config.client_config.tracked_shards.clone(),
+            None,
+            None,
+        ));
+        let mut chain = Chain::new(
+            runtime_adapter,
+            &ChainGenesis::from(&near_config.genesis),
+            DoomslugThresholdMode::TwoThirds,
+        )
+        .expect("Failed to open the chain");
+        if !chain.check_sync_hash_validity(&self.sync_hash).expect("Unknown sync hash") {
+            panic!("{} is not the first block of an epoch", self.sync_hash);
+        }
+        let shard_ids = if self.shard_ids.is_empty() {
+            let num_shards =
+                chain.get_block(&self.sync_hash).expect("Failed to get the block").chunks().len();
+            (0..num_shards as ShardId).collect()
+        } else {
+            self.shard_ids
+        };
+        for shard_id in shard_ids {
+            let num_parts = export_state(&mut chain, &self.destination, shard_id, self.sync_hash)
+                .unwrap_or_else(|err| panic!("Failed to export shard {}: {:?}", shard_id, err));
+            info!(target: "neard", "Exported {} state parts of shard {} to {}", num_parts, shard_id, self.destination.display());
+        }
+    }
+}
+
 fn init_logging(verbose: Option<&str>) {
     let mut env_filter = EnvFilter::new(
         "tokio_reactor=info,near=info,stats=info,telemetry=info,delay_detector=info,\
@@ -20,7 +20,7 @@ use near_primitives::{
     views::{StateItem, ViewApplyState, ViewStateResult},
 };
 use near_runtime_utils::is_valid_account_id;
-use near_store::{get_access_key, get_account, get_code, TrieUpdate};
+use near_store::{get_access_key, get_account, get_code, prefix_range_end, TrieUpdate};
 use near_vm_logic::ReturnData;
 use std::{str, sync::Arc, time::Instant};
 
@@ -133,11 +133,14 @@ impl TrieViewer {
         access_keys
     }
 
+    /// Returns the contract data of the account with keys starting with `prefix`. The result
+    /// carries a proof of the whole range if `include_proof` is set.
     pub fn view_state(
         &self,
         state_update: &TrieUpdate,
         account_id: &AccountId,
         prefix: &[u8],
+        include_proof: bool,
     ) -> Result<ViewStateResult, errors::ViewStateError> {
         if !is_valid_account_id(account_id) {
             return Err(errors::ViewStateError::InvalidAccountId {
@@ -164,24 +167,56 @@ impl TrieViewer {
             }
         };
 
-        let mut values = vec![];
         let query = trie_key_parsers::get_raw_prefix_for_contract_data(account_id, prefix);
         let acc_sep_len = query.len() - prefix.len();
-        let mut iter = state_update.trie.iter(&state_update.get_root())?;
-        iter.seek(&query)?;
-        for item in iter {
-            let (key, value) = item?;
-            if !key.starts_with(&query.as_ref()) {
-                break;
+        if !include_proof {
+            if let Some(items) =
+                state_update.trie.get_prefix_from_flat_state(&state_update.get_root(), &query)?
+            {
+                let values = items
+                    .iter()
+                    .map(|(key, value)| StateItem {
+                        key: to_base64(&key[acc_sep_len..]),
+                        value: to_base64(value),
+                        proof: vec![],
+                    })
+                    .collect();
+                return Ok(ViewStateResult { values, proof: vec![] });
             }
-            values.push(StateItem {
+            let mut values = vec![];
+            let mut iter = state_update.trie.iter(&state_update.get_root())?;
+            iter.seek(&query)?;
+            for item in iter {
+                let (key, value) = item?;
+                if !key.starts_with(&query.as_ref()) {
+                    break;
+                }
+                values.push(StateItem {
+                    key: to_base64(&key[acc_sep_len..]),
+                    value: to_base64(&value),
+                    proof: vec![],
+                });
+            }
+            return Ok(ViewStateResult { values, proof: vec![] });
+        }
+        let range = state_update.trie.get_range_with_proof(
+            &state_update.get_root(),
+            &query,
+            prefix_range_end(&query).as_deref(),
+            usize::MAX,
+        )?;
+        // The proof covers the whole range, so items don't have separate proofs.
+        let values = range
+            .items
+            .iter()
+            .map(|(key, value)| StateItem {
                 key: to_base64(&key[acc_sep_len..]),
-                value: to_base64(&value),
+                value: to_base64(value),
                 proof: vec![],
-            });
-        }
-        // TODO(2076): Add proofs for the storage items.
-        Ok(ViewStateResult { values, proof: vec![] })
+            })
+            .collect();
+        let proof = range.proof.0.iter().map(to_base64).collect();
+        Ok(ViewStateResult { values, proof })
     }
 
     pub fn call_function(
@@ -306,10 +341,13 @@ mod tests {
     #[cfg(feature = "protocol_feature_evm")]
     use near_chain_configs::TESTNET_EVM_CHAIN_ID;
     use near_primitives::{
+        hash::hash,
         test_utils::MockEpochInfoProvider,
         trie_key::TrieKey,
+        trie_proof::{verify_query_response, TrieProofError},
         types::{EpochId, StateChangeCause},
         version::PROTOCOL_VERSION,
+        views::{AccountView, ContractCodeView, QueryRequest, QueryResponseKind},
     };
     use testlib::runtime_utils::{
         alice_account, encode_int, get_runtime_and_trie, get_test_trie_viewer,
@@ -473,8 +511,21 @@ mod tests {
 
         let state_update = tries.new_trie_update(0, new_root);
         let trie_viewer = TrieViewer::default();
-        let result = trie_viewer.view_state(&state_update, &alice_account(), b"").unwrap();
-        assert_eq!(result.proof, Vec::<String>::new());
+        let result = trie_viewer.view_state(&state_update, &alice_account(), b"", true).unwrap();
+        let request =
+            QueryRequest::ViewState { account_id: alice_account(), prefix: b"".to_vec().into() };
+        let response = QueryResponseKind::ViewState(result.clone());
+        assert_eq!(verify_query_response(&new_root, &request, &response, &result.proof), Ok(()));
+        let mut forged = result.clone();
+        forged.values.pop();
+        let response = QueryResponseKind::ViewState(forged);
+        assert_eq!(
+            verify_query_response(&new_root, &request, &response, &result.proof),
+            Err(TrieProofError::ResponseMismatch)
+        );
+        let plain = trie_viewer.view_state(&state_update, &alice_account(), b"", false).unwrap();
+        assert_eq!(plain.values, result.values);
+        assert!(plain.proof.is_empty());
         assert_eq!(
             result.values,
             [
@@ -490,9 +541,11 @@ mod tests {
                 }
             ]
         );
-        let result = trie_viewer.view_state(&state_update, &alice_account(), b"xyz").unwrap();
+        let result =
+            trie_viewer.view_state(&state_update, &alice_account(), b"xyz", false).unwrap();
         assert_eq!(result.values, []);
-        let result = trie_viewer.view_state(&state_update, &alice_account(), b"test123").unwrap();
+        let result =
+            trie_viewer.view_state(&state_update, &alice_account(), b"test123", false).unwrap();
         assert_eq!(
             result.values,
             [StateItem {
@@ -503,6 +556,59 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_view_account_proof() {
+        let (_, tries, root) = get_runtime_and_trie();
+        let trie_viewer = TrieViewer::default();
+        let state_update = tries.new_trie_update_view_recording(0, root);
+        let account = trie_viewer.view_account(&state_update, &alice_account()).unwrap();
+        let proof: Vec<_> =
+            state_update.trie.recorded_storage().unwrap().nodes.0.iter().map(to_base64).collect();
+        let request = QueryRequest::ViewAccount { account_id: alice_account() };
+        let response = QueryResponseKind::ViewAccount(account.clone().into());
+        assert_eq!(verify_query_response(&root, &request, &response, &proof), Ok(()));
+
+        let mut forged = AccountView::from(account);
+        forged.amount += 1;
+        let response = QueryResponseKind::ViewAccount(forged);
+        assert_eq!(
+            verify_query_response(&root, &request, &response, &proof),
+            Err(TrieProofError::ResponseMismatch)
+        );
+        let request = QueryRequest::ViewAccount { account_id: "bob.near".to_string() };
+        assert!(verify_query_response(&root, &request, &response, &proof).is_err());
+        assert!(verify_query_response(&CryptoHash::default(), &request, &response, &proof).is_err());
+    }
+
+    #[test]
+    fn test_view_code_proof_without_code() {
+        let (_, tries, root) = get_runtime_and_trie();
+        let trie_viewer = TrieViewer::default();
+        let account_id = "test.contract".to_string();
+        let state_update = tries.new_trie_update_view_recording(0, root);
+        let code = trie_viewer.view_contract_code(&state_update, &account_id).unwrap();
+        let proof: Vec<_> = state_update
+            .trie
+            .recorded_storage()
+            .unwrap()
+            .nodes
+            .0
+            .iter()
+            .filter(|item| hash(item) != code.hash)
+            .map(to_base64)
+            .collect();
+        let request = QueryRequest::ViewCode { account_id };
+        let response = QueryResponseKind::ViewCode(code.into());
+        assert_eq!(verify_query_response(&root, &request, &response, &proof), Ok(()));
+
+        let forged = ContractCodeView { code: b"forged".to_vec(), hash: hash(b"forged") };
+        let response = QueryResponseKind::ViewCode(forged);
+        assert_eq!(
+            verify_query_response(&root, &request, &response, &proof),
+            Err(TrieProofError::ResponseMismatch)
+        );
+    }
+
     #[test]
     fn test_view_state_too_large() {
         let (_, tries, root) = get_runtime_and_trie();
@@ -513,7 +619,7 @@ mod tests {
             &Account::new(0, 0, CryptoHash::default(), 50_001),
         );
         let trie_viewer = TrieViewer::new(Some(50_000), None);
-        let result = trie_viewer.view_state(&state_update, &alice_account(), b"");
+        let result = trie_viewer.view_state(&state_update, &alice_account(), b"", false);
         assert!(matches!(result, Err(errors::ViewStateError::AccountStateTooLarge { .. })));
     }
 
@@ -531,7 +637,7 @@ mod tests {
             [0; Account::MAX_ACCOUNT_DELETION_STORAGE_USAGE as usize].to_vec(),
         );
         let trie_viewer = TrieViewer::new(Some(50_000), None);
-        let result = trie_viewer.view_state(&state_update, &alice_account(), b"");
+        let result = trie_viewer.view_state(&state_update, &alice_account(), b"", false);
         assert!(result.is_ok());
     }
 
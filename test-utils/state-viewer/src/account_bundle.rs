@@ -0,0 +1,181 @@
+//! Export of the full state of a single account into a portable bundle, which can be added to the
+//! genesis records of another network or applied to a sandbox node with `sandbox_patch_state`.
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use near_chain::RuntimeAdapter;
+use near_chain_configs::{Genesis, GenesisRecords};
+use near_primitives::block::BlockHeader;
+use near_primitives::state_record::{state_record_to_account_id, StateRecord};
+use near_primitives::trie_key::{trie_key_parsers, TrieKey};
+use near_primitives::types::{AccountId, BlockHeight, StateRoot};
+use near_store::TrieIterator;
+use nearcore::NightshadeRuntime;
+
+/// Account, contract code, access keys and contract data of an account. The JSON of a bundle is
+/// also valid params of the `sandbox_patch_state` RPC.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct AccountBundle {
+    pub account_id: AccountId,
+    /// Height of the block the state was exported at.
+    pub height: BlockHeight,
+    pub records: Vec<StateRecord>,
+}
+
+impl AccountBundle {
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    pub fn to_file(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+}
+
+fn to_io_error<E: std::fmt::Display>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err.to_string())
+}
+
+/// Collects all state records of the account. The locked balance is moved to the amount, as the
+/// account is not a validator wherever the bundle is applied.
+pub fn export_account_bundle(
+    runtime: &NightshadeRuntime,
+    state_roots: &[StateRoot],
+    header: &BlockHeader,
+    account_id: &AccountId,
+) -> io::Result<AccountBundle> {
+    let shard_id = runtime.account_id_to_shard_id(account_id);
+    let trie = runtime.get_trie_for_shard(shard_id);
+    let state_root = &state_roots[shard_id as usize];
+    let prefixes = vec![
+        TrieKey::Account { account_id: account_id.clone() }.to_vec(),
+        TrieKey::ContractCode { account_id: account_id.clone() }.to_vec(),
+        trie_key_parsers::get_raw_prefix_for_access_keys(account_id),
+        trie_key_parsers::get_raw_prefix_for_contract_data(account_id, &[]),
+    ];
+    let mut records = vec![];
+    for prefix in prefixes {
+        let mut iter = TrieIterator::new(&trie, state_root).map_err(to_io_error)?;
+        iter.seek(&prefix).map_err(to_io_error)?;
+        for item in iter {
+            let (key, value) = item.map_err(to_io_error)?;
+            if !key.starts_with(&prefix) {
+                break;
+            }
+            let mut record = match StateRecord::from_raw_key_value(key, value) {
+                Some(record) => record,
+                None => continue,
+            };
+            // Keys of `alice` are also a prefix of the keys of `alice2`
+            if state_record_to_account_id(&record) != account_id {
+                continue;
+            }
+            if let StateRecord::Account { account, .. } = &mut record {
+                account.set_amount(account.amount() + account.locked());
+                account.set_locked(0);
+            }
+            records.push(record);
+        }
+    }
+    if !records.iter().any(|record| matches!(record, StateRecord::Account { .. })) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Account {} does not exist at #{}", account_id, header.height()),
+        ));
+    }
+    Ok(AccountBundle { account_id: account_id.clone(), height: header.height(), records })
+}
+
+/// Replaces all records of the bundle's account in `genesis` with the records of the bundle.
+/// The total supply is recomputed.
+pub fn add_account_bundle_to_genesis(genesis: Genesis, bundle: AccountBundle) -> Genesis {
+    let mut records = vec![];
+    genesis.for_each_record(|record| {
+        let replaced = match record {
+            StateRecord::PostponedReceipt(_) | StateRecord::DelayedReceipt(_) => false,
+            record => state_record_to_account_id(record) == &bundle.account_id,
+        };
+        if !replaced {
+            records.push(record.clone());
+        }
+    });
+    records.extend(bundle.records);
+    Genesis::new(genesis.config, GenesisRecords(records))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use near_chain::{ChainGenesis, RuntimeAdapter};
+    use near_chain_configs::Genesis;
+    use near_client::test_utils::TestEnv;
+    use near_primitives::state_record::{state_record_to_account_id, StateRecord};
+    use near_store::test_utils::create_test_store;
+    use nearcore::config::GenesisExt;
+    use nearcore::genesis_validate::validate_genesis;
+    use nearcore::NightshadeRuntime;
+
+    use crate::account_bundle::{add_account_bundle_to_genesis, export_account_bundle};
+
+    #[test]
+    fn test_export_account_bundle() {
+        let epoch_length = 4;
+        let genesis = Genesis::test(vec!["test0", "test1", "test10"], 1);
+        let store = create_test_store();
+        let runtime =
+            NightshadeRuntime::new(Path::new("."), store, &genesis, vec![], vec![], None, None);
+        let runtime = Arc::new(runtime);
+        let runtimes: Vec<Arc<dyn RuntimeAdapter>> = vec![runtime.clone()];
+        let mut chain_genesis = ChainGenesis::test();
+        chain_genesis.epoch_length = epoch_length;
+        chain_genesis.gas_limit = genesis.config.gas_limit;
+        let mut env = TestEnv::new_with_runtime(chain_genesis, 1, 1, runtimes);
+        for i in 1..=epoch_length {
+            env.produce_block(0, i);
+        }
+        let head = env.clients[0].chain.head().unwrap();
+        let last_block = env.clients[0].chain.get_block(&head.last_block_hash).unwrap().clone();
+        let state_roots: Vec<_> =
+            last_block.chunks().iter().map(|chunk| chunk.prev_state_root()).collect();
+
+        let bundle = export_account_bundle(
+            &runtime,
+            &state_roots,
+            last_block.header(),
+            &"test1".to_string(),
+        )
+        .unwrap();
+        assert!(bundle.records.iter().all(|record| state_record_to_account_id(record) == "test1"));
+        assert!(bundle
+            .records
+            .iter()
+            .any(|record| matches!(record, StateRecord::AccessKey { .. })));
+        assert!(bundle.records.iter().all(|record| match record {
+            StateRecord::Account { account, .. } => account.locked() == 0,
+            _ => true,
+        }));
+        assert!(export_account_bundle(
+            &runtime,
+            &state_roots,
+            last_block.header(),
+            &"test2".to_string()
+        )
+        .is_err());
+
+        let new_genesis = Genesis::test(vec!["test0"], 1);
+        let new_genesis = add_account_bundle_to_genesis(new_genesis, bundle);
+        let mut accounts = vec![];
+        new_genesis.for_each_record(|record| {
+            if let StateRecord::Account { account_id, .. } = record {
+                accounts.push(account_id.clone());
+            }
+        });
+        assert!(accounts.contains(&"test1".to_string()));
+        validate_genesis(&new_genesis);
+    }
+}
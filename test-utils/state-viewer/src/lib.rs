@@ -1,2 +1,6 @@
+mod account_bundle;
 mod state_dump;
+pub use crate::account_bundle::{
+    add_account_bundle_to_genesis, export_account_bundle, AccountBundle,
+};
 pub use crate::state_dump::state_dump;
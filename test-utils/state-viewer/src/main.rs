@@ -8,11 +8,13 @@ use std::sync::Arc;
 use ansi_term::Color::Red;
 use clap::{App, Arg, SubCommand};
 
+use account_bundle::{add_account_bundle_to_genesis, export_account_bundle, AccountBundle};
 use borsh::BorshSerialize;
 use near_chain::chain::collect_receipts_from_response;
 use near_chain::migrations::check_if_block_is_first_with_chunk_of_version;
 use near_chain::types::{ApplyTransactionResult, BlockHeaderInfo};
 use near_chain::{ChainStore, ChainStoreAccess, ChainStoreUpdate, RuntimeAdapter};
+use near_jsonrpc_primitives::types::sandbox::RpcSandboxPatchStateRequest;
 use near_logger_utils::init_integration_logger;
 use near_network::peer_store::PeerStore;
 use near_primitives::block::BlockHeader;
@@ -24,11 +26,12 @@ use near_primitives::trie_key::TrieKey;
 use near_primitives::types::chunk_extra::ChunkExtra;
 use near_primitives::types::{BlockHeight, ShardId, StateRoot};
 use near_store::test_utils::create_test_store;
-use near_store::{create_store, Store, TrieIterator};
+use near_store::{create_store_with_config, Store, TrieIterator};
 use nearcore::{get_default_home, get_store_path, load_config, NearConfig, NightshadeRuntime};
 use node_runtime::adapter::ViewRuntimeAdapter;
-use state_dump::state_dump;
+use state_dump::{state_dump, state_dump_records, RecordsFormat, StateDumpProgress};
 
+mod account_bundle;
 mod state_dump;
 
 #[allow(unused)]
@@ -415,6 +418,18 @@ fn dump_code(account: &str, contract_code: ContractCode, output: &str) {
     println!("Dump contract of account {} into file {}", account, output);
 }
 
+/// Applies the records of the bundle to a running node built with the `sandbox` feature.
+fn sandbox_patch_state(rpc_addr: &str, bundle: AccountBundle) {
+    let rpc_addr = rpc_addr.to_string();
+    let request = RpcSandboxPatchStateRequest { records: bundle.records };
+    let sys = actix::System::new();
+    sys.block_on(async move {
+        near_jsonrpc_client::new_client(&rpc_addr).sandbox_patch_state(request).await
+    })
+    .unwrap_or_else(|err| panic!("Failed to patch the state: {:?}", err));
+    println!("Patched the state of account {}", bundle.account_id);
+}
+
 fn main() {
     init_integration_logger();
 
@@ -437,6 +452,41 @@ fn main() {
                     .takes_value(true),
             ),
         )
+        .subcommand(
+            SubCommand::with_name("dump_state_records")
+                .arg(
+                    Arg::with_name("height")
+                        .long("height")
+                        .help("Desired stop height of state dump")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .help("Output records file (default \"<home>/records.jsonl\" or \"<home>/records.borsh\")")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .help("Format of the records file")
+                        .possible_values(&["json", "borsh"])
+                        .takes_value(true)
+                        .default_value("json"),
+                )
+                .arg(
+                    Arg::with_name("account_prefix")
+                        .long("account_prefix")
+                        .help("Only dump records of accounts starting with this prefix")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("resume")
+                        .long("resume")
+                        .help("Continue an interrupted dump into the same output file"),
+                )
+                .help("stream state records into a file without building a genesis in memory"),
+        )
         .subcommand(
             SubCommand::with_name("chain")
                 .arg(
@@ -565,12 +615,84 @@ fn main() {
                 )
                 .help("dump contract data in storage of given account to binary file"),
         )
+        .subcommand(
+            SubCommand::with_name("export_account_bundle")
+                .arg(
+                    Arg::with_name("account")
+                        .long("account")
+                        .help("account name")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .help("output bundle file")
+                        .takes_value(true)
+                        .default_value("bundle.json"),
+                )
+                .arg(
+                    Arg::with_name("block_height")
+                        .long("block_height")
+                        .help("block height of the state to export, number or \"latest\"")
+                        .takes_value(true)
+                        .default_value("latest"),
+                )
+                .help("export all state records of given account to a bundle file"),
+        )
+        .subcommand(
+            SubCommand::with_name("import_account_bundle")
+                .arg(
+                    Arg::with_name("input")
+                        .long("input")
+                        .help("bundle file created by export_account_bundle")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .help("output genesis file (default \"<home>/output.json\")")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("rpc_addr")
+                        .long("rpc_addr")
+                        .help("patch the state of a running sandbox node instead, e.g. \"http://127.0.0.1:3030\"")
+                        .takes_value(true),
+                )
+                .help("add the records of a bundle to the genesis or to a sandbox node"),
+        )
         .get_matches();
 
     let home_dir = matches.value_of("home").map(|dir| Path::new(dir)).unwrap();
     let near_config = load_config(home_dir);
 
-    let store = create_store(&get_store_path(&home_dir));
+    // Doesn't need the store, which may be locked by the running sandbox node
+    if let ("import_account_bundle", Some(args)) = matches.subcommand() {
+        let bundle = AccountBundle::from_file(Path::new(args.value_of("input").unwrap()))
+            .unwrap_or_else(|err| panic!("Failed to read the bundle: {}", err));
+        println!(
+            "Importing {} records of account {} from #{}",
+            bundle.records.len(),
+            bundle.account_id,
+            bundle.height
+        );
+        if let Some(rpc_addr) = args.value_of("rpc_addr") {
+            sandbox_patch_state(rpc_addr, bundle);
+        } else {
+            let output_path = match args.value_of("output") {
+                Some(output) => PathBuf::from(output),
+                None => home_dir.join("output.json"),
+            };
+            let new_genesis = add_account_bundle_to_genesis(near_config.genesis, bundle);
+            println!("Saving genesis into {}", output_path.display());
+            new_genesis.to_file(&output_path);
+        }
+        return;
+    }
+
+    let store = create_store_with_config(&get_store_path(&home_dir), &near_config.store_config);
 
     match matches.subcommand() {
         ("peers", Some(_args)) => {
@@ -616,6 +738,52 @@ fn main() {
             );
             new_genesis.to_file(&output_path);
         }
+        ("dump_state_records", Some(args)) => {
+            let format = match args.value_of("format").unwrap() {
+                "borsh" => RecordsFormat::Borsh,
+                _ => RecordsFormat::JsonLines,
+            };
+            let output_path = match args.value_of("output") {
+                Some(output) => PathBuf::from(output),
+                None => home_dir.join(match format {
+                    RecordsFormat::JsonLines => "records.jsonl",
+                    RecordsFormat::Borsh => "records.borsh",
+                }),
+            };
+            let resume = args.is_present("resume");
+            let progress = if resume {
+                StateDumpProgress::load_for_resume(&output_path).unwrap_or_else(|err| {
+                    panic!("Failed to resume the dump into {}: {}", output_path.display(), err)
+                })
+            } else {
+                None
+            };
+            // Resumed dump has to continue from the same state it started with.
+            let mode = match (&progress, args.value_of("height")) {
+                (Some(progress), _) => LoadTrieMode::Height(progress.height),
+                (None, Some(h)) => LoadTrieMode::LastFinalFromHeight(h.parse::<u64>().unwrap()),
+                (None, None) => LoadTrieMode::Latest,
+            };
+            let (runtime, state_roots, header) =
+                load_trie_stop_at_height(store, home_dir, &near_config, mode);
+            println!(
+                "Saving state records at {:?} @ {} into {}",
+                state_roots,
+                header.height(),
+                output_path.display(),
+            );
+            let num_records = state_dump_records(
+                &runtime,
+                &state_roots,
+                &header,
+                &output_path,
+                format,
+                args.value_of("account_prefix"),
+                progress,
+            )
+            .unwrap_or_else(|err| panic!("Failed to dump state records: {}", err));
+            println!("Saved {} records", num_records);
+        }
         ("chain", Some(args)) => {
             let start_index =
                 args.value_of("start_index").map(|s| s.parse::<u64>().unwrap()).unwrap();
@@ -703,6 +871,36 @@ fn main() {
             println!("Storage under key {} of account {} not found", storage_key, account_id);
             std::process::exit(1);
         }
+        ("export_account_bundle", Some(args)) => {
+            let account_id = args.value_of("account").unwrap().to_string();
+            let output = args.value_of("output").unwrap();
+            let block_height = args.value_of("block_height").unwrap();
+            let block_height = if block_height == "latest" {
+                LoadTrieMode::Latest
+            } else if let Ok(height) = block_height.parse::<u64>() {
+                LoadTrieMode::Height(height)
+            } else {
+                panic!("block_height should be either number or \"latest\"")
+            };
+            let (runtime, state_roots, header) =
+                load_trie_stop_at_height(store, &home_dir, &near_config, block_height);
+            match export_account_bundle(&runtime, &state_roots, &header, &account_id) {
+                Ok(bundle) => {
+                    bundle.to_file(Path::new(output)).unwrap();
+                    println!(
+                        "Exported {} records of account {} at #{} into {}",
+                        bundle.records.len(),
+                        account_id,
+                        bundle.height,
+                        output
+                    );
+                }
+                Err(err) => {
+                    println!("{}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
         (_, _) => unreachable!(),
     }
 }
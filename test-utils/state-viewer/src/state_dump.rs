@@ -1,29 +1,32 @@
 use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use borsh::BorshSerialize;
+use serde::{Deserialize, Serialize};
 
 use near_chain::RuntimeAdapter;
 use near_chain_configs::{Genesis, GenesisConfig};
+use near_crypto::PublicKey;
 use near_primitives::block::BlockHeader;
-use near_primitives::state_record::StateRecord;
-use near_primitives::types::{AccountInfo, StateRoot};
+use near_primitives::serialize::option_base64_format;
+use near_primitives::state_record::{state_record_to_account_id, StateRecord};
+use near_primitives::types::{AccountId, AccountInfo, Balance, BlockHeight, ShardId, StateRoot};
 use near_store::TrieIterator;
 use nearcore::NightshadeRuntime;
 
-pub fn state_dump(
-    runtime: NightshadeRuntime,
-    state_roots: Vec<StateRoot>,
-    last_block_header: BlockHeader,
-    genesis_config: &GenesisConfig,
-) -> Genesis {
-    println!(
-        "Generating genesis from state data of #{} / {}",
-        last_block_header.height(),
-        last_block_header.hash()
-    );
-    let genesis_height = last_block_header.height() + 1;
+/// Progress of `state_dump_records` is saved after this many records.
+const PROGRESS_SAVE_INTERVAL: u64 = 10_000;
+
+fn get_validators(
+    runtime: &NightshadeRuntime,
+    last_block_header: &BlockHeader,
+) -> HashMap<AccountId, (PublicKey, Balance)> {
     let block_producers = runtime
         .get_epoch_block_producers_ordered(&last_block_header.epoch_id(), last_block_header.hash())
         .unwrap();
-    let validators = block_producers
+    block_producers
         .into_iter()
         .filter_map(|(info, is_slashed)| {
             if !is_slashed {
@@ -33,7 +36,33 @@ pub fn state_dump(
                 None
             }
         })
-        .collect::<HashMap<_, _>>();
+        .collect::<HashMap<_, _>>()
+}
+
+/// Returns locked tokens to the accounts which are not validators in the dumped epoch.
+fn unlock_stake(record: &mut StateRecord, validators: &HashMap<AccountId, (PublicKey, Balance)>) {
+    if let StateRecord::Account { account_id, account } = record {
+        if account.locked() > 0 {
+            let stake = *validators.get(account_id).map(|(_, s)| s).unwrap_or(&0);
+            account.set_amount(account.amount() + account.locked() - stake);
+            account.set_locked(stake);
+        }
+    }
+}
+
+pub fn state_dump(
+    runtime: NightshadeRuntime,
+    state_roots: Vec<StateRoot>,
+    last_block_header: BlockHeader,
+    genesis_config: &GenesisConfig,
+) -> Genesis {
+    println!(
+        "Generating genesis from state data of #{} / {}",
+        last_block_header.height(),
+        last_block_header.hash()
+    );
+    let genesis_height = last_block_header.height() + 1;
+    let validators = get_validators(&runtime, &last_block_header);
 
     let mut records = vec![];
     for (shard_id, state_root) in state_roots.iter().enumerate() {
@@ -42,13 +71,7 @@ pub fn state_dump(
         for item in trie {
             let (key, value) = item.unwrap();
             if let Some(mut sr) = StateRecord::from_raw_key_value(key, value) {
-                if let StateRecord::Account { account_id, account } = &mut sr {
-                    if account.locked() > 0 {
-                        let stake = *validators.get(account_id).map(|(_, s)| s).unwrap_or(&0);
-                        account.set_amount(account.amount() + account.locked() - stake);
-                        account.set_locked(stake);
-                    }
-                }
+                unlock_stake(&mut sr, &validators);
                 records.push(sr);
             }
         }
@@ -63,6 +86,186 @@ pub fn state_dump(
     Genesis::new(genesis_config, records.into())
 }
 
+/// Format of the records file written by `state_dump_records`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordsFormat {
+    /// One JSON encoded `StateRecord` per line.
+    JsonLines,
+    /// Borsh encoded `StateRecord`s one after another.
+    Borsh,
+}
+
+impl RecordsFormat {
+    fn write(self, writer: &mut impl Write, record: &StateRecord) -> io::Result<u64> {
+        let bytes = match self {
+            RecordsFormat::JsonLines => {
+                let mut bytes = serde_json::to_vec(record)?;
+                bytes.push(b'\n');
+                bytes
+            }
+            RecordsFormat::Borsh => record.try_to_vec()?,
+        };
+        writer.write_all(&bytes)?;
+        Ok(bytes.len() as u64)
+    }
+}
+
+/// Saved next to the records file, so that an interrupted dump can be resumed.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct StateDumpProgress {
+    /// Height of the block whose state is dumped.
+    pub height: BlockHeight,
+    pub state_roots: Vec<StateRoot>,
+    /// Shard which is being dumped.
+    pub shard_id: ShardId,
+    /// Trie key of the last record of the shard written to the records file.
+    #[serde(with = "option_base64_format")]
+    pub last_key: Option<Vec<u8>>,
+    /// Size of the records file after writing the record with `last_key`. Anything after it
+    /// was written after the progress was saved and is dropped on resume.
+    pub output_len: u64,
+    pub num_records: u64,
+}
+
+impl StateDumpProgress {
+    pub fn path(output: &Path) -> PathBuf {
+        let mut path = output.as_os_str().to_owned();
+        path.push(".progress");
+        PathBuf::from(path)
+    }
+
+    pub fn load(output: &Path) -> io::Result<Option<Self>> {
+        match fs::read(Self::path(output)) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Loads the progress to resume the dump into `output` from. Fails if `output` exists without
+    /// any saved progress, as resuming would start the dump over and overwrite it.
+    pub fn load_for_resume(output: &Path) -> io::Result<Option<Self>> {
+        let progress = Self::load(output)?;
+        if progress.is_none() && output.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists, but there is no saved progress to resume it", output.display()),
+            ));
+        }
+        Ok(progress)
+    }
+
+    fn save(&self, output: &Path) -> io::Result<()> {
+        // Write to a temporary file first, so that an interruption never leaves a broken file.
+        let path = Self::path(output);
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, serde_json::to_vec(self)?)?;
+        fs::rename(&tmp_path, &path)
+    }
+}
+
+/// Writes the state records at `state_roots` into `output` without keeping them in memory.
+/// Records are adjusted the same way as in `state_dump`. Only records of accounts starting with
+/// `account_prefix` are written if it is given, delayed receipts are skipped in that case.
+///
+/// The progress is saved in `StateDumpProgress::path(output)` every `PROGRESS_SAVE_INTERVAL`
+/// records. Given the saved progress, the dump continues from it, otherwise `output` is created
+/// anew. The progress file is removed once the dump is complete. Returns the total number of
+/// written records.
+pub fn state_dump_records(
+    runtime: &NightshadeRuntime,
+    state_roots: &[StateRoot],
+    last_block_header: &BlockHeader,
+    output: &Path,
+    format: RecordsFormat,
+    account_prefix: Option<&str>,
+    saved_progress: Option<StateDumpProgress>,
+) -> io::Result<u64> {
+    let validators = get_validators(runtime, last_block_header);
+    let (mut progress, file) = match saved_progress {
+        Some(progress) => {
+            if progress.state_roots != state_roots {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Saved progress is for state roots {:?} at #{}, not {:?}",
+                        progress.state_roots, progress.height, state_roots
+                    ),
+                ));
+            }
+            println!(
+                "Resuming state dump of shard {} after {} records",
+                progress.shard_id, progress.num_records
+            );
+            let mut file = OpenOptions::new().write(true).open(output)?;
+            file.set_len(progress.output_len)?;
+            file.seek(SeekFrom::End(0))?;
+            (progress, file)
+        }
+        None => {
+            let progress = StateDumpProgress {
+                height: last_block_header.height(),
+                state_roots: state_roots.to_vec(),
+                shard_id: 0,
+                last_key: None,
+                output_len: 0,
+                num_records: 0,
+            };
+            (progress, File::create(output)?)
+        }
+    };
+    let mut writer = BufWriter::new(file);
+
+    for (shard_id, state_root) in state_roots.iter().enumerate().skip(progress.shard_id as usize) {
+        let shard_id = shard_id as ShardId;
+        if shard_id != progress.shard_id {
+            progress.shard_id = shard_id;
+            progress.last_key = None;
+        }
+        let trie = runtime.get_trie_for_shard(shard_id);
+        let mut iter = TrieIterator::new(&trie, &state_root)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+        if let Some(last_key) = &progress.last_key {
+            iter.seek(last_key)
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+        }
+        for item in iter {
+            let (key, value) =
+                item.map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+            if progress.last_key.as_ref() >= Some(&key) {
+                continue;
+            }
+            if let Some(mut record) = StateRecord::from_raw_key_value(key.clone(), value) {
+                let matches_prefix = match (account_prefix, &record) {
+                    (None, _) => true,
+                    (Some(_), StateRecord::DelayedReceipt(_)) => false,
+                    (Some(prefix), record) => {
+                        state_record_to_account_id(record).starts_with(prefix)
+                    }
+                };
+                if matches_prefix {
+                    unlock_stake(&mut record, &validators);
+                    progress.output_len += format.write(&mut writer, &record)?;
+                    progress.num_records += 1;
+                    progress.last_key = Some(key);
+                    if progress.num_records % PROGRESS_SAVE_INTERVAL == 0 {
+                        writer.flush()?;
+                        progress.save(output)?;
+                    }
+                }
+            }
+        }
+    }
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    match fs::remove_file(StateDumpProgress::path(output)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    Ok(progress.num_records)
+}
+
 #[cfg(test)]
 mod test {
     use std::collections::HashSet;
@@ -74,16 +277,17 @@ mod test {
     use near_chain_configs::Genesis;
     use near_client::test_utils::TestEnv;
     use near_crypto::{InMemorySigner, KeyType};
+    use near_primitives::state_record::StateRecord;
     use near_primitives::transaction::SignedTransaction;
     use near_primitives::types::NumBlocks;
     use near_store::test_utils::create_test_store;
-    use near_store::Store;
+    use near_store::{Store, TrieIterator};
     use nearcore::config::GenesisExt;
     use nearcore::config::TESTING_INIT_STAKE;
     use nearcore::genesis_validate::validate_genesis;
     use nearcore::NightshadeRuntime;
 
-    use crate::state_dump::state_dump;
+    use crate::state_dump::{state_dump, state_dump_records, RecordsFormat, StateDumpProgress};
 
     fn setup(epoch_length: NumBlocks) -> (Arc<Store>, Genesis, TestEnv) {
         let mut genesis = Genesis::test(vec!["test0", "test1"], 1);
@@ -315,4 +519,98 @@ mod test {
         assert_eq!(new_genesis.config.validators.len(), 2);
         validate_genesis(&new_genesis);
     }
+
+    /// Test that the streamed records match `state_dump` and that an interrupted dump is resumed
+    /// from the saved progress.
+    #[test]
+    fn test_dump_state_records_resume() {
+        let epoch_length = 4;
+        let (store, genesis, mut env) = setup(epoch_length);
+        for i in 1..=epoch_length + 1 {
+            env.produce_block(0, i);
+        }
+        let head = env.clients[0].chain.head().unwrap();
+        let last_block = env.clients[0].chain.get_block(&head.last_block_hash).unwrap().clone();
+        let state_roots: Vec<_> =
+            last_block.chunks().iter().map(|chunk| chunk.prev_state_root()).collect();
+        let create_runtime = || {
+            NightshadeRuntime::new(
+                Path::new("."),
+                store.clone(),
+                &genesis,
+                vec![],
+                vec![],
+                None,
+                None,
+            )
+        };
+        let expected = state_dump(
+            create_runtime(),
+            state_roots.clone(),
+            last_block.header().clone(),
+            &genesis.config,
+        )
+        .records
+        .0
+        .iter()
+        .map(|record| serde_json::to_string(record).unwrap())
+        .collect::<Vec<_>>();
+
+        let dir = tempfile::Builder::new().prefix("state_dump").tempdir().unwrap();
+        let output = dir.path().join("records.jsonl");
+        let runtime = create_runtime();
+        let num_records = state_dump_records(
+            &runtime,
+            &state_roots,
+            last_block.header(),
+            &output,
+            RecordsFormat::JsonLines,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(num_records, expected.len() as u64);
+        let content = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(content.lines().collect::<Vec<_>>(), expected);
+        assert!(!StateDumpProgress::path(&output).exists());
+        // The complete dump isn't overwritten by a resume.
+        assert!(StateDumpProgress::load_for_resume(&output).is_err());
+
+        // Pretend that the dump was interrupted after the first record, with a partially written
+        // second record.
+        let trie = runtime.get_trie_for_shard(0);
+        let first_key = TrieIterator::new(&trie, &state_roots[0])
+            .unwrap()
+            .map(Result::unwrap)
+            .find(|(key, value)| {
+                StateRecord::from_raw_key_value(key.clone(), value.clone()).is_some()
+            })
+            .unwrap()
+            .0;
+        let progress = StateDumpProgress {
+            height: last_block.header().height(),
+            state_roots: state_roots.clone(),
+            shard_id: 0,
+            last_key: Some(first_key),
+            output_len: expected[0].len() as u64 + 1,
+            num_records: 1,
+        };
+        std::fs::write(StateDumpProgress::path(&output), serde_json::to_vec(&progress).unwrap())
+            .unwrap();
+        std::fs::write(&output, format!("{}\n{{\"Acc", expected[0])).unwrap();
+        let num_records = state_dump_records(
+            &runtime,
+            &state_roots,
+            last_block.header(),
+            &output,
+            RecordsFormat::JsonLines,
+            None,
+            StateDumpProgress::load_for_resume(&output).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(num_records, expected.len() as u64);
+        let content = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(content.lines().collect::<Vec<_>>(), expected);
+        assert!(!StateDumpProgress::path(&output).exists());
+    }
 }
@@ -4,13 +4,23 @@ use std::sync::Arc;
 
 use ansi_term::Color::{Green, Red, White, Yellow};
 use clap::{App, Arg, SubCommand};
+use serde_json::json;
 
-use near_chain::store_validator::StoreValidator;
+use near_chain::store_validator::{Severity, StoreValidator};
 use near_chain::RuntimeAdapter;
 use near_logger_utils::init_integration_logger;
-use near_store::create_store;
+use near_store::create_store_with_config;
 use nearcore::{get_default_home, get_store_path, load_config};
 
+/// Exit code for the most severe problem found. Warnings alone don't fail the validation.
+fn exit_code(severity: Severity) -> i32 {
+    match severity {
+        Severity::Warning => 0,
+        Severity::Error => 2,
+        Severity::Critical => 3,
+    }
+}
+
 fn main() {
     init_integration_logger();
 
@@ -23,13 +33,32 @@ fn main() {
                 .help("Directory for config and data (default \"~/.near\")")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("repair")
+                .long("repair")
+                .help("Fix the inconsistencies which can be fixed safely")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .help("Print the results as JSON")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("validators")
+                .long("validators")
+                .help("Comma separated names of the validators to run (default all)")
+                .takes_value(true)
+                .use_delimiter(true),
+        )
         .subcommand(SubCommand::with_name("validate"))
         .get_matches();
 
     let home_dir = matches.value_of("home").map(|dir| Path::new(dir)).unwrap();
     let near_config = load_config(home_dir);
 
-    let store = create_store(&get_store_path(&home_dir));
+    let store = create_store_with_config(&get_store_path(&home_dir), &near_config.store_config);
 
     let runtime_adapter: Arc<dyn RuntimeAdapter> = Arc::new(nearcore::NightshadeRuntime::new(
         &home_dir,
@@ -47,11 +76,69 @@ fn main() {
         runtime_adapter.clone(),
         store.clone(),
     );
+    store_validator.set_gc_policy(near_config.client_config.gc_policy);
+    if let Some(validators) = matches.values_of("validators") {
+        if let Err(err) =
+            store_validator.select_validators(validators.map(|name| name.to_string()).collect())
+        {
+            eprintln!("{}", Red.bold().paint(err));
+            process::exit(exit_code(Severity::Error));
+        }
+    }
+    if matches.is_present("repair") {
+        store_validator.enable_repair();
+    }
     store_validator.validate();
+    let repairs = if matches.is_present("repair") {
+        store_validator.repair().expect("Failed to repair the store")
+    } else {
+        vec![]
+    };
+    let severity = store_validator.errors.iter().map(|error| error.severity).max();
+
+    if matches.is_present("json") {
+        let errors: Vec<_> = store_validator
+            .errors
+            .iter()
+            .map(|error| {
+                json!({
+                    "col": error.col,
+                    "key": error.key,
+                    "validator": error.validator,
+                    "height": error.height,
+                    "severity": format!("{:?}", error.severity).to_lowercase(),
+                    "error": error.err.to_string(),
+                })
+            })
+            .collect();
+        let repairs: Vec<_> = repairs
+            .iter()
+            .map(|repair| json!({"col": repair.col, "key": repair.key, "action": repair.action}))
+            .collect();
+        let gc_counters: serde_json::Map<_, _> = store_validator
+            .get_gc_counters()
+            .into_iter()
+            .map(|(col, count)| (col, count.into()))
+            .collect();
+        let result = json!({
+            "tests_done": store_validator.tests_done(),
+            "errors": errors,
+            "repairs": repairs,
+            "gc_counters": gc_counters,
+        });
+        println!("{}", serde_json::to_string_pretty(&result).unwrap());
+        if store_validator.tests_done() == 0 {
+            process::exit(exit_code(Severity::Error));
+        }
+        if let Some(severity) = severity {
+            process::exit(exit_code(severity));
+        }
+        return;
+    }
 
     if store_validator.tests_done() == 0 {
         println!("{}", Red.bold().paint("No conditions has been validated"));
-        process::exit(1);
+        process::exit(exit_code(Severity::Error));
     }
     println!(
         "{} {}",
@@ -59,21 +146,37 @@ fn main() {
         Green.bold().paint(store_validator.tests_done().to_string())
     );
     for error in store_validator.errors.iter() {
-        println!(
-            "{}  {}  {}",
-            Red.bold().paint(&error.col),
-            Yellow.bold().paint(&error.key),
-            error.err
-        );
+        let col = match error.severity {
+            Severity::Warning => Yellow.bold().paint(&error.col),
+            _ => Red.bold().paint(&error.col),
+        };
+        println!("{}  {}  {}", col, Yellow.bold().paint(&error.key), error.err);
+    }
+    if matches.is_present("repair") {
+        for repair in repairs.iter() {
+            println!(
+                "{}  {}  {}",
+                Green.bold().paint(&repair.col),
+                Yellow.bold().paint(&repair.key),
+                repair.action
+            );
+        }
+        println!("Repairs applied: {}", Green.bold().paint(repairs.len().to_string()));
     }
     if store_validator.is_failed() {
         println!("Errors found: {}", Red.bold().paint(store_validator.num_failed().to_string()));
-        process::exit(1);
     } else {
         println!("{}", Green.bold().paint("No errors found"));
     }
+    let num_warnings = store_validator.errors.len() as u64 - store_validator.num_failed();
+    if num_warnings > 0 {
+        println!("Warnings found: {}", Yellow.bold().paint(num_warnings.to_string()));
+    }
     let gc_counters = store_validator.get_gc_counters();
     for (col, count) in gc_counters {
         println!("{} {}", White.bold().paint(col), count);
     }
+    if let Some(severity) = severity {
+        process::exit(exit_code(severity));
+    }
 }
@@ -10,7 +10,7 @@ use near_primitives::hash::CryptoHash;
 use near_primitives::receipt::Receipt;
 #[cfg(not(feature = "protocol_feature_restore_receipts_after_fix"))]
 use near_primitives::receipt::ReceiptResult;
-use near_store::create_store;
+use near_store::create_store_with_config;
 #[cfg(feature = "protocol_feature_restore_receipts_after_fix")]
 use nearcore::migrations::load_migration_data;
 use nearcore::{get_default_home, get_store_path, load_config, NightshadeRuntime};
@@ -57,7 +57,7 @@ fn main() -> Result<()> {
     let shard_id = 0u64;
     let home_dir = matches.value_of("home").map(Path::new).unwrap();
     let near_config = load_config(&home_dir);
-    let store = create_store(&get_store_path(&home_dir));
+    let store = create_store_with_config(&get_store_path(&home_dir), &near_config.store_config);
     let mut chain_store = ChainStore::new(store.clone(), near_config.genesis.config.genesis_height);
     let runtime = NightshadeRuntime::new(
         &home_dir,